@@ -0,0 +1,347 @@
+use crate::terminfo::{self, Terminfo};
+use std::ops::RangeInclusive;
+
+/// The 16 basic colors, as xterm shows them.
+const BASIC: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+/// Levels of red, green and blue in the 6x6x6 cube of colors 16 to 231.
+const CUBE: [u8; 6] = [0, 95, 135, 175, 215, 255];
+/// Names of the basic colors, the bright ones with `bright-` in front.
+const NAMES: [&str; 8] = [
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+];
+
+/// How many colors a terminal shows.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorDepth {
+    /// The 8 basic colors.
+    Eight,
+    /// The basic colors and their bright versions.
+    Sixteen,
+    /// The 256 with numbers.
+    Indexed,
+    /// Any color, given exactly.
+    TrueColor,
+}
+
+impl ColorDepth {
+    /// Works out the colors of the terminal from `$COLORTERM`, which terminals showing exact
+    /// colors set to `truecolor` or `24bit`, or else from the terminfo entry for `$TERM`.
+    /// Without one, names like `xterm-256color` go by what they say, and the rest are taken to
+    /// have the 16 basic colors.
+    pub fn detect() -> Self {
+        if let Ok("truecolor" | "24bit") = std::env::var("COLORTERM").as_deref() {
+            return ColorDepth::TrueColor;
+        }
+        let term = std::env::var("TERM").unwrap_or_default();
+        // An entry without max_colors has no colors to speak of.
+        let colors =
+            Terminfo::load(&term).map(|info| info.number(terminfo::MAX_COLORS).unwrap_or(0));
+        let colors = colors.unwrap_or(match term.as_str() {
+            "dumb" => 8,
+            term if term.contains("256color") => 256,
+            _ => 16,
+        });
+        ColorDepth::from_count(colors)
+    }
+
+    pub fn from_count(colors: u32) -> Self {
+        match colors {
+            0..=15 => ColorDepth::Eight,
+            16..=255 => ColorDepth::Sixteen,
+            256..=0xff_ffff => ColorDepth::Indexed,
+            _ => ColorDepth::TrueColor,
+        }
+    }
+
+    /// The depth as the config file gives it: `8`, `16`, `256` or `truecolor`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "truecolor" | "24bit" => Some(ColorDepth::TrueColor),
+            count => count.parse().ok().map(ColorDepth::from_count),
+        }
+    }
+}
+
+/// A color a program's output asked for: one of the 256 the terminal has numbers for, the
+/// first 16 of which are the basic ones, or an exact one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Color {
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    /// Parameters selecting this color, for the foreground or the background.
+    fn params(self, background: bool) -> String {
+        let base = if background { 40 } else { 30 };
+        match self {
+            Color::Indexed(n) if n < 8 => (base + n as u16).to_string(),
+            Color::Indexed(n) if n < 16 => (base + 60 + n as u16 - 8).to_string(),
+            Color::Indexed(n) => format!("{};5;{}", base + 8, n),
+            Color::Rgb(r, g, b) => format!("{};2;{};{};{}", base + 8, r, g, b),
+        }
+    }
+
+    /// A color by name, like `red` or `bright-red`, by number up to 255, or as `#rrggbb`.
+    fn parse(name: &str) -> Option<Color> {
+        if let Some(hex) = name.strip_prefix('#') {
+            let value = u32::from_str_radix(hex, 16)
+                .ok()
+                .filter(|_| hex.len() == 6)?;
+            return Some(Color::Rgb(
+                (value >> 16) as u8,
+                (value >> 8) as u8,
+                value as u8,
+            ));
+        }
+        if let Ok(n) = name.parse() {
+            return Some(Color::Indexed(n));
+        }
+        let (base, name) = match name.strip_prefix("bright-") {
+            Some(name) => (8, name),
+            None => (0, name),
+        };
+        let n = NAMES.iter().position(|&known| known == name)?;
+        Some(Color::Indexed(base + n as u8))
+    }
+
+    fn rgb(self) -> (u8, u8, u8) {
+        match self {
+            Color::Rgb(r, g, b) => (r, g, b),
+            Color::Indexed(n) if n < 16 => BASIC[n as usize],
+            Color::Indexed(n) if n < 232 => {
+                let n = (n - 16) as usize;
+                (CUBE[n / 36], CUBE[n / 6 % 6], CUBE[n % 6])
+            }
+            Color::Indexed(n) => {
+                let level = 8 + 10 * (n - 232);
+                (level, level, level)
+            }
+        }
+    }
+
+    /// The nearest color a terminal showing `depth` colors has. The bright basic colors
+    /// become the plain ones on terminals with only 8.
+    fn reduce(self, depth: ColorDepth) -> Color {
+        match (self, depth) {
+            (_, ColorDepth::TrueColor) | (Color::Indexed(_), ColorDepth::Indexed) => self,
+            (Color::Indexed(n), ColorDepth::Sixteen) if n < 16 => self,
+            (Color::Indexed(n), ColorDepth::Eight) if n < 16 => Color::Indexed(n % 8),
+            (_, ColorDepth::Indexed) => nearest(self.rgb(), 16..=255),
+            (_, ColorDepth::Sixteen) => nearest(self.rgb(), 0..=15),
+            (_, ColorDepth::Eight) => nearest(self.rgb(), 0..=7),
+        }
+    }
+}
+
+/// The one of `candidates` closest to `rgb`.
+fn nearest(rgb: (u8, u8, u8), candidates: RangeInclusive<u8>) -> Color {
+    let distance = |n: &u8| {
+        let (r, g, b) = Color::Indexed(*n).rgb();
+        [(r, rgb.0), (g, rgb.1), (b, rgb.2)]
+            .iter()
+            .map(|&(a, b)| (a as i32 - b as i32).pow(2))
+            .sum::<i32>()
+    };
+    Color::Indexed(candidates.min_by_key(distance).unwrap_or(0))
+}
+
+/// How a character of colored output is drawn, as built up by the SGR sequences before it.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Style {
+    foreground: Option<Color>,
+    background: Option<Color>,
+    bold: bool,
+    dim: bool,
+    italic: bool,
+    underline: bool,
+    reverse: bool,
+}
+
+impl Style {
+    /// A style in words, like `bold yellow`, `black on #fabd2f` or `italic 244`: any of
+    /// `bold`, `dim`, `italic`, `underline` and `reverse`, a color for the text, and one after
+    /// `on` for the background.
+    pub fn parse(spec: &str) -> Result<Style, String> {
+        let mut style = Style::default();
+        let mut words = spec.split_whitespace();
+        while let Some(word) = words.next() {
+            match word {
+                "bold" => style.bold = true,
+                "dim" => style.dim = true,
+                "italic" => style.italic = true,
+                "underline" => style.underline = true,
+                "reverse" => style.reverse = true,
+                "on" => {
+                    let name = words.next().ok_or("no color after on")?;
+                    let color =
+                        Color::parse(name).ok_or_else(|| format!("unknown color {}", name))?;
+                    style.background = Some(color);
+                }
+                name => {
+                    let color =
+                        Color::parse(name).ok_or_else(|| format!("unknown color {}", name))?;
+                    style.foreground = Some(color);
+                }
+            }
+        }
+        Ok(style)
+    }
+
+    /// This style with its colors brought down to ones a terminal showing `depth` has.
+    fn reduce(self, depth: ColorDepth) -> Style {
+        Style {
+            foreground: self.foreground.map(|color| color.reduce(depth)),
+            background: self.background.map(|color| color.reduce(depth)),
+            ..self
+        }
+    }
+
+    /// Applies the parameters of an SGR sequence, `1;31` in `\x1b[1;31m`.
+    fn apply(&mut self, params: &str) {
+        let mut codes = params
+            .split([';', ':'])
+            .map(|code| code.parse::<u16>().unwrap_or(0));
+        while let Some(code) = codes.next() {
+            match code {
+                0 => *self = Style::default(),
+                1 => self.bold = true,
+                2 => self.dim = true,
+                3 => self.italic = true,
+                4 => self.underline = true,
+                7 => self.reverse = true,
+                22 => (self.bold, self.dim) = (false, false),
+                23 => self.italic = false,
+                24 => self.underline = false,
+                27 => self.reverse = false,
+                30..=37 => self.foreground = Some(Color::Indexed(code as u8 - 30)),
+                39 => self.foreground = None,
+                40..=47 => self.background = Some(Color::Indexed(code as u8 - 40)),
+                49 => self.background = None,
+                90..=97 => self.foreground = Some(Color::Indexed(code as u8 - 90 + 8)),
+                100..=107 => self.background = Some(Color::Indexed(code as u8 - 100 + 8)),
+                38 | 48 => {
+                    let color = match codes.next() {
+                        Some(5) => codes.next().map(|n| Color::Indexed(n as u8)),
+                        Some(2) => match (codes.next(), codes.next(), codes.next()) {
+                            (Some(r), Some(g), Some(b)) => {
+                                Some(Color::Rgb(r as u8, g as u8, b as u8))
+                            }
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+                    if code == 38 {
+                        self.foreground = color;
+                    } else {
+                        self.background = color;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Escape sequence switching a terminal in its normal state to this style, or nothing for
+    /// the normal style.
+    pub fn escape(&self) -> String {
+        let mut params = Vec::new();
+        for (on, code) in [
+            (self.bold, "1"),
+            (self.dim, "2"),
+            (self.italic, "3"),
+            (self.underline, "4"),
+            (self.reverse, "7"),
+        ] {
+            if on {
+                params.push(code.to_string());
+            }
+        }
+        params.extend(self.foreground.map(|color| color.params(false)));
+        params.extend(self.background.map(|color| color.params(true)));
+        if params.is_empty() {
+            String::new()
+        } else {
+            format!("\x1b[{}m", params.join(";"))
+        }
+    }
+}
+
+/// `escape`, made of SGR sequences, with its colors brought down to ones a terminal showing
+/// `depth` has.
+pub fn reduce(escape: &str, depth: ColorDepth) -> String {
+    if depth == ColorDepth::TrueColor {
+        return escape.to_string();
+    }
+    let mut style = Style::default();
+    strip(escape, &mut style);
+    style.reduce(depth).escape()
+}
+
+/// Whether `line` has escape sequences in it, like the output of a program writing in color.
+pub fn has_escapes(line: &str) -> bool {
+    line.contains("\x1b[")
+}
+
+/// `line` without its escape sequences, with the byte offsets in it where the style changes
+/// and the style from there on. `style` is the one the line starts in, and is left as the
+/// one it ends in, since a color carries on over line breaks. Sequences other than SGR, like
+/// moving the cursor or naming the window, are dropped.
+pub fn strip(line: &str, style: &mut Style) -> (String, Vec<(usize, Style)>) {
+    let mut text = String::with_capacity(line.len());
+    let mut styles = vec![(0, *style)];
+    let mut chars = line.chars().peekable();
+    while let Some(ch) = chars.next() {
+        if ch != '\x1b' {
+            text.push(ch);
+            continue;
+        }
+        match chars.next() {
+            // CSI: parameters and intermediates, then a final byte saying what it does.
+            Some('[') => {
+                let mut params = String::new();
+                while let Some(&next) = chars.peek() {
+                    if !('\x20'..='\x3f').contains(&next) {
+                        break;
+                    }
+                    params.push(next);
+                    chars.next();
+                }
+                if chars.next() == Some('m') {
+                    style.apply(&params);
+                    match styles.last_mut() {
+                        Some((at, last)) if *at == text.len() => *last = *style,
+                        _ => styles.push((text.len(), *style)),
+                    }
+                }
+            }
+            // OSC, like a hyperlink or a window title, up to BEL or ST.
+            Some(']') => {
+                while let Some(next) = chars.next() {
+                    if next == '\x07' || (next == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    (text, styles)
+}
@@ -0,0 +1,623 @@
+//! What `Terminal` needs from the platform underneath it. Unix terminals go through termios,
+//! Windows consoles through the Win32 console API in virtual terminal mode, so that both read
+//! and write the same escape sequences and the rest of the editor doesn't tell them apart.
+
+use std::fs::File;
+use std::io::Result;
+use std::time::Duration;
+
+#[cfg(unix)]
+pub use unix::Termios as Native;
+#[cfg(windows)]
+pub use windows::Console as Native;
+
+/// Raw access to standard input and the size of the window. Standard input and output belong
+/// to the whole process, so only switching modes keeps any state, to put things back after.
+/// That state gets copied into the panic hook, to put things back even then.
+pub trait Backend: Sized + Clone + Send + Sync + 'static {
+    /// Switches the terminal to raw input, remembering how it was, and starts watching for
+    /// window size changes.
+    fn enable_raw_mode() -> Result<Self>;
+
+    /// Puts the terminal back the way `enable_raw_mode` found it.
+    fn restore(&self) -> Result<()>;
+
+    /// Points standard input and output at the terminal where they're redirected, for the
+    /// editor to run there in the middle of a pipeline. Returns the standard output it
+    /// replaced, if it did.
+    fn attach_terminal() -> Result<Option<File>>;
+
+    /// Rows and columns of the window.
+    fn window_size() -> Result<(usize, usize)>;
+
+    /// Whether the window changed size since the last call.
+    fn take_resize() -> bool;
+
+    /// Stops the process, as the shell's job control does, until the shell continues it.
+    /// Returns straight away where there's no job control.
+    fn stop();
+
+    /// Whether the process was asked to stop since the last call, by a signal rather than a
+    /// key, which raw mode reads as a key.
+    fn take_stop() -> bool;
+
+    /// Whether the process was continued since the last call after being stopped, other than
+    /// by `stop`.
+    fn take_continue() -> bool;
+
+    /// Waits up to `timeout` for input. Returns false if none came, or if a resize cut the
+    /// wait short.
+    fn wait_for_input(timeout: Duration) -> Result<bool>;
+
+    /// Reads one byte of input, or None if none arrives within a tenth of a second or so.
+    fn read_byte() -> Option<Result<u8>>;
+}
+
+#[cfg(unix)]
+mod unix {
+    use super::Backend;
+    use libc::{c_int, c_ulong, c_void, termios, winsize};
+    use libc::{
+        BRKINT, CS8, ECHO, ICANON, ICRNL, IEXTEN, INPCK, ISIG, ISTRIP, IXON, OPOST, STDIN_FILENO,
+        STDOUT_FILENO, TIOCGWINSZ, VMIN, VTIME,
+    };
+    use std::fs::{File, OpenOptions};
+    use std::io::{self, Error, ErrorKind, Read, Result, Write};
+    use std::mem;
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+
+    /// Set by the SIGWINCH handler, and taken by the next read to report an `Event::Resize`.
+    static RESIZED: AtomicBool = AtomicBool::new(false);
+    /// Set by the SIGTSTP and SIGCONT handlers, for the next read to stop the process itself
+    /// once the terminal is put back, or take it over again after the process was stopped.
+    static STOP_ASKED: AtomicBool = AtomicBool::new(false);
+    static CONTINUED: AtomicBool = AtomicBool::new(false);
+
+    extern "C" fn on_resize(_: c_int) {
+        RESIZED.store(true, Ordering::Relaxed);
+    }
+
+    extern "C" fn on_stop(_: c_int) {
+        STOP_ASKED.store(true, Ordering::Relaxed);
+    }
+
+    extern "C" fn on_continue(_: c_int) {
+        CONTINUED.store(true, Ordering::Relaxed);
+    }
+
+    extern "C" {
+        pub fn tcgetattr(fd: c_int, termios: *mut termios) -> c_int;
+        pub fn tcsetattr(fd: c_int, optional_actions: c_int, termios: *const termios) -> c_int;
+        pub fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+    }
+
+    /// Terminal attributes from before raw mode.
+    #[derive(Clone)]
+    pub struct Termios {
+        orig: termios,
+    }
+
+    fn get_attr() -> Result<termios> {
+        let mut termios = unsafe { mem::zeroed::<termios>() };
+        unsafe {
+            if tcgetattr(STDIN_FILENO, &mut termios) != 0 {
+                return Err(Error::other("Can't get term attributes"));
+            }
+        }
+        Ok(termios)
+    }
+
+    fn set_attr(termios: &termios) -> Result<()> {
+        unsafe {
+            if tcsetattr(STDIN_FILENO, libc::TCSAFLUSH, termios) != 0 {
+                return Err(Error::other("Can't set term attributes"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Falls back on moving the cursor as far as it goes and asking where it ended up, for
+    /// terminals that don't answer TIOCGWINSZ.
+    fn get_cursor_position() -> Result<(usize, usize)> {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(b"\x1b[999C\x1b[999B\x1b[6n\r\n")?;
+        stdout.flush()?;
+
+        let cursor_buf = io::stdin()
+            .lock()
+            .bytes()
+            .take_while(|c| !matches!(c, Ok(b'R')))
+            .collect::<Result<Vec<_>>>()?;
+
+        let dimensions = cursor_buf
+            .get(2..)
+            .unwrap_or_default()
+            .split(|&c| c == b';')
+            .filter_map(|buf| std::str::from_utf8(buf).ok())
+            .filter_map(|buf| buf.parse().ok())
+            .collect::<Vec<_>>();
+
+        if dimensions.len() != 2 {
+            return Err(Error::other("Can't get window size"));
+        }
+
+        Ok((dimensions[0], dimensions[1]))
+    }
+
+    impl Backend for Termios {
+        fn enable_raw_mode() -> Result<Self> {
+            let orig = get_attr()?;
+            let mut raw = orig;
+            raw.c_lflag &= !(ECHO | ICANON | ISIG | IEXTEN);
+            raw.c_iflag &= !(IXON | ICRNL | BRKINT | INPCK | ISTRIP);
+            raw.c_oflag &= !(OPOST);
+            raw.c_oflag |= CS8;
+            raw.c_cc[VMIN] = 0;
+            raw.c_cc[VTIME] = 1;
+            set_attr(&raw)?;
+
+            unsafe {
+                libc::signal(
+                    libc::SIGWINCH,
+                    on_resize as extern "C" fn(c_int) as libc::sighandler_t,
+                );
+                libc::signal(
+                    libc::SIGTSTP,
+                    on_stop as extern "C" fn(c_int) as libc::sighandler_t,
+                );
+                libc::signal(
+                    libc::SIGCONT,
+                    on_continue as extern "C" fn(c_int) as libc::sighandler_t,
+                );
+            }
+            Ok(Self { orig })
+        }
+
+        fn restore(&self) -> Result<()> {
+            set_attr(&self.orig)
+        }
+
+        fn attach_terminal() -> Result<Option<File>> {
+            let (input, output) =
+                unsafe { (libc::isatty(STDIN_FILENO), libc::isatty(STDOUT_FILENO)) };
+            if input == 1 && output == 1 {
+                return Ok(None);
+            }
+            let tty = OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+            let mut replaced = None;
+            if output != 1 {
+                let fd = unsafe { libc::dup(STDOUT_FILENO) };
+                if fd == -1 {
+                    return Err(Error::last_os_error());
+                }
+                replaced = Some(unsafe { File::from_raw_fd(fd) });
+            }
+            for (redirected, fd) in [(input != 1, STDIN_FILENO), (output != 1, STDOUT_FILENO)] {
+                if redirected && unsafe { libc::dup2(tty.as_raw_fd(), fd) } == -1 {
+                    return Err(Error::last_os_error());
+                }
+            }
+            Ok(replaced)
+        }
+
+        fn window_size() -> Result<(usize, usize)> {
+            let mut ws = unsafe { mem::zeroed::<winsize>() };
+            if unsafe { ioctl(STDOUT_FILENO, TIOCGWINSZ, &mut ws) } == -1 || ws.ws_col == 0 {
+                return get_cursor_position();
+            }
+            Ok((ws.ws_row as usize, ws.ws_col as usize))
+        }
+
+        fn take_resize() -> bool {
+            RESIZED.swap(false, Ordering::Relaxed)
+        }
+
+        fn stop() {
+            // The handler only takes note, so the signal gets its usual effect back to stop
+            // the process, and runs on from here once it's continued.
+            unsafe {
+                libc::signal(libc::SIGTSTP, libc::SIG_DFL);
+                libc::raise(libc::SIGTSTP);
+                libc::signal(
+                    libc::SIGTSTP,
+                    on_stop as extern "C" fn(c_int) as libc::sighandler_t,
+                );
+            }
+            CONTINUED.store(false, Ordering::Relaxed);
+        }
+
+        fn take_stop() -> bool {
+            STOP_ASKED.swap(false, Ordering::Relaxed)
+        }
+
+        fn take_continue() -> bool {
+            CONTINUED.swap(false, Ordering::Relaxed)
+        }
+
+        fn wait_for_input(timeout: Duration) -> Result<bool> {
+            let mut stdin = libc::pollfd {
+                fd: STDIN_FILENO,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let millis = timeout
+                .as_nanos()
+                .div_ceil(1_000_000)
+                .min(c_int::MAX as u128) as c_int;
+            match unsafe { libc::poll(&mut stdin, 1, millis) } {
+                -1 => {
+                    // A resize interrupts the wait.
+                    let err = Error::last_os_error();
+                    if err.kind() == ErrorKind::Interrupted {
+                        Ok(false)
+                    } else {
+                        Err(err)
+                    }
+                }
+                0 => Ok(false),
+                _ => Ok(true),
+            }
+        }
+
+        fn read_byte() -> Option<Result<u8>> {
+            let mut byte = 0u8;
+            match unsafe { libc::read(STDIN_FILENO, &mut byte as *mut u8 as *mut c_void, 1) } {
+                1 => Some(Ok(byte)),
+                0 => None,
+                _ => match Error::last_os_error() {
+                    err if err.kind() == ErrorKind::Interrupted => None,
+                    err => Some(Err(err)),
+                },
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+mod windows {
+    use super::Backend;
+    use std::collections::VecDeque;
+    use std::ffi::c_void;
+    use std::fs::File;
+    use std::io::{Error, Result};
+    use std::mem;
+    use std::os::windows::io::FromRawHandle;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Mutex;
+    use std::time::{Duration, Instant};
+
+    type Handle = *mut c_void;
+
+    const STD_INPUT_HANDLE: u32 = -10i32 as u32;
+    const STD_OUTPUT_HANDLE: u32 = -11i32 as u32;
+
+    const ENABLE_PROCESSED_INPUT: u32 = 0x0001;
+    const ENABLE_LINE_INPUT: u32 = 0x0002;
+    const ENABLE_ECHO_INPUT: u32 = 0x0004;
+    const ENABLE_WINDOW_INPUT: u32 = 0x0008;
+    const ENABLE_MOUSE_INPUT: u32 = 0x0010;
+    const ENABLE_QUICK_EDIT_MODE: u32 = 0x0040;
+    const ENABLE_EXTENDED_FLAGS: u32 = 0x0080;
+    const ENABLE_VIRTUAL_TERMINAL_INPUT: u32 = 0x0200;
+
+    const ENABLE_PROCESSED_OUTPUT: u32 = 0x0001;
+    const ENABLE_VIRTUAL_TERMINAL_PROCESSING: u32 = 0x0004;
+    const DISABLE_NEWLINE_AUTO_RETURN: u32 = 0x0008;
+
+    const GENERIC_READ: u32 = 0x8000_0000;
+    const GENERIC_WRITE: u32 = 0x4000_0000;
+    const FILE_SHARE_READ: u32 = 0x0001;
+    const FILE_SHARE_WRITE: u32 = 0x0002;
+    const OPEN_EXISTING: u32 = 3;
+
+    const CP_UTF8: u32 = 65001;
+    const WAIT_OBJECT_0: u32 = 0;
+    const WAIT_TIMEOUT: u32 = 0x102;
+    const KEY_EVENT: u16 = 0x0001;
+    const WINDOW_BUFFER_SIZE_EVENT: u16 = 0x0004;
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct Coord {
+        x: i16,
+        y: i16,
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct SmallRect {
+        left: i16,
+        top: i16,
+        right: i16,
+        bottom: i16,
+    }
+
+    #[repr(C)]
+    struct ConsoleScreenBufferInfo {
+        size: Coord,
+        cursor_position: Coord,
+        attributes: u16,
+        window: SmallRect,
+        maximum_window_size: Coord,
+    }
+
+    /// The key event member of `INPUT_RECORD`'s union, which is as big as any of the others.
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct KeyEventRecord {
+        key_down: i32,
+        repeat_count: u16,
+        virtual_key_code: u16,
+        virtual_scan_code: u16,
+        unicode_char: u16,
+        control_key_state: u32,
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct InputRecord {
+        event_type: u16,
+        event: KeyEventRecord,
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetStdHandle(std_handle: u32) -> Handle;
+        fn SetStdHandle(std_handle: u32, handle: Handle) -> i32;
+        fn CreateFileW(
+            name: *const u16,
+            access: u32,
+            share_mode: u32,
+            security: *mut c_void,
+            disposition: u32,
+            flags: u32,
+            template: Handle,
+        ) -> Handle;
+        fn GetConsoleMode(console: Handle, mode: *mut u32) -> i32;
+        fn SetConsoleMode(console: Handle, mode: u32) -> i32;
+        fn GetConsoleCP() -> u32;
+        fn GetConsoleOutputCP() -> u32;
+        fn SetConsoleCP(code_page: u32) -> i32;
+        fn SetConsoleOutputCP(code_page: u32) -> i32;
+        fn GetConsoleScreenBufferInfo(output: Handle, info: *mut ConsoleScreenBufferInfo) -> i32;
+        fn WaitForSingleObject(handle: Handle, millis: u32) -> u32;
+        fn PeekConsoleInputW(input: Handle, buf: *mut InputRecord, len: u32, read: *mut u32)
+            -> i32;
+        fn ReadConsoleInputW(input: Handle, buf: *mut InputRecord, len: u32, read: *mut u32)
+            -> i32;
+        fn ReadFile(
+            file: Handle,
+            buf: *mut c_void,
+            len: u32,
+            read: *mut u32,
+            overlapped: *mut c_void,
+        ) -> i32;
+    }
+
+    /// Set when a window size event is drained from the input queue.
+    static RESIZED: AtomicBool = AtomicBool::new(false);
+    /// Bytes read from the console but not yet handed out. A read can't ask for fewer bytes
+    /// than a character takes, so they come in batches.
+    static PENDING: Mutex<VecDeque<u8>> = Mutex::new(VecDeque::new());
+
+    fn handle(which: u32) -> Result<Handle> {
+        let handle = unsafe { GetStdHandle(which) };
+        if handle.is_null() || handle as isize == -1 {
+            return Err(Error::other("No console attached"));
+        }
+        Ok(handle)
+    }
+
+    /// Whether `which` of the standard handles is the console rather than a file or a pipe.
+    fn is_console(which: u32) -> bool {
+        let mut mode = 0;
+        handle(which).is_ok_and(|handle| unsafe { GetConsoleMode(handle, &mut mode) } != 0)
+    }
+
+    /// Opens `name`, `CONIN$` or `CONOUT$`, the console whatever the standard handles are.
+    fn open_console(name: &str) -> Result<Handle> {
+        let name = name.encode_utf16().chain([0]).collect::<Vec<u16>>();
+        let handle = unsafe {
+            CreateFileW(
+                name.as_ptr(),
+                GENERIC_READ | GENERIC_WRITE,
+                FILE_SHARE_READ | FILE_SHARE_WRITE,
+                std::ptr::null_mut(),
+                OPEN_EXISTING,
+                0,
+                std::ptr::null_mut(),
+            )
+        };
+        if handle as isize == -1 {
+            return Err(Error::last_os_error());
+        }
+        Ok(handle)
+    }
+
+    fn check(result: i32) -> Result<()> {
+        if result == 0 {
+            Err(Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn pending() -> std::sync::MutexGuard<'static, VecDeque<u8>> {
+        PENDING.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Console modes and code pages from before raw mode.
+    #[derive(Clone)]
+    pub struct Console {
+        input_mode: u32,
+        output_mode: u32,
+        input_cp: u32,
+        output_cp: u32,
+    }
+
+    impl Backend for Console {
+        fn enable_raw_mode() -> Result<Self> {
+            let (input, output) = (handle(STD_INPUT_HANDLE)?, handle(STD_OUTPUT_HANDLE)?);
+            let (mut input_mode, mut output_mode) = (0, 0);
+            unsafe {
+                check(GetConsoleMode(input, &mut input_mode))?;
+                check(GetConsoleMode(output, &mut output_mode))?;
+            }
+            let orig = Self {
+                input_mode,
+                output_mode,
+                input_cp: unsafe { GetConsoleCP() },
+                output_cp: unsafe { GetConsoleOutputCP() },
+            };
+
+            // Keys come in as the same escape sequences a Unix terminal sends, and the
+            // console interprets the ones going out.
+            let raw_input = (input_mode
+                & !(ENABLE_PROCESSED_INPUT
+                    | ENABLE_LINE_INPUT
+                    | ENABLE_ECHO_INPUT
+                    | ENABLE_MOUSE_INPUT
+                    | ENABLE_QUICK_EDIT_MODE))
+                | ENABLE_WINDOW_INPUT
+                | ENABLE_EXTENDED_FLAGS
+                | ENABLE_VIRTUAL_TERMINAL_INPUT;
+            let raw_output = output_mode
+                | ENABLE_PROCESSED_OUTPUT
+                | ENABLE_VIRTUAL_TERMINAL_PROCESSING
+                | DISABLE_NEWLINE_AUTO_RETURN;
+            unsafe {
+                check(SetConsoleMode(input, raw_input))?;
+                if let Err(err) = check(SetConsoleMode(output, raw_output)) {
+                    // Consoles older than Windows 10 don't understand escape sequences.
+                    let _ = orig.restore();
+                    return Err(err);
+                }
+                SetConsoleCP(CP_UTF8);
+                SetConsoleOutputCP(CP_UTF8);
+            }
+            Ok(orig)
+        }
+
+        fn attach_terminal() -> Result<Option<File>> {
+            if !is_console(STD_INPUT_HANDLE) {
+                check(unsafe { SetStdHandle(STD_INPUT_HANDLE, open_console("CONIN$")?) })?;
+            }
+            if is_console(STD_OUTPUT_HANDLE) {
+                return Ok(None);
+            }
+            let replaced = unsafe { File::from_raw_handle(handle(STD_OUTPUT_HANDLE)?) };
+            check(unsafe { SetStdHandle(STD_OUTPUT_HANDLE, open_console("CONOUT$")?) })?;
+            Ok(Some(replaced))
+        }
+
+        fn restore(&self) -> Result<()> {
+            unsafe {
+                SetConsoleCP(self.input_cp);
+                SetConsoleOutputCP(self.output_cp);
+                check(SetConsoleMode(handle(STD_INPUT_HANDLE)?, self.input_mode))?;
+                check(SetConsoleMode(handle(STD_OUTPUT_HANDLE)?, self.output_mode))
+            }
+        }
+
+        fn window_size() -> Result<(usize, usize)> {
+            let mut info = unsafe { mem::zeroed::<ConsoleScreenBufferInfo>() };
+            unsafe {
+                check(GetConsoleScreenBufferInfo(
+                    handle(STD_OUTPUT_HANDLE)?,
+                    &mut info,
+                ))?;
+            }
+            let rows = info.window.bottom - info.window.top + 1;
+            let cols = info.window.right - info.window.left + 1;
+            Ok((rows.max(1) as usize, cols.max(1) as usize))
+        }
+
+        fn take_resize() -> bool {
+            RESIZED.swap(false, Ordering::Relaxed)
+        }
+
+        fn stop() {}
+
+        fn take_stop() -> bool {
+            false
+        }
+
+        fn take_continue() -> bool {
+            false
+        }
+
+        fn wait_for_input(timeout: Duration) -> Result<bool> {
+            if !pending().is_empty() {
+                return Ok(true);
+            }
+            let input = handle(STD_INPUT_HANDLE)?;
+            let deadline = Instant::now() + timeout;
+            loop {
+                let wait = deadline.saturating_duration_since(Instant::now());
+                let millis = wait
+                    .as_nanos()
+                    .div_ceil(1_000_000)
+                    .min(u32::MAX as u128 - 1);
+                match unsafe { WaitForSingleObject(input, millis as u32) } {
+                    WAIT_OBJECT_0 => {}
+                    WAIT_TIMEOUT => return Ok(false),
+                    _ => return Err(Error::last_os_error()),
+                }
+                // The handle is signalled for any input record, but only key presses carry
+                // bytes to read. Anything else is taken off the queue so that reading doesn't
+                // block on it, and a window size change cuts the wait short.
+                let mut record = unsafe { mem::zeroed::<InputRecord>() };
+                let mut count = 0;
+                unsafe { check(PeekConsoleInputW(input, &mut record, 1, &mut count))? };
+                if count == 0 {
+                    continue;
+                }
+                if record.event_type == KEY_EVENT
+                    && record.event.key_down != 0
+                    && record.event.unicode_char != 0
+                {
+                    return Ok(true);
+                }
+                unsafe { check(ReadConsoleInputW(input, &mut record, 1, &mut count))? };
+                if record.event_type == WINDOW_BUFFER_SIZE_EVENT {
+                    RESIZED.store(true, Ordering::Relaxed);
+                    return Ok(false);
+                }
+            }
+        }
+
+        fn read_byte() -> Option<Result<u8>> {
+            if let Some(byte) = pending().pop_front() {
+                return Some(Ok(byte));
+            }
+            match Self::wait_for_input(Duration::from_millis(100)) {
+                Ok(true) => {}
+                Ok(false) => return None,
+                Err(err) => return Some(Err(err)),
+            }
+            let mut buf = [0u8; 64];
+            let mut read = 0;
+            let input = match handle(STD_INPUT_HANDLE) {
+                Ok(input) => input,
+                Err(err) => return Some(Err(err)),
+            };
+            let result = unsafe {
+                ReadFile(
+                    input,
+                    buf.as_mut_ptr() as *mut c_void,
+                    buf.len() as u32,
+                    &mut read,
+                    std::ptr::null_mut(),
+                )
+            };
+            if let Err(err) = check(result) {
+                return Some(Err(err));
+            }
+            let mut pending = pending();
+            pending.extend(&buf[..read as usize]);
+            pending.pop_front().map(Ok)
+        }
+    }
+}
@@ -0,0 +1,343 @@
+use crate::buffer::Buffer;
+use crate::pattern::Pattern;
+use crate::save;
+use std::io::{Error, ErrorKind, Result, Write};
+
+/// A line picked out by number, counting from 1, or the last line.
+#[derive(Clone, Copy, Debug)]
+enum LineNumber {
+    Number(usize),
+    Last,
+}
+
+impl LineNumber {
+    /// Index of the line in `buffer`, which may be past its end.
+    fn index(self, buffer: &Buffer) -> usize {
+        match self {
+            LineNumber::Number(number) => number - 1,
+            LineNumber::Last => buffer.line_count().saturating_sub(1),
+        }
+    }
+}
+
+/// Lines a command applies to.
+#[derive(Debug)]
+enum Address {
+    Lines(LineNumber, LineNumber),
+    Matching(Pattern),
+}
+
+#[derive(Debug)]
+enum Command {
+    Substitute {
+        pattern: Pattern,
+        replacement: String,
+        global: bool,
+    },
+    Delete,
+    Write,
+    Print,
+}
+
+/// Commands to run over a file without showing it, as given to `milo --execute`.
+#[derive(Debug)]
+pub struct Script {
+    steps: Vec<(Option<Address>, Command)>,
+}
+
+impl Script {
+    /// Reads a script of commands separated by newlines or `;`, or says what's wrong with it:
+    ///
+    /// - `[ADDRESS]s/PATTERN/REPLACEMENT/[g]` replaces the first match of the regular
+    ///   expression on each line, or every one with `g`. In the replacement `&` stands for the
+    ///   match and `\n` for a line break; a `\` takes the character after it as it is. Any
+    ///   other punctuation can stand in for the `/`.
+    /// - `ADDRESS d` deletes lines.
+    /// - `w` saves the file and `p` prints it. Nothing is saved without a `w`.
+    ///
+    /// An address is a line number, `$` for the last line, two of those as `FIRST,LAST`, or
+    /// `/PATTERN/` for the lines with a match. Substitutions go over every line without one.
+    pub fn parse(text: &str) -> std::result::Result<Self, String> {
+        let mut parser = Parser {
+            chars: text.chars().collect(),
+            pos: 0,
+        };
+        let mut steps = Vec::new();
+        loop {
+            parser.skip(|ch| ch.is_whitespace() || ch == ';');
+            if parser.peek().is_none() {
+                return Ok(Self { steps });
+            }
+            let address = parser.address()?;
+            parser.skip(|ch| ch == ' ' || ch == '\t');
+            let command = match parser.next() {
+                Some('s') => {
+                    let delimiter = parser.delimiter()?;
+                    let pattern = parser.delimited(delimiter, true)?;
+                    let replacement = parser.delimited(delimiter, false)?;
+                    let global = parser.peek() == Some('g');
+                    if global {
+                        parser.pos += 1;
+                    }
+                    Command::Substitute {
+                        pattern: Pattern::regex(&pattern, false)?,
+                        replacement,
+                        global,
+                    }
+                }
+                Some('d') if address.is_none() => return Err("d needs an address".to_string()),
+                Some('d') => Command::Delete,
+                Some('w') => Command::Write,
+                Some('p') => Command::Print,
+                Some(other) => return Err(format!("Unknown command: {}", other)),
+                None => return Err("Address without a command".to_string()),
+            };
+            parser.skip(|ch| ch == ' ' || ch == '\t');
+            if !matches!(parser.peek(), None | Some('\n') | Some(';')) {
+                return Err(format!(
+                    "Unexpected {} after a command",
+                    parser.peek().unwrap_or_default()
+                ));
+            }
+            steps.push((address, command));
+        }
+    }
+
+    /// Runs the script over `buffer`, saving it to its file on `w` and printing it to `out` on
+    /// `p`.
+    pub fn run(&self, buffer: &mut Buffer, out: &mut dyn Write) -> Result<()> {
+        for (address, command) in &self.steps {
+            match command {
+                Command::Substitute {
+                    pattern,
+                    replacement,
+                    global,
+                } => substitute(buffer, address.as_ref(), pattern, replacement, *global),
+                Command::Delete => delete(buffer, address.as_ref()),
+                Command::Write => {
+                    let path = buffer
+                        .filename()
+                        .clone()
+                        .ok_or_else(|| Error::other("No file to write to"))?;
+                    save::write_file(&path, &buffer.to_bytes()?, false)?;
+                    buffer.not_dirty();
+                }
+                Command::Print => out.write_all(buffer.rows_to_string().as_bytes())?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// First and last index of the lines `address` gives by number, or of all of them without
+/// one. None for addresses going by the content of lines.
+fn line_range(buffer: &Buffer, address: Option<&Address>) -> Option<(usize, usize)> {
+    match address {
+        None => Some((0, buffer.line_count().saturating_sub(1))),
+        Some(Address::Lines(first, last)) => Some((first.index(buffer), last.index(buffer))),
+        Some(Address::Matching(_)) => None,
+    }
+}
+
+/// Whether line `row` is one of those `address` gives by their content.
+fn selects(buffer: &Buffer, address: Option<&Address>, row: usize) -> bool {
+    match address {
+        Some(Address::Matching(pattern)) => buffer
+            .line(row)
+            .is_some_and(|line| !pattern.find_iter(line.content()).is_empty()),
+        _ => true,
+    }
+}
+
+fn substitute(
+    buffer: &mut Buffer,
+    address: Option<&Address>,
+    pattern: &Pattern,
+    replacement: &str,
+    global: bool,
+) {
+    let (mut row, mut last) = line_range(buffer, address).unwrap_or((0, usize::MAX));
+    while row <= last && row < buffer.line_count() {
+        let lines = buffer.line_count();
+        if selects(buffer, address, row) {
+            let content = buffer.line(row).map(|line| line.content().to_string());
+            let content = content.unwrap_or_default();
+            let mut found = pattern.find_iter(&content);
+            if !global {
+                found.truncate(1);
+            }
+            // From the right, so the matches to the left stay where they were found.
+            for (start, end) in found.into_iter().rev() {
+                let text = expand(replacement, &content[start..end]);
+                buffer.replace_text((row, start), end - start, &text);
+            }
+        }
+        // Line breaks in replacements push the lines after them down.
+        let added = buffer.line_count() - lines;
+        row += 1 + added;
+        last = last.saturating_add(added);
+    }
+}
+
+/// `replacement` with the match put in for each `&`, which parsing left as a NUL.
+fn expand(replacement: &str, found: &str) -> String {
+    replacement.replace('\u{0}', found)
+}
+
+fn delete(buffer: &mut Buffer, address: Option<&Address>) {
+    if let Some((first, last)) = line_range(buffer, address) {
+        if first <= last {
+            buffer.delete_lines(first, last);
+        }
+        return;
+    }
+    let mut row = 0;
+    while row < buffer.line_count() {
+        if selects(buffer, address, row) {
+            buffer.delete_lines(row, row);
+        } else {
+            row += 1;
+        }
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let ch = self.peek();
+        self.pos += 1;
+        ch
+    }
+
+    fn skip(&mut self, skip: impl Fn(char) -> bool) {
+        while self.peek().is_some_and(&skip) {
+            self.pos += 1;
+        }
+    }
+
+    fn address(&mut self) -> std::result::Result<Option<Address>, String> {
+        if self.peek() == Some('/') {
+            self.pos += 1;
+            let pattern = self.delimited('/', true)?;
+            return Ok(Some(Address::Matching(Pattern::regex(&pattern, false)?)));
+        }
+        let Some(first) = self.line_number()? else {
+            return Ok(None);
+        };
+        if self.peek() != Some(',') {
+            return Ok(Some(Address::Lines(first, first)));
+        }
+        self.pos += 1;
+        let last = self
+            .line_number()?
+            .ok_or("Line range without an end".to_string())?;
+        Ok(Some(Address::Lines(first, last)))
+    }
+
+    fn line_number(&mut self) -> std::result::Result<Option<LineNumber>, String> {
+        if self.peek() == Some('$') {
+            self.pos += 1;
+            return Ok(Some(LineNumber::Last));
+        }
+        let start = self.pos;
+        self.skip(|ch| ch.is_ascii_digit());
+        if start == self.pos {
+            return Ok(None);
+        }
+        let digits = self.chars[start..self.pos].iter().collect::<String>();
+        match digits.parse::<usize>() {
+            Ok(number) if number > 0 => Ok(Some(LineNumber::Number(number))),
+            _ => Err(format!("Bad line number: {}", digits)),
+        }
+    }
+
+    fn delimiter(&mut self) -> std::result::Result<char, String> {
+        match self.next() {
+            Some(ch) if ch.is_ascii_punctuation() && ch != '\\' => Ok(ch),
+            _ => Err("s needs a delimiter like /".to_string()),
+        }
+    }
+
+    /// Text up to the next `delimiter` not escaped by a `\`, which is dropped. Patterns keep
+    /// their other escapes for the regular expression; replacements turn them into the
+    /// characters they stand for, and `&` into a NUL for `expand` to fill in.
+    fn delimited(&mut self, delimiter: char, pattern: bool) -> std::result::Result<String, String> {
+        let mut text = String::new();
+        loop {
+            match self.next() {
+                None => return Err(format!("Missing closing {}", delimiter)),
+                Some(ch) if ch == delimiter => return Ok(text),
+                Some('\\') => match self.next() {
+                    None => return Err(format!("Missing closing {}", delimiter)),
+                    Some(ch) if ch == delimiter => text.push(ch),
+                    Some(ch) if pattern => {
+                        text.push('\\');
+                        text.push(ch);
+                    }
+                    Some('n') => text.push('\n'),
+                    Some('t') => text.push('\t'),
+                    Some(ch) => text.push(ch),
+                },
+                Some('&') if !pattern => text.push('\u{0}'),
+                Some(ch) => text.push(ch),
+            }
+        }
+    }
+}
+
+/// Runs the script at the start of `args` over each of the files after it, as
+/// `milo --execute SCRIPT FILE...` does.
+pub fn run(args: &[String]) -> Result<()> {
+    let (script, files) = args
+        .split_first()
+        .ok_or_else(|| Error::other("Usage: milo --execute SCRIPT FILE..."))?;
+    let script = Script::parse(script).map_err(|err| Error::other(format!("Script: {}", err)))?;
+    if files.is_empty() {
+        return Err(Error::other("Nothing to execute on: no files given"));
+    }
+    let mut stdout = std::io::stdout().lock();
+    for file in files {
+        let with_name = |err: Error| Error::new(err.kind(), format!("{}: {}", file, err));
+        let mut buffer = Buffer::new();
+        buffer.set_filename(Some(file.clone()));
+        match std::fs::read(file) {
+            Ok(bytes) => buffer.read_bytes(&bytes, None).map_err(with_name)?,
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => return Err(with_name(err)),
+        }
+        buffer.not_dirty();
+        script.run(&mut buffer, &mut stdout).map_err(with_name)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `text` after running `script` over it.
+    fn execute(script: &str, text: &str) -> String {
+        let mut buffer = Buffer::new();
+        buffer.read_bytes(text.as_bytes(), None).expect("text");
+        let script = Script::parse(script).expect("a valid script");
+        script.run(&mut buffer, &mut Vec::new()).expect("no saving");
+        buffer.rows_to_string()
+    }
+
+    #[test]
+    fn substitutions_at_anchors() {
+        assert_eq!(execute("s/^/> /", "a\nb\n"), "> a\n> b\n");
+        assert_eq!(execute("$s/$/!/", "a\nb\n"), "a\nb!\n");
+        assert_eq!(execute(r"s/\b/|/g", "ab cd\n"), "|ab| |cd|\n");
+        assert_eq!(execute("s/x*/-/g", "axxb\n"), "-a-b-\n");
+        assert_eq!(execute("/^b/d", "a\nb\n"), "a\n");
+    }
+}
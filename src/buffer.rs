@@ -1,25 +1,207 @@
-use crate::line::Line;
+use crate::ansi::{self, Style};
+use crate::diff;
+use crate::encoding::Encoding;
+use crate::filetype::Filetype;
+use crate::highlight::{self, Highlight, LineState};
+use crate::history::{History, Travel};
+use crate::line::{Line, TAB_STOP};
+use crate::pattern::Pattern;
+use crate::region::{self, Position, Region};
+use crate::storage::{Lines, Storage};
+#[cfg(feature = "syntax-backend")]
+use crate::syntax::{self, SyntaxBackend};
 use crate::terminal::Motion;
-use std::cmp::min;
+use std::cmp::{min, Reverse};
+use std::fs::{File, TryLockError};
+use std::io::{BufRead, Error, ErrorKind, Result};
 use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+/// Columns left between the widest cell of a column of elastic tab stops and the next column.
+const ELASTIC_PADDING: usize = 2;
+
+/// What a buffer holds, which decides how it behaves.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum BufferKind {
+    /// Contents of a file, or of one yet to be named.
+    #[default]
+    File,
+    /// Throwaway text, never saved and never warned about when left with changes.
+    Scratch,
+    /// Documentation, read-only.
+    Help,
+    /// Output of a command, like a list of search results, read-only.
+    Results,
+    /// Entries of a directory, one per line, to open and change files from.
+    Directory,
+    /// Differences between a buffer and its file or another buffer, read-only.
+    Diff,
+    /// Text read from standard input, saved like a file once it has a name.
+    Stdin,
+}
+
+impl BufferKind {
+    /// Name shown for buffers of this kind that have no file name.
+    pub fn name(self) -> &'static str {
+        match self {
+            BufferKind::File => "[No Name]",
+            BufferKind::Scratch => "[Scratch]",
+            BufferKind::Help => "[Help]",
+            BufferKind::Results => "[Results]",
+            BufferKind::Directory => "[Directory]",
+            BufferKind::Diff => "[Diff]",
+            BufferKind::Stdin => "[Standard Input]",
+        }
+    }
+
+    /// Whether buffers of this kind are meant to be saved to a file.
+    pub fn is_file_backed(self) -> bool {
+        matches!(self, BufferKind::File | BufferKind::Stdin)
+    }
+
+    pub fn is_read_only(self) -> bool {
+        matches!(
+            self,
+            BufferKind::Help | BufferKind::Results | BufferKind::Directory | BufferKind::Diff
+        )
+    }
+}
+
+/// What ends each line in the file.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    /// A carriage return before the line feed, as files from Windows have.
+    Crlf,
+}
+
+impl LineEnding {
+    pub fn name(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "LF",
+            LineEnding::Crlf => "CRLF",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<LineEnding> {
+        match name.to_ascii_lowercase().as_str() {
+            "lf" | "unix" => Some(LineEnding::Lf),
+            "crlf" | "dos" => Some(LineEnding::Crlf),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::Crlf => "\r\n",
+        }
+    }
+
+    /// The line ending most lines of `text` end in.
+    pub fn detect(text: &str) -> LineEnding {
+        let crlf = text.matches("\r\n").count();
+        if crlf > text.matches('\n').count() - crlf {
+            LineEnding::Crlf
+        } else {
+            LineEnding::Lf
+        }
+    }
+}
 
 #[derive(Default)]
 pub struct Buffer {
+    kind: BufferKind,
+    encoding: Encoding,
+    line_ending: LineEnding,
     render_col: usize,
     cursor_col: usize,
     cursor_row: usize,
-    lines: Vec<Line>,
+    lines: Lines,
     row_offset: usize,
     col_offset: usize,
     filename: Option<PathBuf>,
+    filetype: Option<&'static Filetype>,
     dirty: bool,
+    changes: usize,
+    read_only: bool,
+    /// Whether keys page through the text instead of typing, as in view mode.
+    viewing: bool,
+    lock: Option<File>,
+    disk_state: Option<(SystemTime, u64)>,
+    /// Columns between tab stops, when not the default `TAB_STOP`.
+    tab_stop: Option<usize>,
+    /// Whether long lines wrap onto further screen rows rather than scrolling sideways.
+    wrap: bool,
+    /// One level of indentation in this buffer in particular, as found in the file or set for
+    /// it, over what the filetype or the settings say.
+    own_indent: Option<String>,
+    /// Spaces Tab types instead of a tab, for files nothing else says the indentation of.
+    indent_spaces: Option<usize>,
+    /// Whether tabs line cells up in columns across neighbouring lines, and the value of
+    /// `changes` the columns were last worked out at.
+    elastic_tabs: (bool, Option<usize>),
+    /// Lines and columns kept in view around the cursor, where the buffer allows.
+    scroll_margins: (usize, usize),
+    /// See `Cursor::desired_col`.
+    desired_col: Option<(usize, (usize, usize))>,
+    /// State highlighting is in at the start of each of the first few lines, like inside a
+    /// block comment, with how many of them still hold. An edit to a line leaves those of the
+    /// lines up to the one after it, and the rest are worked out again from there.
+    line_states: (usize, Vec<LineState>),
+    /// Pass working out `line_states` for a big buffer, with the line it started at, how many
+    /// lines it has reported on so far and the value of `changes` it was started at.
+    background: Option<(highlight::Background, usize, usize, usize)>,
+    /// Backend highlighting the text from a parse of it, when there's one for the filetype,
+    /// with the first line changed since it was last given the text.
+    #[cfg(feature = "syntax-backend")]
+    syntax: Option<(Box<dyn SyntaxBackend>, Option<usize>)>,
+    /// Lines wider than a text width, with the value of `changes` and the width they were
+    /// counted at.
+    overlong_lines: Option<(usize, usize, usize)>,
+    history: History,
+    /// Value of `changes` when the swap file was last written.
+    swapped_at: usize,
+    /// Whether autosave leaves the buffer alone.
+    autosave_off: bool,
+    /// Escape sequences the text came with, when it's shown as colored output.
+    ansi: Option<AnsiText>,
+    /// Places named by a letter to jump back to, in the order of their names.
+    marks: Vec<(char, Position)>,
+}
+
+/// Colored output of a program a buffer was opened from.
+struct AnsiText {
+    /// Lines as they are in the file, escape sequences and all.
+    raw: Vec<String>,
+    /// Lines without their escape sequences.
+    stripped: Vec<String>,
+    /// Byte offsets in each stripped line where the style changes, with the style from there.
+    styles: Vec<Vec<(usize, Style)>>,
+    /// Whether the buffer shows the stripped lines in their colors, rather than the raw ones.
+    colors: bool,
 }
 
+/// Where the cursor line should end up when aligning the view to it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ViewAlign {
+    Top,
+    Center,
+    Bottom,
+}
+
+/// A view into the buffer: where its cursor is and how far it's scrolled.
+#[derive(Clone, Copy, Default)]
 pub struct Cursor {
     pub cursor_row: usize,
     pub cursor_col: usize,
     pub row_offset: usize,
     pub col_offset: usize,
+    /// Render column that vertical motions aim for, together with the cursor position they
+    /// left behind. Only honoured while the cursor is still there; `usize::MAX` sticks to the
+    /// end of line.
+    pub(crate) desired_col: Option<(usize, (usize, usize))>,
 }
 
 impl Buffer {
@@ -27,59 +209,657 @@ impl Buffer {
         Self::default()
     }
 
+    pub fn with_kind(kind: BufferKind) -> Self {
+        Self {
+            kind,
+            ..Self::default()
+        }
+    }
+
+    pub fn kind(&self) -> BufferKind {
+        self.kind
+    }
+
+    /// Whether the buffer has changes worth saving. Never true for kinds without a file.
     pub fn is_dirty(&self) -> bool {
-        self.dirty
+        self.dirty && self.kind.is_file_backed()
     }
 
     pub fn not_dirty(&mut self) {
         self.dirty = false;
     }
 
+    /// Marks the buffer as changed from line `row` on, which the lines after it depend on for
+    /// their highlighting.
+    fn mark_dirty(&mut self, row: usize) {
+        self.dirty = true;
+        self.changes += 1;
+        self.stale_from(row);
+    }
+
+    /// Lets highlighting know that line `row` changed, and with it what the lines after it
+    /// depend on.
+    fn stale_from(&mut self, row: usize) {
+        self.line_states.0 = min(self.line_states.0, row + 1);
+        #[cfg(feature = "syntax-backend")]
+        if let Some((_, stale)) = &mut self.syntax {
+            *stale = Some(stale.map_or(row, |first| min(first, row)));
+        }
+    }
+
+    /// Records the text in the buffer's history, unless it's there already.
+    pub fn record_history(&mut self) {
+        if self.history.has_seen(self.changes) {
+            return;
+        }
+        let lines = self.lines.iter().map(Line::content).collect::<Vec<&str>>();
+        self.history
+            .record(&lines, (self.cursor_row, self.cursor_col), self.changes);
+    }
+
+    /// Writes the buffer's history next to its file, for `load_history` to pick up when the
+    /// file is opened again.
+    pub fn save_history(&mut self) -> Result<()> {
+        self.record_history();
+        match self.sidecar_path("undo") {
+            Some(path) => self.history.save(&path),
+            None => Ok(()),
+        }
+    }
+
+    /// Takes up the history saved next to the file, if it leads up to the text as loaded.
+    pub fn load_history(&mut self) -> Result<()> {
+        let Some(path) = self.sidecar_path("undo").filter(|path| path.exists()) else {
+            return Ok(());
+        };
+        let lines = self
+            .lines
+            .iter()
+            .map(|line| line.content().to_string())
+            .collect::<Vec<String>>();
+        self.history = History::load(&path, &lines, self.changes)?;
+        Ok(())
+    }
+
+    /// Whether the buffer has unsaved changes that aren't in the swap file yet.
+    pub fn swap_pending(&self) -> bool {
+        self.is_dirty() && self.filename.is_some() && self.changes != self.swapped_at
+    }
+
+    /// Writes the text to the recovery file, so that unsaved changes survive a crash.
+    pub fn write_swap(&mut self) -> Result<()> {
+        if let Some(path) = self.recovery_path() {
+            std::fs::write(path, self.rows_to_string())?;
+            self.swapped_at = self.changes;
+        }
+        Ok(())
+    }
+
+    /// Deletes the recovery file, once its changes are saved or thrown away.
+    pub fn remove_swap(&self) {
+        if let Some(path) = self.recovery_path() {
+            std::fs::remove_file(path).unwrap_or(());
+        }
+    }
+
+    /// Replaces all the text with `text`, as a change to be saved. Returns the row each line
+    /// went to, as `apply_text` does.
+    pub fn restore_text(&mut self, text: &str) -> Vec<usize> {
+        self.record_history();
+        let (rows, first) = self.apply_text(text);
+        self.mark_dirty(first);
+        rows
+    }
+
+    /// Takes up `text` as what the file holds now that it changed on disk, leaving the text
+    /// as it was in the history to go back to. Returns the row each line went to, as
+    /// `apply_text` does.
+    pub fn reload_text(&mut self, text: &str) -> Vec<usize> {
+        self.record_history();
+        let (rows, first) = self.apply_text(text);
+        self.mark_dirty(first);
+        self.dirty = false;
+        self.record_disk_state();
+        rows
+    }
+
+    /// Turns the text into `text` by replacing only the runs of lines that differ, so that
+    /// lines that stay keep their place for the cursor. Returns the row each line went to, or
+    /// for a line that went away, the row of what came after it, for other views of the buffer
+    /// to follow, and the first row that changed.
+    fn apply_text(&mut self, text: &str) -> (Vec<usize>, usize) {
+        let new = text.lines().collect::<Vec<&str>>();
+        let edits = {
+            let old = self.lines.iter().map(Line::content).collect::<Vec<&str>>();
+            diff::diff(&old, &new)
+        };
+        let tab_stop = self.tab_stop();
+        let changes = diff::changes(&edits);
+        let first = changes
+            .first()
+            .map_or(self.lines.len(), |(old_rows, _)| old_rows.start);
+        // From the bottom up, so that the rows of the changes above stay where they were.
+        for (old_rows, new_rows) in changes.into_iter().rev() {
+            let lines = new[new_rows]
+                .iter()
+                .map(|line| Line::with_tab_stop(line.to_string(), tab_stop))
+                .collect();
+            self.lines.remove_lines(old_rows.clone());
+            self.lines.insert_lines(old_rows.start, lines);
+        }
+        let rows = diff::index_map(&edits);
+        let last = self.lines.len().saturating_sub(1);
+        let follow = |row: usize| rows.get(row).map_or(last, |&row| min(row, last));
+        self.cursor_row = follow(self.cursor_row);
+        self.row_offset = follow(self.row_offset);
+        self.cursor_col = self
+            .lines
+            .get(self.cursor_row)
+            .map_or(0, |line| line.floor_char_boundary(self.cursor_col));
+        self.desired_col = None;
+        (rows, first)
+    }
+
+    /// Takes the text back in its history, or forward unless `back`, to the state `travel`
+    /// leads to. Returns the number of that state, counting from 1, how many states there are,
+    /// and how long ago it was recorded.
+    pub fn travel(&mut self, travel: Travel, back: bool) -> Option<(usize, usize, Duration)> {
+        self.record_history();
+        let idx = self.history.destination(travel, back)?;
+        let tab_stop = self.tab_stop();
+        let (lines, (row, col)) = self.history.arrive(idx, self.changes + 1);
+        self.lines = lines
+            .into_iter()
+            .map(|line| Line::with_tab_stop(line, tab_stop))
+            .collect();
+        self.cursor_row = min(row, self.lines.len().saturating_sub(1));
+        self.cursor_col = self
+            .lines
+            .get(self.cursor_row)
+            .map_or(0, |line| line.floor_char_boundary(col));
+        self.desired_col = None;
+        self.mark_dirty(0);
+        Some((idx + 1, self.history.len(), self.history.age(idx)))
+    }
+
+    /// Takes the escape sequences in the text as colored output of a program, showing the text
+    /// without them in the colors they ask for, read-only. Returns whether there were any.
+    pub fn interpret_ansi(&mut self) -> bool {
+        let raw = self
+            .lines
+            .iter()
+            .map(|line| line.content().to_string())
+            .collect::<Vec<String>>();
+        if !raw.iter().any(|line| ansi::has_escapes(line)) {
+            return false;
+        }
+        let mut style = Style::default();
+        let (stripped, styles) = raw.iter().map(|line| ansi::strip(line, &mut style)).unzip();
+        self.ansi = Some(AnsiText {
+            raw,
+            stripped,
+            styles,
+            colors: false,
+        });
+        self.read_only = true;
+        self.show_ansi_colors(true);
+        true
+    }
+
+    /// Whether the colors of colored output are shown, or the escape sequences for them. None
+    /// if the buffer isn't colored output.
+    pub fn ansi_colors(&self) -> Option<bool> {
+        self.ansi.as_ref().map(|ansi| ansi.colors)
+    }
+
+    /// Shows colored output in its colors, or as the text with its escape sequences.
+    pub fn show_ansi_colors(&mut self, colors: bool) {
+        let Some(ansi) = self.ansi.as_mut() else {
+            return;
+        };
+        ansi.colors = colors;
+        let lines = if colors { &ansi.stripped } else { &ansi.raw };
+        let tab_stop = self.tab_stop.unwrap_or(TAB_STOP);
+        self.lines = lines
+            .iter()
+            .map(|line| Line::with_tab_stop(line.clone(), tab_stop))
+            .collect();
+        self.cursor_col = self
+            .lines
+            .get(self.cursor_row)
+            .map_or(0, |line| line.floor_char_boundary(self.cursor_col));
+        self.desired_col = None;
+        // Not a change to save, but one for everything worked out from the text.
+        self.changes += 1;
+        self.stale_from(0);
+    }
+
+    /// Number of modifications made over the buffer's lifetime. Never decreases, so callers can
+    /// remember a value and later tell whether the buffer changed since.
+    pub fn changes(&self) -> usize {
+        self.changes
+    }
+
     pub fn is_empty(&self) -> bool {
         self.lines.is_empty()
     }
 
+    pub fn autosave_off(&self) -> bool {
+        self.autosave_off
+    }
+
+    pub fn set_autosave_off(&mut self, off: bool) {
+        self.autosave_off = off;
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only || self.viewing || self.kind.is_read_only()
+    }
+
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    pub fn is_viewing(&self) -> bool {
+        self.viewing
+    }
+
+    /// Has keys page through the text, like a pager's, instead of typing, which leaves the
+    /// buffer read-only for as long.
+    pub fn set_viewing(&mut self, viewing: bool) {
+        self.viewing = viewing;
+    }
+
+    /// Takes an advisory lock on the backing file, held for as long as the buffer lives, so
+    /// that other instances can tell the file is being edited. Returns false if another
+    /// process already holds it.
+    pub fn lock(&mut self) -> Result<bool> {
+        let file = match &self.filename {
+            Some(path) => File::open(path)?,
+            None => return Ok(true),
+        };
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => return Ok(false),
+            Err(TryLockError::Error(err)) => return Err(err),
+        }
+        self.lock = Some(file);
+        Ok(true)
+    }
+
+    /// Takes the lock again, on the file now at the buffer's path. Saving puts a new file in
+    /// place of the old one, and the lock stays with the old one.
+    pub fn relock(&mut self) -> Result<bool> {
+        // A lock on the same file through another handle would count as someone else's.
+        self.lock = None;
+        self.lock()
+    }
+
+    pub fn tab_stop(&self) -> usize {
+        self.tab_stop.unwrap_or(TAB_STOP)
+    }
+
+    /// Expands tabs to every `tab_stop` columns from now on, re-rendering every line.
+    pub fn set_tab_stop(&mut self, tab_stop: usize) {
+        self.tab_stop = Some(tab_stop.max(1));
+        for line in self.lines.iter_mut() {
+            line.set_tab_stop(tab_stop);
+        }
+        self.overlong_lines = None;
+    }
+
+    /// What Tab types and one level of indentation is: the buffer's own indentation, or else
+    /// the filetype's, or else `indent_spaces` spaces or a tab.
+    pub fn indent_unit(&self) -> String {
+        match (&self.own_indent, self.filetype, self.indent_spaces) {
+            (Some(indent), _, _) => indent.clone(),
+            (None, Some(filetype), _) => filetype.indent.to_string(),
+            (None, None, Some(spaces)) => " ".repeat(spaces),
+            (None, None, None) => "\t".to_string(),
+        }
+    }
+
+    /// Has Tab type `spaces` spaces, or a tab with None, unless the buffer or its filetype
+    /// says otherwise.
+    pub fn set_indent_spaces(&mut self, spaces: Option<usize>) {
+        self.indent_spaces = spaces.map(|spaces| spaces.max(1));
+    }
+
+    /// Sets the indentation of this buffer in particular, or with None goes back to what the
+    /// filetype or the settings say.
+    pub fn set_own_indent(&mut self, indent: Option<String>) {
+        self.own_indent = indent;
+    }
+
+    /// Whether the indentation comes from the buffer itself, found in the file or set for it.
+    pub fn has_own_indent(&self) -> bool {
+        self.own_indent.is_some()
+    }
+
+    /// Sets the buffer's own indentation to what most of its indented lines use, if they use
+    /// anything consistently.
+    pub fn detect_indent(&mut self) {
+        self.own_indent = detect_indent(self.lines.iter().map(Line::content));
+    }
+
+    /// Lines cells ending in tabs up in columns across neighbouring lines, each column as wide
+    /// as its widest cell, instead of expanding tabs to fixed tab stops. Columns are worked out
+    /// again as the text changes, for table-like files.
+    pub fn set_elastic_tabs(&mut self, elastic: bool) {
+        self.elastic_tabs = (elastic, None);
+        if elastic {
+            self.align_tabs();
+        } else {
+            for line in self.lines.iter_mut() {
+                line.set_elastic_stops(Vec::new());
+            }
+        }
+    }
+
+    pub fn elastic_tabs(&self) -> bool {
+        self.elastic_tabs.0
+    }
+
+    /// Works out the elastic tab stops of every line again, if tabs are elastic and the text
+    /// changed since they were last.
+    fn align_tabs(&mut self) {
+        if !self.elastic_tabs.0 || self.elastic_tabs.1 == Some(self.changes) {
+            return;
+        }
+        self.elastic_tabs.1 = Some(self.changes);
+        let cells = self
+            .lines
+            .iter()
+            .map(Line::cell_widths)
+            .collect::<Vec<Vec<usize>>>();
+        for (line, stops) in self.lines.iter_mut().zip(elastic_stops(&cells)) {
+            line.set_elastic_stops(stops);
+        }
+    }
+
+    /// Wraps long lines onto as many screen rows as they take, instead of scrolling the view
+    /// sideways to the cursor.
+    pub fn set_wrap(&mut self, wrap: bool) {
+        self.wrap = wrap;
+        self.col_offset = 0;
+    }
+
+    pub fn set_scroll_margins(&mut self, lines: usize, cols: usize) {
+        self.scroll_margins = (lines, cols);
+    }
+
+    /// Lines kept in view above and below the cursor in a view `rows` high, as many as asked
+    /// for while the cursor line can still be anywhere in the middle.
+    fn line_margin(&self, rows: usize) -> usize {
+        min(self.scroll_margins.0, rows.saturating_sub(1) / 2)
+    }
+
     pub fn filename(&self) -> &Option<PathBuf> {
         &self.filename
     }
 
+    pub fn filetype(&self) -> Option<&'static Filetype> {
+        self.filetype
+    }
+
+    /// Sets the filetype that highlighting, comments and indentation follow.
+    pub fn set_filetype(&mut self, filetype: Option<&'static Filetype>) {
+        self.filetype = filetype;
+        self.line_states = (0, Vec::new());
+        self.background = None;
+        #[cfg(feature = "syntax-backend")]
+        {
+            self.syntax = filetype
+                .and_then(syntax::backend_for)
+                .map(|backend| (backend, Some(0)));
+        }
+    }
+
+    /// Sets the filetype from the file name and the first line.
+    pub fn detect_filetype(&mut self) {
+        let first_line = self.lines.get(0).map(|line| line.content());
+        self.set_filetype(Filetype::detect(self.filename.as_deref(), first_line));
+    }
+
     pub fn set_filename(&mut self, filename: Option<String>) {
         self.filename = filename.map(|filename| filename.into());
+        self.detect_filetype();
+    }
+
+    fn read_disk_state(&self) -> Option<(SystemTime, u64)> {
+        let metadata = std::fs::metadata(self.filename.as_ref()?).ok()?;
+        Some((metadata.modified().ok()?, metadata.len()))
+    }
+
+    /// Remembers the backing file's modification time and size, to be called whenever the
+    /// buffer and the file are known to agree.
+    pub fn record_disk_state(&mut self) {
+        self.disk_state = self.read_disk_state();
+    }
+
+    /// Whether the backing file was written by someone else since it was last loaded or saved.
+    pub fn changed_on_disk(&self) -> bool {
+        match (self.disk_state, self.read_disk_state()) {
+            (Some(recorded), Some(current)) => recorded != current,
+            _ => false,
+        }
+    }
+
+    /// Sidecar file holding unsaved changes, next to the file itself.
+    pub fn recovery_path(&self) -> Option<PathBuf> {
+        self.sidecar_path("swp")
+    }
+
+    /// Hidden file next to the file itself, named after it with `extension`.
+    fn sidecar_path(&self, extension: &str) -> Option<PathBuf> {
+        self.filename.as_ref().map(|path| {
+            let name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            path.with_file_name(format!(
+                ".{}.{}.{}",
+                name,
+                env!("CARGO_PKG_NAME"),
+                extension
+            ))
+        })
     }
 
     pub fn line_count(&self) -> usize {
         self.lines.len()
     }
 
+    pub fn line(&self, row: usize) -> Option<&Line> {
+        self.lines.get(row)
+    }
+
     pub fn cursor_position(&self) -> Cursor {
         Cursor {
             cursor_row: self.cursor_row,
             cursor_col: self.cursor_col,
             row_offset: self.row_offset,
             col_offset: self.col_offset,
+            desired_col: self.desired_col,
         }
     }
 
     pub fn set_cursor_position(&mut self, cursor: Cursor) {
-        self.cursor_row = cursor.cursor_row;
-        self.cursor_col = cursor.cursor_col;
+        self.cursor_row = min(cursor.cursor_row, self.lines.len().saturating_sub(1));
+        self.cursor_col = self
+            .lines
+            .get(self.cursor_row)
+            .map_or(0, |line| line.floor_char_boundary(cursor.cursor_col));
         self.row_offset = cursor.row_offset;
         self.col_offset = cursor.col_offset;
+        self.desired_col = cursor.desired_col;
     }
 
-    pub fn cursor_placement(&self) -> (usize, usize) {
-        (
-            self.cursor_row - self.row_offset + 1,
-            self.render_col - self.col_offset + 1,
-        )
+    /// Names the cursor's place `name`, in place of any other place by that name.
+    pub fn set_mark(&mut self, name: char) {
+        let at = (self.cursor_row, self.cursor_col);
+        match self.marks.binary_search_by_key(&name, |&(known, _)| known) {
+            Ok(idx) => self.marks[idx].1 = at,
+            Err(idx) => self.marks.insert(idx, (name, at)),
+        }
+    }
+
+    /// The place named `name`, as it was when named. Edits since don't move it.
+    pub fn mark(&self, name: char) -> Option<Position> {
+        self.marks
+            .iter()
+            .find(|&&(known, _)| known == name)
+            .map(|&(_, at)| at)
+    }
+
+    pub fn marks(&self) -> &[(char, Position)] {
+        &self.marks
+    }
+
+    /// Row and column of the cursor in a view `cols` columns wide, counting from 1.
+    pub fn cursor_placement(&self, cols: usize) -> (usize, usize) {
+        if !self.wrap {
+            return (
+                self.cursor_row - self.row_offset + 1,
+                self.render_col - self.col_offset + 1,
+            );
+        }
+        let above = (self.row_offset..self.cursor_row)
+            .map(|row| self.screen_rows(row, cols))
+            .sum::<usize>();
+        let (segment, start) = self.cursor_segment(cols);
+        (above + segment + 1, self.render_col - start + 1)
     }
 
+    /// Screen rows line `row` takes in a view `cols` columns wide.
+    fn screen_rows(&self, row: usize, cols: usize) -> usize {
+        match self.lines.get(row) {
+            Some(line) if self.wrap => line.wrap_starts(cols).len(),
+            _ => 1,
+        }
+    }
+
+    /// Which of the wrapped rows of its line the cursor is on, and the render column that row
+    /// starts at.
+    fn cursor_segment(&self, cols: usize) -> (usize, usize) {
+        self.lines.get(self.cursor_row).map_or((0, 0), |line| {
+            let starts = line.wrap_starts(cols);
+            let segment = starts
+                .iter()
+                .rposition(|&start| start <= self.render_col)
+                .unwrap_or(0);
+            (segment, starts[segment])
+        })
+    }
+
+    /// Moves the cursor to the end of the word it's in or the next one, or back to the start
+    /// of the word it's in or the one before, going over line breaks on the way. A run of
+    /// punctuation counts as a word of its own, so `foo.bar()` takes four steps to cross.
+    fn move_word(&mut self, forward: bool) {
+        let (mut row, mut col) = (self.cursor_row, self.cursor_col);
+        while let Some(line) = self.lines.get(row).map(Line::content) {
+            if forward {
+                let found = line[col..]
+                    .char_indices()
+                    .find(|&(_, ch)| !ch.is_whitespace())
+                    .map(|(start, ch)| (col + start, char_class(ch)));
+                match found {
+                    Some((start, class)) => {
+                        col = line[start..]
+                            .find(|ch| char_class(ch) != class)
+                            .map_or(line.len(), |end| start + end);
+                        break;
+                    }
+                    None if row + 1 < self.lines.len() => (row, col) = (row + 1, 0),
+                    None => {
+                        col = line.len();
+                        break;
+                    }
+                }
+            } else {
+                let found = line[..col]
+                    .char_indices()
+                    .rev()
+                    .find(|&(_, ch)| !ch.is_whitespace());
+                match found {
+                    Some((last, ch)) => {
+                        let class = char_class(ch);
+                        col = line[..last]
+                            .char_indices()
+                            .rev()
+                            .find(|&(_, ch)| char_class(ch) != class)
+                            .map_or(0, |(before, ch)| before + ch.len_utf8());
+                        break;
+                    }
+                    None if row > 0 => (row, col) = (row - 1, self.lines[row - 1].len()),
+                    None => {
+                        col = 0;
+                        break;
+                    }
+                }
+            }
+        }
+        self.cursor_row = row;
+        self.cursor_col = col;
+        self.desired_col = None;
+    }
+
+    /// Moves the cursor down to the blank line after the paragraph it's in, or up to the one
+    /// before, over any blank lines it starts on. Without one it stops at the end of the last
+    /// line or the start of the first.
+    fn move_paragraph(&mut self, down: bool) {
+        let lines = &self.lines;
+        let blank = |row: usize| lines[row].content().trim().is_empty();
+        let last = lines.len().saturating_sub(1);
+        let mut row = self.cursor_row;
+        let step = |row: usize| if down { row + 1 } else { row.wrapping_sub(1) };
+        while step(row) <= last && blank(step(row)) {
+            row = step(row);
+        }
+        while step(row) <= last && !blank(step(row)) {
+            row = step(row);
+        }
+        row = if step(row) <= last { step(row) } else { row };
+        self.cursor_row = row;
+        self.cursor_col = match lines.get(row) {
+            Some(line) if down && row == last && !blank(row) => line.len(),
+            _ => 0,
+        };
+        self.desired_col = None;
+    }
+
+    /// Moves the cursor by `motion`, where pages are `rows` long. When wrapping, vertical
+    /// motions go by screen rows of a view `cols` columns wide.
     pub fn move_cursor(&mut self, motion: Motion, rows: usize, cols: usize) {
+        match motion {
+            Motion::WordForward => return self.move_word(true),
+            Motion::WordBackward => return self.move_word(false),
+            Motion::ParagraphDown => return self.move_paragraph(true),
+            Motion::ParagraphUp => return self.move_paragraph(false),
+            _ => {}
+        }
+        if self.wrap {
+            match motion {
+                Motion::Up => return self.move_by_screen_rows(true, 1, cols),
+                Motion::Down => return self.move_by_screen_rows(false, 1, cols),
+                Motion::PgUp => return self.move_by_screen_rows(true, rows, cols),
+                Motion::PgDn => return self.move_by_screen_rows(false, rows, cols),
+                _ => {}
+            }
+        }
+        let desired_col = match self.desired_col {
+            Some((col, at)) if at == (self.cursor_row, self.cursor_col) => col,
+            _ => self
+                .lines
+                .get(self.cursor_row)
+                .map_or(0, |line| line.cursor_to_render_position(self.cursor_col)),
+        };
         match motion {
             Motion::Up => self.cursor_row = self.cursor_row.saturating_sub(1),
             Motion::Left => {
                 if self.cursor_col != 0 {
-                    self.cursor_col -= 1;
+                    self.cursor_col =
+                        self.lines[self.cursor_row].prev_char_boundary(self.cursor_col);
                 } else if self.cursor_row > 0 {
                     self.cursor_row -= 1;
                     self.cursor_col = self.lines[self.cursor_row].len();
@@ -91,7 +871,7 @@ impl Buffer {
             Motion::Right => {
                 if let Some(row) = self.lines.get(self.cursor_row) {
                     if self.cursor_col < row.len() {
-                        self.cursor_col += 1;
+                        self.cursor_col = row.next_char_boundary(self.cursor_col);
                     } else if self.cursor_row < self.lines.len() - 1 {
                         self.cursor_row += 1;
                         self.cursor_col = 0;
@@ -103,32 +883,199 @@ impl Buffer {
                 self.cursor_row = min(self.lines.len().saturating_sub(1), self.cursor_row + rows)
             }
             Motion::Home => self.cursor_col = 0,
-            Motion::End => self.cursor_col = cols - 1,
+            Motion::End => self.cursor_col = usize::MAX,
+            _ => {}
         }
 
+        let desired_col = match motion {
+            Motion::Up | Motion::Down | Motion::PgUp | Motion::PgDn => {
+                if let Some(line) = self.lines.get(self.cursor_row) {
+                    self.cursor_col = line.render_to_cursor_position(desired_col);
+                }
+                Some(desired_col)
+            }
+            Motion::End => Some(usize::MAX),
+            _ => None,
+        };
+
         if let Some(row) = self.lines.get(self.cursor_row) {
-            self.cursor_col = min(row.len(), self.cursor_col);
+            self.cursor_col = row.floor_char_boundary(self.cursor_col);
         }
+        self.desired_col = desired_col.map(|col| (col, (self.cursor_row, self.cursor_col)));
+    }
+
+    /// Moves the cursor `count` screen rows up or down through wrapped lines, keeping to the
+    /// column on screen that vertical motions aim for.
+    fn move_by_screen_rows(&mut self, up: bool, count: usize, cols: usize) {
+        let Some(line) = self.lines.get(self.cursor_row) else {
+            return;
+        };
+        let render_col = line.cursor_to_render_position(self.cursor_col);
+        let mut starts = line.wrap_starts(cols);
+        let mut segment = starts
+            .iter()
+            .rposition(|&start| start <= render_col)
+            .unwrap_or(0);
+        let desired_col = match self.desired_col {
+            Some((col, at)) if at == (self.cursor_row, self.cursor_col) => col,
+            _ => render_col - starts[segment],
+        };
+        let mut row = self.cursor_row;
+        for _ in 0..count {
+            if up && segment > 0 {
+                segment -= 1;
+            } else if up && row > 0 {
+                row -= 1;
+                starts = self.lines[row].wrap_starts(cols);
+                segment = starts.len() - 1;
+            } else if !up && segment + 1 < starts.len() {
+                segment += 1;
+            } else if !up && row + 1 < self.lines.len() {
+                row += 1;
+                starts = self.lines[row].wrap_starts(cols);
+                segment = 0;
+            } else {
+                break;
+            }
+        }
+        // Short of the last row, the cursor stays off the first column of the next one.
+        let last_col = starts.get(segment + 1).map_or(usize::MAX, |next| next - 1);
+        self.cursor_row = row;
+        self.cursor_col = self.lines[row]
+            .render_to_cursor_position(min(starts[segment].saturating_add(desired_col), last_col));
+        self.desired_col = Some((desired_col, (self.cursor_row, self.cursor_col)));
     }
 
     pub fn scroll(&mut self, rows: usize, cols: usize) {
+        self.align_tabs();
         self.render_col = self
             .lines
             .get(self.cursor_row)
             .map(|line| line.cursor_to_render_position(self.cursor_col))
             .unwrap_or_default();
 
-        if self.cursor_row < self.row_offset {
-            self.row_offset = self.cursor_row;
-        } else if self.cursor_row >= self.row_offset + rows {
-            self.row_offset = 1 + self.cursor_row - rows;
+        let margin = self.line_margin(rows);
+        if self.wrap {
+            self.col_offset = 0;
+            self.row_offset = min(self.row_offset, self.cursor_row.saturating_sub(margin));
+            // Walk up from the cursor to the highest line the view can start at and still
+            // show the cursor and the lines below it in the margin.
+            let mut top = self.cursor_row;
+            let below = (self.cursor_row + 1..self.lines.len())
+                .take(margin)
+                .map(|row| self.screen_rows(row, cols))
+                .sum::<usize>();
+            let (segment, _) = self.cursor_segment(cols);
+            let mut used = if margin > 0 {
+                self.screen_rows(self.cursor_row, cols) + below
+            } else {
+                segment + 1
+            };
+            while top > self.row_offset && used + self.screen_rows(top - 1, cols) <= rows {
+                top -= 1;
+                used += self.screen_rows(top, cols);
+            }
+            self.row_offset = top;
+            return;
         }
 
-        if self.render_col < self.col_offset {
-            self.col_offset = self.render_col;
-        } else if self.render_col >= self.col_offset + cols {
-            self.col_offset = 1 + self.render_col - cols;
+        // The margin below stops at the last line, or the cursor past it.
+        let bottom = min(
+            self.cursor_row + margin,
+            self.lines.len().saturating_sub(1).max(self.cursor_row),
+        );
+        if self.cursor_row < self.row_offset + margin {
+            self.row_offset = self.cursor_row.saturating_sub(margin);
+        } else if bottom >= self.row_offset + rows {
+            self.row_offset = 1 + bottom - rows;
         }
+
+        let margin = min(self.scroll_margins.1, cols.saturating_sub(1) / 2);
+        if self.render_col < self.col_offset + margin {
+            self.col_offset = self.render_col.saturating_sub(margin);
+        } else if self.render_col + margin >= self.col_offset + cols {
+            self.col_offset = 1 + self.render_col + margin - cols;
+        }
+    }
+
+    /// Scrolls the view by `delta` rows, leaving the cursor where it is unless that would take
+    /// it out of view.
+    pub fn scroll_view(&mut self, delta: isize, rows: usize) {
+        let last = self.lines.len().saturating_sub(1);
+        self.row_offset = if delta < 0 {
+            self.row_offset.saturating_sub(delta.unsigned_abs())
+        } else {
+            min(self.row_offset + delta as usize, last)
+        };
+        // Keep the cursor out of the margins, except where the view meets the ends.
+        let margin = self.line_margin(rows);
+        let top = if self.row_offset > 0 {
+            self.row_offset + margin
+        } else {
+            0
+        };
+        let bottom = self.row_offset + rows.max(1) - 1;
+        let bottom = if bottom < last {
+            bottom - margin
+        } else {
+            bottom
+        };
+        self.cursor_row = self.cursor_row.max(top).min(bottom).min(last);
+        if let Some(line) = self.lines.get(self.cursor_row) {
+            self.cursor_col = line.floor_char_boundary(self.cursor_col);
+        }
+    }
+
+    /// Moves the cursor `count` rows up or down and scrolls the view by as many lines as the
+    /// cursor crossed, so it keeps its place on the screen. Going down, the view stops once the
+    /// last line reaches the bottom of `rows` while the cursor carries on.
+    pub fn scroll_with_cursor(&mut self, up: bool, count: usize, rows: usize, cols: usize) {
+        let from = self.cursor_row;
+        let motion = if up { Motion::PgUp } else { Motion::PgDn };
+        self.move_cursor(motion, count, cols);
+        self.row_offset = if up {
+            self.row_offset.saturating_sub(from - self.cursor_row)
+        } else {
+            let bottom = self.lines.len().saturating_sub(rows.max(1));
+            self.row_offset
+                .max(min(self.row_offset + self.cursor_row - from, bottom))
+        };
+    }
+
+    /// Scrolls the view so the cursor line sits at the top, center or bottom of `rows`.
+    pub fn align_view(&mut self, align: ViewAlign, rows: usize) {
+        self.row_offset = match align {
+            ViewAlign::Top => self.cursor_row,
+            ViewAlign::Center => self.cursor_row.saturating_sub(rows / 2),
+            ViewAlign::Bottom => (self.cursor_row + 1).saturating_sub(rows),
+        };
+    }
+
+    /// Moves the cursor to the character drawn at `row` and `col` of a view `cols` columns
+    /// wide, or as close to it as the text goes.
+    pub fn place_cursor_in_view(&mut self, row: usize, col: usize, cols: usize) {
+        let frame = self.frame_rows(row + 1, cols);
+        let Some((line, start, width)) = frame.into_iter().flatten().next_back() else {
+            return;
+        };
+        self.cursor_row = line;
+        self.cursor_col =
+            self.lines[line].render_to_cursor_position(start + min(col, width.saturating_sub(1)));
+        self.desired_col = None;
+    }
+
+    /// Moves the cursor to character `col` of line `row`, both counting from 1, or as close to
+    /// it as the text goes.
+    pub fn goto(&mut self, row: usize, col: usize) {
+        self.cursor_row = min(row.saturating_sub(1), self.lines.len().saturating_sub(1));
+        self.cursor_col = self.lines.get(self.cursor_row).map_or(0, |line| {
+            let content = line.content();
+            content
+                .char_indices()
+                .nth(col.saturating_sub(1))
+                .map_or(content.len(), |(idx, _)| idx)
+        });
+        self.desired_col = None;
     }
 
     pub fn place_cursor(&mut self, row: usize, col: usize) {
@@ -137,43 +1084,399 @@ impl Buffer {
         self.row_offset = self.lines.len();
     }
 
-    pub fn frame_content(&self, rows: usize, cols: usize) -> String {
-        self.lines
+    /// Line, first render column and width of the text shown on each of `rows` rows of a view
+    /// `cols` columns wide, or `None` for rows past the end of the buffer.
+    pub fn frame_rows(&self, rows: usize, cols: usize) -> Vec<Option<(usize, usize, usize)>> {
+        let mut frame = Vec::with_capacity(rows);
+        for row in self.row_offset..self.lines.len() {
+            if frame.len() >= rows {
+                break;
+            }
+            if !self.wrap {
+                frame.push(Some((row, self.col_offset, cols)));
+                continue;
+            }
+            let starts = self.lines[row].wrap_starts(cols);
+            for (n, &start) in starts.iter().enumerate() {
+                let end = starts.get(n + 1).copied().unwrap_or(start + cols);
+                frame.push(Some((row, start, end - start)));
+            }
+        }
+        frame.resize(rows, None);
+        frame
+    }
+
+    pub fn frame_content(&self, rows: usize, cols: usize) -> Vec<String> {
+        self.frame_rows(rows, cols)
+            .into_iter()
+            .map(|shown| match shown {
+                Some((row, start, width)) => self.lines[row].rendered_slice(start, width),
+                None => "~",
+            })
+            .map(|line| line.to_string())
+            .collect()
+    }
+
+    /// How many lines take up more than `width` columns on screen.
+    pub fn overlong_lines(&mut self, width: usize) -> usize {
+        match self.overlong_lines {
+            Some((changes, counted_width, count))
+                if changes == self.changes && counted_width == width =>
+            {
+                count
+            }
+            _ => {
+                let count = self
+                    .lines
+                    .iter()
+                    .filter(|line| line.columns() > width)
+                    .count();
+                self.overlong_lines = Some((self.changes, width, count));
+                count
+            }
+        }
+    }
+
+    /// Highlights for the text returned by `frame_content`, with matches of `search` marked on
+    /// top.
+    pub fn frame_highlights(
+        &mut self,
+        rows: usize,
+        cols: usize,
+        search: Option<&Pattern>,
+    ) -> Vec<Vec<Highlight>> {
+        let frame = self.frame_rows(rows, cols);
+        let end = frame
             .iter()
-            .skip(self.row_offset)
-            .map(|line| line.rendered())
-            .chain(
-                std::iter::repeat("~")
-                    .take(rows.saturating_sub(self.lines.len().saturating_sub(self.row_offset))),
-            )
-            .map(|line| {
-                line.chars()
-                    .skip(self.col_offset)
-                    .take(cols)
-                    .chain("\x1b[K\r\n".chars())
+            .flatten()
+            .last()
+            .map_or(0, |&(row, _, _)| row + 1);
+        // Colored output comes with colors of its own.
+        let colored = self.ansi.as_ref().filter(|ansi| ansi.colors).is_some();
+        let filetype = self.filetype().filter(|_| !colored);
+        // Without block comments or long strings there's no state to carry between lines.
+        let stateful = |filetype: &&Filetype| {
+            filetype.block_comment.is_some() || !filetype.long_strings.is_empty()
+        };
+        #[cfg(feature = "syntax-backend")]
+        if let Some((backend, stale)) = self.syntax.as_mut().filter(|_| filetype.is_some()) {
+            if let Some(first) = stale.take() {
+                let lines = self.lines.iter().map(Line::content).collect::<Vec<&str>>();
+                backend.update(&lines, first);
+            }
+        }
+        match filetype.filter(stateful).filter(|_| !self.has_syntax()) {
+            Some(filetype) if self.lines.len() > highlight::BACKGROUND_LINES => {
+                self.poll_line_states(filetype)
+            }
+            Some(filetype) => {
+                // State carries from line to line, so it's worked out from the top once and
+                // kept for the lines above any edit since.
+                let (valid, states) = &mut self.line_states;
+                states.truncate(*valid);
+                while states.len() < end {
+                    let state = match states.len() {
+                        0 => LineState::Code,
+                        n => {
+                            highlight::highlight_line(
+                                self.lines[n - 1].rendered(),
+                                filetype,
+                                states[n - 1],
+                            )
+                            .1
+                        }
+                    };
+                    states.push(state);
+                }
+                *valid = states.len();
+            }
+            None => {}
+        }
+
+        // Only as far into each line as the frame shows of it, so a long line costs no more
+        // than a short one.
+        let mut shown = vec![0; end.saturating_sub(self.row_offset)];
+        for &(row, start, width) in frame.iter().flatten() {
+            let cols = &mut shown[row - self.row_offset];
+            *cols = (*cols).max(start + width);
+        }
+        let lines = (self.row_offset..end)
+            .map(|row| {
+                let line = &self.lines[row];
+                let rendered = line.rendered();
+                let cols = min(shown[row - self.row_offset], line.columns());
+                let syntax = filetype.and_then(|_| self.syntax_highlights(row, cols));
+                let mut highlights = match (filetype, syntax) {
+                    (_, Some(highlights)) => highlights,
+                    (Some(filetype), None) => {
+                        let state = self.line_states.1.get(row).copied();
+                        highlight::highlight_line(rendered, filetype, state.unwrap_or_default()).0
+                    }
+                    (None, None) if colored => self.ansi_highlights(row, cols),
+                    (None, None) => Vec::new(),
+                };
+                highlights.resize(cols, Highlight::Normal);
+                if let Some(pattern) = search.filter(|pattern| !pattern.is_empty()) {
+                    let matches = line.match_indices_before(pattern, cols);
+                    highlight::highlight_matches(rendered, &mut highlights, &matches);
+                }
+                highlights
             })
-            .take(rows)
+            .collect::<Vec<Vec<Highlight>>>();
+        frame
+            .into_iter()
             .flatten()
-            .collect::<String>()
+            .map(|(row, start, width)| {
+                lines[row - self.row_offset]
+                    .iter()
+                    .skip(start)
+                    .take(width)
+                    .copied()
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Highlights of the first `cols` columns of line `row` of colored output, in the styles
+    /// its escape sequences asked for.
+    fn ansi_highlights(&self, row: usize, cols: usize) -> Vec<Highlight> {
+        let line = &self.lines[row];
+        let mut highlights = vec![Highlight::Normal; cols];
+        let styles = self.ansi.as_ref().and_then(|ansi| ansi.styles.get(row));
+        for (n, &(at, style)) in styles.into_iter().flatten().enumerate() {
+            if style == Style::default() {
+                continue;
+            }
+            let start = line.cursor_to_render_position(at);
+            if start >= cols {
+                break;
+            }
+            let end = match styles.and_then(|styles| styles.get(n + 1)) {
+                Some(&(next, _)) => line.cursor_to_render_position(next),
+                None => highlights.len(),
+            };
+            for highlight in highlights.iter_mut().take(end).skip(start) {
+                *highlight = Highlight::Ansi(style);
+            }
+        }
+        highlights
+    }
+
+    /// Keeps `line_states` coming from a background pass over the buffer from the first line
+    /// whose state no longer holds, started again whenever the buffer changes. Until the pass
+    /// gets to them, lines keep the states they had before the change, which are right more
+    /// often than not.
+    fn poll_line_states(&mut self, filetype: &'static Filetype) {
+        let tab_stop = self.tab_stop();
+        let (valid, states) = &mut self.line_states;
+        let current = matches!(self.background, Some((_, _, _, at)) if at == self.changes);
+        if !current && *valid < self.lines.len() {
+            // The pass starts at the last line whose state holds.
+            let from = valid.saturating_sub(1);
+            let lines = self
+                .lines
+                .iter()
+                .skip(from)
+                .map(|line| line.content().to_string())
+                .collect();
+            let state = states.get(from).copied().unwrap_or_default();
+            let background = highlight::Background::spawn(lines, tab_stop, filetype, state);
+            self.background = Some((background, from, 0, self.changes));
+        }
+        let Some((background, from, reported, _)) = &mut self.background else {
+            return;
+        };
+        let found = background.take();
+        let start = *from + *reported;
+        let stale = min(start, states.len())..min(start + found.len(), states.len());
+        *reported += found.len();
+        states.splice(stale, found);
+        *valid = *from + *reported;
+        if *valid >= self.lines.len() {
+            states.truncate(self.lines.len());
+            self.background = None;
+        }
+    }
+
+    /// Highlights of line `row` by the buffer's filetype, enough to tell code from strings
+    /// and comments. Lines below those drawn so far may start in a state still being worked
+    /// out, and so get a best guess.
+    pub fn line_highlights(&self, row: usize) -> Vec<Highlight> {
+        let (Some(line), Some(filetype)) = (self.lines.get(row), self.filetype()) else {
+            return Vec::new();
+        };
+        let cols = min(line.columns(), highlight::MAX_COLUMNS);
+        self.syntax_highlights(row, cols).unwrap_or_else(|| {
+            let state = self.line_states.1.get(row).copied().unwrap_or_default();
+            highlight::highlight_line(line.rendered(), filetype, state).0
+        })
+    }
+
+    /// Whether a syntax backend highlights the buffer in place of its keyword highlighter.
+    #[cfg(feature = "syntax-backend")]
+    fn has_syntax(&self) -> bool {
+        self.syntax.is_some()
+    }
+
+    #[cfg(not(feature = "syntax-backend"))]
+    fn has_syntax(&self) -> bool {
+        false
     }
 
+    /// Highlights of the first `cols` columns of line `row` by the buffer's syntax backend, or
+    /// None without one.
+    #[cfg(feature = "syntax-backend")]
+    fn syntax_highlights(&self, row: usize, cols: usize) -> Option<Vec<Highlight>> {
+        let (backend, _) = self.syntax.as_ref()?;
+        Some(syntax::span_highlights(
+            &self.lines[row],
+            &backend.spans(row),
+            cols,
+        ))
+    }
+
+    #[cfg(not(feature = "syntax-backend"))]
+    fn syntax_highlights(&self, _row: usize, _cols: usize) -> Option<Vec<Highlight>> {
+        None
+    }
+
+    /// What the syntax backend draws one way, like strings and comments, on the line `region`
+    /// starts on, as regions. Spans that run on from the end of one line into the start of the
+    /// next, like a block comment's, make up one region.
+    #[cfg(feature = "syntax-backend")]
+    pub fn syntax_regions(&self, region: Region) -> Vec<Region> {
+        let Some((backend, _)) = &self.syntax else {
+            return Vec::new();
+        };
+        let line_len = |row: usize| self.lines.get(row).map_or(0, Line::len);
+        // The span of line `row` that runs to its end, and the one that starts at its start.
+        let at_end = |row: usize| {
+            let spans = backend.spans(row);
+            let len = line_len(row);
+            spans.into_iter().find(|(range, _)| range.end == len)
+        };
+        let at_start = |row: usize| {
+            let spans = backend.spans(row);
+            spans.into_iter().find(|(range, _)| range.start == 0)
+        };
+        let row = region.start.0;
+        let mut regions = Vec::new();
+        for (range, highlight) in backend.spans(row) {
+            let (mut start, mut end) = ((row, range.start), (row, range.end));
+            while start.1 == 0 && start.0 > 0 {
+                match at_end(start.0 - 1) {
+                    Some((before, kind)) if kind == highlight => {
+                        start = (start.0 - 1, before.start)
+                    }
+                    _ => break,
+                }
+            }
+            while end.1 == line_len(end.0) && end.0 + 1 < self.lines.len() {
+                match at_start(end.0 + 1) {
+                    Some((after, kind)) if kind == highlight => end = (end.0 + 1, after.end),
+                    _ => break,
+                }
+            }
+            regions.push(Region::new(start, end));
+        }
+        regions
+    }
+
+    #[cfg(not(feature = "syntax-backend"))]
+    pub fn syntax_regions(&self, _region: Region) -> Vec<Region> {
+        Vec::new()
+    }
+
+    /// Whether highlighting is still catching up with the buffer in the background.
+    pub fn is_highlighting(&self) -> bool {
+        self.background.is_some()
+    }
+
+    /// The text as it's saved, each line ending in the buffer's line ending.
     pub fn rows_to_string(&self) -> String {
+        let ending = self.line_ending.as_str();
         let mut content = self
             .lines
             .iter()
             .map(|line| line.content().to_string())
             .collect::<Vec<String>>()
-            .join("\n");
-        content.push('\n');
+            .join(ending);
+        content.push_str(ending);
         content
     }
 
+    /// Appends the lines of the file read as `bytes`, in `encoding` or else the one they look
+    /// to be in, which the text is saved in again.
+    pub fn read_bytes(&mut self, bytes: &[u8], encoding: Option<Encoding>) -> Result<()> {
+        self.encoding = encoding.unwrap_or_else(|| Encoding::detect(bytes));
+        self.read_lines(self.encoding.decode(bytes).as_bytes())
+    }
+
+    /// The text as it's saved, in the buffer's encoding.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        self.encoding.encode(&self.rows_to_string()).map_err(|ch| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("{:?} can't be saved in {}", ch, self.encoding.name()),
+            )
+        })
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// Sets the encoding the text is saved in, as a change to be saved.
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        if encoding != self.encoding {
+            self.encoding = encoding;
+            self.dirty = true;
+            self.changes += 1;
+        }
+    }
+
+    /// Appends the lines `reader` has, and takes up the line ending most of them end in.
+    pub fn read_lines(&mut self, mut reader: impl BufRead) -> Result<()> {
+        let (mut crlf, mut lf) = (0, 0);
+        let mut line = String::new();
+        while reader.read_line(&mut line)? > 0 {
+            if line.ends_with("\r\n") {
+                crlf += 1;
+                line.truncate(line.len() - 2);
+            } else if line.ends_with('\n') {
+                lf += 1;
+                line.pop();
+            }
+            self.append_row(std::mem::take(&mut line));
+        }
+        self.line_ending = if crlf > lf {
+            LineEnding::Crlf
+        } else {
+            LineEnding::Lf
+        };
+        Ok(())
+    }
+
+    pub fn line_ending(&self) -> LineEnding {
+        self.line_ending
+    }
+
+    /// Sets the line ending the text is saved with, as a change to be saved.
+    pub fn convert_line_ending(&mut self, ending: LineEnding) {
+        if ending != self.line_ending {
+            self.line_ending = ending;
+            self.dirty = true;
+            self.changes += 1;
+        }
+    }
+
     fn insert_row(&mut self, index: usize, line: String) {
         if index > self.lines.len() {
             return;
         }
-        self.lines.insert(index, Line::new(line));
-        self.dirty = true;
+        let line = Line::with_tab_stop(line, self.tab_stop());
+        self.lines.insert(index, line);
+        self.mark_dirty(index);
     }
 
     pub fn append_row(&mut self, line: String) {
@@ -186,27 +1489,469 @@ impl Buffer {
         } else {
             let tail = self.lines[self.cursor_row].split_off(self.cursor_col);
             self.insert_row(self.cursor_row + 1, tail);
+            // The line split changed too.
+            self.mark_dirty(self.cursor_row);
         }
         self.cursor_row += 1;
         self.cursor_col = 0;
     }
 
+    /// Comment leader to continue on a new line split at the cursor, if the text before the
+    /// cursor is inside a comment of the buffer's filetype.
+    pub fn comment_leader(&self) -> Option<String> {
+        let before = self
+            .lines
+            .get(self.cursor_row)?
+            .content()
+            .get(..self.cursor_col)?;
+        self.filetype()?.comment_leader(before)
+    }
+
+    /// Characters just before and just after the cursor on its line.
+    fn chars_around_cursor(&self) -> (Option<char>, Option<char>) {
+        let content = self
+            .lines
+            .get(self.cursor_row)
+            .map(|line| line.content())
+            .unwrap_or_default();
+        let (before, after) = content.split_at(min(self.cursor_col, content.len()));
+        (before.chars().next_back(), after.chars().next())
+    }
+
+    /// Types `ch`, keeping brackets and double quotes paired: an opener also gets its closer
+    /// after the cursor, and a closer typed over the same closer steps over it.
+    pub fn insert_paired(&mut self, ch: char) {
+        let (before, after) = self.chars_around_cursor();
+        let is_closer = ch == '"' || region::PAIRS.iter().any(|&(_, close)| close == ch);
+        if is_closer && after == Some(ch) {
+            self.cursor_col += ch.len_utf8();
+            return;
+        }
+        // Only pair up where the opener starts something new, not in the middle of a word.
+        let open_space = after.is_none_or(|next| {
+            next.is_whitespace() || region::PAIRS.iter().any(|&(_, close)| close == next)
+        });
+        let close = match region::PAIRS.iter().find(|&&(open, _)| open == ch) {
+            Some(&(_, close)) => Some(close),
+            None if ch == '"' && !before.is_some_and(region::is_word_char) => Some('"'),
+            None => None,
+        };
+        match close.filter(|_| open_space) {
+            Some(close) => {
+                self.insert_text(&format!("{}{}", ch, close));
+                self.cursor_col -= close.len_utf8();
+            }
+            None => self.insert_char(ch),
+        }
+    }
+
+    /// One level of indentation inside lines indented with `indent`: a tab if they are indented
+    /// with tabs, or what Tab types.
+    fn indent_level(&self, indent: &str) -> String {
+        if indent.starts_with('\t') {
+            "\t".to_string()
+        } else {
+            self.indent_unit()
+        }
+    }
+
+    /// Types what Tab does: a tab, or with an indentation of spaces enough of them to reach
+    /// the next multiple of its width.
+    pub fn insert_tab(&mut self) {
+        let unit = self.indent_unit();
+        if unit.starts_with('\t') {
+            return self.insert_char('\t');
+        }
+        let width = unit.len();
+        let column = self
+            .lines
+            .get(self.cursor_row)
+            .map_or(0, |line| line.cursor_to_render_position(self.cursor_col));
+        self.insert_text(&" ".repeat(width - column % width));
+    }
+
+    /// Deletes back to the previous level of indentation when the cursor is in indentation
+    /// made of spaces, with spaces being what Tab types. Returns false, doing nothing,
+    /// anywhere else.
+    pub fn dedent(&mut self) -> bool {
+        let width = self.indent_unit().len();
+        let before = self
+            .lines
+            .get(self.cursor_row)
+            .map_or("", |line| &line.content()[..self.cursor_col]);
+        if self.indent_unit().starts_with('\t')
+            || before.is_empty()
+            || before.contains(|ch| ch != ' ')
+        {
+            return false;
+        }
+        for _ in 0..(before.len() - 1) % width + 1 {
+            self.delete_char();
+        }
+        true
+    }
+
+    /// Indentation for a line broken off at the cursor: that of the cursor line, and with
+    /// `smart` another level if the text before the cursor ends in a character the filetype
+    /// indents after, like an opening brace.
+    pub fn new_line_indent(&self, smart: bool) -> String {
+        let Some(line) = self.lines.get(self.cursor_row) else {
+            return String::new();
+        };
+        let indent = line.indent();
+        // Breaking a line inside its indentation keeps only the part before the cursor.
+        let mut indent = indent[..min(indent.len(), self.cursor_col)].to_string();
+        let before = line.content()[..self.cursor_col].trim_end();
+        let opens = self.filetype.is_some_and(|filetype| {
+            before
+                .chars()
+                .next_back()
+                .is_some_and(|ch| filetype.indent_after.contains(&ch))
+        });
+        if smart && opens {
+            indent.push_str(&self.indent_level(&indent));
+        }
+        indent
+    }
+
+    /// Splits the line at the cursor when it sits between a pair of brackets, putting the
+    /// closer on a line of its own and the cursor on an indented blank line in between.
+    /// Returns false, doing nothing, anywhere else.
+    pub fn split_pair(&mut self) -> bool {
+        match self.chars_around_cursor() {
+            (Some(open), Some(close)) if region::PAIRS.contains(&(open, close)) => {}
+            _ => return false,
+        }
+        let indent = self.lines[self.cursor_row].indent().to_string();
+        let level = self.indent_level(&indent);
+        self.insert_text(&format!("\n{}{}\n{}", indent, level, indent));
+        self.cursor_row -= 1;
+        self.cursor_col = indent.len() + level.len();
+        true
+    }
+
+    /// Deletes the opener before the cursor together with its closer, when nothing but
+    /// whitespace separates the two: either right after the cursor, or on a line of its own
+    /// below, past at most one blank line. Returns false, doing nothing, anywhere else.
+    pub fn delete_pair(&mut self) -> bool {
+        let close = match self.chars_around_cursor() {
+            (Some('"'), _) => '"',
+            (Some(open), _) => match region::PAIRS.iter().find(|&&(first, _)| first == open) {
+                Some(&(_, close)) => close,
+                None => return false,
+            },
+            _ => return false,
+        };
+        let (row, col) = (self.cursor_row, self.cursor_col);
+        let start = (row, col - 1);
+        let line = self.lines[row].content();
+        if line[col..].starts_with(close) {
+            self.delete_region(Region::new(start, (row, col + close.len_utf8())));
+            return true;
+        }
+        if !line[col..].trim().is_empty() {
+            return false;
+        }
+        let is_blank = |row: usize| {
+            self.lines
+                .get(row)
+                .is_some_and(|line| line.content().trim().is_empty())
+        };
+        let closer_row = if is_blank(row + 1) { row + 2 } else { row + 1 };
+        let closer = match self.lines.get(closer_row) {
+            Some(closer) if closer.content().trim() == close.to_string() => closer,
+            _ => return false,
+        };
+        let end = (closer_row, closer.len());
+        self.delete_region(Region::new(start, end));
+        true
+    }
+
     pub fn insert_char(&mut self, ch: char) {
         if self.cursor_row == self.lines.len() {
             self.insert_row(self.cursor_row, String::new());
         }
         if let Some(line) = self.lines.get_mut(self.cursor_row) {
             line.insert(self.cursor_col, ch);
-            self.cursor_col += 1;
-            self.dirty = true;
+            self.cursor_col += ch.len_utf8();
+            self.mark_dirty(self.cursor_row);
         }
     }
 
-    fn delete_row(&mut self) {
-        if self.cursor_row < self.lines.len() {
-            self.lines.remove(self.cursor_row);
-            self.dirty = true;
+    pub fn insert_text(&mut self, text: &str) {
+        if self.cursor_row == self.lines.len() {
+            self.insert_row(self.cursor_row, String::new());
+        }
+        let row = self.cursor_row;
+        let mut pieces = text.split('\n').map(|piece| piece.trim_end_matches('\r'));
+        let first = pieces.next().unwrap_or_default();
+        let tail = self.lines[self.cursor_row].split_off(self.cursor_col);
+        self.lines[self.cursor_row].push_str(first);
+        self.cursor_col += first.len();
+        // All new rows go in at once, keeping large pastes linear.
+        let rows = pieces
+            .map(|piece| Line::with_tab_stop(piece.to_string(), self.tab_stop()))
+            .collect::<Vec<Line>>();
+        if let Some(last) = rows.last() {
+            self.cursor_col = last.len();
+        }
+        let at = self.cursor_row + 1;
+        self.cursor_row += rows.len();
+        self.lines.insert_lines(at, rows);
+        self.lines[self.cursor_row].push_str(&tail);
+        self.mark_dirty(row);
+    }
+
+    /// Inserts multi-line text with its indentation shifted to match the line at the cursor,
+    /// instead of keeping the absolute indentation it had at its source.
+    pub fn insert_text_reindented(&mut self, text: &str) {
+        let indent = self
+            .lines
+            .get(self.cursor_row)
+            .map(|line| line.indent().to_string())
+            .unwrap_or_default();
+        self.insert_text(&reindent(text, &indent, self.tab_stop()));
+    }
+
+    /// Types `ch` over the character under the cursor, or appends it at the end of the line.
+    pub fn replace_char(&mut self, ch: char) {
+        if let Some(line) = self.lines.get_mut(self.cursor_row) {
+            if self.cursor_col < line.len() {
+                line.remove(self.cursor_col);
+            }
+        }
+        self.insert_char(ch);
+    }
+
+    /// Text covered by `region`, with lines joined by newlines.
+    pub fn region_text(&self, region: Region) -> String {
+        let (start, end) = (region.start, region.end);
+        (start.0..=end.0)
+            .filter_map(|row| self.lines.get(row).map(|line| (row, line.content())))
+            .map(|(row, content)| {
+                let from = if row == start.0 { start.1 } else { 0 };
+                let to = if row == end.0 { end.1 } else { content.len() };
+                content.get(from..to).unwrap_or_default()
+            })
+            .collect::<Vec<&str>>()
+            .join("\n")
+    }
+
+    /// Text in render columns `left` up to `right` of lines `top` to `bottom`, a string per
+    /// line, with tabs expanded.
+    pub fn rectangle_text(
+        &self,
+        (top, bottom): (usize, usize),
+        (left, right): (usize, usize),
+    ) -> Vec<String> {
+        (top..=bottom)
+            .filter_map(|row| self.lines.get(row))
+            .map(|line| line.rendered_slice(left, right - left).to_string())
+            .collect()
+    }
+
+    /// Removes render columns `left` up to `right` of lines `top` to `bottom`.
+    pub fn delete_rectangle(
+        &mut self,
+        (top, bottom): (usize, usize),
+        (left, right): (usize, usize),
+    ) {
+        for line in self.lines.iter_mut().take(bottom + 1).skip(top) {
+            let start = line.render_to_cursor_position(left);
+            let tail = line.split_off(line.render_to_cursor_position(right));
+            line.split_off(start);
+            line.push_str(&tail);
+        }
+        self.mark_dirty(top);
+    }
+
+    /// Inserts `rows` of text at render column `col` of successive lines from `row` on,
+    /// padding short lines with spaces and adding lines past the end of the buffer.
+    pub fn insert_rectangle(&mut self, row: usize, col: usize, rows: &[&str]) {
+        for (n, text) in rows.iter().enumerate() {
+            if row + n >= self.lines.len() {
+                self.insert_row(self.lines.len(), String::new());
+            }
+            let line = &mut self.lines[row + n];
+            let width = line.columns();
+            if width < col {
+                line.push_str(&" ".repeat(col - width));
+            }
+            line.insert_str(line.render_to_cursor_position(col), text);
+        }
+        self.mark_dirty(row);
+    }
+
+    /// Removes the text covered by `region` and leaves the cursor at its start. Returns the
+    /// removed text.
+    pub fn delete_region(&mut self, region: Region) -> String {
+        let text = self.region_text(region);
+        let (start, end) = (region.start, region.end);
+        if start >= end || start.0 >= self.lines.len() {
+            return text;
+        }
+        let end_row = min(end.0, self.lines.len() - 1);
+        let tail = if end.0 < self.lines.len() {
+            self.lines[end_row]
+                .content()
+                .get(end.1..)
+                .unwrap_or_default()
+                .to_string()
+        } else {
+            String::new()
+        };
+        self.lines[start.0].split_off(start.1);
+        self.lines[start.0].push_str(&tail);
+        self.lines.remove_lines(start.0 + 1..end_row + 1);
+        self.cursor_row = start.0;
+        self.cursor_col = start.1;
+        self.mark_dirty(start.0);
+        text
+    }
+
+    /// Deletes lines `first` to `last`, as far as the buffer goes.
+    pub fn delete_lines(&mut self, first: usize, last: usize) {
+        let last = min(last, self.lines.len().saturating_sub(1));
+        if first > last || first >= self.lines.len() {
+            return;
+        }
+        self.lines.remove_lines(first..last + 1);
+        self.cursor_row = min(self.cursor_row, self.lines.len().saturating_sub(1));
+        self.cursor_col = 0;
+        self.mark_dirty(first);
+    }
+
+    /// Moves lines `first` to `last` up a line, or down, past the line next to them, taking
+    /// the cursor along. Returns whether there was a line to move past.
+    pub fn move_lines(&mut self, first: usize, last: usize, up: bool) -> bool {
+        let last = min(last, self.lines.len().saturating_sub(1));
+        if first > last || (up && first == 0) || (!up && last + 1 >= self.lines.len()) {
+            return false;
+        }
+        // The line passed over goes to the other side of them.
+        let (from, to) = if up {
+            (first - 1, last)
+        } else {
+            (last + 1, first)
+        };
+        let passed = self.lines[from].content().to_string();
+        self.lines.remove(from);
+        self.lines
+            .insert(to, Line::with_tab_stop(passed, self.tab_stop()));
+        if up {
+            self.cursor_row -= 1;
+        } else {
+            self.cursor_row += 1;
+        }
+        self.mark_dirty(min(from, to));
+        true
+    }
+
+    /// Puts a copy of lines `first` to `last` after them, and the cursor on the same place in
+    /// the copy.
+    pub fn duplicate_lines(&mut self, first: usize, last: usize) {
+        let last = min(last, self.lines.len().saturating_sub(1));
+        if first > last || first >= self.lines.len() {
+            return;
+        }
+        let copies = (first..=last)
+            .map(|row| Line::with_tab_stop(self.lines[row].content().to_string(), self.tab_stop()))
+            .collect();
+        self.lines.insert_lines(last + 1, copies);
+        self.cursor_row += last + 1 - first;
+        self.mark_dirty(last + 1);
+    }
+
+    /// Joins lines `first` to `last` into one, or `first` and the line after it if that's all
+    /// there is, dropping the indentation of each line joined on and putting a space between
+    /// unless one side is blank. The cursor goes where the last two met. Returns whether there
+    /// was a line to join.
+    pub fn join_lines(&mut self, first: usize, last: usize) -> bool {
+        let last = min(last.max(first + 1), self.lines.len().saturating_sub(1));
+        if first >= last {
+            return false;
+        }
+        let mut joined = self.lines[first].content().to_string();
+        for row in first + 1..=last {
+            let next = self.lines[row].content().trim_start();
+            joined.truncate(joined.trim_end().len());
+            self.cursor_col = joined.len();
+            if !joined.is_empty() && !next.is_empty() {
+                joined.push(' ');
+            }
+            joined.push_str(next);
+        }
+        self.lines.remove_lines(first + 1..last + 1);
+        self.lines[first] = Line::with_tab_stop(joined, self.tab_stop());
+        self.cursor_row = first;
+        self.desired_col = None;
+        self.mark_dirty(first);
+        true
+    }
+
+    /// Comments out lines `first` to `last` with the filetype's line comments, or with its
+    /// block comments around each line if it has no others, or takes the comments off if
+    /// every line of them with text has one. Blank lines are left alone. Returns whether the
+    /// filetype has comments.
+    pub fn toggle_comment(&mut self, first: usize, last: usize) -> bool {
+        let Some((open, close)) = self.filetype().and_then(|filetype| {
+            (filetype.line_comment.map(|marker| (marker, ""))).or(filetype.block_comment)
+        }) else {
+            return false;
+        };
+        let last = min(last, self.lines.len().saturating_sub(1));
+        let rows = (first..=last)
+            .filter(|&row| !self.lines[row].content().trim().is_empty())
+            .collect::<Vec<usize>>();
+        let commented = |line: &Line| {
+            let text = line.content().trim();
+            text.len() >= open.len() + close.len()
+                && text.starts_with(open)
+                && text.ends_with(close)
+        };
+        let uncomment = rows.iter().all(|&row| commented(&self.lines[row]));
+        // Comments line up at the least indented line's indentation.
+        let indent = rows
+            .iter()
+            .map(|&row| self.lines[row].indent().len())
+            .min()
+            .unwrap_or(0);
+        for &row in &rows {
+            let content = self.lines[row].content();
+            let own_indent = self.lines[row].indent().len();
+            let new = if uncomment {
+                let (indent, text) = content.split_at(own_indent);
+                let text = text.trim_end();
+                let inner = &text[open.len()..text.len() - close.len()];
+                let inner = inner.strip_prefix(' ').unwrap_or(inner);
+                let inner = match close {
+                    "" => inner,
+                    _ => inner.strip_suffix(' ').unwrap_or(inner),
+                };
+                format!("{}{}", indent, inner)
+            } else {
+                let (indent, text) = content.split_at(indent);
+                match close {
+                    "" => format!("{}{} {}", indent, open, text),
+                    _ => format!("{}{} {} {}", indent, open, text, close),
+                }
+            };
+            if row == self.cursor_row {
+                let col = if uncomment && self.cursor_col > own_indent {
+                    own_indent.max(self.cursor_col.saturating_sub(content.len() - new.len()))
+                } else if !uncomment && self.cursor_col >= indent {
+                    self.cursor_col + new.len() - content.len()
+                } else {
+                    self.cursor_col
+                };
+                self.cursor_col = new.floor_char_boundary(min(col, new.len()));
+            }
+            self.lines[row] = Line::with_tab_stop(new, self.tab_stop());
         }
+        if let Some(&row) = rows.first() {
+            self.mark_dirty(row);
+        }
+        true
     }
 
     pub fn delete_char(&mut self) {
@@ -215,33 +1960,39 @@ impl Buffer {
         }
         if let Some(line) = self.lines.get_mut(self.cursor_row) {
             if self.cursor_col > 0 {
-                line.remove(self.cursor_col - 1);
-                self.cursor_col -= 1;
-                self.dirty = true;
+                self.cursor_col = line.prev_char_boundary(self.cursor_col);
+                line.remove(self.cursor_col);
+                self.mark_dirty(self.cursor_row);
             } else {
                 self.cursor_col = self.lines[self.cursor_row - 1].len();
                 let tail = self.lines[self.cursor_row].content().to_string();
                 self.lines[self.cursor_row - 1].push_str(&tail);
-                self.delete_row();
+                self.lines.remove(self.cursor_row);
                 self.cursor_row -= 1;
+                self.mark_dirty(self.cursor_row);
             }
         }
     }
 
-    pub fn find_forward(&self, query: &str, mut skip_once: bool) -> (usize, usize) {
+    pub fn find_forward(&self, pattern: &Pattern, mut skip_once: bool) -> (usize, usize) {
         let idx_lines = self
             .lines
             .iter()
             .enumerate()
             .cycle()
             .skip(self.cursor_row)
-            .take(self.lines.len());
+            .take(self.lines.len() + 1)
+            .enumerate();
 
-        for (row, line) in idx_lines {
-            let matches = line.match_indices(query);
+        // The cursor row comes around again at the end, for the matches before the cursor.
+        let wrapped = self.lines.len();
+        for (pass, (row, line)) in idx_lines {
+            let matches = line.match_indices(pattern);
             for (col, _) in matches {
-                let col = line.render_to_cursor_position(col);
-                if row == self.cursor_row && col < self.cursor_col {
+                let col = line.render_to_cursor_position(line.rendered_column(col));
+                if (pass == 0 && col < self.cursor_col)
+                    || (pass == wrapped && col >= self.cursor_col)
+                {
                     continue;
                 }
                 if skip_once {
@@ -254,7 +2005,57 @@ impl Buffer {
         (self.cursor_row, self.cursor_col)
     }
 
-    pub fn find_reverse(&self, query: &str, mut skip_once: bool) -> (usize, usize) {
+    /// Position of the first occurrence of `query` at or after `from`, without wrapping
+    /// around.
+    pub fn find_text(&self, query: &str, (row, col): Position) -> Option<Position> {
+        self.lines
+            .iter()
+            .enumerate()
+            .skip(row)
+            .find_map(|(idx, line)| {
+                let skip = if idx == row { col } else { 0 };
+                let found = line.content().get(skip..)?.find(query)?;
+                Some((idx, skip + found))
+            })
+    }
+
+    /// Row and byte range of the first match of `pattern` at or after `from`, without wrapping
+    /// around. With `after_empty`, an empty match right at `from` doesn't count, as one was
+    /// just found there.
+    pub fn find_match(
+        &self,
+        pattern: &Pattern,
+        (row, col): Position,
+        after_empty: bool,
+    ) -> Option<(usize, usize, usize)> {
+        self.lines
+            .iter()
+            .enumerate()
+            .skip(row)
+            .find_map(|(idx, line)| {
+                let (start, end) =
+                    pattern
+                        .find_iter(line.content())
+                        .into_iter()
+                        .find(|&(start, end)| {
+                            idx > row
+                                || start > col
+                                || (start == col && !(after_empty && start == end))
+                        })?;
+                Some((idx, start, end))
+            })
+    }
+
+    /// Replaces the `len` bytes at `pos` with `text`, leaving the cursor after it.
+    pub fn replace_text(&mut self, (row, col): Position, len: usize, text: &str) {
+        self.delete_region(Region::new((row, col), (row, col + len)));
+        // Deleting nothing leaves the cursor where it was.
+        self.cursor_row = row;
+        self.cursor_col = col;
+        self.insert_text(text);
+    }
+
+    pub fn find_reverse(&self, pattern: &Pattern, mut skip_once: bool) -> (usize, usize) {
         let idx_lines = self
             .lines
             .iter()
@@ -262,13 +2063,17 @@ impl Buffer {
             .rev()
             .cycle()
             .skip(self.lines.len() - self.cursor_row - 1)
-            .take(self.lines.len());
+            .take(self.lines.len() + 1)
+            .enumerate();
 
-        for (row, line) in idx_lines {
-            let matches = line.match_indices(query);
+        let wrapped = self.lines.len();
+        for (pass, (row, line)) in idx_lines {
+            let matches = line.match_indices(pattern);
             for (col, _) in matches.into_iter().rev() {
-                let col = line.render_to_cursor_position(col);
-                if row == self.cursor_row && col > self.cursor_col {
+                let col = line.render_to_cursor_position(line.rendered_column(col));
+                if (pass == 0 && col > self.cursor_col)
+                    || (pass == wrapped && col <= self.cursor_col)
+                {
                     continue;
                 }
                 if skip_once {
@@ -281,3 +2086,179 @@ impl Buffer {
         (self.cursor_row, self.cursor_col)
     }
 }
+
+/// `text` with its lines after the first moved to start at `indent` and keep the indentation
+/// they had relative to each other, measured in columns so that tabs and spaces mix.
+fn reindent(text: &str, indent: &str, tab_stop: usize) -> String {
+    let is_indent = [' ', '\t'];
+    let lines = text
+        .split('\n')
+        .map(|line| line.trim_end_matches('\r'))
+        .collect::<Vec<&str>>();
+    // The first line lands at the cursor without its indent, but still counts towards the
+    // common one, so the lines after it keep theirs relative to it.
+    let common = lines
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| indent_columns(line, tab_stop))
+        .min()
+        .unwrap_or_default();
+
+    lines
+        .iter()
+        .enumerate()
+        .map(|(n, line)| {
+            if n == 0 {
+                line.trim_start_matches(is_indent).to_string()
+            } else if line.trim().is_empty() {
+                String::new()
+            } else {
+                format!("{}{}", indent, outdent(line, common, tab_stop))
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Columns the indentation of `line` takes up.
+fn indent_columns(line: &str, tab_stop: usize) -> usize {
+    line.chars()
+        .take_while(|&ch| ch == ' ' || ch == '\t')
+        .fold(0, |columns, ch| match ch {
+            '\t' => columns + tab_stop - columns % tab_stop,
+            _ => columns + 1,
+        })
+}
+
+/// `line` with `columns` of its indentation taken off. What's left of a tab straddling them
+/// becomes spaces.
+fn outdent(line: &str, columns: usize, tab_stop: usize) -> String {
+    let mut at = 0;
+    for (idx, ch) in line.char_indices() {
+        if at >= columns {
+            return line[idx..].to_string();
+        }
+        match ch {
+            ' ' => at += 1,
+            '\t' => at += tab_stop - at % tab_stop,
+            _ => return line[idx..].to_string(),
+        }
+        if at > columns {
+            return format!("{}{}", " ".repeat(at - columns), &line[idx + 1..]);
+        }
+    }
+    String::new()
+}
+
+/// Render columns the tabs of each line go to when cells ending in tabs, given by their
+/// widths, are lined up as elastic tab stops. The cells at the same place on adjacent lines
+/// form a column as wide as its widest cell and some padding; a line with fewer cells ends it.
+fn elastic_stops(cells: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    let mut stops = cells
+        .iter()
+        .map(|widths| Vec::with_capacity(widths.len()))
+        .collect::<Vec<Vec<usize>>>();
+    let columns = cells.iter().map(Vec::len).max().unwrap_or(0);
+    for col in 0..columns {
+        let mut row = 0;
+        while row < cells.len() {
+            if cells[row].len() <= col {
+                row += 1;
+                continue;
+            }
+            let end = (row..cells.len())
+                .find(|&end| cells[end].len() <= col)
+                .unwrap_or(cells.len());
+            let width = cells[row..end].iter().map(|widths| widths[col]).max();
+            let width = width.unwrap_or(0) + ELASTIC_PADDING;
+            // Lines in a column all share the columns before it, so start at the same place.
+            for line in &mut stops[row..end] {
+                let start = line.last().copied().unwrap_or(0);
+                line.push(start + width);
+            }
+            row = end;
+        }
+    }
+    stops
+}
+
+/// Kinds of characters words are made of: whitespace between them, letters, digits and
+/// underscores, and punctuation.
+fn char_class(ch: char) -> u8 {
+    if ch.is_whitespace() {
+        0
+    } else if region::is_word_char(ch) {
+        1
+    } else {
+        2
+    }
+}
+
+/// One level of the indentation most indented lines in `lines` use: a tab, or the step in
+/// spaces seen most often between one line and the next. None if too few lines are indented
+/// to tell, or as many with tabs as with spaces.
+fn detect_indent<'a>(lines: impl Iterator<Item = &'a str>) -> Option<String> {
+    let (mut tabs, mut spaces) = (0, 0);
+    let mut steps = [0; 9];
+    let mut previous = 0;
+    for line in lines.take(10_000) {
+        let body = line.trim_start_matches([' ', '\t']);
+        if body.is_empty() {
+            continue;
+        }
+        let indent = &line[..line.len() - body.len()];
+        if indent.starts_with('\t') {
+            tabs += 1;
+            continue;
+        }
+        // Continued block comments sit one space in, without being another level.
+        if body.starts_with('*') && indent.len() % 2 == 1 {
+            continue;
+        }
+        if !indent.is_empty() {
+            spaces += 1;
+        }
+        let step = indent.len().abs_diff(previous);
+        if (2..steps.len()).contains(&step) {
+            steps[step] += 1;
+        }
+        previous = indent.len();
+    }
+    if tabs > spaces {
+        return Some("\t".to_string());
+    }
+    let (width, &seen) = steps
+        .iter()
+        .enumerate()
+        .max_by_key(|&(width, seen)| (seen, Reverse(width)))?;
+    if spaces > tabs && seen > 0 {
+        Some(" ".repeat(width))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reindent_keeps_relative_indents() {
+        // Nested blocks, with a blank line among them, move over to the new indent.
+        let text = "    if x {\n        y();\n\n            z();\n    }";
+        assert_eq!(
+            reindent(text, "\t", 4),
+            "if x {\n\t    y();\n\n\t        z();\n\t}"
+        );
+        // Lines less indented than the first keep their place relative to it.
+        assert_eq!(reindent("  a\nb\n  c", "  ", 4), "a\n  b\n    c");
+        // A tab and four spaces are the same indent, and a line one tab deeper stays so.
+        let text = "\tfn f() {\n    \tg();\n\t}";
+        assert_eq!(reindent(text, "", 4), "fn f() {\n\tg();\n}");
+        // A tab reaching past the common indent leaves spaces for the rest of it.
+        assert_eq!(reindent("  a\n\tb", "", 4), "a\n  b");
+        // Carriage returns go along with the line feeds.
+        let text = "    a\r\n        b\r\n\r\n    c\r\n";
+        assert_eq!(reindent(text, "  ", 4), "a\n      b\n\n  c\n");
+    }
+}
@@ -0,0 +1,106 @@
+use std::io::{Error, Result, Write};
+use std::process::{Command, Stdio};
+
+/// The X11 and Wayland selections text can be copied to and pasted from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Selection {
+    /// What copy and paste in other programs use.
+    Clipboard,
+    /// Whatever text was last selected, pasted with the middle mouse button.
+    Primary,
+}
+
+impl Selection {
+    pub fn name(self) -> &'static str {
+        match self {
+            Selection::Clipboard => "clipboard",
+            Selection::Primary => "primary",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Selection> {
+        match name {
+            "clipboard" => Some(Selection::Clipboard),
+            "primary" => Some(Selection::Primary),
+            _ => None,
+        }
+    }
+}
+
+/// Program and arguments that copy standard input to `selection`, or print it with `paste`,
+/// under the running display server.
+fn command(selection: Selection, paste: bool) -> Result<Command> {
+    let primary = selection == Selection::Primary;
+    let mut command = if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        let mut command = Command::new(if paste { "wl-paste" } else { "wl-copy" });
+        if primary {
+            command.arg("--primary");
+        }
+        if paste {
+            command.arg("--no-newline");
+        }
+        command
+    } else if std::env::var_os("DISPLAY").is_some() {
+        let mut command = Command::new("xclip");
+        command
+            .args(["-selection", selection.name()])
+            .arg(if paste { "-out" } else { "-in" });
+        command
+    } else {
+        return Err(Error::other("No X11 or Wayland display to share text with"));
+    };
+    command.stderr(Stdio::null());
+    Ok(command)
+}
+
+/// Puts `text` in `selection`.
+pub fn copy(selection: Selection, text: &str) -> Result<()> {
+    let mut child = command(selection, false)?
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()?;
+    // Dropping stdin closes it, which lets the copy program take the text and go.
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(text.as_bytes())?;
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(Error::other(format!(
+            "Copying to the {} failed",
+            selection.name()
+        )));
+    }
+    Ok(())
+}
+
+/// Text currently in `selection`.
+pub fn paste(selection: Selection) -> Result<String> {
+    let output = command(selection, true)?.stdin(Stdio::null()).output()?;
+    if !output.status.success() {
+        return Err(Error::other(format!("The {} is empty", selection.name())));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Escape sequence asking the terminal to put `text` on the system clipboard, which works
+/// wherever the terminal runs, even with the editor on the other end of an ssh connection.
+/// Terminals that don't support OSC 52 ignore it.
+pub fn osc52(text: &str) -> String {
+    const DIGITS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(text.len().div_ceil(3) * 4);
+    for chunk in text.as_bytes().chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (n, &byte)| {
+            bits | (byte as u32) << (16 - 8 * n)
+        });
+        for n in 0..4 {
+            encoded.push(if n <= chunk.len() {
+                DIGITS[(bits >> (18 - 6 * n) & 0x3f) as usize] as char
+            } else {
+                '='
+            });
+        }
+    }
+    format!("\x1b]52;c;{}\x07", encoded)
+}
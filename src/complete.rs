@@ -0,0 +1,39 @@
+use crate::region::is_word_char;
+
+/// The part of a word before byte `col` of `line`, the prefix to complete.
+pub fn prefix_before(line: &str, col: usize) -> &str {
+    let before = line.get(..col).unwrap_or_default();
+    let start = before
+        .char_indices()
+        .rev()
+        .take_while(|&(_, ch)| is_word_char(ch))
+        .last()
+        .map_or(before.len(), |(idx, _)| idx);
+    &before[start..]
+}
+
+/// Words in `lines` that start with `prefix` and are longer than it, each once, in the order
+/// they first come up. Words starting with a digit aren't worth completing to. Stops after
+/// `limit` of them.
+pub fn words_with_prefix<'a>(
+    prefix: &str,
+    lines: impl Iterator<Item = &'a str>,
+    limit: usize,
+) -> Vec<String> {
+    let mut words: Vec<String> = Vec::new();
+    for line in lines {
+        let found = line
+            .split(|ch: char| !is_word_char(ch))
+            .filter(|word| word.len() > prefix.len() && word.starts_with(prefix))
+            .filter(|word| !word.starts_with(|ch: char| ch.is_ascii_digit()));
+        for word in found {
+            if !words.iter().any(|known| known == word) {
+                words.push(word.to_string());
+                if words.len() == limit {
+                    return words;
+                }
+            }
+        }
+    }
+    words
+}
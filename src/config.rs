@@ -0,0 +1,930 @@
+use crate::ansi::{ColorDepth, Style};
+use crate::clipboard::Selection;
+use crate::editor::{Action, AutosaveTarget, CommandStep, Editor};
+use crate::encoding::Encoding;
+use crate::filetype::Filetype;
+use crate::highlight::{Theme, THEMES};
+use crate::hook::HookEvent;
+use crate::terminal::{Key, Modifiers, Motion};
+use crate::window::{BorderStyle, LineNumbers};
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Config file, under the user's config directory.
+const CONFIG_FILE: &str = "milo/config.toml";
+/// Directory of syntax files, next to the config file.
+const SYNTAX_DIR: &str = "syntax";
+/// Directory of plugins, next to the config file.
+const PLUGINS_DIR: &str = "plugins";
+
+/// Where the config file is looked for: under `$XDG_CONFIG_HOME`, or else `~/.config`.
+pub fn config_path() -> Option<PathBuf> {
+    let dir = match std::env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => Path::new(&std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(dir.join(CONFIG_FILE))
+}
+
+/// Where plugins are looked for, next to the config file. Shell commands find it in
+/// `MILO_PLUGINS`, to run the scripts that come with plugins.
+pub fn plugins_dir() -> Option<PathBuf> {
+    Some(config_path()?.with_file_name(PLUGINS_DIR))
+}
+
+/// Applies the config file to `editor`, if there is one, after registering the filetypes of
+/// any syntax files and applying the plugins. Problems with them show up in the message bar
+/// instead of stopping the editor; whatever they affect keeps its default.
+pub fn load(editor: &mut Editor) {
+    let path = match config_path() {
+        Some(path) => path,
+        None => return,
+    };
+    let mut errors = load_syntax(&path.with_file_name(SYNTAX_DIR));
+    errors.extend(load_plugins(editor, &path.with_file_name(PLUGINS_DIR)));
+    match std::fs::read_to_string(&path) {
+        Ok(text) => errors.extend(
+            apply(editor, &text)
+                .into_iter()
+                .map(|err| format!("{}: {}", path.display(), err)),
+        ),
+        Err(err) if err.kind() == ErrorKind::NotFound => {}
+        Err(err) => errors.push(format!("Can't read {}: {}", path.display(), err)),
+    }
+    if let Some(first) = errors.first() {
+        let more = match errors.len() {
+            1 => String::new(),
+            count => format!(" (and {} more)", count - 1),
+        };
+        editor.set_status(format!("{}{}", first, more));
+    }
+}
+
+/// Registers the filetype each file in `dir` defines, in the subset of TOML the config file
+/// is in, or in JSON, with the same keys as `Filetype` has fields. Only `name` is needed:
+///
+/// ```toml
+/// name = "Go"
+/// extensions = ["go"]
+/// indent = "\t"
+/// indent_after = "{(["
+/// line_comment = "//"
+/// block_comment = ["/*", "*/"]
+/// quotes = "\"'`"
+/// raw_strings = [["`", "`"]]      # Going over lines, as `long_strings` but without escapes
+/// keywords = [
+///     "break", "case", "chan", "const", "continue", "default", "defer", "else", "func",
+/// ]
+/// ```
+///
+/// A filetype with the name, an extension or a file name of a built-in one takes its place.
+/// Returns a description of every problem found, with the file it's in.
+fn load_syntax(dir: &Path) -> Vec<String> {
+    let mut paths = match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "toml" || extension == "json")
+            })
+            .collect::<Vec<PathBuf>>(),
+        Err(_) => return Vec::new(),
+    };
+    // Later files go ahead of earlier ones, so the order has to be the same every time.
+    paths.sort();
+    let mut errors = Vec::new();
+    for path in paths {
+        let filetype = std::fs::read_to_string(&path)
+            .map_err(|err| err.to_string())
+            .and_then(|text| parse_table(&text))
+            .and_then(syntax_filetype);
+        match filetype {
+            Ok(filetype) => Filetype::register(filetype),
+            Err(err) => errors.push(format!("{}: {}", path.display(), err)),
+        }
+    }
+    errors
+}
+
+/// Applies each plugin in `dir`: a file ending in `.toml`, or a directory with a `plugin.toml`
+/// in it and the scripts its commands run. Plugins are written like the config file, adding
+/// commands, hooks and the keys that run them, and go in order of their names so that the
+/// config file can change what they set. Returns a description of every problem found, with
+/// the file it's in.
+fn load_plugins(editor: &mut Editor, dir: &Path) -> Vec<String> {
+    let mut paths = match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter_map(|path| match path.extension() {
+                Some(extension) if extension == "toml" => Some(path),
+                _ => Some(path.join("plugin.toml")).filter(|path| path.is_file()),
+            })
+            .collect::<Vec<PathBuf>>(),
+        Err(_) => return Vec::new(),
+    };
+    paths.sort();
+    let mut errors = Vec::new();
+    for path in paths {
+        match std::fs::read_to_string(&path) {
+            Ok(text) => errors.extend(
+                apply(editor, &text)
+                    .into_iter()
+                    .map(|err| format!("{}: {}", path.display(), err)),
+            ),
+            Err(err) => errors.push(format!("Can't read {}: {}", path.display(), err)),
+        }
+    }
+    errors
+}
+
+/// The filetype a syntax file defines with `entries`.
+fn syntax_filetype(entries: Vec<(String, Value)>) -> Result<Filetype, String> {
+    let mut filetype = Filetype {
+        name: "",
+        extensions: &[],
+        filenames: &[],
+        interpreters: &[],
+        indent: "    ",
+        indent_after: &[],
+        line_comment: None,
+        block_comment: None,
+        keywords: &[],
+        quotes: &['"', '\''],
+        long_strings: &[],
+    };
+    let mut long_strings = Vec::new();
+    for (key, value) in entries {
+        let with_key = |err: String| format!("{}: {}", key, err);
+        match key.as_str() {
+            "name" => filetype.name = leak(value.string().map_err(with_key)?),
+            "extensions" => filetype.extensions = leak_all(value.strings().map_err(with_key)?),
+            "filenames" => filetype.filenames = leak_all(value.strings().map_err(with_key)?),
+            "interpreters" => filetype.interpreters = leak_all(value.strings().map_err(with_key)?),
+            "indent" => filetype.indent = leak(value.string().map_err(with_key)?),
+            "indent_after" => filetype.indent_after = leak_chars(value.string().map_err(with_key)?),
+            "line_comment" => filetype.line_comment = Some(leak(value.string().map_err(with_key)?)),
+            "block_comment" => match value.strings().map_err(with_key)?.as_slice() {
+                [open, close] => {
+                    filetype.block_comment = Some((leak(open.clone()), leak(close.clone())))
+                }
+                _ => return Err(with_key("expected an opener and a closer".to_string())),
+            },
+            "keywords" => filetype.keywords = leak_all(value.strings().map_err(with_key)?),
+            "quotes" => filetype.quotes = leak_chars(value.string().map_err(with_key)?),
+            "long_strings" | "raw_strings" => {
+                let Value::Array(pairs) = value else {
+                    return Err(with_key(
+                        "expected a list of openers and closers".to_string(),
+                    ));
+                };
+                for pair in pairs {
+                    match pair.strings().map_err(with_key)?.as_slice() {
+                        [open, close] => long_strings.push((
+                            leak(open.clone()),
+                            leak(close.clone()),
+                            key == "long_strings",
+                        )),
+                        _ => return Err(with_key("expected an opener and a closer".to_string())),
+                    }
+                }
+            }
+            _ => return Err(format!("unknown key {}", key)),
+        }
+    }
+    if filetype.name.is_empty() {
+        return Err("no name".to_string());
+    }
+    filetype.long_strings = Box::leak(long_strings.into_boxed_slice());
+    Ok(filetype)
+}
+
+// Filetypes live as long as the editor does, so what they're made of can too.
+
+fn leak(text: String) -> &'static str {
+    Box::leak(text.into_boxed_str())
+}
+
+fn leak_all(texts: Vec<String>) -> &'static [&'static str] {
+    Box::leak(
+        texts
+            .into_iter()
+            .map(leak)
+            .collect::<Vec<_>>()
+            .into_boxed_slice(),
+    )
+}
+
+fn leak_chars(text: String) -> &'static [char] {
+    Box::leak(text.chars().collect::<Vec<_>>().into_boxed_slice())
+}
+
+/// Sets `key` in the `[options]` section of the config file to `value`, written as it goes in
+/// the file, like `true` or `"mono"`. The file and the section are created if needed, and the
+/// rest of the file is left as it was.
+pub fn save_option(key: &str, value: &str) -> std::io::Result<()> {
+    let path = config_path().ok_or_else(|| Error::other("No home directory to keep it in"))?;
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err),
+    };
+    let mut lines = text.lines().map(str::to_string).collect::<Vec<String>>();
+    let entry = format!("{} = {}", key, value);
+    let mut section = String::new();
+    // Last line of the options section so far, and the line setting `key` in it if any.
+    let mut options_end = None;
+    let mut existing = None;
+    for (idx, line) in lines.iter().enumerate() {
+        let line = strip_comment(line).trim();
+        if let Some(name) = line
+            .strip_prefix('[')
+            .and_then(|rest| rest.strip_suffix(']'))
+        {
+            section = name.trim().to_string();
+            if section == "options" {
+                options_end = Some(idx);
+            }
+        } else if section == "options" && !line.is_empty() {
+            options_end = Some(idx);
+            if parse_entry(line).is_ok_and(|(name, _)| name == key) {
+                existing = Some(idx);
+            }
+        }
+    }
+    match (existing, options_end) {
+        (Some(idx), _) => lines[idx] = entry,
+        (None, Some(idx)) => lines.insert(idx + 1, entry),
+        (None, None) => {
+            if !lines.is_empty() {
+                lines.push(String::new());
+            }
+            lines.push("[options]".to_string());
+            lines.push(entry);
+        }
+    }
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, lines.join("\n") + "\n")
+}
+
+/// Applies the settings in `text`, a config file in a subset of TOML, to `editor`. Returns a
+/// description of every problem found, with its line number.
+///
+/// ```toml
+/// [keys]                  # Actions from the bindings page, bound to one key or a list
+/// save = "Ctrl-O"
+/// page-up = ["PgUp", "Ctrl-B"]
+///
+/// [options]
+/// tab_stop = 4
+/// mouse = true
+///
+/// [insert_map]            # Typed characters and the keys they turn into
+/// jj = "Escape"
+///
+/// [commands]              # Commands for Ctrl-X :, made of command lines and keys
+/// home = [":cd ~", ":pwd"]
+///
+/// [command_keys]          # Command lines, like a command's name, bound to one key or a list
+/// home = "Alt-h"
+///
+/// [hooks]                 # Event, optionally followed by a pattern, and the steps to run
+/// "BufWritePost *.rs" = ":pwd"
+/// BufWritePre = ":trim"
+///
+/// [language_servers]      # Commands starting each filetype's language server, with the lsp
+/// rust = "rust-analyzer"  # feature
+///
+/// [theme.dusk]            # A theme for the options to pick, in styles as Style::parse reads
+/// keyword = "bold #fabd2f"
+/// search_match = "black on yellow"
+/// ```
+pub fn apply(editor: &mut Editor, text: &str) -> Vec<String> {
+    // Themes go first, so that options further up can pick them.
+    let mut errors = define_themes(text);
+    let mut section = String::new();
+    for (idx, line) in text.lines().enumerate() {
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        let result = match line
+            .strip_prefix('[')
+            .and_then(|rest| rest.strip_suffix(']'))
+        {
+            Some(name) => {
+                section = name.trim().to_string();
+                match section.as_str() {
+                    "keys" | "options" | "insert_map" | "commands" | "command_keys" | "hooks"
+                    | "language_servers" => Ok(()),
+                    name if name.starts_with("theme.") => Ok(()),
+                    _ => Err(format!("unknown section [{}]", section)),
+                }
+            }
+            None if section.starts_with("theme.") => Ok(()),
+            None => parse_entry(line)
+                .and_then(|(key, value)| apply_entry(editor, &section, &key, value)),
+        };
+        if let Err(err) = result {
+            errors.push((idx, err));
+        }
+    }
+    errors.sort_by_key(|&(idx, _)| idx);
+    errors
+        .into_iter()
+        .map(|(idx, err)| format!("line {}: {}", idx + 1, err))
+        .collect()
+}
+
+/// Registers the theme of each `[theme.NAME]` section in `text`. Colors left out are those of
+/// the default theme. Returns the problems found, by line index.
+fn define_themes(text: &str) -> Vec<(usize, String)> {
+    let mut errors = Vec::new();
+    let mut theme: Option<Theme> = None;
+    for (idx, line) in text.lines().enumerate() {
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        match line
+            .strip_prefix('[')
+            .and_then(|rest| rest.strip_suffix(']'))
+        {
+            Some(name) => {
+                if let Some(theme) = theme.take() {
+                    Theme::register(theme);
+                }
+                theme = name.trim().strip_prefix("theme.").map(|name| Theme {
+                    name: leak(name.trim().to_string()),
+                    ..THEMES[0].clone()
+                });
+            }
+            None => {
+                if let Some(theme) = theme.as_mut() {
+                    if let Err(err) = parse_entry(line).and_then(|(key, value)| {
+                        let color = theme
+                            .color_mut(&key)
+                            .ok_or_else(|| format!("unknown theme color {}", key))?;
+                        *color = leak(Style::parse(&value.string()?)?.escape());
+                        Ok(())
+                    }) {
+                        errors.push((idx, err));
+                    }
+                }
+            }
+        }
+    }
+    if let Some(theme) = theme {
+        Theme::register(theme);
+    }
+    errors
+}
+
+fn apply_entry(editor: &mut Editor, section: &str, key: &str, value: Value) -> Result<(), String> {
+    match section {
+        "keys" => {
+            let action = Action::from_name(key).ok_or_else(|| format!("unknown action {}", key))?;
+            let keys = value
+                .strings()?
+                .iter()
+                .map(|name| parse_key(name).ok_or_else(|| format!("unknown key {}", name)))
+                .collect::<Result<Vec<Key>, String>>()?;
+            editor.bind_keys(action, keys);
+        }
+        "options" => apply_option(editor, key, value)?,
+        "insert_map" => {
+            let keys = value.string()?;
+            editor.map_insert(key, parse_keys(&keys));
+        }
+        "commands" => {
+            let steps = value
+                .strings()?
+                .iter()
+                .map(|step| parse_step(step))
+                .collect();
+            editor.define_command(key, steps);
+        }
+        "command_keys" => {
+            let keys = value
+                .strings()?
+                .iter()
+                .map(|name| parse_key(name).ok_or_else(|| format!("unknown key {}", name)))
+                .collect::<Result<Vec<Key>, String>>()?;
+            editor.bind_command(key, keys);
+        }
+        "hooks" => {
+            let mut words = key.split_whitespace();
+            let event = match words.next().unwrap_or_default() {
+                "BufOpen" => HookEvent::BufOpen,
+                "BufWritePre" => HookEvent::BufWritePre,
+                "BufWritePost" => HookEvent::BufWritePost,
+                "FileType" => HookEvent::FileType,
+                "Resize" => HookEvent::Resize,
+                "ModeChange" => HookEvent::ModeChange,
+                "CursorMove" => HookEvent::CursorMove,
+                event => return Err(format!("unknown event {}", event)),
+            };
+            let steps = value
+                .strings()?
+                .iter()
+                .map(|step| parse_step(step))
+                .collect();
+            editor.add_hook(event, words.next(), steps);
+        }
+        #[cfg(feature = "lsp")]
+        "language_servers" => editor.set_language_server(key, &value.string()?),
+        #[cfg(not(feature = "lsp"))]
+        "language_servers" => return Err("built without language servers".to_string()),
+        _ => return Err(format!("{} outside of a section", key)),
+    }
+    Ok(())
+}
+
+/// Sets option `key` as the config file does, to `value` written as it goes there, or taken as
+/// a string if it doesn't read as anything else. For `set` on the command line.
+pub fn set_option(editor: &mut Editor, key: &str, value: &str) -> Result<(), String> {
+    let mut chars = value.chars().peekable();
+    let value = match parse_value(&mut chars) {
+        Ok(parsed) if chars.all(char::is_whitespace) => parsed,
+        _ => Value::String(value.to_string()),
+    };
+    apply_option(editor, key, value)
+}
+
+/// Names of the options `apply_option` knows, for completing them.
+pub const OPTIONS: &[&str] = &[
+    "tab_stop",
+    "mouse",
+    "escape_timeout",
+    "wheel_step",
+    "wheel_fast_multiplier",
+    "comment_leaders",
+    "auto_pairs",
+    "delete_pairs",
+    "auto_indent",
+    "smart_indent",
+    "expand_tabs",
+    "indent_width",
+    "detect_indent",
+    "restore_positions",
+    "sessions",
+    "swap_files",
+    "undo_files",
+    "text_width",
+    "wrap",
+    "elastic_tabs",
+    "modal",
+    "git",
+    "whitespace",
+    "trim_on_save",
+    "smooth_scroll",
+    "watch_files",
+    "backups",
+    "scrolloff",
+    "sidescrolloff",
+    "scroll",
+    "osc52",
+    "ansi_colors",
+    "line_numbers",
+    "colors",
+    "encoding",
+    "theme",
+    "yank_selections",
+    "paste_selection",
+    "border_style",
+    "autosave_idle",
+    "autosave_modifications",
+    "autosave_focus_lost",
+    "autosave_buffer_switch",
+    "autosave_target",
+];
+
+fn apply_option(editor: &mut Editor, key: &str, value: Value) -> Result<(), String> {
+    let mut autosave = editor.autosave_settings().clone();
+    let (wheel_step, wheel_fast_multiplier) = editor.wheel_scroll();
+    let (auto_pairs, delete_pairs) = editor.auto_pairs();
+    let (auto_indent, smart_indent) = editor.auto_indent();
+    let (expand_tabs, indent_width) = editor.indent();
+    let (yank_selections, paste_selection) = editor.clipboard_settings();
+    let (yank_selections, paste_selection) = (yank_selections.to_vec(), paste_selection);
+    match key {
+        "tab_stop" => editor.set_tab_stop(value.count()?),
+        "mouse" => editor.set_mouse(value.boolean()?),
+        "escape_timeout" => {
+            editor.set_escape_timeout(Duration::from_millis(value.count()? as u64));
+        }
+        "wheel_step" => editor.set_wheel_scroll(value.count()?, wheel_fast_multiplier),
+        "wheel_fast_multiplier" => editor.set_wheel_scroll(wheel_step, value.count()?),
+        "comment_leaders" => editor.set_comment_leaders(value.boolean()?),
+        "auto_pairs" => editor.set_auto_pairs(value.boolean()?, delete_pairs),
+        "delete_pairs" => editor.set_auto_pairs(auto_pairs, value.boolean()?),
+        "auto_indent" => editor.set_auto_indent(value.boolean()?, smart_indent),
+        "smart_indent" => editor.set_auto_indent(auto_indent, value.boolean()?),
+        "expand_tabs" => editor.set_indent(value.boolean()?, indent_width),
+        "indent_width" => editor.set_indent(expand_tabs, value.count()?),
+        "detect_indent" => editor.set_detect_indent(value.boolean()?),
+        "restore_positions" => editor.set_restore_positions(value.boolean()?),
+        "sessions" => editor.set_sessions(value.boolean()?),
+        "swap_files" => editor.set_swap_files(value.boolean()?),
+        "undo_files" => editor.set_undo_files(value.boolean()?),
+        "text_width" => editor.set_text_width(Some(value.count()?)),
+        "wrap" => editor.set_wrap(value.boolean()?),
+        "elastic_tabs" => editor.set_elastic_tabs(value.boolean()?),
+        "modal" => editor.set_modal(value.boolean()?),
+        "git" => editor.set_git(value.boolean()?),
+        "whitespace" => editor.set_show_whitespace(value.boolean()?),
+        "trim_on_save" => editor.set_trim_on_save(value.boolean()?),
+        "scrolloff" => editor.set_scrolloff(value.amount()?),
+        "sidescrolloff" => editor.set_sidescrolloff(value.amount()?),
+        "scroll" => editor.set_scroll(value.amount()?),
+        "smooth_scroll" => editor.set_smooth_scroll(value.boolean()?),
+        "watch_files" => editor.set_watch_files(value.boolean()?),
+        "backups" => editor.set_backups(value.boolean()?),
+        "osc52" => editor.set_osc52(value.boolean()?),
+        "ansi_colors" => editor.set_ansi_colors(value.boolean()?),
+        "line_numbers" => {
+            let name = value.string()?;
+            let line_numbers = LineNumbers::from_name(&name)
+                .ok_or_else(|| format!("unknown line numbers {}", name))?;
+            editor.set_line_numbers(line_numbers);
+        }
+        "colors" => {
+            let colors = match value {
+                Value::Integer(count) if count > 0 => Some(ColorDepth::from_count(count as u32)),
+                value => match value.string()?.as_str() {
+                    "auto" => Some(ColorDepth::detect()),
+                    name => ColorDepth::from_name(name),
+                },
+            };
+            editor.set_colors(colors.ok_or("expected auto, 8, 16, 256 or truecolor")?);
+        }
+        "encoding" => {
+            let name = value.string()?;
+            let encoding = match name.as_str() {
+                "auto" => None,
+                name => Some(
+                    Encoding::from_name(name)
+                        .ok_or_else(|| format!("unknown encoding {}", name))?,
+                ),
+            };
+            editor.set_encoding(encoding);
+        }
+        "theme" => {
+            let name = value.string()?;
+            let theme = Theme::by_name(&name).ok_or_else(|| format!("unknown theme {}", name))?;
+            editor.set_theme(theme);
+        }
+        "yank_selections" => {
+            let yank = value
+                .strings()?
+                .iter()
+                .map(|name| selection(name))
+                .collect::<Result<Vec<Selection>, String>>()?;
+            editor.set_clipboard(yank, paste_selection);
+        }
+        "paste_selection" => editor.set_clipboard(yank_selections, selection(&value.string()?)?),
+        "border_style" => editor.set_border_style(match value.string()?.as_str() {
+            "bar" => BorderStyle::Bar,
+            "line" => BorderStyle::Line,
+            "heavy" => BorderStyle::Heavy,
+            "double" => BorderStyle::Double,
+            style => return Err(format!("unknown border style {}", style)),
+        }),
+        "autosave_idle" => {
+            autosave.idle = Some(Duration::from_secs(value.count()? as u64));
+            editor.set_autosave(autosave);
+        }
+        "autosave_modifications" => {
+            autosave.modifications = Some(value.count()?);
+            editor.set_autosave(autosave);
+        }
+        "autosave_focus_lost" => {
+            autosave.focus_lost = value.boolean()?;
+            editor.set_autosave(autosave);
+        }
+        "autosave_buffer_switch" => {
+            autosave.buffer_switch = value.boolean()?;
+            editor.set_autosave(autosave);
+        }
+        "autosave_target" => {
+            autosave.target = match value.string()?.as_str() {
+                "file" => AutosaveTarget::File,
+                "recovery" => AutosaveTarget::Recovery,
+                target => return Err(format!("unknown autosave target {}", target)),
+            };
+            editor.set_autosave(autosave);
+        }
+        _ => return Err(format!("unknown option {}", key)),
+    }
+    Ok(())
+}
+
+fn selection(name: &str) -> Result<Selection, String> {
+    Selection::from_name(name).ok_or_else(|| format!("unknown selection {}", name))
+}
+
+/// A key by the name shown for it on the bindings page, like `Ctrl-S`, `PgDn`, `F5`,
+/// `Ctrl-Shift-Left` or `a`.
+pub fn parse_key(name: &str) -> Option<Key> {
+    let mut chars = name.chars();
+    if let (Some(ch), None) = (chars.next(), chars.next()) {
+        return Some(Key::Printable(ch));
+    }
+    // Modifiers come first, in any order, as in Ctrl-Shift-Left.
+    let mut modifiers = Modifiers::NONE;
+    let mut rest = name;
+    loop {
+        let prefixes = [
+            ("Ctrl-", Modifiers::CTRL),
+            ("C-", Modifiers::CTRL),
+            ("Alt-", Modifiers::ALT),
+            ("M-", Modifiers::ALT),
+            ("Shift-", Modifiers::SHIFT),
+            ("S-", Modifiers::SHIFT),
+        ];
+        match prefixes
+            .iter()
+            .find_map(|&(prefix, modifier)| Some((rest.strip_prefix(prefix)?, modifier)))
+        {
+            Some((after, modifier)) if !after.is_empty() => {
+                modifiers = modifiers | modifier;
+                rest = after;
+            }
+            _ => break,
+        }
+    }
+    let lower = rest.to_ascii_lowercase();
+    let motion = match lower.as_str() {
+        "up" => Some(Motion::Up),
+        "down" => Some(Motion::Down),
+        "left" => Some(Motion::Left),
+        "right" => Some(Motion::Right),
+        "pgup" | "pageup" => Some(Motion::PgUp),
+        "pgdn" | "pagedown" => Some(Motion::PgDn),
+        "home" => Some(Motion::Home),
+        "end" => Some(Motion::End),
+        _ => None,
+    };
+    if let Some(motion) = motion {
+        return Some(Key::motion(motion, modifiers));
+    }
+    let mut chars = rest.chars();
+    let single = match (chars.next(), chars.next()) {
+        _ if lower == "space" => Some(' '),
+        (Some(ch), None) => Some(ch),
+        _ => None,
+    };
+    match (single, modifiers) {
+        (Some(' '), Modifiers::CTRL) => return Some(Key::Control('@')),
+        (Some(ch), Modifiers::CTRL) if ('@'..='_').contains(&ch.to_ascii_uppercase()) => {
+            return Some(Key::Control(ch.to_ascii_uppercase()))
+        }
+        (Some(ch), Modifiers::ALT) if ch == ' ' || ch.is_ascii_graphic() => {
+            return Some(Key::Alt(ch))
+        }
+        (_, Modifiers::NONE) => {}
+        _ => return None,
+    }
+    if let Some(number) = lower.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+        return (1..=12).contains(&number).then_some(Key::Function(number));
+    }
+    Some(match lower.as_str() {
+        "insert" => Key::Insert,
+        "delete" => Key::Delete,
+        "backspace" => Key::Backspace,
+        "enter" | "newline" => Key::Newline,
+        "escape" | "esc" => Key::Escape,
+        "tab" => Key::Tab,
+        "space" => Key::Printable(' '),
+        _ => return None,
+    })
+}
+
+/// Keys separated by spaces. Words that aren't the name of a key stand for typing them.
+fn parse_keys(text: &str) -> Vec<Key> {
+    text.split_whitespace()
+        .flat_map(|word| match parse_key(word) {
+            Some(key) => vec![key],
+            None => word.chars().map(Key::Printable).collect(),
+        })
+        .collect()
+}
+
+/// A step of a command or hook: a command line after a `:`, or else keys.
+pub fn parse_step(step: &str) -> CommandStep {
+    match step.strip_prefix(':') {
+        Some(command) => CommandStep::Command(command.to_string()),
+        None => CommandStep::Keys(parse_keys(step)),
+    }
+}
+
+enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    fn string(self) -> Result<String, String> {
+        match self {
+            Value::String(text) => Ok(text),
+            _ => Err("expected a string".to_string()),
+        }
+    }
+
+    /// A string, or an array of them.
+    fn strings(self) -> Result<Vec<String>, String> {
+        match self {
+            Value::Array(values) => values.into_iter().map(Value::string).collect(),
+            value => Ok(vec![value.string()?]),
+        }
+    }
+
+    fn boolean(self) -> Result<bool, String> {
+        match self {
+            Value::Boolean(value) => Ok(value),
+            _ => Err("expected true or false".to_string()),
+        }
+    }
+
+    /// A positive integer, or zero.
+    fn amount(self) -> Result<usize, String> {
+        match self {
+            Value::Integer(value) if value >= 0 => Ok(value as usize),
+            _ => Err("expected a number, 0 or more".to_string()),
+        }
+    }
+
+    /// A positive integer.
+    fn count(self) -> Result<usize, String> {
+        match self {
+            Value::Integer(value) if value > 0 => Ok(value as usize),
+            _ => Err("expected a positive number".to_string()),
+        }
+    }
+}
+
+/// `line` up to a `#` that isn't inside a string.
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (idx, ch) in line.char_indices() {
+        match ch {
+            '#' if !in_string => return &line[..idx],
+            '"' if !escaped => in_string = !in_string,
+            _ => {}
+        }
+        escaped = ch == '\\' && !escaped;
+    }
+    line
+}
+
+/// Splits a `key = value` line into its key, bare or quoted, and parsed value.
+fn parse_entry(line: &str) -> Result<(String, Value), String> {
+    let mut chars = line.chars().peekable();
+    let key = parse_key_name(&mut chars)?;
+    skip_spaces(&mut chars);
+    if key.is_empty() || chars.next() != Some('=') {
+        return Err(format!("expected key = value, found {}", line));
+    }
+    let value = parse_value(&mut chars)?;
+    skip_spaces(&mut chars);
+    match chars.next() {
+        None => Ok((key, value)),
+        Some(ch) => Err(format!("unexpected {} after the value of {}", ch, key)),
+    }
+}
+
+/// Reads the keys and values of a whole file without sections: `key = value` entries whose
+/// arrays may go over several lines, or a JSON object.
+fn parse_table(text: &str) -> Result<Vec<(String, Value)>, String> {
+    let mut entries = Vec::new();
+    if text.trim_start().starts_with('{') {
+        let mut chars = text.chars().peekable();
+        skip_spaces(&mut chars);
+        chars.next();
+        loop {
+            skip_spaces(&mut chars);
+            if chars.next_if_eq(&'}').is_some() {
+                return Ok(entries);
+            }
+            if chars.peek() != Some(&'"') {
+                return Err("expected a quoted key".to_string());
+            }
+            let key = parse_string(&mut chars)?;
+            skip_spaces(&mut chars);
+            if chars.next() != Some(':') {
+                return Err(format!("expected : after {}", key));
+            }
+            entries.push((key, parse_value(&mut chars)?));
+            skip_spaces(&mut chars);
+            match chars.next() {
+                Some(',') => {}
+                Some('}') => return Ok(entries),
+                _ => return Err("unterminated object".to_string()),
+            }
+        }
+    }
+    let text = text
+        .lines()
+        .map(strip_comment)
+        .collect::<Vec<&str>>()
+        .join("\n");
+    let mut chars = text.chars().peekable();
+    loop {
+        skip_spaces(&mut chars);
+        if chars.peek().is_none() {
+            return Ok(entries);
+        }
+        let key = parse_key_name(&mut chars)?;
+        skip_spaces(&mut chars);
+        if key.is_empty() || chars.next() != Some('=') {
+            return Err(format!("expected key = value after {}", key));
+        }
+        entries.push((key, parse_value(&mut chars)?));
+    }
+}
+
+/// Reads a key, bare or quoted.
+fn parse_key_name(chars: &mut Chars) -> Result<String, String> {
+    if chars.peek() == Some(&'"') {
+        return parse_string(chars);
+    }
+    let mut key = String::new();
+    while let Some(ch) = chars.next_if(|&ch| ch.is_ascii_alphanumeric() || ch == '_' || ch == '-') {
+        key.push(ch);
+    }
+    Ok(key)
+}
+
+type Chars<'a> = std::iter::Peekable<std::str::Chars<'a>>;
+
+fn skip_spaces(chars: &mut Chars) {
+    while chars.next_if(|ch| ch.is_whitespace()).is_some() {}
+}
+
+fn parse_value(chars: &mut Chars) -> Result<Value, String> {
+    skip_spaces(chars);
+    match chars.peek() {
+        Some('"') => parse_string(chars).map(Value::String),
+        Some('[') => {
+            chars.next();
+            let mut values = Vec::new();
+            loop {
+                skip_spaces(chars);
+                if chars.next_if_eq(&']').is_some() {
+                    return Ok(Value::Array(values));
+                }
+                values.push(parse_value(chars)?);
+                skip_spaces(chars);
+                match chars.next() {
+                    Some(',') => {}
+                    Some(']') => return Ok(Value::Array(values)),
+                    _ => return Err("unterminated array".to_string()),
+                }
+            }
+        }
+        Some(_) => {
+            let mut word = String::new();
+            while let Some(ch) =
+                chars.next_if(|&ch| ch.is_ascii_alphanumeric() || "+-_".contains(ch))
+            {
+                word.push(ch);
+            }
+            match word.as_str() {
+                "true" => Ok(Value::Boolean(true)),
+                "false" => Ok(Value::Boolean(false)),
+                _ => word
+                    .replace('_', "")
+                    .parse()
+                    .map(Value::Integer)
+                    .map_err(|_| format!("invalid value {}", word)),
+            }
+        }
+        None => Err("missing value".to_string()),
+    }
+}
+
+/// Reads a double-quoted string with its escapes.
+fn parse_string(chars: &mut Chars) -> Result<String, String> {
+    chars.next();
+    let mut text = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(text),
+            Some('\\') => text.push(match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('e') => '\x1b',
+                Some(ch @ ('"' | '\\')) => ch,
+                Some(ch) => return Err(format!("unknown escape \\{}", ch)),
+                None => return Err("unterminated string".to_string()),
+            }),
+            Some(ch) => text.push(ch),
+            None => return Err("unterminated string".to_string()),
+        }
+    }
+}
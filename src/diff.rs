@@ -0,0 +1,393 @@
+use std::cmp::min;
+use std::ops::Range;
+
+/// One step turning the old sequence into the new one, with the indices involved.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Edit {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+/// Differences searched at most for the middle of a change, beyond which the range left is
+/// taken as replaced whole. That keeps inputs with nothing in common from taking time in
+/// proportion to the product of their lengths.
+const MAX_COST: usize = 1024;
+
+/// Shortest edit script between `old` and `new` using Myers' algorithm, in its linear space
+/// form. Changes too big to be worth the search come out as a deletion and an insertion.
+pub fn diff<T: PartialEq>(old: &[T], new: &[T]) -> Vec<Edit> {
+    let mut edits = Vec::with_capacity(old.len().max(new.len()));
+    let size = 2 * (old.len() + new.len()).min(2 * MAX_COST) + 4;
+    let mut forward = Diagonals::new(size);
+    let mut backward = Diagonals::new(size);
+    conquer(
+        old,
+        0..old.len(),
+        new,
+        0..new.len(),
+        &mut forward,
+        &mut backward,
+        &mut edits,
+    );
+    // Within a run of changes, what goes comes before what replaces it.
+    for run in edits.split_mut(|edit| matches!(edit, Edit::Equal(..))) {
+        run.sort_by_key(|edit| matches!(edit, Edit::Insert(_)));
+    }
+    edits
+}
+
+/// Furthest x reached on each diagonal k = x - y, which can be negative.
+struct Diagonals {
+    xs: Vec<usize>,
+    offset: isize,
+}
+
+impl Diagonals {
+    fn new(size: usize) -> Self {
+        Diagonals {
+            xs: vec![0; size],
+            offset: size as isize / 2,
+        }
+    }
+}
+
+impl std::ops::Index<isize> for Diagonals {
+    type Output = usize;
+
+    fn index(&self, k: isize) -> &usize {
+        &self.xs[(k + self.offset) as usize]
+    }
+}
+
+impl std::ops::IndexMut<isize> for Diagonals {
+    fn index_mut(&mut self, k: isize) -> &mut usize {
+        &mut self.xs[(k + self.offset) as usize]
+    }
+}
+
+/// Adds the edits turning `old[olds]` into `new[news]`, splitting them at the middle of the
+/// shortest path between them and going on with each half.
+fn conquer<T: PartialEq>(
+    old: &[T],
+    mut olds: Range<usize>,
+    new: &[T],
+    mut news: Range<usize>,
+    forward: &mut Diagonals,
+    backward: &mut Diagonals,
+    edits: &mut Vec<Edit>,
+) {
+    let prefix = common_prefix(&old[olds.clone()], &new[news.clone()]);
+    edits.extend((0..prefix).map(|n| Edit::Equal(olds.start + n, news.start + n)));
+    olds.start += prefix;
+    news.start += prefix;
+    let suffix = common_suffix(&old[olds.clone()], &new[news.clone()]);
+    olds.end -= suffix;
+    news.end -= suffix;
+
+    if olds.is_empty() || news.is_empty() {
+        edits.extend(olds.clone().map(Edit::Delete));
+        edits.extend(news.clone().map(Edit::Insert));
+    } else if let Some((x, y)) =
+        middle_snake(old, olds.clone(), new, news.clone(), forward, backward)
+    {
+        conquer(
+            old,
+            olds.start..x,
+            new,
+            news.start..y,
+            forward,
+            backward,
+            edits,
+        );
+        conquer(old, x..olds.end, new, y..news.end, forward, backward, edits);
+    } else {
+        edits.extend(olds.clone().map(Edit::Delete));
+        edits.extend(news.clone().map(Edit::Insert));
+    }
+    edits.extend((0..suffix).map(|n| Edit::Equal(olds.end + n, news.end + n)));
+}
+
+/// Where a shortest path from the start of both ranges to their end crosses its middle, as
+/// indices into `old` and `new`, searching from both ends at once. None if it's too far.
+fn middle_snake<T: PartialEq>(
+    old: &[T],
+    olds: Range<usize>,
+    new: &[T],
+    news: Range<usize>,
+    forward: &mut Diagonals,
+    backward: &mut Diagonals,
+) -> Option<(usize, usize)> {
+    let (n, m) = (olds.len(), news.len());
+    let delta = n as isize - m as isize;
+    let odd = delta & 1 == 1;
+    forward[1] = 0;
+    backward[1] = 0;
+    let max = ((n + m).div_ceil(2) + 1).min(MAX_COST) as isize;
+    for d in 0..max {
+        for k in (-d..=d).rev().step_by(2) {
+            let mut x = if k == -d || (k != d && forward[k - 1] < forward[k + 1]) {
+                forward[k + 1]
+            } else {
+                forward[k - 1] + 1
+            };
+            let y = (x as isize - k) as usize;
+            let start = (x, y);
+            if x < n && y < m {
+                x += common_prefix(
+                    &old[olds.start + x..olds.end],
+                    &new[news.start + y..news.end],
+                );
+            }
+            forward[k] = x;
+            if odd && (k - delta).abs() < d && forward[k] + backward[-(k - delta)] >= n {
+                return Some((olds.start + start.0, news.start + start.1));
+            }
+        }
+        // Backwards, x and y count from the ends.
+        for k in (-d..=d).rev().step_by(2) {
+            let mut x = if k == -d || (k != d && backward[k - 1] < backward[k + 1]) {
+                backward[k + 1]
+            } else {
+                backward[k - 1] + 1
+            };
+            let mut y = (x as isize - k) as usize;
+            if x < n && y < m {
+                let common = common_suffix(
+                    &old[olds.start..olds.start + n - x],
+                    &new[news.start..news.start + m - y],
+                );
+                x += common;
+                y += common;
+            }
+            backward[k] = x;
+            if !odd && (k - delta).abs() <= d && backward[k] + forward[-(k - delta)] >= n {
+                return Some((olds.start + n - x, news.start + m - y));
+            }
+        }
+    }
+    None
+}
+
+fn common_prefix<T: PartialEq>(a: &[T], b: &[T]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+fn common_suffix<T: PartialEq>(a: &[T], b: &[T]) -> usize {
+    a.iter()
+        .rev()
+        .zip(b.iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count()
+}
+
+/// Runs of edits that change something, as the indices of `old` they replace and the indices
+/// of `new` that replace them, in order.
+pub fn changes(edits: &[Edit]) -> Vec<(Range<usize>, Range<usize>)> {
+    let mut changes: Vec<(Range<usize>, Range<usize>)> = Vec::new();
+    let (mut old_pos, mut new_pos) = (0, 0);
+    let mut changing = false;
+    for edit in edits {
+        match *edit {
+            Edit::Equal(o, n) => {
+                (old_pos, new_pos) = (o + 1, n + 1);
+                changing = false;
+                continue;
+            }
+            Edit::Delete(o) => old_pos = o + 1,
+            Edit::Insert(n) => new_pos = n + 1,
+        }
+        match changes.last_mut() {
+            Some((old, new)) if changing => (old.end, new.end) = (old_pos, new_pos),
+            _ => {
+                let (old_start, new_start) = match *edit {
+                    Edit::Delete(o) => (o, new_pos),
+                    _ => (old_pos, new_pos - 1),
+                };
+                changes.push((old_start..old_pos, new_start..new_pos));
+            }
+        }
+        changing = true;
+    }
+    changes
+}
+
+/// The index in `new` each item of `old` went to: where it is now if it's still there, or
+/// where what came after it is if it was deleted.
+pub fn index_map(edits: &[Edit]) -> Vec<usize> {
+    let mut map = Vec::new();
+    let mut next = 0;
+    for edit in edits {
+        match *edit {
+            Edit::Equal(_, n) => {
+                map.push(n);
+                next = n + 1;
+            }
+            Edit::Delete(_) => map.push(next),
+            Edit::Insert(n) => next = n + 1,
+        }
+    }
+    map
+}
+
+/// Hunks of a unified diff between `old` and `new`, each change surrounded by up to `context`
+/// unchanged lines. Empty when both are the same.
+pub fn unified(old: &[&str], new: &[&str], context: usize) -> Vec<String> {
+    let edits = diff(old, new);
+
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for (n, _) in edits
+        .iter()
+        .enumerate()
+        .filter(|(_, edit)| !matches!(edit, Edit::Equal(..)))
+    {
+        let start = n.saturating_sub(context);
+        let end = min(n + context + 1, edits.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    // Old and new line numbers reached before each edit.
+    let positions = edits
+        .iter()
+        .scan((0, 0), |pos, edit| {
+            let before = *pos;
+            *pos = match edit {
+                Edit::Equal(..) => (pos.0 + 1, pos.1 + 1),
+                Edit::Delete(_) => (pos.0 + 1, pos.1),
+                Edit::Insert(_) => (pos.0, pos.1 + 1),
+            };
+            Some(before)
+        })
+        .collect::<Vec<(usize, usize)>>();
+
+    let mut lines = Vec::new();
+    for (start, end) in hunks {
+        let hunk = &edits[start..end];
+        let old_len = hunk
+            .iter()
+            .filter(|edit| !matches!(edit, Edit::Insert(_)))
+            .count();
+        let new_len = hunk
+            .iter()
+            .filter(|edit| !matches!(edit, Edit::Delete(_)))
+            .count();
+        let (old_start, new_start) = positions[start];
+        lines.push(format!(
+            "@@ -{},{} +{},{} @@",
+            old_start + min(old_len, 1),
+            old_len,
+            new_start + min(new_len, 1),
+            new_len
+        ));
+        lines.extend(hunk.iter().map(|edit| match *edit {
+            Edit::Equal(o, _) => format!(" {}", old[o]),
+            Edit::Delete(o) => format!("-{}", old[o]),
+            Edit::Insert(n) => format!("+{}", new[n]),
+        }));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `new` put back together from `old` and the edits, and how many of them were changes.
+    fn apply(old: &[u8], new: &[u8], edits: &[Edit]) -> (Vec<u8>, usize) {
+        let mut out = Vec::new();
+        let mut changed = 0;
+        let (mut next_old, mut next_new) = (0, 0);
+        for edit in edits {
+            match *edit {
+                Edit::Equal(o, n) => {
+                    assert_eq!((o, n), (next_old, next_new));
+                    assert_eq!(old[o], new[n]);
+                    out.push(old[o]);
+                    (next_old, next_new) = (o + 1, n + 1);
+                }
+                Edit::Delete(o) => {
+                    assert_eq!(o, next_old);
+                    next_old += 1;
+                    changed += 1;
+                }
+                Edit::Insert(n) => {
+                    assert_eq!(n, next_new);
+                    out.push(new[n]);
+                    next_new += 1;
+                    changed += 1;
+                }
+            }
+        }
+        assert_eq!((next_old, next_new), (old.len(), new.len()));
+        (out, changed)
+    }
+
+    /// Length of the longest common subsequence, the slow way.
+    fn lcs(a: &[u8], b: &[u8]) -> usize {
+        let mut row = vec![0; b.len() + 1];
+        for x in a {
+            let mut diagonal = 0;
+            for (j, y) in b.iter().enumerate() {
+                let above = row[j + 1];
+                row[j + 1] = if x == y {
+                    diagonal + 1
+                } else {
+                    above.max(row[j])
+                };
+                diagonal = above;
+            }
+        }
+        row[b.len()]
+    }
+
+    #[test]
+    fn shortest_scripts() {
+        let mut seed = 7u32;
+        let mut random = |len: u32| {
+            (0..len)
+                .map(|_| {
+                    seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                    b"abc"[(seed >> 16) as usize % 3]
+                })
+                .collect::<Vec<u8>>()
+        };
+        for round in 0..200 {
+            let (old, new) = (random(round % 23), random(round % 17));
+            let (out, changed) = apply(&old, &new, &diff(&old, &new));
+            assert_eq!(out, new);
+            assert_eq!(changed, old.len() + new.len() - 2 * lcs(&old, &new));
+        }
+    }
+
+    #[test]
+    fn nothing_in_common() {
+        let old = (0..20_000u32).collect::<Vec<u32>>();
+        let new = (20_000..40_000u32).collect::<Vec<u32>>();
+        let edits = diff(&old, &new);
+        assert_eq!(edits.len(), 40_000);
+        assert_eq!(changes(&edits), [(0..20_000, 0..20_000)]);
+    }
+
+    #[test]
+    fn unified_hunks() {
+        let old = ["a", "b", "c", "d", "e", "f", "g"];
+        let new = ["a", "B", "c", "d", "e", "f", "g", "h"];
+        assert_eq!(
+            unified(&old, &new, 1),
+            [
+                "@@ -1,3 +1,3 @@",
+                " a",
+                "-b",
+                "+B",
+                " c",
+                "@@ -7,1 +7,2 @@",
+                " g",
+                "+h"
+            ]
+        );
+        assert!(unified(&old, &old, 3).is_empty());
+    }
+}
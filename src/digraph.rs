@@ -0,0 +1,287 @@
+/// Two-character names of characters, after RFC 1345, for typing them without a compose key.
+/// Accented letters are the letter followed by a mark: `'` acute, `!` grave, `>` circumflex,
+/// `?` tilde, `:` diaeresis, `,` cedilla, `a` or `A` ring, `/` stroke, `<` caron, `-` macron,
+/// `(` breve, `.` dot and `;` ogonek. Greek letters are the Latin one followed by `*`.
+pub const DIGRAPHS: &[(&str, char)] = &[
+    ("A'", 'Á'),
+    ("a'", 'á'),
+    ("C'", 'Ć'),
+    ("c'", 'ć'),
+    ("E'", 'É'),
+    ("e'", 'é'),
+    ("I'", 'Í'),
+    ("i'", 'í'),
+    ("L'", 'Ĺ'),
+    ("l'", 'ĺ'),
+    ("N'", 'Ń'),
+    ("n'", 'ń'),
+    ("O'", 'Ó'),
+    ("o'", 'ó'),
+    ("R'", 'Ŕ'),
+    ("r'", 'ŕ'),
+    ("S'", 'Ś'),
+    ("s'", 'ś'),
+    ("U'", 'Ú'),
+    ("u'", 'ú'),
+    ("Y'", 'Ý'),
+    ("y'", 'ý'),
+    ("Z'", 'Ź'),
+    ("z'", 'ź'),
+    ("A!", 'À'),
+    ("a!", 'à'),
+    ("E!", 'È'),
+    ("e!", 'è'),
+    ("I!", 'Ì'),
+    ("i!", 'ì'),
+    ("O!", 'Ò'),
+    ("o!", 'ò'),
+    ("U!", 'Ù'),
+    ("u!", 'ù'),
+    ("A>", 'Â'),
+    ("a>", 'â'),
+    ("C>", 'Ĉ'),
+    ("c>", 'ĉ'),
+    ("E>", 'Ê'),
+    ("e>", 'ê'),
+    ("G>", 'Ĝ'),
+    ("g>", 'ĝ'),
+    ("H>", 'Ĥ'),
+    ("h>", 'ĥ'),
+    ("I>", 'Î'),
+    ("i>", 'î'),
+    ("J>", 'Ĵ'),
+    ("j>", 'ĵ'),
+    ("O>", 'Ô'),
+    ("o>", 'ô'),
+    ("S>", 'Ŝ'),
+    ("s>", 'ŝ'),
+    ("U>", 'Û'),
+    ("u>", 'û'),
+    ("W>", 'Ŵ'),
+    ("w>", 'ŵ'),
+    ("Y>", 'Ŷ'),
+    ("y>", 'ŷ'),
+    ("A?", 'Ã'),
+    ("a?", 'ã'),
+    ("I?", 'Ĩ'),
+    ("i?", 'ĩ'),
+    ("N?", 'Ñ'),
+    ("n?", 'ñ'),
+    ("O?", 'Õ'),
+    ("o?", 'õ'),
+    ("U?", 'Ũ'),
+    ("u?", 'ũ'),
+    ("A:", 'Ä'),
+    ("a:", 'ä'),
+    ("E:", 'Ë'),
+    ("e:", 'ë'),
+    ("I:", 'Ï'),
+    ("i:", 'ï'),
+    ("O:", 'Ö'),
+    ("o:", 'ö'),
+    ("U:", 'Ü'),
+    ("u:", 'ü'),
+    ("Y:", 'Ÿ'),
+    ("y:", 'ÿ'),
+    ("C,", 'Ç'),
+    ("c,", 'ç'),
+    ("G,", 'Ģ'),
+    ("g,", 'ģ'),
+    ("K,", 'Ķ'),
+    ("k,", 'ķ'),
+    ("L,", 'Ļ'),
+    ("l,", 'ļ'),
+    ("N,", 'Ņ'),
+    ("n,", 'ņ'),
+    ("R,", 'Ŗ'),
+    ("r,", 'ŗ'),
+    ("S,", 'Ş'),
+    ("s,", 'ş'),
+    ("T,", 'Ţ'),
+    ("t,", 'ţ'),
+    ("AA", 'Å'),
+    ("aa", 'å'),
+    ("UA", 'Ů'),
+    ("ua", 'ů'),
+    ("D/", 'Đ'),
+    ("d/", 'đ'),
+    ("H/", 'Ħ'),
+    ("h/", 'ħ'),
+    ("L/", 'Ł'),
+    ("l/", 'ł'),
+    ("O/", 'Ø'),
+    ("o/", 'ø'),
+    ("T/", 'Ŧ'),
+    ("t/", 'ŧ'),
+    ("C<", 'Č'),
+    ("c<", 'č'),
+    ("D<", 'Ď'),
+    ("d<", 'ď'),
+    ("E<", 'Ě'),
+    ("e<", 'ě'),
+    ("L<", 'Ľ'),
+    ("l<", 'ľ'),
+    ("N<", 'Ň'),
+    ("n<", 'ň'),
+    ("R<", 'Ř'),
+    ("r<", 'ř'),
+    ("S<", 'Š'),
+    ("s<", 'š'),
+    ("T<", 'Ť'),
+    ("t<", 'ť'),
+    ("Z<", 'Ž'),
+    ("z<", 'ž'),
+    ("A-", 'Ā'),
+    ("a-", 'ā'),
+    ("E-", 'Ē'),
+    ("e-", 'ē'),
+    ("I-", 'Ī'),
+    ("i-", 'ī'),
+    ("O-", 'Ō'),
+    ("o-", 'ō'),
+    ("U-", 'Ū'),
+    ("u-", 'ū'),
+    ("A(", 'Ă'),
+    ("a(", 'ă'),
+    ("E(", 'Ĕ'),
+    ("e(", 'ĕ'),
+    ("G(", 'Ğ'),
+    ("g(", 'ğ'),
+    ("I(", 'Ĭ'),
+    ("i(", 'ĭ'),
+    ("O(", 'Ŏ'),
+    ("o(", 'ŏ'),
+    ("U(", 'Ŭ'),
+    ("u(", 'ŭ'),
+    ("C.", 'Ċ'),
+    ("c.", 'ċ'),
+    ("E.", 'Ė'),
+    ("e.", 'ė'),
+    ("G.", 'Ġ'),
+    ("g.", 'ġ'),
+    ("I.", 'İ'),
+    ("Z.", 'Ż'),
+    ("z.", 'ż'),
+    ("A;", 'Ą'),
+    ("a;", 'ą'),
+    ("E;", 'Ę'),
+    ("e;", 'ę'),
+    ("I;", 'Į'),
+    ("i;", 'į'),
+    ("U;", 'Ų'),
+    ("u;", 'ų'),
+    ("ss", 'ß'),
+    ("AE", 'Æ'),
+    ("ae", 'æ'),
+    ("OE", 'Œ'),
+    ("oe", 'œ'),
+    ("TH", 'Þ'),
+    ("th", 'þ'),
+    ("D-", 'Đ'),
+    ("d-", 'đ'),
+    ("i.", 'ı'),
+    ("Eu", '€'),
+    ("Pd", '£'),
+    ("Ye", '¥'),
+    ("Ct", '¢'),
+    ("Co", '©'),
+    ("Rg", '®'),
+    ("TM", '™'),
+    ("SE", '§'),
+    ("PI", '¶'),
+    ("DG", '°'),
+    ("+-", '±'),
+    ("*X", '×'),
+    ("-:", '÷'),
+    ("My", 'µ'),
+    ("NS", '\u{a0}'),
+    ("!I", '¡'),
+    ("?I", '¿'),
+    ("<<", '«'),
+    (">>", '»'),
+    ("12", '½'),
+    ("14", '¼'),
+    ("34", '¾'),
+    ("1S", '¹'),
+    ("2S", '²'),
+    ("3S", '³'),
+    ("-N", '–'),
+    ("-M", '—'),
+    ("'6", '‘'),
+    ("'9", '’'),
+    ("\"6", '“'),
+    ("\"9", '”'),
+    (",.", '…'),
+    ("->", '→'),
+    ("<-", '←'),
+    ("-!", '↑'),
+    ("-v", '↓'),
+    ("!=", '≠'),
+    ("=<", '≤'),
+    (">=", '≥'),
+    ("00", '∞'),
+    ("RT", '√'),
+    ("?2", '≈'),
+    ("OK", '✓'),
+    ("XX", '✗'),
+    ("A*", 'Α'),
+    ("a*", 'α'),
+    ("B*", 'Β'),
+    ("b*", 'β'),
+    ("G*", 'Γ'),
+    ("g*", 'γ'),
+    ("D*", 'Δ'),
+    ("d*", 'δ'),
+    ("E*", 'Ε'),
+    ("e*", 'ε'),
+    ("Z*", 'Ζ'),
+    ("z*", 'ζ'),
+    ("Y*", 'Η'),
+    ("y*", 'η'),
+    ("H*", 'Θ'),
+    ("h*", 'θ'),
+    ("I*", 'Ι'),
+    ("i*", 'ι'),
+    ("K*", 'Κ'),
+    ("k*", 'κ'),
+    ("L*", 'Λ'),
+    ("l*", 'λ'),
+    ("M*", 'Μ'),
+    ("m*", 'μ'),
+    ("N*", 'Ν'),
+    ("n*", 'ν'),
+    ("C*", 'Ξ'),
+    ("c*", 'ξ'),
+    ("O*", 'Ο'),
+    ("o*", 'ο'),
+    ("P*", 'Π'),
+    ("p*", 'π'),
+    ("R*", 'Ρ'),
+    ("r*", 'ρ'),
+    ("S*", 'Σ'),
+    ("s*", 'σ'),
+    ("T*", 'Τ'),
+    ("t*", 'τ'),
+    ("U*", 'Υ'),
+    ("u*", 'υ'),
+    ("F*", 'Φ'),
+    ("f*", 'φ'),
+    ("X*", 'Χ'),
+    ("x*", 'χ'),
+    ("Q*", 'Ψ'),
+    ("q*", 'ψ'),
+    ("W*", 'Ω'),
+    ("w*", 'ω'),
+    ("*s", 'ς'),
+];
+
+/// Character named by `first` and `second`, in either order.
+pub fn lookup(first: char, second: char) -> Option<char> {
+    let find = |a: char, b: char| {
+        DIGRAPHS.iter().find_map(|&(name, ch)| {
+            let mut chars = name.chars();
+            (chars.next() == Some(a) && chars.next() == Some(b)).then_some(ch)
+        })
+    };
+    find(first, second).or_else(|| find(second, first))
+}
@@ -1,256 +1,7603 @@
-use crate::buffer::Buffer;
-use crate::terminal::{Key, Motion, Terminal};
+use crate::ansi::ColorDepth;
+use crate::buffer::{Buffer, BufferKind, Cursor, LineEnding, ViewAlign};
+use crate::clipboard::{self, Selection};
+use crate::complete;
+use crate::config;
+use crate::diff;
+use crate::digraph;
+use crate::encoding::Encoding;
+use crate::export::{self, ExportOptions};
+use crate::filetype::Filetype;
+use crate::finder::{self, Finder};
+use crate::git;
+use crate::grep;
+use crate::highlight::{self, Highlight, Theme, THEMES};
+use crate::history::Travel;
+use crate::hook::{HookEvent, Hooks};
+use crate::kill_ring::{Kill, KillRing};
+use crate::line::{Line, TAB_STOP};
+#[cfg(feature = "lsp")]
+use crate::lsp;
+use crate::modal::{Command as ModalCommand, InsertAt, Modal, Mode, Operator, Target};
+use crate::pattern::Pattern;
+use crate::positions;
+use crate::region::{self, Position, Region};
+use crate::save;
+use crate::session::{self, Session};
+use crate::terminal::{Event, Key, Modifiers, Motion, MouseButton, MouseEvent, Terminal};
+use crate::window::{BorderStyle, Layout, LineNumbers, Rect, Split, Window, MIN_SIZE};
+use std::cmp::{max, min};
+use std::collections::VecDeque;
 use std::fs::File;
-use std::io::Result;
-use std::io::{BufRead, BufReader};
-use std::time::Instant;
+use std::io::Write;
+use std::io::{ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
 
-const STATUS_HEIGHT: usize = 2; // 1 for Status bar. 1 for Status Message
-const TOTAL_QUIT_COUNT: usize = 4;
+const STATUS_HEIGHT: usize = 1; // Status bar of each window
+const MESSAGE_HEIGHT: usize = 1; // Status message below all windows
 const FILE_NAME_WIDTH: usize = 20;
 const STATUS_LINE_BLANK: char = ' ';
+const STATUS_TIMEOUT: Duration = Duration::from_secs(5);
+const MAPPING_TIMEOUT: Duration = Duration::from_millis(500);
+/// Time spent on queued up input before the screen is redrawn, about a frame at 60Hz.
+const FRAME_BUDGET: Duration = Duration::from_millis(16);
+/// Longest wait for input when nothing is due to change on screen.
+const IDLE_TICK: Duration = Duration::from_secs(30);
+/// How often the screen catches up with highlighting done in the background.
+const HIGHLIGHT_POLL: Duration = Duration::from_millis(50);
+/// How often an answer from git, asked in the background, is looked for.
+const GIT_POLL: Duration = Duration::from_millis(50);
+/// Pause in typing after which unsaved changes go to the swap file.
+const SWAP_IDLE: Duration = Duration::from_secs(4);
+/// How often the file shown is looked at for changes made to it by other programs.
+const DISK_POLL: Duration = Duration::from_secs(1);
+const DIFF_CONTEXT: usize = 3;
+const WHEEL_STEP: usize = 3;
+const WHEEL_FAST_MULTIPLIER: usize = 5;
+/// Frames a smooth scroll is drawn in, and how long each shows.
+const SMOOTH_SCROLL_FRAMES: usize = 6;
+const SMOOTH_SCROLL_FRAME: Duration = Duration::from_millis(12);
+/// How deeply user commands may run each other, so one that runs itself can't hang the editor.
+const MAX_COMMAND_DEPTH: usize = 16;
+/// Most files the file finder lists, so that starting it in a huge tree doesn't hang.
+const FINDER_LIMIT: usize = 100_000;
+/// Most matches `:grep` lists.
+const GREP_LIMIT: usize = 10_000;
+/// Texts cut or copied that are kept for pasting again.
+const KILL_RING_SIZE: usize = 30;
+/// Places the jump list keeps; the oldest go first.
+const JUMP_LIST_SIZE: usize = 100;
+/// Most words offered to complete one with, and how many of them show at a time.
+const COMPLETION_LIMIT: usize = 100;
+const COMPLETION_HEIGHT: usize = 8;
+/// How often running language servers are checked for diagnostics and answers.
+#[cfg(feature = "lsp")]
+const LSP_POLL: Duration = Duration::from_millis(50);
+/// Most lines of a language server's description of a name shown at a time.
+#[cfg(feature = "lsp")]
+const HOVER_HEIGHT: usize = 12;
 
+type Binding = (&'static str, &'static str, &'static str);
+
+/// Built-in bindings by group: keys, command and what it does. Kept next to the dispatch in
+/// `process_key`, `window_command` and `extended_command`; update both together.
+const BINDINGS: &[(&str, &[Binding])] = &[
+    (
+        "Global",
+        &[
+            (
+                "Ctrl-Q",
+                "quit",
+                "Quit, asking to save, discard or cancel for each unsaved buffer",
+            ),
+            (
+                "Ctrl-Z",
+                "suspend",
+                "Stop, back to the shell until it's brought to the foreground",
+            ),
+            (
+                "F1",
+                "help",
+                "Show every key binding and command, with the keys bound now",
+            ),
+            (
+                "Ctrl-W",
+                "window",
+                "Start a window command, one of those under Windows",
+            ),
+            (
+                "Ctrl-X",
+                "extended",
+                "Start an extended command, one of those under Extended",
+            ),
+            (
+                "Ctrl-S",
+                "save",
+                "Save the buffer, asking for a name if it has none",
+            ),
+            ("Ctrl-F", "find", "Search forward incrementally"),
+            ("Ctrl-G", "find-reverse", "Search backward incrementally"),
+            (
+                "Ctrl-R",
+                "replace",
+                "Replace matches, asking about each: y, n, a(ll), q(uit)",
+            ),
+            (
+                "Ctrl-D",
+                "digraph",
+                "Type the character two keys name, like e' for é",
+            ),
+            (
+                "Alt-x",
+                "command-line",
+                "Run a command by name, as Ctrl-X : does",
+            ),
+            ("Ctrl-E", "scroll-down", "Scroll the view down a line"),
+            ("Ctrl-Y", "scroll-up", "Scroll the view up a line"),
+            (
+                "Ctrl-N / U",
+                "half-page-down/up",
+                "Move the cursor and the view half a window down or up",
+            ),
+            (
+                "Alt-/",
+                "complete",
+                "After part of a word, complete it from words in the open buffers",
+            ),
+            (
+                "Ctrl-T / B",
+                "full-page-down/up",
+                "Move the cursor and the view a window down or up",
+            ),
+            (
+                "Ctrl-L",
+                "recenter",
+                "Put the cursor line at the center, top or bottom",
+            ),
+            ("Insert", "overwrite", "Toggle overwrite mode"),
+            ("Ctrl-P", "next-buffer", "Show the next buffer"),
+            ("Alt-p", "previous-buffer", "Show the previous buffer"),
+            (
+                "Ctrl-O",
+                "open-file",
+                "Open a file in a buffer of its own, or a new one by that name",
+            ),
+            (
+                "Alt-f",
+                "find-file",
+                "Find a file under the working directory by typing parts of its path",
+            ),
+            (
+                "Ctrl-Space",
+                "select",
+                "Start selecting from the cursor, or stop",
+            ),
+            (
+                "Shift-Arrow",
+                "select-move",
+                "Move the cursor, selecting the text on the way",
+            ),
+            (
+                "Ctrl-Left",
+                "word-left",
+                "Move to the start of the word or the one before, selecting with Shift",
+            ),
+            (
+                "Ctrl-Right",
+                "word-right",
+                "Move to the end of the word or the one after, selecting with Shift",
+            ),
+            (
+                "Ctrl-Up",
+                "paragraph-up",
+                "Move to the blank line before the paragraph, selecting with Shift",
+            ),
+            (
+                "Ctrl-Down",
+                "paragraph-down",
+                "Move to the blank line after the paragraph, selecting with Shift",
+            ),
+            (
+                "Ctrl-]",
+                "match-bracket",
+                "Go to the bracket paired with the one at the cursor",
+            ),
+            (
+                "Alt-o / i",
+                "jump-back/forward",
+                "Go back to before a search or other jump, or forward again",
+            ),
+            (
+                "Alt-m LETTER",
+                "set-mark",
+                "Name the cursor's place after a letter, for this buffer",
+            ),
+            (
+                "Alt-g LETTER",
+                "goto-mark",
+                "Go to the place a letter names",
+            ),
+            (
+                "Alt-Up / Down",
+                "move-lines-up/down",
+                "Move the line, or the lines selected, up or down",
+            ),
+            (
+                "Alt-d",
+                "duplicate-lines",
+                "Copy the line, or the lines selected, below them",
+            ),
+            (
+                "Alt-j",
+                "join-lines",
+                "Join the line and the next, or the lines selected",
+            ),
+            (
+                "Ctrl-/",
+                "toggle-comment",
+                "Comment out the line or the lines selected, or uncomment them",
+            ),
+            ("Ctrl-C", "copy", "Copy the selection"),
+            ("Ctrl-K", "cut", "Cut the selection"),
+            (
+                "Ctrl-V",
+                "paste",
+                "Insert the text last copied or cut, over any selection",
+            ),
+            (
+                "Alt-v",
+                "paste-plain",
+                "Paste like Ctrl-V, keeping the indentation the text had",
+            ),
+            (
+                "Alt-y",
+                "yank-pop",
+                "Right after pasting, swap the text for the one copied or cut before",
+            ),
+            (
+                "Alt-n",
+                "cursor-next-match",
+                "Select the word, then add a cursor at each next match of it",
+            ),
+            (
+                "Alt-Shift-Down",
+                "cursor-below",
+                "Add a cursor on the line below; typing goes to every cursor",
+            ),
+            (
+                "Alt-Shift-Up",
+                "cursor-above",
+                "Add a cursor on the line above",
+            ),
+            (
+                "Click",
+                "click",
+                "Focus the window and put the cursor there, or drag to select",
+            ),
+            (
+                "Ctrl-click",
+                "add-cursor",
+                "Add a cursor there; Escape drops the others",
+            ),
+            (
+                "Middle-click",
+                "paste-primary",
+                "Put the cursor there and insert the primary selection",
+            ),
+            (
+                "Wheel",
+                "scroll",
+                "Scroll the view, faster with a modifier held",
+            ),
+        ],
+    ),
+    (
+        "Search prompt",
+        &[
+            ("Arrows", "find-next", "Jump to the next or previous match"),
+            ("Ctrl-W", "whole-word", "Toggle matching whole words only"),
+            ("Enter", "accept", "Keep the cursor at the match"),
+            ("Esc", "cancel", "Return to where the search started"),
+        ],
+    ),
+    (
+        "Windows (Ctrl-W, optionally followed by a count)",
+        &[
+            ("s", "split", "Split the window, one above the other"),
+            ("v", "vsplit", "Split the window, side by side"),
+            ("w / W", "next-window", "Focus the next or previous window"),
+            ("c / q", "close-window", "Close the window"),
+            (
+                "+ / -",
+                "resize-height",
+                "Grow or shrink the window's height by count",
+            ),
+            (
+                "> / <",
+                "resize-width",
+                "Grow or shrink the window's width by count",
+            ),
+            ("=", "equalize", "Make all windows the same size"),
+            (
+                "z",
+                "zoom",
+                "Give the window the whole screen, or restore the layout",
+            ),
+            (
+                "b",
+                "scroll-bind",
+                "Toggle scrolling together with other bound windows",
+            ),
+        ],
+    ),
+    (
+        "Extended (Ctrl-X)",
+        &[
+            ("d", "diff", "Show unsaved changes against the file on disk"),
+            (
+                "*",
+                "search-word",
+                "Search forward for the word under the cursor",
+            ),
+            (
+                "#",
+                "search-word-reverse",
+                "Search backward for the word under the cursor",
+            ),
+            (
+                "n / N",
+                "search-next",
+                "Repeat the last search forward or backward",
+            ),
+            (
+                "f / F CHAR",
+                "find-char",
+                "Move to the next or previous CHAR on the line",
+            ),
+            (
+                "t / T CHAR",
+                "till-char",
+                "Move up to the next or previous CHAR on the line",
+            ),
+            (
+                "; / ,",
+                "repeat-find-char",
+                "Repeat the last character search, the same or other way",
+            ),
+            (
+                "Down / Up",
+                "scroll-half-page",
+                "Scroll the view by half a page",
+            ),
+            ("PgDn / PgUp", "scroll-page", "Scroll the view by a page"),
+            (
+                "a",
+                "char-info",
+                "Show the code point, bytes and width of the character",
+            ),
+            ("b", "pick-buffer", "Show a buffer picked by number or name"),
+            (
+                "g",
+                "goto-line",
+                "Go to a line, or LINE:COLUMN, centering it in the view",
+            ),
+            (
+                "y",
+                "yank-line",
+                "Copy the cursor line to the register and yank selections",
+            ),
+            (
+                "p",
+                "paste-selection",
+                "Insert the text in the paste selection",
+            ),
+            (
+                "r",
+                "block-select",
+                "Select a rectangle, or switch the selection to one and back",
+            ),
+            (
+                "e",
+                "expand-selection",
+                "Select the next enclosing word, string, brackets or block",
+            ),
+            (
+                "E",
+                "shrink-selection",
+                "Go back to the selection before the last expansion",
+            ),
+            (
+                "o",
+                "options",
+                "Preview and pick a theme, flip options, save them",
+            ),
+            (
+                "l",
+                "line-numbers",
+                "Cycle the line number gutter: off, absolute, relative",
+            ),
+            (
+                "k",
+                "close-buffer",
+                "Close the buffer, asking if it has unsaved changes",
+            ),
+            (
+                "( REG",
+                "record-macro",
+                "Record keys into the macro in register REG",
+            ),
+            (")", "stop-macro", "Stop recording the macro"),
+            (
+                "@ [N] REG",
+                "play-macro",
+                "Play the macro in REG N times; @ plays the last again",
+            ),
+            (":", "command", "Run a command from the list below"),
+            ("?", "bindings", "Show this list"),
+        ],
+    ),
+    (
+        "Directory listings",
+        &[
+            (
+                "Enter",
+                "open-entry",
+                "Open the file or list the directory under the cursor",
+            ),
+            ("-", "parent", "List the directory above"),
+            (
+                "+",
+                "create",
+                "Create a file, or a directory if the name ends in /",
+            ),
+            ("r", "rename", "Rename the entry under the cursor"),
+            (
+                "d",
+                "delete",
+                "Delete the entry under the cursor, asking first",
+            ),
+            ("g", "relist", "List the directory again"),
+        ],
+    ),
+    (
+        "View mode",
+        &[
+            ("j k", "scroll", "Scroll down or up a line"),
+            ("Space b", "page", "Page down or up; f pages down too"),
+            ("d u", "half-page", "Scroll down or up half a page"),
+            ("g G", "ends", "Go to the first or last line"),
+            ("/ ?", "search", "Search forward or back"),
+            ("n N", "search-next", "Go to the next or previous match"),
+            ("v", "edit", "Leave view mode"),
+            (
+                "q",
+                "close",
+                "Close the buffer, or quit if it's the only one",
+            ),
+        ],
+    ),
+    (
+        "Modal editing (modal option)",
+        &[
+            ("h j k l", "move", "Move left, down, up or right"),
+            ("w b { }", "jump", "Move by words or paragraphs"),
+            ("0 $", "line-ends", "Go to the start or end of the line"),
+            ("gg G", "goto", "Go to a line by count, or the last"),
+            (
+                "Ctrl-D Ctrl-U",
+                "half-page",
+                "Move the cursor and the view half a page, or count lines",
+            ),
+            (
+                "zz zt zb",
+                "align",
+                "Put the cursor line at the center, top or bottom",
+            ),
+            ("i a I A", "insert", "Insert at or after the cursor or line"),
+            ("o O", "open-line", "Insert on a new line below or above"),
+            ("Esc", "normal", "Back to normal mode"),
+            ("x", "delete-char", "Delete characters"),
+            (
+                "d c y MOTION",
+                "operators",
+                "Delete, change or copy up to where a motion goes",
+            ),
+            ("dd cc yy", "lines", "Delete, change or copy lines"),
+            (
+                "d c y i/a OBJ",
+                "text-objects",
+                "Work on a word, quotes or brackets, like diw or ca(",
+            ),
+            (
+                "f t F T CHAR",
+                "find-char",
+                "Go to a character on the line, or next to it",
+            ),
+            (
+                "; ,",
+                "repeat-find",
+                "Repeat the last f or t, or the other way",
+            ),
+            ("p P", "put", "Paste after or before the cursor"),
+            ("u Ctrl-R", "undo-redo", "Undo or redo"),
+            (
+                "v",
+                "visual",
+                "Select; d, x or y cut or copy it, c changes it",
+            ),
+            (": / ?", "prompt", "Run a command or search"),
+            ("m LETTER", "mark", "Name the cursor's place after a letter"),
+            (
+                "' `",
+                "goto-mark",
+                "Go to a mark's line, or to the mark itself",
+            ),
+            (
+                "Ctrl-O Tab",
+                "jumps",
+                "Go back through the jumps, or forward",
+            ),
+            ("q REG  q", "macro", "Record a macro into REG, then stop"),
+            ("@REG  @@", "play", "Play a macro, or the last one again"),
+        ],
+    ),
+    (
+        "Language servers (lsp feature)",
+        &[
+            (
+                "F12",
+                "goto-definition",
+                "Go to the definition of the name at the cursor",
+            ),
+            (
+                "Alt-k",
+                "hover",
+                "Show what the language server says about the name at the cursor",
+            ),
+            (
+                "Alt-e",
+                "next-diagnostic",
+                "Go to the next problem the language server found, showing it",
+            ),
+        ],
+    ),
+    (
+        "File prompts",
+        &[(
+            "Tab",
+            "complete",
+            "Complete file and directory names, listing them, cycling on repeated presses",
+        )],
+    ),
+    (
+        "Commands (Ctrl-X :)",
+        &[
+            (
+                "Tab",
+                "complete",
+                "Complete the command name, or the option, theme, filetype or file after it",
+            ),
+            (
+                "NAME",
+                "by-name",
+                "Run an action or a window or extended command by its name on this page",
+            ),
+            (
+                "w [FILE]",
+                "write",
+                "Save the buffer, to FILE from now on if given",
+            ),
+            (
+                "q",
+                "quit",
+                "Quit, asking about unsaved buffers; wq or x saves first",
+            ),
+            (
+                "open FILE",
+                "open",
+                "Open FILE in a buffer, or switch to it; e for short",
+            ),
+            (
+                "set OPT[=V]",
+                "set",
+                "Set an option as the config file does, or OPT/noOPT on or off",
+            ),
+            (
+                "endings",
+                "endings",
+                "Show the line ending, LF or CRLF, or convert the buffer to the one named",
+            ),
+            (
+                "view",
+                "view",
+                "Switch view mode, paging through the buffer read-only, on or off",
+            ),
+            (
+                "readonly",
+                "readonly",
+                "Switch the buffer between read-only and editable",
+            ),
+            (
+                "explore DIR",
+                "explore",
+                "List DIR, or the directory of the file without it, to open files from",
+            ),
+            (
+                "theme NAME",
+                "theme",
+                "Show the theme, or switch to the one named",
+            ),
+            (
+                "cd [DIR]",
+                "cd",
+                "Change the working directory, home if DIR is left out",
+            ),
+            (
+                "lcd [DIR]",
+                "lcd",
+                "Change the working directory of this window only",
+            ),
+            ("pwd", "pwd", "Show the working directory of this window"),
+            (
+                "kills",
+                "kills",
+                "Pick one of the last texts copied or cut to paste",
+            ),
+            (
+                "mark LETTER",
+                "mark",
+                "Name the cursor's place after a letter",
+            ),
+            ("marks", "marks", "List the marks of this buffer"),
+            (
+                "diff [BUFFER]",
+                "diff",
+                "Show unsaved changes, or the differences from another buffer, in a buffer",
+            ),
+            (
+                "grep PATTERN",
+                "grep",
+                "List the lines of files under the working directory matching PATTERN",
+            ),
+            (
+                "blame",
+                "blame",
+                "Show the commit and author that last changed this line",
+            ),
+            (
+                "earlier [N]",
+                "earlier",
+                "Go back N states of the buffer, or a time like 30s, 5m, 2h, 1d",
+            ),
+            (
+                "later [N]",
+                "later",
+                "Go forward N states of the buffer, or by a time like 5m",
+            ),
+            ("wa", "save-all", "Save every buffer with unsaved changes"),
+            (
+                "wqa",
+                "save-all-quit",
+                "Save every buffer with unsaved changes, then quit",
+            ),
+            (
+                "autosave",
+                "autosave",
+                "Show whether autosave applies to this buffer, or set it on or off",
+            ),
+            (
+                "filetype",
+                "filetype",
+                "Show the filetype, or set it to the one named after it, or none",
+            ),
+            (
+                "%s/A/B/[a]",
+                "substitute",
+                "Replace matches of the regex A with B like Ctrl-R, or all without asking",
+            ),
+            (
+                "fill TEXT",
+                "fill",
+                "Write TEXT, repeated, over every row of the selected rectangle",
+            ),
+            ("digraphs", "digraphs", "List the digraphs Ctrl-D types"),
+            (
+                "ansi",
+                "ansi",
+                "Switch colored output between its colors and its escape sequences",
+            ),
+            ("N[:COL]", "goto", "Go to line N, or column COL of it"),
+            (
+                "!CMD",
+                "shell",
+                "Run CMD in the shell and show what it prints, or filter the selection",
+            ),
+            (
+                "%!CMD",
+                "filter",
+                "Pipe the buffer through CMD and put what it prints in its place",
+            ),
+            (
+                "eval CMD",
+                "eval",
+                "Pipe the buffer to CMD and run the :commands and keys it prints",
+            ),
+            (
+                "indent [N]",
+                "indent",
+                "Show the indentation, or set it to tabs, N spaces or auto",
+            ),
+            (
+                "reload",
+                "reload",
+                "Read the file again, keeping the cursor on lines that didn't change",
+            ),
+            (
+                "format CMD",
+                "format",
+                "Pass the text through CMD, like a formatter, and take what it prints",
+            ),
+            ("trim", "trim", "Delete the blanks at the ends of lines"),
+            (
+                "elastic",
+                "elastic",
+                "Switch this buffer between elastic and fixed tab stops",
+            ),
+            (
+                "export ...",
+                "export",
+                "Write the text for printing to the FILE given last: -n, -H, -w COLS, -l A-B",
+            ),
+            (
+                "NAME [ARGS]",
+                "user-command",
+                "Run a command defined with Editor::define_command",
+            ),
+        ],
+    ),
+];
+
+/// On/off options the options picker can flip.
+const TOGGLES: &[&str] = &[
+    "mouse",
+    "comment_leaders",
+    "auto_pairs",
+    "delete_pairs",
+    "auto_indent",
+    "smart_indent",
+    "expand_tabs",
+    "detect_indent",
+    "restore_positions",
+    "sessions",
+    "swap_files",
+    "undo_files",
+    "wrap",
+    "osc52",
+    "ansi_colors",
+    "elastic_tabs",
+    "modal",
+    "git",
+    "whitespace",
+    "trim_on_save",
+    "smooth_scroll",
+    "watch_files",
+    "backups",
+];
+
+/// An entry of the options picker.
+#[derive(Clone, Copy)]
+enum Choice {
+    Theme(&'static Theme),
+    Toggle(&'static str),
+}
+
+#[derive(Clone, Copy)]
 enum SearchDirection {
     Forward,
     Reverse,
 }
 
+/// A place the cursor jumped away from, in whichever buffer it was.
+#[derive(Clone)]
+struct Jump {
+    /// The buffer's file, or None for one without a file, told apart by its kind.
+    file: Option<PathBuf>,
+    kind: BufferKind,
+    at: Position,
+}
+
+/// Where autosave writes the buffer contents.
+#[derive(Clone, Copy, PartialEq)]
+pub enum AutosaveTarget {
+    /// The file itself, as if saved with Ctrl-S.
+    File,
+    /// A recovery copy next to the file, leaving the file untouched.
+    Recovery,
+}
+
+/// Something a key can be bound to, named as in the bindings page.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    Quit,
+    /// Gives the terminal back to the shell and stops, until the shell continues the editor.
+    Suspend,
+    /// Pages through the key bindings and commands.
+    Help,
+    Save,
+    Find,
+    FindReverse,
+    ScrollDown,
+    ScrollUp,
+    /// Moves the cursor and the view together by half the window's height.
+    HalfPageDown,
+    HalfPageUp,
+    /// Moves the cursor and the view together by the window's height.
+    FullPageDown,
+    FullPageUp,
+    Recenter,
+    Overwrite,
+    /// Starts a window command, read from the keys that follow.
+    WindowCommand,
+    /// Starts an extended command, read from the keys that follow.
+    ExtendedCommand,
+    NextBuffer,
+    PreviousBuffer,
+    /// Starts selecting from the cursor, or stops.
+    Select,
+    Copy,
+    Cut,
+    /// Inserts the text last copied, cut or yanked.
+    Paste,
+    /// Pastes without reindenting the text to the cursor line.
+    PastePlain,
+    /// Swaps the text just pasted for the one copied or cut before it.
+    YankPop,
+    /// Replaces matches of a query, asking about each.
+    Replace,
+    /// Types the character named by the two keys that follow.
+    Digraph,
+    /// Asks for a command to run, like Ctrl-X :.
+    CommandLine,
+    /// Asks for a file to open.
+    OpenFile,
+    /// Picks a file under the working directory to open, by parts of its path.
+    FindFile,
+    /// Selects the word under the cursor, or adds a cursor selecting the next match of the
+    /// selection.
+    CursorNextMatch,
+    /// Adds a cursor on the line below, or above, in the same column.
+    CursorBelow,
+    CursorAbove,
+    /// Moves to the bracket paired with the one at the cursor.
+    MatchBracket,
+    /// Goes back to where the cursor was before the last jump, like a search or going to a
+    /// line or another buffer, or forward again.
+    JumpBack,
+    JumpForward,
+    /// Names the cursor's place after the letter typed next.
+    SetMark,
+    /// Goes to the place named by the letter typed next.
+    GotoMark,
+    /// Moves the cursor line, or the lines selected, up or down past the line next to them.
+    MoveLinesUp,
+    MoveLinesDown,
+    /// Puts a copy of the cursor line, or the lines selected, after them.
+    DuplicateLines,
+    /// Joins the cursor line and the next, or the lines selected.
+    JoinLines,
+    /// Comments out the cursor line, or the lines selected, or takes their comments off.
+    ToggleComment,
+    /// Offers words from the open buffers to complete the one before the cursor with.
+    Complete,
+    /// Asks the language server where the name at the cursor is defined, and goes there.
+    GotoDefinition,
+    /// Shows what the language server says about the name at the cursor.
+    Hover,
+    /// Moves to the next diagnostic from the language server, showing its message.
+    NextDiagnostic,
+    Move(Motion),
+}
+
+impl Action {
+    pub const ALL: [Action; 58] = [
+        Action::Quit,
+        Action::Suspend,
+        Action::Help,
+        Action::Save,
+        Action::Find,
+        Action::FindReverse,
+        Action::ScrollDown,
+        Action::ScrollUp,
+        Action::HalfPageDown,
+        Action::HalfPageUp,
+        Action::FullPageDown,
+        Action::FullPageUp,
+        Action::Recenter,
+        Action::Overwrite,
+        Action::WindowCommand,
+        Action::ExtendedCommand,
+        Action::NextBuffer,
+        Action::PreviousBuffer,
+        Action::Select,
+        Action::Copy,
+        Action::Cut,
+        Action::Paste,
+        Action::PastePlain,
+        Action::YankPop,
+        Action::Replace,
+        Action::Digraph,
+        Action::CommandLine,
+        Action::OpenFile,
+        Action::FindFile,
+        Action::CursorNextMatch,
+        Action::CursorBelow,
+        Action::CursorAbove,
+        Action::MatchBracket,
+        Action::JumpBack,
+        Action::JumpForward,
+        Action::SetMark,
+        Action::GotoMark,
+        Action::MoveLinesUp,
+        Action::MoveLinesDown,
+        Action::DuplicateLines,
+        Action::JoinLines,
+        Action::ToggleComment,
+        Action::Complete,
+        Action::GotoDefinition,
+        Action::Hover,
+        Action::NextDiagnostic,
+        Action::Move(Motion::Up),
+        Action::Move(Motion::Down),
+        Action::Move(Motion::Left),
+        Action::Move(Motion::Right),
+        Action::Move(Motion::PgUp),
+        Action::Move(Motion::PgDn),
+        Action::Move(Motion::Home),
+        Action::Move(Motion::End),
+        Action::Move(Motion::WordBackward),
+        Action::Move(Motion::WordForward),
+        Action::Move(Motion::ParagraphUp),
+        Action::Move(Motion::ParagraphDown),
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Quit => "quit",
+            Action::Suspend => "suspend",
+            Action::Help => "help",
+            Action::Save => "save",
+            Action::Find => "find",
+            Action::FindReverse => "find-reverse",
+            Action::ScrollDown => "scroll-down",
+            Action::ScrollUp => "scroll-up",
+            Action::HalfPageDown => "half-page-down",
+            Action::HalfPageUp => "half-page-up",
+            Action::FullPageDown => "full-page-down",
+            Action::FullPageUp => "full-page-up",
+            Action::Recenter => "recenter",
+            Action::Overwrite => "overwrite",
+            Action::WindowCommand => "window",
+            Action::ExtendedCommand => "extended",
+            Action::NextBuffer => "next-buffer",
+            Action::PreviousBuffer => "previous-buffer",
+            Action::Select => "select",
+            Action::Copy => "copy",
+            Action::Cut => "cut",
+            Action::Paste => "paste",
+            Action::PastePlain => "paste-plain",
+            Action::YankPop => "yank-pop",
+            Action::Replace => "replace",
+            Action::Digraph => "digraph",
+            Action::CommandLine => "command-line",
+            Action::OpenFile => "open-file",
+            Action::FindFile => "find-file",
+            Action::CursorNextMatch => "cursor-next-match",
+            Action::CursorBelow => "cursor-below",
+            Action::CursorAbove => "cursor-above",
+            Action::MatchBracket => "match-bracket",
+            Action::JumpBack => "jump-back",
+            Action::JumpForward => "jump-forward",
+            Action::SetMark => "set-mark",
+            Action::GotoMark => "goto-mark",
+            Action::MoveLinesUp => "move-lines-up",
+            Action::MoveLinesDown => "move-lines-down",
+            Action::DuplicateLines => "duplicate-lines",
+            Action::JoinLines => "join-lines",
+            Action::ToggleComment => "toggle-comment",
+            Action::Complete => "complete",
+            Action::GotoDefinition => "goto-definition",
+            Action::Hover => "hover",
+            Action::NextDiagnostic => "next-diagnostic",
+            Action::Move(Motion::Up) => "up",
+            Action::Move(Motion::Down) => "down",
+            Action::Move(Motion::Left) => "left",
+            Action::Move(Motion::Right) => "right",
+            Action::Move(Motion::PgUp) => "page-up",
+            Action::Move(Motion::PgDn) => "page-down",
+            Action::Move(Motion::Home) => "home",
+            Action::Move(Motion::End) => "end",
+            Action::Move(Motion::WordBackward) => "word-left",
+            Action::Move(Motion::WordForward) => "word-right",
+            Action::Move(Motion::ParagraphUp) => "paragraph-up",
+            Action::Move(Motion::ParagraphDown) => "paragraph-down",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Action> {
+        Action::ALL
+            .iter()
+            .copied()
+            .find(|action| action.name() == name)
+    }
+}
+
+/// Keys bound to each action until they're rebound.
+const DEFAULT_KEY_BINDINGS: &[(Key, Action)] = &[
+    (Key::Control('Q'), Action::Quit),
+    (Key::Control('Z'), Action::Suspend),
+    (Key::Function(1), Action::Help),
+    (Key::Control('S'), Action::Save),
+    (Key::Control('F'), Action::Find),
+    (Key::Control('G'), Action::FindReverse),
+    (Key::Control('E'), Action::ScrollDown),
+    (Key::Control('Y'), Action::ScrollUp),
+    (Key::Control('N'), Action::HalfPageDown),
+    (Key::Control('U'), Action::HalfPageUp),
+    (Key::Control('T'), Action::FullPageDown),
+    (Key::Control('B'), Action::FullPageUp),
+    (Key::Control('L'), Action::Recenter),
+    (Key::Insert, Action::Overwrite),
+    (Key::Control('W'), Action::WindowCommand),
+    (Key::Control('X'), Action::ExtendedCommand),
+    (Key::Control('P'), Action::NextBuffer),
+    (Key::Alt('p'), Action::PreviousBuffer),
+    (Key::Control('O'), Action::OpenFile),
+    (Key::Alt('f'), Action::FindFile),
+    (Key::Control('@'), Action::Select),
+    (Key::Control('C'), Action::Copy),
+    (Key::Control('K'), Action::Cut),
+    (Key::Control('V'), Action::Paste),
+    (Key::Alt('v'), Action::PastePlain),
+    (Key::Alt('y'), Action::YankPop),
+    (Key::Control('R'), Action::Replace),
+    (Key::Control('D'), Action::Digraph),
+    (Key::Alt('x'), Action::CommandLine),
+    (Key::Alt('/'), Action::Complete),
+    (Key::Alt('n'), Action::CursorNextMatch),
+    (Key::Control(']'), Action::MatchBracket),
+    (Key::Alt('o'), Action::JumpBack),
+    (Key::Alt('i'), Action::JumpForward),
+    (Key::Alt('m'), Action::SetMark),
+    (Key::Alt('g'), Action::GotoMark),
+    (Key::Alt('d'), Action::DuplicateLines),
+    (Key::Alt('j'), Action::JoinLines),
+    // What terminals send for Ctrl-/.
+    (Key::Control('_'), Action::ToggleComment),
+    (Key::Function(12), Action::GotoDefinition),
+    (Key::Alt('k'), Action::Hover),
+    (Key::Alt('e'), Action::NextDiagnostic),
+    (
+        Key::Modified(
+            Motion::Down,
+            Modifiers(Modifiers::ALT.0 | Modifiers::SHIFT.0),
+        ),
+        Action::CursorBelow,
+    ),
+    (
+        Key::Modified(Motion::Up, Modifiers(Modifiers::ALT.0 | Modifiers::SHIFT.0)),
+        Action::CursorAbove,
+    ),
+    (Key::Move(Motion::Up), Action::Move(Motion::Up)),
+    (Key::Move(Motion::Down), Action::Move(Motion::Down)),
+    (Key::Move(Motion::Left), Action::Move(Motion::Left)),
+    (Key::Move(Motion::Right), Action::Move(Motion::Right)),
+    (Key::Move(Motion::PgUp), Action::Move(Motion::PgUp)),
+    (Key::Move(Motion::PgDn), Action::Move(Motion::PgDn)),
+    (Key::Move(Motion::Home), Action::Move(Motion::Home)),
+    (Key::Move(Motion::End), Action::Move(Motion::End)),
+    (
+        Key::Modified(Motion::Left, Modifiers::CTRL),
+        Action::Move(Motion::WordBackward),
+    ),
+    (
+        Key::Modified(Motion::Right, Modifiers::CTRL),
+        Action::Move(Motion::WordForward),
+    ),
+    (
+        Key::Modified(Motion::Up, Modifiers::CTRL),
+        Action::Move(Motion::ParagraphUp),
+    ),
+    (
+        Key::Modified(Motion::Down, Modifiers::CTRL),
+        Action::Move(Motion::ParagraphDown),
+    ),
+    // Alt with the sideways arrows does the same, as it does on macOS.
+    (
+        Key::Modified(Motion::Left, Modifiers::ALT),
+        Action::Move(Motion::WordBackward),
+    ),
+    (
+        Key::Modified(Motion::Right, Modifiers::ALT),
+        Action::Move(Motion::WordForward),
+    ),
+    (
+        Key::Modified(Motion::Up, Modifiers::ALT),
+        Action::MoveLinesUp,
+    ),
+    (
+        Key::Modified(Motion::Down, Modifiers::ALT),
+        Action::MoveLinesDown,
+    ),
+];
+
+/// One step of a user-defined command.
+#[derive(Clone)]
+pub enum CommandStep {
+    /// A command line as typed after Ctrl-X :. `$1` to `$9` are replaced by the arguments the
+    /// user command was run with, `$*` by all of them and `$$` by a dollar sign.
+    Command(String),
+    /// Keys handled as if typed, including the ones answering any prompts they bring up.
+    Keys(Vec<Key>),
+}
+
+/// When autosave fires. Every trigger is off by default.
+#[derive(Clone)]
+pub struct Autosave {
+    /// Save after this long without a keypress.
+    pub idle: Option<Duration>,
+    /// Save after this many modifications.
+    pub modifications: Option<usize>,
+    /// Save when the terminal loses focus.
+    pub focus_lost: bool,
+    /// Save when another buffer is shown in its place.
+    pub buffer_switch: bool,
+    pub target: AutosaveTarget,
+}
+
+impl Default for Autosave {
+    fn default() -> Self {
+        Self {
+            idle: None,
+            modifications: None,
+            focus_lost: false,
+            buffer_switch: false,
+            target: AutosaveTarget::File,
+        }
+    }
+}
+
 pub struct Editor {
     terminal: Terminal,
+    /// The buffer shown in the windows.
     buffer: Buffer,
+    /// Every open buffer in the order they were opened, each with the value of `changes` it
+    /// was last autosaved at. The slot of the shown buffer holds a placeholder meanwhile.
+    buffers: Vec<(Buffer, usize)>,
+    current_buffer: usize,
     status_msg: String,
     status_msg_ts: Instant,
-    quit_count: usize,
+    status_msg_timeout: Duration,
+    /// Set once the editor is on its way out.
+    quit: bool,
+    /// Standard output, when it's a pipe, for the text read from standard input or else the
+    /// buffer shown to go to on quitting.
+    output: Option<File>,
+    insert_mappings: Vec<(String, Vec<Key>)>,
+    key_bindings: Vec<(Key, Action)>,
+    user_commands: Vec<(String, Vec<CommandStep>)>,
+    /// Keys running command lines, like the name of a user command, ahead of any action bound
+    /// to them.
+    command_keys: Vec<(Key, String)>,
+    /// Keys of a user command or macro still to be handled, read ahead of the terminal.
+    replayed_keys: VecDeque<Key>,
+    /// Register and keys so far of the macro being recorded.
+    recording: Option<(char, Vec<Key>)>,
+    /// How many keys were recorded before the one being handled, so that the keys stopping a
+    /// recording stay out of it.
+    recorded_before: usize,
+    macros: Vec<(char, Vec<Key>)>,
+    /// Register of the macro played last, for playing it again.
+    last_macro: Option<char>,
+    command_depth: usize,
+    hooks: Hooks,
+    autosave: Autosave,
+    autosaved_at: usize,
+    windows: Vec<Window>,
+    layout: Layout,
+    zoomed: Option<Layout>,
+    separators: Vec<Rect>,
+    active: usize,
+    overwrite: bool,
+    recenter: usize,
+    search: Pattern,
+    /// Character last looked for on the line, forward or not, and whether to stop short of it.
+    char_search: Option<(char, bool, bool)>,
+    wheel_step: usize,
+    wheel_fast_multiplier: usize,
+    comment_leaders: bool,
+    cwd: PathBuf,
+    border_style: BorderStyle,
+    /// Keys of a chord typed so far, while the rest of it is awaited.
+    pending_keys: String,
+    /// Pattern whose matches are highlighted, while a search is typed.
+    search_highlight: Option<Pattern>,
+    restore_positions: bool,
+    sessions: bool,
+    swap_files: bool,
+    undo_files: bool,
+    auto_pairs: bool,
+    pair_deletion: bool,
+    auto_indent: bool,
+    smart_indent: bool,
+    /// Spaces Tab types instead of a tab, where neither the file nor its type say.
+    indent_spaces: Option<usize>,
+    indent_width: usize,
+    detect_indent: bool,
+    last_key_at: Instant,
+    tab_stop: usize,
+    text_width: Option<usize>,
+    wrap: bool,
+    /// Whether tabs, no-break spaces and blanks ending lines are drawn so they stand out.
+    show_whitespace: bool,
+    /// Whether the blanks ending lines are deleted before saving.
+    trim_on_save: bool,
+    /// Whether files open with elastic tab stops.
+    elastic_tabs: bool,
+    scrolloff: usize,
+    sidescrolloff: usize,
+    /// Lines the half-page scrolls move by, or 0 for half the window.
+    scroll: usize,
+    /// Whether scrolling by pages goes through the lines in between instead of jumping.
+    smooth_scroll: bool,
+    /// Whether the file shown is reloaded, or asked about, when changed on disk.
+    watch_files: bool,
+    /// When the file shown was last looked at for changes on disk, or None to look next time.
+    disk_checked_at: Option<Instant>,
+    /// Whether saving copies the file as it was to its name with a `~` after it.
+    backups: bool,
+    line_numbers: LineNumbers,
+    yank_selections: Vec<Selection>,
+    /// Text last copied, cut or yanked, or picked from the kill ring since, for pasting.
+    register: String,
+    /// Whether the register holds a rectangle, a line of text per row.
+    register_block: bool,
+    /// Texts copied, cut or yanked before, newest first.
+    kills: KillRing,
+    /// Where the register last went in as text, the buffer's count of changes then and the
+    /// index of the kill it was, for yank-pop to swap it for an older one.
+    last_paste: Option<(Region, usize, usize)>,
+    /// Places jumped away from, oldest first.
+    jumps: Vec<Jump>,
+    /// Index in `jumps` of the place gone back to, or their count when not going through them.
+    jump_idx: usize,
+    /// Whether yanks also go to the system clipboard through the terminal, with OSC 52.
+    osc52: bool,
+    /// Whether files with color escape sequences open showing the colors.
+    ansi_colors: bool,
+    /// Fixed end of the selection, with the cursor at the other, and whether it was started
+    /// with Shift or the mouse, so that moving without them ends it.
+    anchor: Option<(Position, bool)>,
+    /// Selections that expanding the selection went through, the one it made last, for
+    /// shrinking to go back through.
+    expansions: Vec<Region>,
+    /// Cursors besides the main one, each with the anchor of its own selection. Typing,
+    /// deleting and moving along lines happen at all of them.
+    cursors: Vec<(Cursor, Option<(Position, bool)>)>,
+    /// Whether the selection is the rectangle with the anchor and the cursor at its corners.
+    block: bool,
+    paste_selection: Selection,
+    theme: &'static Theme,
+    /// Colors the terminal is taken to show.
+    colors: ColorDepth,
+    /// The theme as drawn, with its colors brought down to those.
+    palette: &'static Theme,
+    mouse: bool,
+    /// Encoding files are read in, instead of the one they look to be in.
+    encoding: Option<Encoding>,
+    /// Whether files open read-only, and whether in view mode too.
+    open_read_only: (bool, bool),
+    /// Vi-like modes keys go through first, when they're on.
+    modal: Option<Modal>,
+    /// Lines drawn over the windows, from the message bar up, like the file finder's.
+    overlay: Vec<String>,
+    /// Lines drawn over the text next to the cursor, like the words to complete with, with
+    /// how wide they are and how many columns left of the cursor they start.
+    popup: (Vec<String>, usize, usize),
+    /// What git says about the file shown, or None with the `git` option off.
+    git: Option<git::Tracker>,
+    #[cfg(feature = "lsp")]
+    lsp: lsp::Servers,
+    /// Request to a language server awaiting its answer: the filetype of the server, the id
+    /// and what was asked.
+    #[cfg(feature = "lsp")]
+    lsp_request: Option<(String, usize, lsp::Request)>,
 }
 
 impl Editor {
     pub fn new() -> Result<Self> {
-        Ok(Self {
+        let colors = ColorDepth::detect();
+        let mut editor = Self {
             terminal: Terminal::new()?,
             buffer: Buffer::new(),
+            buffers: vec![(Buffer::new(), 0)],
+            current_buffer: 0,
             status_msg: String::new(),
             status_msg_ts: Instant::now(),
-            quit_count: TOTAL_QUIT_COUNT,
-        })
+            status_msg_timeout: STATUS_TIMEOUT,
+            quit: false,
+            output: None,
+            insert_mappings: Vec::new(),
+            key_bindings: DEFAULT_KEY_BINDINGS.to_vec(),
+            user_commands: Vec::new(),
+            command_keys: Vec::new(),
+            replayed_keys: VecDeque::new(),
+            recording: None,
+            recorded_before: 0,
+            macros: Vec::new(),
+            last_macro: None,
+            command_depth: 0,
+            hooks: Hooks::default(),
+            autosave: Autosave::default(),
+            autosaved_at: 0,
+            windows: vec![Window::default()],
+            layout: Layout::new(0),
+            zoomed: None,
+            separators: Vec::new(),
+            active: 0,
+            overwrite: false,
+            recenter: 0,
+            search: Pattern::default(),
+            char_search: None,
+            wheel_step: WHEEL_STEP,
+            wheel_fast_multiplier: WHEEL_FAST_MULTIPLIER,
+            comment_leaders: true,
+            cwd: std::env::current_dir()?,
+            border_style: BorderStyle::Bar,
+            pending_keys: String::new(),
+            search_highlight: None,
+            restore_positions: true,
+            sessions: true,
+            swap_files: true,
+            undo_files: false,
+            auto_pairs: true,
+            pair_deletion: true,
+            auto_indent: true,
+            smart_indent: true,
+            indent_spaces: None,
+            indent_width: 4,
+            detect_indent: true,
+            last_key_at: Instant::now(),
+            tab_stop: TAB_STOP,
+            text_width: None,
+            wrap: false,
+            show_whitespace: false,
+            trim_on_save: false,
+            elastic_tabs: false,
+            scrolloff: 0,
+            sidescrolloff: 0,
+            scroll: 0,
+            smooth_scroll: false,
+            watch_files: true,
+            disk_checked_at: None,
+            backups: false,
+            line_numbers: LineNumbers::Off,
+            yank_selections: vec![Selection::Clipboard],
+            register: String::new(),
+            register_block: false,
+            kills: KillRing::new(KILL_RING_SIZE),
+            last_paste: None,
+            jumps: Vec::new(),
+            jump_idx: 0,
+            osc52: false,
+            ansi_colors: true,
+            anchor: None,
+            expansions: Vec::new(),
+            cursors: Vec::new(),
+            block: false,
+            paste_selection: Selection::Clipboard,
+            theme: &THEMES[0],
+            colors,
+            palette: THEMES[0].for_colors(colors),
+            mouse: false,
+            encoding: None,
+            open_read_only: (false, false),
+            modal: None,
+            overlay: Vec::new(),
+            popup: (Vec::new(), 0, 0),
+            git: Some(git::Tracker::default()),
+            #[cfg(feature = "lsp")]
+            lsp: lsp::Servers::default(),
+            #[cfg(feature = "lsp")]
+            lsp_request: None,
+        };
+        editor.terminal.report_paste(true);
+        editor.arrange_windows();
+        Ok(editor)
+    }
+
+    pub fn rows(&self) -> usize {
+        self.windows[self.active]
+            .rect
+            .height
+            .saturating_sub(STATUS_HEIGHT)
+    }
+
+    /// Columns of text the focused window shows, leaving out the gutter.
+    pub fn cols(&self) -> usize {
+        let width = self.windows[self.active].rect.width;
+        width - self.gutter_width(width)
+    }
+
+    /// Columns of the line number gutter in a window `width` columns wide. Windows too narrow
+    /// to fit any text beside it, and the home screen, go without one.
+    fn gutter_width(&self, width: usize) -> usize {
+        let gutter = self.line_numbers.width(self.buffer.line_count());
+        if self.buffer.is_empty() || gutter >= width {
+            0
+        } else {
+            gutter
+        }
+    }
+
+    pub fn keep_alive(&self) -> bool {
+        !self.quit
+    }
+
+    pub fn set_status(&mut self, msg: String) {
+        self.set_status_timeout(msg, STATUS_TIMEOUT);
+    }
+
+    /// Shows `msg` in the message bar for `timeout` instead of the usual few seconds.
+    pub fn set_status_timeout(&mut self, msg: String, timeout: Duration) {
+        self.status_msg = msg;
+        self.status_msg_ts = Instant::now();
+        self.status_msg_timeout = timeout;
+    }
+
+    /// Binds `action` to `keys` instead of the keys it had, taking them away from any other
+    /// action they were bound to.
+    pub fn bind_keys(&mut self, action: Action, keys: Vec<Key>) {
+        self.key_bindings
+            .retain(|(key, bound)| *bound != action && !keys.contains(key));
+        self.key_bindings
+            .extend(keys.into_iter().map(|key| (key, action)));
+    }
+
+    /// Shows the keys for the most basic actions in the message bar.
+    pub fn show_help(&mut self) {
+        self.set_status(format!(
+            "HELP: {} = save | {} = find | {} = quit",
+            self.action_keys(Action::Save),
+            self.action_keys(Action::Find),
+            self.action_keys(Action::Quit)
+        ));
+    }
+
+    /// Maps a sequence of typed characters to other keys, like `jj` to Escape. The typed
+    /// characters are held back until the sequence resolves or times out.
+    pub fn map_insert(&mut self, lhs: &str, rhs: Vec<Key>) {
+        self.insert_mappings.retain(|(mapped, _)| mapped != lhs);
+        if !lhs.is_empty() {
+            self.insert_mappings.push((lhs.to_string(), rhs));
+        }
+    }
+
+    /// Defines a command for the command line that runs `steps` in order, replacing any user
+    /// command of the same name. Built-in commands can't be redefined.
+    pub fn define_command(&mut self, name: &str, steps: Vec<CommandStep>) {
+        self.user_commands.retain(|(defined, _)| defined != name);
+        if !name.is_empty() && !steps.is_empty() {
+            self.user_commands.push((name.to_string(), steps));
+        }
+    }
+
+    /// Has `keys` run the command line `command`, like the name of a user command that needn't
+    /// be defined yet, in place of whatever they did before.
+    pub fn bind_command(&mut self, command: &str, keys: Vec<Key>) {
+        self.command_keys.retain(|(bound, _)| !keys.contains(bound));
+        self.command_keys
+            .extend(keys.into_iter().map(|key| (key, command.to_string())));
+    }
+
+    /// Runs `steps` whenever `event` happens, or with a `pattern` only when it matches the
+    /// event's subject: the file's full path for buffer events and `CursorMove`, like `*.rs`,
+    /// the filetype's name for `FileType`, like `Rust`, and the mode's for `ModeChange`, like
+    /// `INSERT`. The subject is passed to the steps as their arguments, followed by the mode
+    /// left for `ModeChange`. `Resize` passes the new size instead, and `CursorMove` the line
+    /// and column the cursor went to.
+    pub fn add_hook(&mut self, event: HookEvent, pattern: Option<&str>, steps: Vec<CommandStep>) {
+        self.hooks.add(event, pattern, steps);
+    }
+
+    pub fn autosave_settings(&self) -> &Autosave {
+        &self.autosave
+    }
+
+    pub fn set_autosave(&mut self, autosave: Autosave) {
+        self.terminal.report_focus(autosave.focus_lost);
+        self.autosave = autosave;
+    }
+
+    pub fn set_mouse(&mut self, enable: bool) {
+        self.terminal.report_mouse(enable);
+        self.mouse = enable;
+    }
+
+    /// How long Escape waits for the rest of a sequence before counting as a key of its own,
+    /// 50 milliseconds by default. Over slow connections, longer keeps Alt keys whole.
+    pub fn set_escape_timeout(&mut self, timeout: Duration) {
+        self.terminal.set_escape_timeout(timeout);
+    }
+
+    pub fn set_theme(&mut self, theme: &'static Theme) {
+        self.theme = theme;
+        self.palette = theme.for_colors(self.colors);
+    }
+
+    /// Colors the terminal is taken to show, instead of the ones it was found to. Themes with
+    /// more are drawn in the nearest of those.
+    pub fn set_colors(&mut self, colors: ColorDepth) {
+        self.colors = colors;
+        self.set_theme(self.theme);
+    }
+
+    pub fn wheel_scroll(&self) -> (usize, usize) {
+        (self.wheel_step, self.wheel_fast_multiplier)
+    }
+
+    /// Lines scrolled per notch of the mouse wheel, and how many times that when a modifier
+    /// key is held.
+    pub fn set_wheel_scroll(&mut self, step: usize, fast_multiplier: usize) {
+        self.wheel_step = step;
+        self.wheel_fast_multiplier = fast_multiplier;
+    }
+
+    /// Whether Enter inside a comment continues it on the new line. On by default.
+    pub fn set_comment_leaders(&mut self, enable: bool) {
+        self.comment_leaders = enable;
+    }
+
+    pub fn set_border_style(&mut self, style: BorderStyle) {
+        self.border_style = style;
+    }
+
+    pub fn auto_pairs(&self) -> (bool, bool) {
+        (self.auto_pairs, self.pair_deletion)
+    }
+
+    /// Whether typed brackets and double quotes get their closer inserted along with them, and
+    /// Enter between a pair of brackets opens an indented line for their contents. With
+    /// `delete_pairs`, deleting an opener also deletes its closer if nothing's between them.
+    /// On by default.
+    pub fn set_auto_pairs(&mut self, enable: bool, delete_pairs: bool) {
+        self.auto_pairs = enable;
+        self.pair_deletion = delete_pairs;
+    }
+
+    pub fn auto_indent(&self) -> (bool, bool) {
+        (self.auto_indent, self.smart_indent)
+    }
+
+    /// Whether Enter starts the new line with the indentation of the one it breaks, and with
+    /// `smart` another level after an opening brace or the like, as the filetype has it.
+    /// Pastes are left as they are either way. On by default.
+    pub fn set_auto_indent(&mut self, enable: bool, smart: bool) {
+        self.auto_indent = enable;
+        self.smart_indent = smart;
+    }
+
+    pub fn indent(&self) -> (bool, usize) {
+        (self.indent_spaces.is_some(), self.indent_width)
+    }
+
+    /// Whether Tab types `width` spaces, and Backspace deletes back to the previous multiple
+    /// of them in indentation, rather than a tab. Only for files whose indentation isn't told
+    /// by what's in them or their filetype. Off by default, with a width of 4.
+    pub fn set_indent(&mut self, expand_tabs: bool, width: usize) {
+        self.indent_width = width.max(1);
+        self.indent_spaces = Some(self.indent_width).filter(|_| expand_tabs);
+        self.buffer.set_indent_spaces(self.indent_spaces);
+        for (buffer, _) in &mut self.buffers {
+            buffer.set_indent_spaces(self.indent_spaces);
+        }
+    }
+
+    /// Whether opening a file finds what indentation it uses, for Tab to type the same. On by
+    /// default.
+    pub fn set_detect_indent(&mut self, detect: bool) {
+        self.detect_indent = detect;
+    }
+
+    /// Columns between the tab stops that tabs are expanded to. 8 by default.
+    pub fn set_tab_stop(&mut self, tab_stop: usize) {
+        self.tab_stop = tab_stop.max(1);
+        self.buffer.set_tab_stop(self.tab_stop);
+        for (buffer, _) in &mut self.buffers {
+            buffer.set_tab_stop(self.tab_stop);
+        }
+    }
+
+    /// Columns lines are meant to fit in. Anything past them is highlighted, and the status
+    /// bar counts the lines that don't fit. Off by default.
+    pub fn set_text_width(&mut self, text_width: Option<usize>) {
+        self.text_width = text_width;
+    }
+
+    /// Whether long lines wrap onto further rows of the window rather than scrolling it
+    /// sideways. Off by default.
+    pub fn set_wrap(&mut self, wrap: bool) {
+        self.wrap = wrap;
+        self.buffer.set_wrap(wrap);
+        for (buffer, _) in &mut self.buffers {
+            buffer.set_wrap(wrap);
+        }
+    }
+
+    /// Lines cells ending in tabs up in columns across neighbouring lines, in every buffer and
+    /// the files opened from now on. Off by default; `:elastic` switches a single buffer.
+    pub fn set_elastic_tabs(&mut self, elastic: bool) {
+        self.elastic_tabs = elastic;
+        self.buffer.set_elastic_tabs(elastic);
+        for (buffer, _) in &mut self.buffers {
+            buffer.set_elastic_tabs(elastic);
+        }
+    }
+
+    /// Lines kept in view above and below the cursor, scrolling early to show them, where
+    /// the buffer and the window allow. 0 by default.
+    pub fn set_scrolloff(&mut self, lines: usize) {
+        self.scrolloff = lines;
+        self.apply_scroll_margins();
+    }
+
+    /// Columns kept in view left and right of the cursor when lines scroll sideways. 0 by
+    /// default.
+    pub fn set_sidescrolloff(&mut self, cols: usize) {
+        self.sidescrolloff = cols;
+        self.apply_scroll_margins();
+    }
+
+    /// Lines the half-page scrolls, like Ctrl-U, move the cursor and the view by. 0, the
+    /// default, moves half the window's height.
+    pub fn set_scroll(&mut self, lines: usize) {
+        self.scroll = lines;
+    }
+
+    /// Whether scrolling by pages and half pages draws a few frames on the way, so the eye
+    /// can follow. Off by default.
+    pub fn set_smooth_scroll(&mut self, enable: bool) {
+        self.smooth_scroll = enable;
+    }
+
+    /// Whether the file shown is looked at every second for changes made by other programs.
+    /// A buffer without unsaved changes is reloaded quietly; otherwise the choice is between
+    /// reloading, keeping the buffer, and seeing the differences first. On by default.
+    pub fn set_watch_files(&mut self, enable: bool) {
+        self.watch_files = enable;
+        self.disk_checked_at = None;
+    }
+
+    /// Whether saving over a file first copies it as it was to its name with a `~` after it,
+    /// replacing the backup made by the save before. Off by default.
+    pub fn set_backups(&mut self, enable: bool) {
+        self.backups = enable;
+    }
+
+    fn apply_scroll_margins(&mut self) {
+        let (lines, cols) = (self.scrolloff, self.sidescrolloff);
+        self.buffer.set_scroll_margins(lines, cols);
+        for (buffer, _) in &mut self.buffers {
+            buffer.set_scroll_margins(lines, cols);
+        }
+    }
+
+    /// Whether a gutter of line numbers is shown left of the text, and what numbers. Off by
+    /// default.
+    pub fn set_line_numbers(&mut self, line_numbers: LineNumbers) {
+        self.line_numbers = line_numbers;
+    }
+
+    /// Steps the line number gutter through off, absolute and relative.
+    fn cycle_line_numbers(&mut self) {
+        self.line_numbers = match self.line_numbers {
+            LineNumbers::Off => LineNumbers::Absolute,
+            LineNumbers::Absolute => LineNumbers::Relative,
+            LineNumbers::Relative => LineNumbers::Off,
+        };
+        self.set_status(format!("Line numbers: {}", self.line_numbers.name()));
+    }
+
+    /// Selections that yanked text goes to, and the one pasted from. Both are the clipboard by
+    /// default; the middle mouse button always pastes the primary selection.
+    pub fn set_clipboard(&mut self, yank: Vec<Selection>, paste: Selection) {
+        self.yank_selections = yank;
+        self.paste_selection = paste;
+    }
+
+    /// Whether yanked text also goes to the system clipboard through an OSC 52 escape
+    /// sequence, for terminals that support it. Off by default.
+    pub fn set_osc52(&mut self, enable: bool) {
+        self.osc52 = enable;
+    }
+
+    /// Whether files with color escape sequences in them, like saved compiler output, open
+    /// read-only showing the text in those colors. On by default; `:ansi` switches a buffer
+    /// between the colors and the escape sequences.
+    pub fn set_ansi_colors(&mut self, enable: bool) {
+        self.ansi_colors = enable;
+    }
+
+    /// Has files read in `encoding`, or in the one they look to be in without one. The file in
+    /// the buffer is read in it again, or with unsaved changes, saved in it.
+    pub fn set_encoding(&mut self, encoding: Option<Encoding>) {
+        self.encoding = encoding;
+        let (Some(encoding), Some(path)) = (encoding, self.buffer.filename().clone()) else {
+            return;
+        };
+        if !self.buffer.kind().is_file_backed() {
+            return;
+        }
+        if self.buffer.is_dirty() || !path.exists() {
+            self.buffer.set_encoding(encoding);
+            return self.set_status(format!("Saving in {}", encoding.name()));
+        }
+        match self.read_again(&path) {
+            Ok(()) => self.set_status(format!("Read in {}", encoding.name())),
+            Err(err) => self.set_status(format!("Can't read it again! I/O error: {}", err)),
+        }
+    }
+
+    /// Has the files opened from now on read-only, as `milo -R` does, and with `view`, in view
+    /// mode too, paging through them with single keys like a pager.
+    pub fn set_open_read_only(&mut self, read_only: bool, view: bool) {
+        self.open_read_only = (read_only || view, view);
+    }
+
+    /// Turns vi-like modal editing on, starting in normal mode, or off for the usual modeless
+    /// keys.
+    pub fn set_modal(&mut self, enable: bool) {
+        if enable != self.modal.is_some() {
+            self.modal = enable.then(Modal::default);
+        }
+    }
+
+    /// Whether changes since the last commit are marked in the gutter and the branch shown in
+    /// the status bar, for files in a git repository. On by default.
+    pub fn set_git(&mut self, enable: bool) {
+        if enable != self.git.is_some() {
+            self.git = enable.then(git::Tracker::default);
+        }
+    }
+
+    /// Whether tabs are drawn as `»`, no-break spaces as `␣` and blanks at the ends of lines
+    /// in a color of their own, each in the theme's color for it. Off by default.
+    pub fn set_show_whitespace(&mut self, enable: bool) {
+        self.show_whitespace = enable;
+    }
+
+    /// Whether the blanks at the ends of lines are trimmed before each save, as `:trim` does,
+    /// ahead of any `BufWritePre` hooks. Off by default.
+    pub fn set_trim_on_save(&mut self, enable: bool) {
+        self.trim_on_save = enable;
+    }
+
+    pub fn clipboard_settings(&self) -> (&[Selection], Selection) {
+        (&self.yank_selections, self.paste_selection)
+    }
+
+    /// Whether files reopen with the cursor where it was when they were last edited. On by
+    /// default.
+    pub fn set_restore_positions(&mut self, enable: bool) {
+        self.restore_positions = enable;
+    }
+
+    /// Whether the open files and the windows are saved on quitting, for `milo --restore` to
+    /// bring back when started in the same directory. On by default.
+    pub fn set_sessions(&mut self, enable: bool) {
+        self.sessions = enable;
+    }
+
+    /// Whether unsaved changes are kept in a swap file next to the file, written whenever
+    /// typing pauses, and offered back when the file is opened after a crash. On by default.
+    pub fn set_swap_files(&mut self, enable: bool) {
+        self.swap_files = enable;
+    }
+
+    /// Whether each file's history is saved next to it along with the file, so `:earlier`
+    /// reaches back past the time it was opened. Off by default.
+    pub fn set_undo_files(&mut self, enable: bool) {
+        self.undo_files = enable;
+    }
+
+    /// Waits for the next event and handles it. Returns early, without any key, when the
+    /// screen needs redrawing: after a resize, or when the status message is due to go away.
+    pub fn process_keypress(&mut self) -> Result<()> {
+        self.poll_sources();
+        let wait = self
+            .next_deadline()
+            .saturating_duration_since(Instant::now());
+        self.process_event(wait)
+    }
+
+    /// Does what has come due without waiting for a key: a resize, writing the swap file,
+    /// saving after a pause in typing, checking the file on disk and talking to language
+    /// servers. Event loops the embedding application owns call it at `next_deadline`.
+    pub fn process_due(&mut self) -> Result<()> {
+        self.poll_sources();
+        self.process_event(Duration::ZERO)
+    }
+
+    /// When the editor next has something to do without a key being pressed, like saving
+    /// after a pause in typing or seeing whether the file changed on disk.
+    pub fn next_deadline(&self) -> Instant {
+        let now = Instant::now();
+        let mut deadline = now + IDLE_TICK;
+        if let Some(idle) = self.autosave.idle.filter(|_| self.is_autosave_pending()) {
+            deadline = min(deadline, self.last_key_at + idle);
+        }
+        if self.swap_pending() {
+            deadline = min(deadline, self.last_key_at + SWAP_IDLE);
+        }
+        if self.buffer.is_highlighting() {
+            deadline = min(deadline, now + HIGHLIGHT_POLL);
+        }
+        if self.git.as_ref().is_some_and(git::Tracker::is_asking) {
+            deadline = min(deadline, now + GIT_POLL);
+        }
+        if self.watch_files && self.buffer.filename().is_some() {
+            let checked = self.disk_checked_at.map_or(now, |at| at + DISK_POLL);
+            deadline = min(deadline, checked);
+        }
+        #[cfg(feature = "lsp")]
+        if self.lsp.is_running() {
+            deadline = min(deadline, now + LSP_POLL);
+        }
+        if !self.status_msg.is_empty() && self.status_msg_ts.elapsed() < self.status_msg_timeout {
+            deadline = min(deadline, self.status_msg_ts + self.status_msg_timeout);
+        }
+        deadline
+    }
+
+    /// Whether the buffer shown has changes its swap file doesn't have yet.
+    fn swap_pending(&self) -> bool {
+        self.swap_files && self.buffer.swap_pending() && !self.buffer.is_read_only()
+    }
+
+    /// Checks on what changes without a key being pressed: the file on disk, once a
+    /// `DISK_POLL`, and the language servers.
+    fn poll_sources(&mut self) {
+        let watched = self.watch_files && self.buffer.filename().is_some();
+        if watched
+            && self
+                .disk_checked_at
+                .is_none_or(|at| at.elapsed() >= DISK_POLL)
+        {
+            self.check_disk();
+        }
+        #[cfg(feature = "lsp")]
+        self.sync_language_servers();
+    }
+
+    /// Waits up to `wait` for the next event and handles it, or does what's due after a
+    /// pause in typing if none comes.
+    fn process_event(&mut self, wait: Duration) -> Result<()> {
+        self.recorded_before = self.recording.as_ref().map_or(0, |(_, keys)| keys.len());
+        let key = match self.read_event(wait)? {
+            Event::Key(key) => key,
+            Event::Resize(rows, cols) => {
+                self.refresh_screen();
+                self.run_hooks(
+                    HookEvent::Resize,
+                    None,
+                    &[&rows.to_string(), &cols.to_string()],
+                );
+                return Ok(());
+            }
+            Event::Tick => {
+                #[cfg(feature = "lsp")]
+                self.sync_language_servers();
+                let paused = self.last_key_at.elapsed();
+                if self.swap_pending() && paused >= SWAP_IDLE {
+                    self.write_swap();
+                }
+                let idle = self.autosave.idle.filter(|_| self.is_autosave_pending());
+                return match idle {
+                    Some(idle) if paused >= idle => self.autosave(),
+                    _ => Ok(()),
+                };
+            }
+        };
+        self.last_key_at = Instant::now();
+        let mode = self.modal.as_ref().map(Modal::mode);
+        let cursor = self.buffer.cursor_position();
+        let handled = self.handle_key(key);
+        self.run_change_hooks(mode, cursor);
+        handled
+    }
+
+    /// Runs the hooks of what a key changed: the mode, from `mode`, and where the cursor is,
+    /// from `cursor`.
+    fn run_change_hooks(&mut self, mode: Option<Mode>, cursor: Cursor) {
+        if self.hooks.is_empty() {
+            return;
+        }
+        match (mode, self.modal.as_ref().map(Modal::mode)) {
+            (Some(old), Some(new)) if old != new => self.run_hooks(
+                HookEvent::ModeChange,
+                Some(new.name()),
+                &[new.name(), old.name()],
+            ),
+            _ => {}
+        }
+        let moved = self.buffer.cursor_position();
+        if (moved.cursor_row, moved.cursor_col) != (cursor.cursor_row, cursor.cursor_col) {
+            let line = (moved.cursor_row + 1).to_string();
+            let column = self.cursor_column().to_string();
+            let file = self
+                .buffer
+                .filename()
+                .as_ref()
+                .map(|file| self.resolve_path(file).to_string_lossy().to_string());
+            self.run_hooks(HookEvent::CursorMove, file.as_deref(), &[&line, &column]);
+        }
+    }
+
+    /// Handles a key read by `process_keypress` or replayed by a macro, along with any keys
+    /// following it that it takes.
+    fn handle_key(&mut self, key: Key) -> Result<()> {
+        // What a popup shows is only there until the next key.
+        if !self.popup.0.is_empty() {
+            self.popup.0.clear();
+            self.terminal.screen().invalidate();
+        }
+        // The state before the keys goes into the history too, in case they change it.
+        self.buffer.record_history();
+        let key = match key {
+            Key::Printable(_) | Key::Tab | Key::Newline
+                if self.burst_pending()
+                    && !self.starts_mapping(&key)
+                    && !self.overwrite
+                    && !self.buffer.is_read_only()
+                    && self.typing()
+                    && self.cursors.is_empty() =>
+            {
+                self.insert_burst(key)?
+            }
+            key => Some(key),
+        };
+        if let Some(key) = key {
+            let keys = if self.typing() {
+                self.apply_mappings(key)?
+            } else {
+                vec![key]
+            };
+            for key in keys {
+                self.process_modal_key(key)?;
+            }
+        }
+        self.buffer.record_history();
+        if let Some(count) = self.autosave.modifications {
+            if self.buffer.changes() - self.autosaved_at >= count && self.is_autosave_pending() {
+                self.autosave()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles the keys that can be read without waiting, so that a burst of them, like a held
+    /// down arrow key or a stream of mouse reports, costs a single redraw. Stops after a frame's
+    /// worth of time, leaving the rest for after the next redraw so the screen keeps up.
+    pub fn process_pending(&mut self) -> Result<()> {
+        let start = Instant::now();
+        while self.keep_alive() && self.terminal.input_pending() && start.elapsed() < FRAME_BUDGET {
+            self.process_keypress()?;
+        }
+        Ok(())
+    }
+
+    /// Inserts text arriving faster than anyone types, like a paste into a terminal without
+    /// bracketed paste, in one go instead of key by key. Mappings and comment leaders don't
+    /// apply to it, so a key that could start a mapping ends it. Returns the key that ended the
+    /// burst, if any.
+    fn insert_burst(&mut self, first: Key) -> Result<Option<Key>> {
+        let mut text = String::new();
+        let mut key = first;
+        let rest = loop {
+            match key {
+                Key::Printable(_) if self.starts_mapping(&key) => break Some(key),
+                Key::Printable(ch) => text.push(ch),
+                Key::Tab => text.push('\t'),
+                Key::Newline => text.push('\n'),
+                key => break Some(key),
+            }
+            if !self.burst_pending() {
+                break None;
+            }
+            key = self.read_key()?;
+        };
+        self.buffer.insert_text(&text);
+        Ok(rest)
+    }
+
+    /// Waits up to `timeout` for a key or a resize like `Terminal::read_event`, but takes
+    /// replayed keys ahead of the terminal's, and records the typed ones into the macro being
+    /// recorded.
+    fn read_event(&mut self, timeout: Duration) -> Result<Event> {
+        if let Some(key) = self.replayed_keys.pop_front() {
+            return Ok(Event::Key(key));
+        }
+        let event = self.terminal.read_event(timeout)?;
+        if let (Event::Key(key), Some((_, keys))) = (&event, self.recording.as_mut()) {
+            keys.push(key.clone());
+        }
+        Ok(event)
+    }
+
+    fn read_key_timeout(&mut self, timeout: Duration) -> Result<Option<Key>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.read_event(deadline.saturating_duration_since(Instant::now()))? {
+                Event::Key(key) => return Ok(Some(key)),
+                Event::Tick => return Ok(None),
+                Event::Resize(..) => {}
+            }
+        }
+    }
+
+    fn read_key(&mut self) -> Result<Key> {
+        loop {
+            if let Event::Key(key) = self.read_event(IDLE_TICK)? {
+                return Ok(key);
+            }
+        }
+    }
+
+    /// Whether typed keys are waiting to be read, as in a burst too fast for typing. Replayed
+    /// keys aren't one: they go key by key, as they went when typed.
+    fn burst_pending(&self) -> bool {
+        self.replayed_keys.is_empty() && self.terminal.input_pending()
+    }
+
+    /// Whether `key` is the first of an insert mapping's characters.
+    fn starts_mapping(&self, key: &Key) -> bool {
+        let Key::Printable(ch) = key else {
+            return false;
+        };
+        self.insert_mappings
+            .iter()
+            .any(|(lhs, _)| lhs.starts_with(*ch))
+    }
+
+    /// Shows `keys` in the status bar as the start of an unfinished chord while reading the
+    /// key that continues it.
+    fn read_chord_key(&mut self, keys: &str) -> Key {
+        self.pending_keys = keys.to_string();
+        self.refresh_screen();
+        let key = self.read_prompt_key();
+        self.pending_keys.clear();
+        key
+    }
+
+    fn apply_mappings(&mut self, key: Key) -> Result<Vec<Key>> {
+        let mut typed = String::new();
+        let mut resolved = None;
+        let mut next = Some(key);
+        while let Some(Key::Printable(ch)) = next {
+            let candidate = format!("{}{}", typed, ch);
+            let (exact, longer) =
+                self.insert_mappings
+                    .iter()
+                    .fold((None, false), |(exact, longer), (lhs, rhs)| {
+                        if *lhs == candidate {
+                            (Some(rhs), longer)
+                        } else {
+                            (exact, longer || lhs.starts_with(&candidate))
+                        }
+                    });
+            if exact.is_none() && !longer {
+                break;
+            }
+            resolved = exact.cloned();
+            typed = candidate;
+            next = if longer {
+                self.pending_keys = typed.clone();
+                self.refresh_screen();
+                let next = self.read_key_timeout(MAPPING_TIMEOUT);
+                self.pending_keys.clear();
+                next?
+            } else {
+                None
+            };
+        }
+
+        let mut keys =
+            resolved.unwrap_or_else(|| typed.chars().map(Key::Printable).collect::<Vec<Key>>());
+        match next {
+            Some(next) if !typed.is_empty() => keys.extend(self.apply_mappings(next)?),
+            next => keys.extend(next),
+        }
+        Ok(keys)
+    }
+
+    /// Whether typed characters go into the text, as they do unless modal editing is on and
+    /// not in insert mode.
+    fn typing(&self) -> bool {
+        self.modal
+            .as_ref()
+            .is_none_or(|modal| modal.mode() == Mode::Insert)
+    }
+
+    /// Handles `key` through the modes of modal editing first, when it's on. Directory
+    /// listings and view mode have keys of their own, which the modes leave alone.
+    fn process_modal_key(&mut self, key: Key) -> Result<()> {
+        let own_keys = self.buffer.kind() == BufferKind::Directory || self.buffer.is_viewing();
+        let recording = self.recording.is_some();
+        let Some(modal) = self.modal.as_mut().filter(|_| !own_keys) else {
+            return self.process_key(key);
+        };
+        // The selection can end other ways than through the mode, like cutting it.
+        if modal.mode() == Mode::Visual && self.anchor.is_none() {
+            modal.set_mode(Mode::Normal);
+        }
+        let command = modal.key(key, recording);
+        self.pending_keys = modal.pending();
+        match command {
+            Some(ModalCommand::Pass(key)) => self.process_key(key),
+            Some(command) => self.run_modal(command),
+            None => Ok(()),
+        }
+    }
+
+    /// Carries out a command of modal editing's normal or visual mode.
+    fn run_modal(&mut self, command: ModalCommand) -> Result<()> {
+        let (rows, cols) = (self.rows(), self.cols());
+        let row = self.buffer.cursor_position().cursor_row;
+        let col = self.buffer.cursor_position().cursor_col;
+        let line = self
+            .buffer
+            .line(row)
+            .map(|line| line.content().to_string())
+            .unwrap_or_default();
+        let indent = line.len() - line.trim_start_matches([' ', '\t']).len();
+        let editing = matches!(
+            command,
+            ModalCommand::Insert(_)
+                | ModalCommand::DeleteChars(_)
+                | ModalCommand::Operate(Operator::Delete | Operator::Change, _)
+                | ModalCommand::ChangeSelection
+                | ModalCommand::Put { .. }
+        );
+        if editing && self.buffer.is_read_only() {
+            if let Some(modal) = self.modal.as_mut() {
+                modal.set_mode(Mode::Normal);
+            }
+            self.set_status("Buffer is read-only!!!".to_string());
+            return Ok(());
+        }
+        match command {
+            ModalCommand::Move(motion, count) => {
+                for _ in 0..count {
+                    self.buffer.move_cursor(motion, rows, cols);
+                }
+            }
+            ModalCommand::GotoLine(number) => {
+                let number = number.unwrap_or(self.buffer.line_count());
+                self.push_jump();
+                self.buffer.goto(number, 1);
+            }
+            ModalCommand::Insert(at) => match at {
+                InsertAt::Cursor => {}
+                InsertAt::AfterCursor => {
+                    let next = self
+                        .buffer
+                        .line(row)
+                        .map_or(0, |l| l.next_char_boundary(col));
+                    self.place_cursor_at(row, next);
+                }
+                InsertAt::LineStart => self.place_cursor_at(row, indent),
+                InsertAt::LineEnd => self.place_cursor_at(row, line.len()),
+                InsertAt::LineBelow => {
+                    self.place_cursor_at(row, line.len());
+                    self.buffer.insert_text(&format!("\n{}", &line[..indent]));
+                }
+                InsertAt::LineAbove => {
+                    self.place_cursor_at(row, 0);
+                    self.buffer.insert_text(&format!("{}\n", &line[..indent]));
+                    self.place_cursor_at(row, indent);
+                }
+            },
+            ModalCommand::DeleteChars(count) => {
+                let end = line[col..]
+                    .char_indices()
+                    .nth(count)
+                    .map_or(line.len(), |(idx, _)| col + idx);
+                if end > col {
+                    let text = self
+                        .buffer
+                        .delete_region(Region::new((row, col), (row, end)));
+                    self.keep_kill(text, false);
+                }
+            }
+            ModalCommand::FindChar(search, count) => {
+                let found = self.buffer.line(row).and_then(|line| {
+                    (0..count).try_fold(col, |pos, _| {
+                        line.find_char(pos, search.ch, search.forward, search.till)
+                    })
+                });
+                match found {
+                    Some(found) => self.place_cursor_at(row, found),
+                    None => self.set_status(format!("{:?} not found on the line", search.ch)),
+                }
+            }
+            ModalCommand::Operate(operator, target) => self.operate(operator, target),
+            ModalCommand::Put { .. } if self.register.is_empty() => {
+                self.set_status("Nothing copied to paste".to_string())
+            }
+            ModalCommand::Put { before, count } => {
+                let text = self.register.repeat(count);
+                match text.strip_suffix('\n') {
+                    // Whole lines go in above or below the cursor line, instead of into it.
+                    Some(_) if !self.register_block && before => {
+                        self.place_cursor_at(row, 0);
+                        self.buffer.insert_text(&text);
+                        self.place_cursor_at(row, 0);
+                    }
+                    Some(lines) if !self.register_block => {
+                        self.place_cursor_at(row, line.len());
+                        self.buffer.insert_text(&format!("\n{}", lines));
+                        self.place_cursor_at(row + 1, 0);
+                    }
+                    _ => {
+                        if !before {
+                            let next = self
+                                .buffer
+                                .line(row)
+                                .map_or(0, |l| l.next_char_boundary(col));
+                            self.place_cursor_at(row, next);
+                        }
+                        let register = std::mem::replace(&mut self.register, text);
+                        self.paste_register(true);
+                        self.register = register;
+                    }
+                }
+            }
+            ModalCommand::Undo(count) => self.travel(&count.to_string(), true),
+            ModalCommand::Redo(count) => self.travel(&count.to_string(), false),
+            ModalCommand::Scroll { up, count } => {
+                self.scroll_page(up, count.unwrap_or(self.half_page()))
+            }
+            ModalCommand::Align(align) => self.buffer.align_view(align, self.rows()),
+            ModalCommand::Visual => self.start_selection(false),
+            ModalCommand::DeleteSelection | ModalCommand::ChangeSelection => {
+                self.run_action(Action::Cut)?
+            }
+            ModalCommand::YankSelection => self.run_action(Action::Copy)?,
+            ModalCommand::Normal => self.anchor = None,
+            ModalCommand::Search { forward: true } => self.run_action(Action::Find)?,
+            ModalCommand::Search { forward: false } => self.run_action(Action::FindReverse)?,
+            ModalCommand::SearchNext { forward: true } => {
+                self.search_next(SearchDirection::Forward)
+            }
+            ModalCommand::SearchNext { forward: false } => {
+                self.search_next(SearchDirection::Reverse)
+            }
+            ModalCommand::CommandLine => self.run_action(Action::CommandLine)?,
+            ModalCommand::SetMark(name) => self.set_mark(name),
+            ModalCommand::GotoMark { name, line } => self.goto_mark(name, line),
+            ModalCommand::Jump { back, count } => self.jump(back, count),
+            ModalCommand::Record(register) => self.start_recording(register),
+            ModalCommand::StopRecording => self.stop_recording(),
+            ModalCommand::Play { register, count } => self.play_macro(register, count),
+            ModalCommand::Pass(key) => self.process_key(key)?,
+        }
+        Ok(())
+    }
+
+    /// Text `target` covers from the cursor, and whether it goes by whole lines, in which case
+    /// the region runs from the start of its first line to the start of its last.
+    fn target_region(&mut self, target: Target) -> Option<(Region, bool)> {
+        let cursor = self.buffer.cursor_position();
+        let at = (cursor.cursor_row, cursor.cursor_col);
+        let lines = |first: usize, last: usize| Region::new((first, 0), (last, 0));
+        match target {
+            Target::Lines(count) => {
+                let last = min(at.0 + count, self.buffer.line_count()).checked_sub(1)?;
+                Some((lines(at.0, last), true))
+            }
+            Target::Motion(motion, count) => {
+                let (rows, cols) = (self.rows(), self.cols());
+                for _ in 0..count {
+                    self.buffer.move_cursor(motion, rows, cols);
+                }
+                let moved = self.buffer.cursor_position();
+                self.buffer.set_cursor_position(cursor);
+                let mut to = (moved.cursor_row, moved.cursor_col);
+                match motion {
+                    Motion::Up | Motion::Down => {
+                        let (first, last) = (min(at.0, to.0), max(at.0, to.0));
+                        return Some((lines(first, last), true));
+                    }
+                    // Left and right stay on the line, rather than going over its ends.
+                    Motion::Left | Motion::Right if to.0 != at.0 => {
+                        let end = self.buffer.line(at.0).map_or(0, |line| line.len());
+                        to = (at.0, if motion == Motion::Left { 0 } else { end });
+                    }
+                    _ => {}
+                }
+                Some((Region::new(min(at, to), max(at, to)), false))
+            }
+            Target::Char(search, count) => {
+                // Up to the character itself, whether or not `till` stops next to it.
+                let line = self.buffer.line(at.0)?;
+                let found = (0..count).try_fold(at.1, |pos, _| {
+                    line.find_char(pos, search.ch, search.forward, false)
+                })?;
+                let after = found + search.ch.len_utf8();
+                let region = match (search.till, search.forward) {
+                    (false, true) => Region::new(at, (at.0, after)),
+                    (true, true) => Region::new(at, (at.0, found)),
+                    (false, false) => Region::new((at.0, found), at),
+                    (true, false) => Region::new((at.0, after), at),
+                };
+                Some((region, false))
+            }
+            Target::Object(object) => {
+                let region = region::text_object(&self.buffer, at, &object)?;
+                Some((region, false))
+            }
+        }
+    }
+
+    /// Deletes, changes or copies the text `target` covers from the cursor. Whole lines go to
+    /// the register as lines, for `p` to put them on lines of their own.
+    fn operate(&mut self, operator: Operator, target: Target) {
+        let Some((region, whole_lines)) = self.target_region(target) else {
+            if let Some(modal) = self.modal.as_mut() {
+                modal.set_mode(Mode::Normal);
+            }
+            return;
+        };
+        let (first, last) = (region.start.0, region.end.0);
+        if !whole_lines {
+            if region.start == region.end {
+                return;
+            }
+            let text = match operator {
+                Operator::Yank => self.buffer.region_text(region),
+                Operator::Delete | Operator::Change => self.buffer.delete_region(region),
+            };
+            self.place_cursor_at(region.start.0, region.start.1);
+            return self.yank(&text, false);
+        }
+        let text = (first..=last)
+            .filter_map(|row| self.buffer.line(row))
+            .map(|line| format!("{}\n", line.content()))
+            .collect::<String>();
+        self.yank(&text, false);
+        let row = self.buffer.cursor_position().cursor_row;
+        match operator {
+            Operator::Yank if first < row => self.place_cursor_at(first, 0),
+            Operator::Yank => {}
+            Operator::Delete => self.buffer.delete_lines(first, last),
+            Operator::Change => {
+                // The lines give way to an empty one, indented like the first of them.
+                let indent = self.buffer.line(first).map_or(0, |line| {
+                    let content = line.content();
+                    content.len() - content.trim_start_matches([' ', '\t']).len()
+                });
+                let end = self.buffer.line(last).map_or(0, |line| line.len());
+                self.buffer
+                    .delete_region(Region::new((first, indent), (last, end)));
+            }
+        }
+    }
+
+    fn process_key(&mut self, key: Key) -> Result<()> {
+        if !self.cursors.is_empty() && self.at_every_cursor(&key) {
+            return self.process_key_at_cursors(key);
+        }
+        if let Some((_, name)) = self.command_keys.iter().find(|(bound, _)| *bound == key) {
+            let command = name.clone();
+            self.run_command(&command);
+            return Ok(());
+        }
+        if let Some(&(_, action)) = self.key_bindings.iter().find(|(bound, _)| *bound == key) {
+            return self.run_action(action);
+        }
+        // Shift with a key bound to a motion selects on the way, as it does with the arrows.
+        if let Key::Modified(motion, modifiers) = key {
+            let unshifted = Key::motion(motion, modifiers - Modifiers::SHIFT);
+            let bound = self
+                .key_bindings
+                .iter()
+                .find(|(bound, _)| *bound == unshifted);
+            if let Some(&(_, Action::Move(motion))) =
+                bound.filter(|_| modifiers.contains(Modifiers::SHIFT))
+            {
+                if self.anchor.is_none() {
+                    self.start_selection(true);
+                }
+                self.buffer.move_cursor(motion, self.rows(), self.cols());
+                return Ok(());
+            }
+        }
+        if self.buffer.kind() == BufferKind::Directory && self.directory_key(&key) {
+            return Ok(());
+        }
+        if self.buffer.kind() == BufferKind::Results && key == Key::Newline && self.open_result() {
+            return Ok(());
+        }
+        if self.buffer.is_viewing() && self.view_key(&key)? {
+            return Ok(());
+        }
+        // Typing replaces the selection, and deleting takes it out.
+        let editing = matches!(
+            key,
+            Key::Printable(_) | Key::Tab | Key::Newline | Key::Paste(_)
+        );
+        let deleting = matches!(key, Key::Backspace | Key::Control('H') | Key::Delete);
+        if deleting && !self.buffer.is_read_only() {
+            if let Some((rows, cols)) = self.rectangle() {
+                self.anchor = None;
+                self.buffer.delete_rectangle(rows, cols);
+                self.place_cursor_at_column(rows.0, cols.0);
+                return Ok(());
+            }
+        }
+        if (editing || deleting) && !self.buffer.is_read_only() {
+            if let Some(selection) = self.selection() {
+                self.anchor = None;
+                self.buffer.delete_region(selection);
+                if deleting {
+                    return Ok(());
+                }
+            }
+        }
+        match key {
+            Key::Printable(_)
+            | Key::Tab
+            | Key::Newline
+            | Key::Backspace
+            | Key::Control('H')
+            | Key::Delete
+            | Key::Paste(_)
+                if self.buffer.is_read_only() =>
+            {
+                self.set_status("Buffer is read-only!!!".to_string())
+            }
+            Key::Printable(ch) if self.overwrite => self.buffer.replace_char(ch),
+            Key::Tab if self.overwrite => self.buffer.replace_char('\t'),
+            Key::Printable(ch) if self.auto_pairs => self.buffer.insert_paired(ch),
+            Key::Printable(ch) => self.buffer.insert_char(ch),
+            Key::Tab => self.buffer.insert_tab(),
+            Key::Paste(text) => self.buffer.insert_text(&text),
+            Key::Newline if self.auto_pairs && self.buffer.split_pair() => {}
+            Key::Newline => {
+                let leader = if self.comment_leaders {
+                    self.buffer.comment_leader()
+                } else {
+                    None
+                };
+                let leader = leader.or_else(|| {
+                    let smart = self.smart_indent;
+                    self.auto_indent.then(|| self.buffer.new_line_indent(smart))
+                });
+                self.buffer.insert_new_line();
+                if let Some(leader) = leader {
+                    self.buffer.insert_text(&leader);
+                }
+            }
+            Key::FocusLost if self.autosave.focus_lost && self.is_autosave_pending() => {
+                self.autosave()?
+            }
+            Key::Mouse(MouseEvent::Wheel { up, fast }) => {
+                let mut lines = self.wheel_step;
+                if fast {
+                    lines *= self.wheel_fast_multiplier;
+                }
+                let delta = if up {
+                    -(lines as isize)
+                } else {
+                    lines as isize
+                };
+                self.buffer.scroll_view(delta, self.rows());
+            }
+            Key::Select(motion) => {
+                if self.anchor.is_none() {
+                    self.start_selection(true);
+                }
+                self.buffer.move_cursor(motion, self.rows(), self.cols());
+            }
+            Key::Mouse(MouseEvent::Press {
+                button: MouseButton::Left,
+                row,
+                col,
+            }) => {
+                // Dragging from here selects.
+                self.cursors.clear();
+                self.click(row, col, false);
+                self.start_selection(true);
+            }
+            Key::Mouse(MouseEvent::Drag {
+                button: MouseButton::Left,
+                row,
+                col,
+            }) => self.click(row, col, true),
+            Key::Mouse(MouseEvent::Press {
+                button: MouseButton::Middle,
+                row,
+                col,
+            }) if !self.buffer.is_read_only() => {
+                self.click(row, col, false);
+                self.paste(Selection::Primary);
+            }
+            Key::Mouse(MouseEvent::ControlClick { row, col }) => self.add_cursor_at(row, col),
+            Key::Escape => {
+                self.anchor = None;
+                self.cursors.clear();
+            }
+            Key::Backspace | Key::Control('H')
+                if self.auto_pairs && self.pair_deletion && self.buffer.delete_pair() => {}
+            Key::Backspace | Key::Control('H') if self.buffer.dedent() => {}
+            Key::Backspace | Key::Control('H') => self.buffer.delete_char(),
+            Key::Delete => {
+                // At the very end of the buffer there's nothing after the cursor to delete.
+                let cursor = self.buffer.cursor_position();
+                self.buffer
+                    .move_cursor(Motion::Right, self.rows(), self.cols());
+                let moved = self.buffer.cursor_position();
+                if (moved.cursor_row, moved.cursor_col) != (cursor.cursor_row, cursor.cursor_col) {
+                    self.buffer.delete_char();
+                }
+            }
+            _key => {}
+        };
+        self.recenter = 0;
+        Ok(())
+    }
+
+    /// Whether `key` is one that acts at every cursor: one typing or deleting text, or moving
+    /// along lines, rather than one acting on the buffer as a whole.
+    fn at_every_cursor(&self, key: &Key) -> bool {
+        let along_lines = |motion: Motion| !matches!(motion, Motion::PgUp | Motion::PgDn);
+        match self.key_bindings.iter().find(|(bound, _)| bound == key) {
+            Some((_, Action::Move(motion))) => along_lines(*motion),
+            Some(_) => false,
+            None => match key {
+                Key::Select(motion) => along_lines(*motion),
+                Key::Printable(_)
+                | Key::Tab
+                | Key::Newline
+                | Key::Backspace
+                | Key::Control('H')
+                | Key::Delete
+                | Key::Paste(_) => !self.block,
+                _ => false,
+            },
+        }
+    }
+
+    /// Handles `key` at each cursor in turn, from the last in the buffer to the first. What it
+    /// does at one cursor only changes text before the ones already done, so that they keep
+    /// their places counted from the end of the buffer. The history records the whole as one
+    /// change.
+    fn process_key_at_cursors(&mut self, key: Key) -> Result<()> {
+        let main = self.buffer.cursor_position();
+        let mut cursors = std::mem::take(&mut self.cursors);
+        cursors.push((main, self.anchor));
+        cursors.sort_by_key(|(cursor, _)| (cursor.cursor_row, cursor.cursor_col));
+        let main_at = (main.cursor_row, main.cursor_col);
+        let mut done = Vec::with_capacity(cursors.len());
+        let mut result = Ok(());
+        for (cursor, anchor) in cursors.into_iter().rev() {
+            let is_main = (cursor.cursor_row, cursor.cursor_col) == main_at;
+            self.buffer.set_cursor_position(Cursor {
+                cursor_row: cursor.cursor_row,
+                cursor_col: cursor.cursor_col,
+                desired_col: cursor.desired_col,
+                ..self.buffer.cursor_position()
+            });
+            self.anchor = anchor;
+            result = result.and(self.process_key(key.clone()));
+            let cursor = self.buffer.cursor_position();
+            let at = self.counted_from_end((cursor.cursor_row, cursor.cursor_col));
+            let anchor = self
+                .anchor
+                .map(|(pos, shifted)| (self.counted_from_end(pos), shifted));
+            done.push((is_main, at, cursor.desired_col, anchor));
+        }
+
+        let mut cursors = Vec::with_capacity(done.len());
+        for (is_main, at, desired_col, anchor) in done {
+            let (cursor_row, cursor_col) = self.back_from_end(at);
+            let cursor = Cursor {
+                cursor_row,
+                cursor_col,
+                desired_col,
+                ..main
+            };
+            let anchor = anchor.map(|(pos, shifted)| (self.back_from_end(pos), shifted));
+            if is_main {
+                self.buffer.set_cursor_position(cursor);
+                self.anchor = anchor;
+            } else {
+                cursors.push((cursor, anchor));
+            }
+        }
+        // Cursors run into each other deleting the text between them, or at the ends of lines.
+        let main = self.buffer.cursor_position();
+        let main_at = (main.cursor_row, main.cursor_col);
+        cursors.retain(|(cursor, _)| (cursor.cursor_row, cursor.cursor_col) != main_at);
+        cursors.sort_by_key(|(cursor, _)| (cursor.cursor_row, cursor.cursor_col));
+        cursors.dedup_by_key(|(cursor, _)| (cursor.cursor_row, cursor.cursor_col));
+        self.cursors = cursors;
+        result
+    }
+
+    /// `pos` as lines back from the end of the buffer and bytes back from the end of its line.
+    fn counted_from_end(&self, (row, col): Position) -> Position {
+        let len = self.buffer.line(row).map_or(0, |line| line.content().len());
+        (
+            self.buffer.line_count().saturating_sub(row),
+            len.saturating_sub(col),
+        )
+    }
+
+    /// Turns a position from `counted_from_end` back into one counted from the start.
+    fn back_from_end(&self, (rows, cols): Position) -> Position {
+        let row = self.buffer.line_count().saturating_sub(rows);
+        let len = self.buffer.line(row).map_or(0, |line| line.content().len());
+        (row, len.saturating_sub(cols))
+    }
+
+    /// Position of the bracket the cursor is on, or else of one just before it, as after
+    /// typing it.
+    fn bracket_at_cursor(&self) -> Option<Position> {
+        let cursor = self.buffer.cursor_position();
+        let line = self.buffer.line(cursor.cursor_row)?.content();
+        let is_bracket = |ch: char| region::PAIRS.iter().any(|&(o, c)| ch == o || ch == c);
+        let col = match line[cursor.cursor_col..].chars().next() {
+            Some(ch) if is_bracket(ch) => cursor.cursor_col,
+            _ => {
+                let (col, ch) = line[..cursor.cursor_col].char_indices().next_back()?;
+                is_bracket(ch).then_some(col)?
+            }
+        };
+        Some((cursor.cursor_row, col))
+    }
+
+    /// The bracket at the cursor and the one it pairs with, if both are there.
+    fn bracket_pair(&self) -> Option<(Position, Position)> {
+        let at = self.bracket_at_cursor()?;
+        Some((at, region::matching_bracket(&self.buffer, at)?))
+    }
+
+    fn jump_to_bracket(&mut self) {
+        match self.bracket_at_cursor() {
+            None => self.set_status("No bracket at the cursor".to_string()),
+            Some(at) => match region::matching_bracket(&self.buffer, at) {
+                Some((row, col)) => {
+                    self.push_jump();
+                    self.place_cursor_at(row, col);
+                }
+                None => self.set_status("No matching bracket".to_string()),
+            },
+        }
+    }
+
+    /// Adds a cursor where the screen was Ctrl-clicked at `row` and `col`, keeping the one the
+    /// cursor moves from.
+    fn add_cursor_at(&mut self, row: usize, col: usize) {
+        let (active, cursor, anchor) = (self.active, self.buffer.cursor_position(), self.anchor);
+        self.click(row, col, false);
+        let moved = self.buffer.cursor_position();
+        let at = (moved.cursor_row, moved.cursor_col);
+        if self.active != active || at == (cursor.cursor_row, cursor.cursor_col) {
+            return;
+        }
+        // Clicking a cursor again takes it away instead.
+        let before = self.cursors.len();
+        self.cursors
+            .retain(|(cursor, _)| (cursor.cursor_row, cursor.cursor_col) != at);
+        if self.cursors.len() == before {
+            self.cursors.push((cursor, anchor));
+        } else {
+            self.buffer.set_cursor_position(cursor);
+        }
+        self.anchor = None;
+    }
+
+    /// Adds a cursor a line below, or above, the main one, which moves there.
+    fn add_cursor_beside(&mut self, below: bool) {
+        let cursor = self.buffer.cursor_position();
+        let motion = if below { Motion::Down } else { Motion::Up };
+        self.buffer.move_cursor(motion, self.rows(), self.cols());
+        if self.buffer.cursor_position().cursor_row == cursor.cursor_row {
+            return;
+        }
+        self.cursors.push((cursor, self.anchor.take()));
+    }
+
+    /// Selects the word under the cursor, or adds a cursor selecting the next match of the
+    /// selection after it, wrapping around to the top of the buffer.
+    fn add_cursor_at_next_match(&mut self) {
+        let Some(selection) = self.selection() else {
+            let cursor = self.buffer.cursor_position();
+            match region::word(&self.buffer, (cursor.cursor_row, cursor.cursor_col)) {
+                Some(word) => {
+                    self.anchor = Some((word.start, true));
+                    self.place_cursor_at(word.end.0, word.end.1);
+                }
+                None => self.set_status("No word under the cursor".to_string()),
+            }
+            return;
+        };
+        let text = self.buffer.region_text(selection);
+        if text.contains('\n') {
+            return self.set_status("Only a selection within a line has matches".to_string());
+        }
+        let found = self
+            .buffer
+            .find_text(&text, selection.end)
+            .or_else(|| self.buffer.find_text(&text, (0, 0)));
+        let taken = |(row, col): Position| {
+            let end = (row, col + text.len());
+            end == selection.end
+                || self
+                    .cursors
+                    .iter()
+                    .any(|(cursor, _)| (cursor.cursor_row, cursor.cursor_col) == end)
+        };
+        match found.filter(|&start| !taken(start)) {
+            Some((row, col)) => {
+                let cursor = self.buffer.cursor_position();
+                self.cursors.push((cursor, self.anchor));
+                self.anchor = Some(((row, col), true));
+                self.place_cursor_at(row, col + text.len());
+            }
+            None => self.set_status(format!("No more matches of {}", text)),
+        }
+    }
+
+    fn run_action(&mut self, action: Action) -> Result<()> {
+        match action {
+            Action::Quit => self.quit(),
+            Action::Suspend => {
+                self.terminal.stop()?;
+                // The window may have changed size while the shell had it.
+                self.refresh_screen();
+            }
+            Action::Help => self.show_bindings(),
+            Action::Save if self.buffer.is_read_only() => {
+                self.set_status("Buffer is read-only!!!".to_string())
+            }
+            Action::Save => self.save()?,
+            Action::WindowCommand => self.window_command(),
+            Action::ExtendedCommand => self.extended_command(),
+            Action::NextBuffer => self.cycle_buffer(true),
+            Action::PreviousBuffer => self.cycle_buffer(false),
+            Action::Find => self.find(SearchDirection::Forward),
+            Action::FindReverse => self.find(SearchDirection::Reverse),
+            Action::Select if self.anchor.is_some() => {
+                self.anchor = None;
+                self.set_status("Selection cleared".to_string());
+            }
+            Action::Select => {
+                self.start_selection(false);
+                self.set_status(format!(
+                    "Selecting. {} copies, {} cuts",
+                    self.action_keys(Action::Copy),
+                    self.action_keys(Action::Cut)
+                ));
+            }
+            Action::Copy => self.copy_selection(false),
+            Action::Cut
+            | Action::Paste
+            | Action::PastePlain
+            | Action::YankPop
+            | Action::Replace
+            | Action::MoveLinesUp
+            | Action::MoveLinesDown
+            | Action::DuplicateLines
+            | Action::JoinLines
+            | Action::ToggleComment
+                if self.buffer.is_read_only() =>
+            {
+                self.set_status("Buffer is read-only!!!".to_string())
+            }
+            Action::Cut => self.copy_selection(true),
+            Action::Paste | Action::PastePlain if self.register.is_empty() => {
+                self.set_status("Nothing copied to paste".to_string())
+            }
+            Action::Replace => self.replace(),
+            Action::Paste => self.paste_register(true),
+            Action::PastePlain => self.paste_register(false),
+            Action::YankPop => self.yank_pop(),
+            Action::Digraph => self.digraph()?,
+            Action::CommandLine => self.command_line(),
+            Action::FindFile => self.find_file(),
+            Action::OpenFile => {
+                if let Some(file) = self.prompt_path("Open (ESC to cancel, Tab to complete): ") {
+                    self.open_path(&file);
+                }
+            }
+            Action::Move(motion) => {
+                if matches!(self.anchor, Some((_, true))) {
+                    self.anchor = None;
+                }
+                self.buffer.move_cursor(motion, self.rows(), self.cols())
+            }
+            Action::Overwrite => self.overwrite = !self.overwrite,
+            Action::CursorNextMatch => self.add_cursor_at_next_match(),
+            Action::CursorBelow => self.add_cursor_beside(true),
+            Action::CursorAbove => self.add_cursor_beside(false),
+            Action::MatchBracket => self.jump_to_bracket(),
+            Action::JumpBack => self.jump(true, 1),
+            Action::JumpForward => self.jump(false, 1),
+            Action::SetMark => {
+                if let Key::Printable(name) = self.read_chord_key("mark") {
+                    self.set_mark(name);
+                }
+            }
+            Action::GotoMark => {
+                self.set_status(self.marks_summary());
+                if let Key::Printable(name) = self.read_chord_key("go to mark") {
+                    self.goto_mark(name, false);
+                }
+            }
+            Action::MoveLinesUp => self.move_lines(true),
+            Action::MoveLinesDown => self.move_lines(false),
+            Action::DuplicateLines => self.duplicate_lines(),
+            Action::JoinLines => self.join_lines(),
+            Action::ToggleComment => self.toggle_comment(),
+            Action::GotoDefinition | Action::Hover | Action::NextDiagnostic => {
+                self.language_server_action(action)
+            }
+            Action::Complete => self.complete(),
+            Action::ScrollDown => self.buffer.scroll_view(1, self.rows()),
+            Action::ScrollUp => self.buffer.scroll_view(-1, self.rows()),
+            Action::HalfPageDown => self.scroll_page(false, self.half_page()),
+            Action::HalfPageUp => self.scroll_page(true, self.half_page()),
+            Action::FullPageDown => self.scroll_page(false, self.rows()),
+            Action::FullPageUp => self.scroll_page(true, self.rows()),
+            Action::Recenter => {
+                // Repeated presses cycle the cursor line through center, top and bottom.
+                let aligns = [ViewAlign::Center, ViewAlign::Top, ViewAlign::Bottom];
+                self.buffer
+                    .align_view(aligns[self.recenter % aligns.len()], self.rows());
+                self.recenter += 1;
+                return Ok(());
+            }
+        }
+        self.recenter = 0;
+        Ok(())
+    }
+
+    /// Moves the cursor `count` rows up or down with the view following, in the focused
+    /// window.
+    fn scroll_page(&mut self, up: bool, count: usize) {
+        if matches!(self.anchor, Some((_, true))) {
+            self.anchor = None;
+        }
+        let (rows, cols) = (self.rows(), self.cols());
+        let mut left = count.max(1);
+        // Keys played back from macros and commands don't wait on frames nobody sees.
+        let step = if self.smooth_scroll && self.command_depth == 0 && self.replayed_keys.is_empty()
+        {
+            left.div_ceil(SMOOTH_SCROLL_FRAMES)
+        } else {
+            left
+        };
+        while left > 0 {
+            let before = self.buffer.cursor_position().cursor_row;
+            self.buffer
+                .scroll_with_cursor(up, min(step, left), rows, cols);
+            left -= min(step, left);
+            if left == 0 || self.buffer.cursor_position().cursor_row == before {
+                break;
+            }
+            self.refresh_screen();
+            std::thread::sleep(SMOOTH_SCROLL_FRAME);
+        }
+    }
+
+    /// Lines the half-page scrolls move by: the `scroll` option, or half the window's height
+    /// when it's 0.
+    fn half_page(&self) -> usize {
+        match self.scroll {
+            0 => self.rows() / 2,
+            lines => lines,
+        }
+    }
+
+    /// Names of the keys bound to `action`, for messages that tell which key to press.
+    fn action_keys(&self, action: Action) -> String {
+        let keys = self
+            .key_bindings
+            .iter()
+            .filter(|&&(_, bound)| bound == action)
+            .map(|(key, _)| key_name(key))
+            .collect::<Vec<String>>();
+        if keys.is_empty() {
+            format!("({} is unbound)", action.name())
+        } else {
+            keys.join(" / ")
+        }
+    }
+
+    /// Opens `file_arg` in a buffer of its own and shows it, or switches to it if it's open
+    /// already. Without a file, starts an empty buffer. Windows that showed the file before get
+    /// their own views of it back; other windows start where the cursor was when the file was
+    /// last edited.
+    pub fn open(&mut self, file_arg: Option<String>) -> Result<()> {
+        if let Some(dir) = file_arg.as_ref().filter(|file| Path::new(file).is_dir()) {
+            self.explore(Path::new(dir));
+            return Ok(());
+        }
+        if let Some(idx) = file_arg
+            .as_ref()
+            .and_then(|file| self.find_buffer(Path::new(file)))
+        {
+            self.switch_buffer(idx);
+            return Ok(());
+        }
+        let mut buffer = Buffer::new();
+        buffer.set_tab_stop(self.tab_stop);
+        buffer.set_indent_spaces(self.indent_spaces);
+        buffer.set_wrap(self.wrap);
+        buffer.set_scroll_margins(self.scrolloff, self.sidescrolloff);
+        let mut unwritable = false;
+        if let Some(file) = file_arg {
+            buffer.set_filename(Some(file.clone()));
+            unwritable = !is_writable(Path::new(&file));
+            buffer.set_read_only(self.open_read_only.0 || unwritable);
+            buffer.set_viewing(self.open_read_only.1);
+            match std::fs::read(file) {
+                Ok(bytes) => buffer.read_bytes(&bytes, self.encoding)?,
+                // A file that doesn't exist yet gets created on the first save.
+                Err(err) if err.kind() == ErrorKind::NotFound => {}
+                Err(err) => return Err(err),
+            }
+            // Now the first line can tell the filetype of scripts without an extension.
+            buffer.detect_filetype();
+            if self.detect_indent {
+                buffer.detect_indent();
+            }
+            if self.ansi_colors {
+                buffer.interpret_ansi();
+            }
+            buffer.set_elastic_tabs(self.elastic_tabs);
+        }
+        buffer.not_dirty();
+        buffer.record_disk_state();
+        let history = if self.undo_files {
+            buffer.load_history()
+        } else {
+            Ok(())
+        };
+        let saved = match buffer.filename() {
+            Some(file) if self.restore_positions => positions::load(file),
+            _ => None,
+        };
+        self.add_buffer(buffer, saved);
+
+        if let Err(err) = history {
+            self.set_status(format!("Undo history not loaded: {}", err));
+        } else if unwritable {
+            self.set_status("File isn't writable, so it's read-only".to_string());
+        }
+        // Nothing gets saved from a read-only buffer, so there's nobody else to tell or ask.
+        if self.buffer.is_read_only() {
+            return self.opened();
+        }
+        let locked = self.buffer.lock().unwrap_or(true);
+        if !locked {
+            let choice = self.prompt_choice(
+                "File is open in another instance. Open (r)ead-only, (e)dit anyway or (q)uit? ",
+                "req",
+            );
+            match choice {
+                Some('e') => {}
+                Some('q') => self.quit = true,
+                _ => self.buffer.set_read_only(true),
+            }
+            self.set_status(String::new());
+        }
+        // With the file open elsewhere, the swap file is that instance's.
+        if locked && self.swap_files {
+            self.offer_recovery();
+        }
+        self.opened()
+    }
+
+    /// Lets hooks know a file was opened in the buffer.
+    fn opened(&mut self) -> Result<()> {
+        if let Some(filetype) = self.buffer.filetype() {
+            self.run_hooks(HookEvent::FileType, Some(filetype.name), &[filetype.name]);
+        }
+        self.run_buffer_hooks(HookEvent::BufOpen);
+        Ok(())
+    }
+
+    /// Lists the files under the working directory over the windows, narrows them down to the
+    /// ones matching what's typed, and opens the one picked. Up and Down move through them.
+    fn find_file(&mut self) {
+        let mut finder = Finder::new(finder::walk(self.working_dir(), FINDER_LIMIT));
+        let mut query = String::new();
+        let mut selected = 0;
+        let picked = loop {
+            finder.set_query(&query);
+            selected = min(selected, finder.match_count().saturating_sub(1));
+            self.overlay = self.finder_lines(&finder, selected);
+            let prompt = format!(
+                "Find file ({}/{}): ",
+                finder.match_count(),
+                finder.file_count()
+            );
+            match self.prompt_incremental(&prompt, &mut query) {
+                (true, Some(Key::Escape)) => break None,
+                (true, _) => {
+                    break finder
+                        .matches()
+                        .nth(selected)
+                        .map(|(file, _)| file.to_string())
+                }
+                (_, Some(Key::Move(Motion::Up) | Key::Control('P'))) => {
+                    selected = selected.saturating_sub(1)
+                }
+                (_, Some(Key::Move(Motion::Down) | Key::Control('N') | Key::Tab)) => selected += 1,
+                // The query changed, and the best match is the one to start from again.
+                (_, None) => selected = 0,
+                _ => {}
+            }
+        };
+        self.overlay.clear();
+        self.terminal.screen().invalidate();
+        if let Some(file) = picked {
+            self.open_path(&file);
+        }
+    }
+
+    /// Shows the words from the open buffers that complete the one before the cursor, the
+    /// nearest first, for picking one with the arrows, Ctrl-N and Ctrl-P or Tab, and Enter.
+    /// Typing more of the word narrows them down; other keys close the list and go on to do
+    /// what they do.
+    fn complete(&mut self) {
+        let mut selected = 0;
+        let mut first = true;
+        loop {
+            let cursor = self.buffer.cursor_position();
+            let line = self
+                .buffer
+                .line(cursor.cursor_row)
+                .map_or("", |line| line.content());
+            let prefix = complete::prefix_before(line, cursor.cursor_col).to_string();
+            if prefix.is_empty() {
+                if first {
+                    self.set_status("No word before the cursor to complete".to_string());
+                }
+                break;
+            }
+            if first && self.buffer.is_read_only() {
+                self.set_status("Buffer is read-only!!!".to_string());
+                return;
+            }
+            first = false;
+            let words = self.completions(&prefix);
+            if words.is_empty() {
+                self.set_status(format!("No words to complete {} with", prefix));
+                break;
+            }
+            selected = min(selected, words.len() - 1);
+            let (lines, width) = self.completion_lines(&words, selected);
+            self.popup = (lines, width, prefix.chars().count());
+            self.refresh_screen();
+            match self.read_prompt_key() {
+                Key::Move(Motion::Up) | Key::Control('P') => {
+                    selected = selected.checked_sub(1).unwrap_or(words.len() - 1)
+                }
+                Key::Move(Motion::Down) | Key::Control('N') | Key::Tab => {
+                    selected = (selected + 1) % words.len()
+                }
+                Key::Newline => {
+                    self.buffer.insert_text(&words[selected][prefix.len()..]);
+                    break;
+                }
+                Key::Escape => break,
+                Key::Printable(ch) if region::is_word_char(ch) => {
+                    self.buffer.insert_char(ch);
+                    selected = 0;
+                }
+                Key::Backspace | Key::Control('H') => {
+                    self.buffer.delete_char();
+                    selected = 0;
+                }
+                key => {
+                    self.replayed_keys.push_front(key);
+                    break;
+                }
+            }
+        }
+        self.popup.0.clear();
+        self.terminal.screen().invalidate();
+    }
+
+    /// Words starting with `prefix`, from the lines nearest the cursor outwards, then from
+    /// the other buffers.
+    fn completions(&self, prefix: &str) -> Vec<String> {
+        let row = self.buffer.cursor_position().cursor_row;
+        let count = self.buffer.line_count();
+        let nearest = (0..count.max(1) * 2).filter_map(|n| {
+            let row = if n % 2 == 0 {
+                row.checked_sub(n / 2)
+            } else {
+                Some(row + n / 2 + 1)
+            };
+            row.and_then(|row| self.buffer.line(row))
+        });
+        let others = self
+            .buffers
+            .iter()
+            .enumerate()
+            .filter(|&(idx, _)| idx != self.current_buffer)
+            .flat_map(|(_, (buffer, _))| {
+                (0..buffer.line_count()).filter_map(move |row| buffer.line(row))
+            });
+        let lines = nearest.chain(others).map(Line::content);
+        complete::words_with_prefix(prefix, lines, COMPLETION_LIMIT)
+    }
+
+    /// What the popup shows of `words`: as many as fit, including the `selected` one, which is
+    /// marked, and how wide that is.
+    fn completion_lines(&self, words: &[String], selected: usize) -> (Vec<String>, usize) {
+        let width = words
+            .iter()
+            .map(|word| word.chars().count())
+            .max()
+            .unwrap_or(0)
+            + 3;
+        let width = min(width, self.terminal.cols());
+        let first = (selected + 1).saturating_sub(COMPLETION_HEIGHT);
+        let lines = words
+            .iter()
+            .enumerate()
+            .skip(first)
+            .take(COMPLETION_HEIGHT)
+            .map(|(idx, word)| {
+                let marker = if idx == selected { "> " } else { "  " };
+                let text = format!("{}{}", marker, word)
+                    .chars()
+                    .chain(std::iter::repeat(' '))
+                    .take(width)
+                    .collect::<String>();
+                format!("{}{}\x1b[m", self.palette.status_bar, text)
+            })
+            .collect();
+        (lines, width)
+    }
+
+    /// Has `command`, a program and its arguments, start the language server for files of
+    /// the type named `filetype`, as the `[language_servers]` section of the config sets it.
+    #[cfg(feature = "lsp")]
+    pub fn set_language_server(&mut self, filetype: &str, command: &str) {
+        self.lsp.set_command(filetype, command);
+    }
+
+    /// Filetype and full path of the buffer, if it's a file of a type language servers could
+    /// know about.
+    #[cfg(feature = "lsp")]
+    fn language_document(&self) -> Option<(&'static Filetype, PathBuf)> {
+        let filetype = self.buffer.filetype()?;
+        if self.buffer.kind() != BufferKind::File {
+            return None;
+        }
+        let path = self.resolve_path(self.buffer.filename().as_ref()?);
+        Some((filetype, path.canonicalize().unwrap_or(path)))
+    }
+
+    /// Starts the language server of the buffer's filetype if there's one and it isn't
+    /// running yet, brings its copy of the buffer up to date, and takes in what the servers
+    /// have sent since.
+    #[cfg(feature = "lsp")]
+    fn sync_language_servers(&mut self) {
+        if let Some((filetype, path)) = self.language_document() {
+            let root = self.working_dir().to_path_buf();
+            let buffer = &self.buffer;
+            let synced = match self.lsp.client(filetype.name, &root) {
+                Ok(Some(client)) => client
+                    .sync(
+                        &path,
+                        &lsp::language_id(filetype.name),
+                        buffer.changes(),
+                        || buffer.rows_to_string(),
+                    )
+                    .map_err(|err| {
+                        format!("Language server for {} stopped: {}", filetype.name, err)
+                    }),
+                Ok(None) => Ok(()),
+                Err(err) => Err(err),
+            };
+            if let Err(err) = synced {
+                self.lsp.stop(filetype.name);
+                self.set_status(err);
+            }
+        }
+        let (responses, errors) = self.lsp.poll();
+        for err in errors {
+            self.set_status(err);
+        }
+        for (filetype, response) in responses {
+            self.language_server_answered(&filetype, response);
+        }
+    }
+
+    #[cfg(feature = "lsp")]
+    fn language_server_action(&mut self, action: Action) {
+        match action {
+            Action::GotoDefinition => self.ask_language_server(lsp::Request::Definition),
+            Action::Hover => self.ask_language_server(lsp::Request::Hover),
+            _ => self.next_diagnostic(),
+        }
+    }
+
+    #[cfg(not(feature = "lsp"))]
+    fn language_server_action(&mut self, _action: Action) {
+        self.set_status("Built without language servers, the lsp feature".to_string());
+    }
+
+    /// Asks the buffer's language server `request` about the position of the cursor. The
+    /// answer is acted on when it comes.
+    #[cfg(feature = "lsp")]
+    fn ask_language_server(&mut self, request: lsp::Request) {
+        let Some((filetype, path)) = self.language_document() else {
+            return self.set_status("No language server for this buffer".to_string());
+        };
+        let cursor = self.buffer.cursor_position();
+        let line = self
+            .buffer
+            .line(cursor.cursor_row)
+            .map_or("", |line| line.content());
+        let col = lsp::utf16_col(line, cursor.cursor_col);
+        let root = self.working_dir().to_path_buf();
+        let asked = match self.lsp.client(filetype.name, &root) {
+            Ok(Some(client)) => client
+                .ask(request, &path, cursor.cursor_row, col)
+                .map_err(|err| format!("Language server for {} stopped: {}", filetype.name, err)),
+            Ok(None) => Err(format!("No language server for {} files", filetype.name)),
+            Err(err) => Err(err),
+        };
+        match asked {
+            Ok(id) => self.lsp_request = Some((filetype.name.to_string(), id, request)),
+            Err(err) => self.set_status(err),
+        }
+    }
+
+    /// Acts on the answer to the request awaiting one, ignoring answers that came too late.
+    #[cfg(feature = "lsp")]
+    fn language_server_answered(&mut self, filetype: &str, response: lsp::Response) {
+        let request = match &self.lsp_request {
+            Some((name, id, request)) if name == filetype && *id == response.id => *request,
+            _ => return,
+        };
+        self.lsp_request = None;
+        let result = match response.result {
+            Ok(result) => result,
+            Err(err) => return self.set_status(format!("Language server: {}", err)),
+        };
+        match request {
+            lsp::Request::Definition => match lsp::definition_location(&result) {
+                Some((path, line, col)) => {
+                    self.open_path(&path.to_string_lossy());
+                    let row = min(line, self.buffer.line_count().saturating_sub(1));
+                    let content = self.buffer.line(row).map_or("", |line| line.content());
+                    let col = lsp::byte_col(content, col);
+                    self.anchor = None;
+                    self.place_cursor_at(row, col);
+                }
+                None => self.set_status("No definition found".to_string()),
+            },
+            lsp::Request::Hover => {
+                let text = lsp::hover_text(&result);
+                if text.trim().is_empty() {
+                    self.set_status("Nothing to show about this".to_string());
+                } else {
+                    self.popup = self.hover_lines(&text);
+                }
+            }
+        }
+    }
+
+    /// The popup showing `text`, a language server's description of a name, leaving out the
+    /// fences of Markdown code blocks. Longer descriptions are cut short.
+    #[cfg(feature = "lsp")]
+    fn hover_lines(&self, text: &str) -> (Vec<String>, usize, usize) {
+        let lines = text
+            .lines()
+            .filter(|line| !line.trim_start().starts_with("```"))
+            .map(|line| {
+                line.replace('\t', "    ")
+                    .chars()
+                    .filter(|ch| !ch.is_control())
+                    .collect::<String>()
+            })
+            .collect::<Vec<String>>();
+        let first = lines.iter().position(|line| !line.trim().is_empty());
+        let last = lines.iter().rposition(|line| !line.trim().is_empty());
+        let lines = match (first, last) {
+            (Some(first), Some(last)) => &lines[first..=last],
+            _ => &[],
+        };
+        let lines = &lines[..min(lines.len(), HOVER_HEIGHT)];
+        let width = lines
+            .iter()
+            .map(|line| line.chars().count())
+            .max()
+            .unwrap_or(0)
+            + 2;
+        let width = min(width, self.terminal.cols());
+        let lines = lines
+            .iter()
+            .map(|line| {
+                let text = format!(" {}", line)
+                    .chars()
+                    .chain(std::iter::repeat(' '))
+                    .take(width)
+                    .collect::<String>();
+                format!("{}{}\x1b[m", self.palette.status_bar, text)
+            })
+            .collect();
+        (lines, width, 0)
+    }
+
+    /// Diagnostics from the language server of the buffer, if it's running.
+    #[cfg(feature = "lsp")]
+    fn buffer_diagnostics(&self) -> Vec<lsp::Diagnostic> {
+        let Some((filetype, path)) = self.language_document() else {
+            return Vec::new();
+        };
+        let mut found = self
+            .lsp
+            .running(filetype.name)
+            .map_or(Vec::new(), |client| client.diagnostics(&path).to_vec());
+        found.sort_by_key(|diagnostic| (diagnostic.start, diagnostic.severity));
+        found
+    }
+
+    /// Path of the file shown, with the `git` option on, for asking git about it.
+    fn git_file(&self) -> Option<PathBuf> {
+        let filename = self.buffer.filename().as_ref()?;
+        if self.git.is_none() || !self.buffer.kind().is_file_backed() {
+            return None;
+        }
+        Some(self.resolve_path(filename))
+    }
+
+    fn forget_git(&mut self) {
+        if let Some(tracker) = &mut self.git {
+            tracker.forget();
+        }
+    }
+
+    /// Has git asked about the file shown, if what it said before is out of date.
+    fn update_git(&mut self) {
+        let Some(path) = self.git_file() else {
+            return;
+        };
+        let buffer = &self.buffer;
+        if let Some(tracker) = &mut self.git {
+            tracker.update(&path, buffer.changes(), || {
+                (0..buffer.line_count())
+                    .filter_map(|row| buffer.line(row))
+                    .map(Line::content)
+                    .collect()
+            });
+        }
+    }
+
+    /// Rows of the buffer changed since the last commit, and how, for the gutter.
+    fn git_changes(&self) -> Vec<(usize, git::LineChange)> {
+        match (self.git_file(), &self.git) {
+            (Some(path), Some(tracker)) => tracker.marks(&path).to_vec(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Shows who last changed the cursor's line and in what commit, going by the buffer as
+    /// it is, changes not saved yet included.
+    fn blame_line(&mut self) {
+        let Some(path) = self
+            .buffer
+            .filename()
+            .as_ref()
+            .map(|name| self.resolve_path(name))
+        else {
+            return self.set_status("Buffer has no file to blame".to_string());
+        };
+        let row = self.buffer.cursor_position().cursor_row;
+        let status = match git::blame(&path, row, &self.buffer.rows_to_string()) {
+            Some(blame) => blame.describe(),
+            None => format!("git can't blame {}", path.display()),
+        };
+        self.set_status(status);
+    }
+
+    /// Row of each diagnostic for the buffer and the sign of its severity, for the gutter and
+    /// the status bar. The most serious of those on a row comes first.
+    #[cfg(feature = "lsp")]
+    fn diagnostic_signs(&self) -> Vec<(usize, char)> {
+        let mut signs = self
+            .buffer_diagnostics()
+            .iter()
+            .map(|diagnostic| (diagnostic.start.0, diagnostic.severity))
+            .collect::<Vec<_>>();
+        signs.sort();
+        signs
+            .into_iter()
+            .map(|(row, severity)| (row, severity.sign()))
+            .collect()
+    }
+
+    #[cfg(not(feature = "lsp"))]
+    fn diagnostic_signs(&self) -> Vec<(usize, char)> {
+        Vec::new()
+    }
+
+    /// Moves to the first diagnostic after the cursor, going round to the first in the
+    /// buffer, and shows its message.
+    #[cfg(feature = "lsp")]
+    fn next_diagnostic(&mut self) {
+        let found = self.buffer_diagnostics();
+        let cursor = self.buffer.cursor_position();
+        let at = |diagnostic: &lsp::Diagnostic| {
+            let (row, col) = diagnostic.start;
+            let content = self.buffer.line(row).map_or("", |line| line.content());
+            (row, lsp::byte_col(content, col))
+        };
+        let next = found
+            .iter()
+            .find(|diagnostic| at(diagnostic) > (cursor.cursor_row, cursor.cursor_col))
+            .or(found.first());
+        let Some(diagnostic) = next else {
+            return self.set_status("No problems found".to_string());
+        };
+        let (row, col) = at(diagnostic);
+        let message = format!(
+            "{}: {}",
+            diagnostic.severity.sign(),
+            diagnostic.message.lines().next().unwrap_or_default()
+        );
+        self.anchor = None;
+        self.place_cursor_at(min(row, self.buffer.line_count().saturating_sub(1)), col);
+        self.set_status(message);
+    }
+
+    /// What the overlay shows of `finder`: as many matches as fit in half the screen, up to
+    /// the `selected` one, with the best next to the prompt below them. Matched characters are
+    /// highlighted as search matches.
+    fn finder_lines(&self, finder: &Finder, selected: usize) -> Vec<String> {
+        let height = (self.terminal.rows().saturating_sub(1) / 2).max(1);
+        let width = self.terminal.cols();
+        let first = (selected + 1).saturating_sub(height);
+        let mut lines = finder
+            .matches()
+            .enumerate()
+            .skip(first)
+            .take(height)
+            .map(|(idx, (file, matched))| {
+                let marker = if idx == selected { "> " } else { "  " };
+                let text = format!("{}{}", marker, file)
+                    .chars()
+                    .chain(std::iter::repeat(' '))
+                    .take(width)
+                    .collect::<String>();
+                let highlights = (0..width)
+                    .map(|col| match col.checked_sub(marker.len()) {
+                        Some(at) if matched.contains(&at) => Highlight::Match,
+                        _ if idx == selected => Highlight::Selection,
+                        _ => Highlight::Normal,
+                    })
+                    .collect::<Vec<Highlight>>();
+                highlight::paint(&text, &highlights, self.palette)
+            })
+            .collect::<Vec<String>>();
+        lines.reverse();
+        lines
+    }
+
+    /// Lists the entries of `dir` in a buffer, to open files from and create, rename and delete
+    /// them. A directory listed already has its buffer brought up to date instead.
+    fn explore(&mut self, dir: &Path) {
+        let dir = self.resolve_path(dir);
+        let dir = dir.canonicalize().unwrap_or(dir);
+        let entries = match list_dir(&dir) {
+            Ok(entries) => entries,
+            Err(err) => return self.set_status(format!("Can't list {}: {}", dir.display(), err)),
+        };
+        match self.find_buffer(&dir) {
+            Some(idx) => {
+                self.switch_buffer(idx);
+                let rows = self.buffer.reload_text(&entries.join("\n"));
+                self.follow_rows(&rows);
+            }
+            None => {
+                self.open_buffer(BufferKind::Directory, entries);
+                self.buffer
+                    .set_filename(Some(dir.to_string_lossy().to_string()));
+            }
+        }
+        self.set_status(
+            "Enter opens, - goes up, + creates, r renames, d deletes, g lists again".to_string(),
+        );
+    }
+
+    /// Lists the lines of the files under the working directory that the regular expression
+    /// `query` matches, in a results buffer to jump to them from with Enter. Files left out of
+    /// the file finder are left out here too. Searching again lists the new matches in the
+    /// same buffer.
+    fn grep(&mut self, query: &str) {
+        if query.is_empty() {
+            return self.set_status("Usage: grep PATTERN".to_string());
+        }
+        let pattern = match Pattern::regex(query, false) {
+            Ok(pattern) => pattern,
+            Err(err) => return self.set_status(format!("Bad regex: {}", err)),
+        };
+        let root = self.working_dir().to_path_buf();
+        let files = finder::walk(&root, FINDER_LIMIT);
+        let found = grep::search(&root, &files, &pattern, GREP_LIMIT);
+        if found.is_empty() {
+            return self.set_status(format!("No matches for {}", query));
+        }
+        let mut files = found.iter().map(|found| &found.file).collect::<Vec<_>>();
+        files.dedup();
+        let status = format!(
+            "{}{} match{} in {} file{}. Enter jumps to one",
+            if found.len() == GREP_LIMIT {
+                "First "
+            } else {
+                ""
+            },
+            found.len(),
+            if found.len() == 1 { "" } else { "es" },
+            files.len(),
+            if files.len() == 1 { "" } else { "s" }
+        );
+        let lines = found
+            .iter()
+            .map(grep::Match::to_line)
+            .collect::<Vec<String>>();
+        let results =
+            (0..self.buffers.len()).find(|&idx| self.buffer_at(idx).kind() == BufferKind::Results);
+        match results {
+            Some(idx) => {
+                self.switch_buffer(idx);
+                self.buffer.reload_text(&lines.join("\n"));
+                self.buffer.goto(1, 1);
+            }
+            None => self.open_buffer(BufferKind::Results, lines),
+        }
+        self.set_status(status);
+    }
+
+    /// Opens the file of the line of results the cursor is on, at the match. Returns false if
+    /// the line isn't one.
+    fn open_result(&mut self) -> bool {
+        let row = self.buffer.cursor_position().cursor_row;
+        let Some((file, row, col)) = self
+            .buffer
+            .line(row)
+            .and_then(|line| grep::location(line.content()))
+            .map(|(file, row, col)| (file.to_string(), row, col))
+        else {
+            return false;
+        };
+        self.open_path(&file);
+        if self.buffer.filename().is_some() {
+            self.buffer.goto(row, col);
+            self.buffer.align_view(ViewAlign::Center, self.rows());
+        }
+        true
+    }
+
+    /// Handles a key of view mode, returning whether it was one.
+    fn view_key(&mut self, key: &Key) -> Result<bool> {
+        match key {
+            Key::Printable('j') => self.run_action(Action::ScrollDown)?,
+            Key::Printable('k') => self.run_action(Action::ScrollUp)?,
+            Key::Printable(' ') | Key::Printable('f') => self.run_action(Action::FullPageDown)?,
+            Key::Printable('b') => self.run_action(Action::FullPageUp)?,
+            Key::Printable('d') => self.run_action(Action::HalfPageDown)?,
+            Key::Printable('u') => self.run_action(Action::HalfPageUp)?,
+            Key::Printable('g') => self.buffer.goto(1, 1),
+            Key::Printable('G') => self.buffer.goto(self.buffer.line_count(), 1),
+            Key::Printable('/') => self.run_action(Action::Find)?,
+            Key::Printable('?') => self.run_action(Action::FindReverse)?,
+            Key::Printable('n') => self.search_next(SearchDirection::Forward),
+            Key::Printable('N') => self.search_next(SearchDirection::Reverse),
+            Key::Printable('v') => {
+                self.buffer.set_viewing(false);
+                self.set_status(if self.buffer.is_read_only() {
+                    "View mode off, still read-only".to_string()
+                } else {
+                    "View mode off".to_string()
+                });
+            }
+            Key::Printable('q') if self.buffers.len() > 1 => self.close_buffer(),
+            Key::Printable('q') => self.run_action(Action::Quit)?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Handles the keys a directory listing has for its own. Returns false for any other key.
+    fn directory_key(&mut self, key: &Key) -> bool {
+        let dir = match self.buffer.filename() {
+            Some(dir) => dir.clone(),
+            None => return false,
+        };
+        let row = self.buffer.cursor_position().cursor_row;
+        let entry = self
+            .buffer
+            .line(row)
+            .map(|line| line.content().to_string())
+            .unwrap_or_default();
+        let path = dir.join(entry.trim_end_matches('/'));
+        match key {
+            Key::Newline if entry.is_empty() => {}
+            Key::Newline if entry == "../" => self.explore(dir.parent().unwrap_or(&dir)),
+            Key::Newline if entry.ends_with('/') => self.explore(&path),
+            Key::Newline => self.open_path(&path.to_string_lossy()),
+            Key::Printable('-') => self.explore(dir.parent().unwrap_or(&dir)),
+            Key::Printable('g') => self.explore(&dir),
+            Key::Printable('+') => {
+                let mut name = String::new();
+                while !self
+                    .prompt_incremental("New file (end in / for a directory): ", &mut name)
+                    .0
+                {}
+                if name.is_empty() {
+                    return true;
+                }
+                let created = if name.ends_with('/') {
+                    std::fs::create_dir_all(dir.join(&name))
+                } else {
+                    std::fs::OpenOptions::new()
+                        .write(true)
+                        .create_new(true)
+                        .open(dir.join(&name))
+                        .map(|_| ())
+                };
+                match created {
+                    Ok(()) => {
+                        self.relist(&dir, &name);
+                        self.set_status(format!("Created {}", name));
+                    }
+                    Err(err) => self.set_status(format!("Can't create {}: {}", name, err)),
+                }
+            }
+            Key::Printable('r') if entry.is_empty() || entry == "../" => {}
+            Key::Printable('r') => {
+                let mut name = entry.trim_end_matches('/').to_string();
+                while !self
+                    .prompt_incremental(&format!("Rename {} to: ", entry), &mut name)
+                    .0
+                {}
+                if name.is_empty() {
+                    return true;
+                }
+                match std::fs::rename(&path, dir.join(&name)) {
+                    Ok(()) => {
+                        self.relist(&dir, &name);
+                        self.set_status(format!("Renamed {} to {}", entry, name));
+                    }
+                    Err(err) => self.set_status(format!("Can't rename {}: {}", entry, err)),
+                }
+            }
+            Key::Printable('d') if entry.is_empty() || entry == "../" => {}
+            Key::Printable('d') => {
+                let prompt = if entry.ends_with('/') {
+                    format!("Delete {} and everything in it? (y/n) ", entry)
+                } else {
+                    format!("Delete {}? (y/n) ", entry)
+                };
+                if self.prompt_choice(&prompt, "yn") != Some('y') {
+                    return true;
+                }
+                let deleted = if entry.ends_with('/') {
+                    std::fs::remove_dir_all(&path)
+                } else {
+                    std::fs::remove_file(&path)
+                };
+                match deleted {
+                    Ok(()) => {
+                        self.explore(&dir);
+                        self.set_status(format!("Deleted {}", entry));
+                    }
+                    Err(err) => self.set_status(format!("Can't delete {}: {}", entry, err)),
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// Lists `dir` again with the cursor on the entry for `name`, which may be in a directory
+    /// under it.
+    fn relist(&mut self, dir: &Path, name: &str) {
+        self.explore(dir);
+        let first = name.split('/').next().unwrap_or(name);
+        let row = (0..self.buffer.line_count()).find(|&row| {
+            self.buffer
+                .line(row)
+                .is_some_and(|line| line.content().trim_end_matches('/') == first)
+        });
+        if let Some(row) = row {
+            self.buffer.goto(row + 1, 1);
+        }
+    }
+
+    /// Opens `file`, relative to the working directory, as `open` does, saying in the message
+    /// bar if it's new or can't be read.
+    fn open_path(&mut self, file: &str) {
+        let path = self.resolve_path(Path::new(file));
+        let exists = path.exists();
+        match self.open(Some(path.to_string_lossy().to_string())) {
+            Ok(()) if !exists => self.set_status(format!("{} is a new file", file)),
+            Ok(()) => {}
+            Err(err) => self.set_status(format!("Can't open {}: {}", file, err)),
+        }
+    }
+
+    /// Opens a buffer of `kind` holding `lines` and shows it, for text that doesn't come from
+    /// a file.
+    pub fn open_buffer(&mut self, kind: BufferKind, lines: impl IntoIterator<Item = String>) {
+        let mut buffer = Buffer::with_kind(kind);
+        buffer.set_tab_stop(self.tab_stop);
+        buffer.set_indent_spaces(self.indent_spaces);
+        buffer.set_wrap(self.wrap);
+        buffer.set_scroll_margins(self.scrolloff, self.sidescrolloff);
+        for line in lines {
+            buffer.append_row(line);
+        }
+        buffer.not_dirty();
+        self.add_buffer(buffer, None);
+    }
+
+    /// Shows `bytes`, read from standard input, in a buffer of its own.
+    pub fn open_stdin(&mut self, bytes: &[u8]) -> Result<()> {
+        let mut buffer = Buffer::with_kind(BufferKind::Stdin);
+        buffer.set_tab_stop(self.tab_stop);
+        buffer.set_indent_spaces(self.indent_spaces);
+        buffer.set_wrap(self.wrap);
+        buffer.set_scroll_margins(self.scrolloff, self.sidescrolloff);
+        buffer.read_bytes(bytes, self.encoding)?;
+        buffer.detect_filetype();
+        if self.detect_indent {
+            buffer.detect_indent();
+        }
+        buffer.set_elastic_tabs(self.elastic_tabs);
+        buffer.not_dirty();
+        self.add_buffer(buffer, None);
+        self.opened()
+    }
+
+    /// Makes quitting write the text read from standard input, or else the buffer shown, to
+    /// `output`, for the editor to work as a filter in a pipeline. Its changes then count as
+    /// kept rather than asked about.
+    pub fn set_output(&mut self, output: File) {
+        self.output = Some(output);
+    }
+
+    /// Shows `buffer`, in place of the current one if that's an empty buffer nobody typed into
+    /// yet, or else after the other buffers.
+    fn add_buffer(&mut self, buffer: Buffer, fallback: Option<Cursor>) {
+        let untouched = self.buffer.kind() == BufferKind::File
+            && self.buffer.filename().is_none()
+            && self.buffer.changes() == 0;
+        if !untouched {
+            self.push_jump();
+        }
+        self.leave_buffer(false);
+        let autosaved_at = buffer.changes();
+        let previous = std::mem::replace(&mut self.buffer, buffer);
+        if !untouched {
+            self.buffers[self.current_buffer] = (previous, self.autosaved_at);
+            self.buffers.push((Buffer::new(), 0));
+            self.current_buffer = self.buffers.len() - 1;
+        }
+        self.autosaved_at = autosaved_at;
+        self.enter_buffer(fallback);
+    }
+
+    /// Index of the buffer holding `file`, if any.
+    fn find_buffer(&self, file: &Path) -> Option<usize> {
+        let file = self.resolve_path(file);
+        let file = file.canonicalize().unwrap_or(file);
+        (0..self.buffers.len()).find(|&idx| {
+            let buffer = self.buffer_at(idx);
+            buffer.filename().as_ref().is_some_and(|name| {
+                let name = self.resolve_path(name);
+                name.canonicalize().unwrap_or(name) == file
+            })
+        })
+    }
+
+    fn buffer_at(&self, idx: usize) -> &Buffer {
+        if idx == self.current_buffer {
+            &self.buffer
+        } else {
+            &self.buffers[idx].0
+        }
+    }
+
+    /// Shows the buffer at `idx` in the order they were opened, counting from 0. Each buffer
+    /// keeps its own cursor, and each window its own view of the buffers it showed before.
+    pub fn switch_buffer(&mut self, idx: usize) {
+        if idx == self.current_buffer || idx >= self.buffers.len() {
+            return;
+        }
+        self.push_jump();
+        self.leave_buffer(false);
+        let (buffer, autosaved_at) = std::mem::take(&mut self.buffers[idx]);
+        let previous = std::mem::replace(&mut self.buffer, buffer);
+        self.buffers[self.current_buffer] = (previous, self.autosaved_at);
+        self.current_buffer = idx;
+        self.autosaved_at = autosaved_at;
+        let cursor = self.buffer.cursor_position();
+        self.enter_buffer(Some(cursor));
+        self.disk_checked_at = None;
+    }
+
+    fn cycle_buffer(&mut self, forward: bool) {
+        let count = self.buffers.len();
+        if count == 1 {
+            return self.set_status("No other buffers".to_string());
+        }
+        let idx = if forward {
+            (self.current_buffer + 1) % count
+        } else {
+            (self.current_buffer + count - 1) % count
+        };
+        self.switch_buffer(idx);
+    }
+
+    /// Name of the buffer at `idx` for lists, with a `+` if it has unsaved changes.
+    fn buffer_label(&self, idx: usize) -> String {
+        let buffer = self.buffer_at(idx);
+        let name = buffer.filename().as_ref().map_or_else(
+            || buffer.kind().name().to_string(),
+            |file| file.to_string_lossy().to_string(),
+        );
+        let dirty = if buffer.is_dirty() { "+" } else { "" };
+        format!("{}:{}{}", idx + 1, name, dirty)
+    }
+
+    /// Asks for a line to go to, as `goto_line` takes it.
+    fn prompt_goto(&mut self) {
+        let mut reply = String::new();
+        while !self.prompt_incremental("Go to line: ", &mut reply).0 {}
+        if !reply.is_empty() {
+            self.goto_line(&reply);
+        }
+    }
+
+    /// Moves the cursor to `target`, a line number or `LINE:COLUMN`, counting from 1, with the
+    /// line in the middle of the view. Numbers past the end go to the last line or column.
+    fn goto_line(&mut self, target: &str) {
+        let mut numbers = target.trim().splitn(2, ':').map(str::parse::<usize>);
+        let (row, col) = match (numbers.next(), numbers.next()) {
+            (Some(Ok(row)), None) => (row, 1),
+            (Some(Ok(row)), Some(Ok(col))) => (row, col),
+            _ => return self.set_status(format!("Not a line number: {}", target.trim())),
+        };
+        self.push_jump();
+        self.buffer.goto(row, col);
+        self.buffer.align_view(ViewAlign::Center, self.rows());
+    }
+
+    /// Adds the cursor's place to the jump list, ahead of a jump away from it.
+    fn push_jump(&mut self) {
+        let cursor = self.buffer.cursor_position();
+        self.push_jump_from((cursor.cursor_row, cursor.cursor_col));
+    }
+
+    /// Adds place `at` in the buffer to the jump list, in place of any other on its line, and
+    /// stops going through the list.
+    fn push_jump_from(&mut self, at: Position) {
+        let jump = Jump {
+            file: self.buffer.filename().clone(),
+            kind: self.buffer.kind(),
+            at,
+        };
+        self.jumps.retain(|known| {
+            (&known.file, known.kind, known.at.0) != (&jump.file, jump.kind, jump.at.0)
+        });
+        self.jumps.push(jump);
+        if self.jumps.len() > JUMP_LIST_SIZE {
+            self.jumps.remove(0);
+        }
+        self.jump_idx = self.jumps.len();
+    }
+
+    /// Goes `count` places back through the jump list, or forward unless `back`. The place
+    /// first gone back from goes in the list too, to come forward to again. Places in buffers
+    /// closed since are dropped on the way.
+    fn jump(&mut self, back: bool, count: usize) {
+        if back && self.jump_idx == self.jumps.len() {
+            self.push_jump();
+            self.jump_idx = self.jumps.len() - 1;
+        }
+        let target = if back {
+            self.jump_idx.checked_sub(count)
+        } else {
+            Some(self.jump_idx + count).filter(|&idx| idx < self.jumps.len())
+        };
+        let Some(idx) = target else {
+            return self.set_status(format!(
+                "At the {} of the jump list",
+                if back { "start" } else { "end" }
+            ));
+        };
+        let jump = self.jumps[idx].clone();
+        let shown = |buffer: &Buffer| buffer.filename() == &jump.file && buffer.kind() == jump.kind;
+        if !shown(&self.buffer) {
+            let Some(buffer) = (0..self.buffers.len()).find(|&idx| shown(self.buffer_at(idx)))
+            else {
+                self.jumps.remove(idx);
+                self.jump_idx = idx.min(self.jumps.len());
+                return self.set_status("That buffer is closed".to_string());
+            };
+            // Switching buffers is a jump itself, which mustn't go in the list gone through.
+            let jumps = std::mem::take(&mut self.jumps);
+            self.switch_buffer(buffer);
+            self.jumps = jumps;
+        }
+        self.jump_idx = idx;
+        self.move_to(jump.at);
+    }
+
+    /// Moves the cursor to `at`, or as close to it as the text now goes, with the line in the
+    /// middle of the view if it was out of it.
+    fn move_to(&mut self, (row, col): Position) {
+        let mut cursor = self.buffer.cursor_position();
+        let shown = cursor.row_offset..cursor.row_offset + self.rows();
+        cursor.cursor_row = row;
+        cursor.cursor_col = col;
+        cursor.desired_col = None;
+        self.buffer.set_cursor_position(cursor);
+        if !shown.contains(&self.buffer.cursor_position().cursor_row) {
+            self.buffer.align_view(ViewAlign::Center, self.rows());
+        }
+    }
+
+    /// Names the cursor's place `name` in this buffer.
+    fn set_mark(&mut self, name: char) {
+        if !name.is_alphabetic() {
+            return self.set_status("Marks are named by letters".to_string());
+        }
+        self.buffer.set_mark(name);
+        self.set_status(format!("Mark {} set", name));
+    }
+
+    /// Goes to the place `name` names in this buffer, or with `line` to the start of the text
+    /// on its line.
+    fn goto_mark(&mut self, name: char, line: bool) {
+        let Some((row, col)) = self.buffer.mark(name) else {
+            return self.set_status(format!("No mark {}", name));
+        };
+        let col = match self.buffer.line(row) {
+            Some(text) if line => {
+                let content = text.content();
+                content.len() - content.trim_start_matches([' ', '\t']).len()
+            }
+            _ => col,
+        };
+        self.push_jump();
+        self.move_to((row, col));
+    }
+
+    /// The marks of this buffer, each with its line and column counting from 1, for the
+    /// message bar.
+    fn marks_summary(&self) -> String {
+        if self.buffer.marks().is_empty() {
+            return "No marks set".to_string();
+        }
+        let marks = self
+            .buffer
+            .marks()
+            .iter()
+            .map(|&(name, (row, col))| {
+                let col = self.buffer.line(row).map_or(0, |line| {
+                    let content = line.content();
+                    content[..line.floor_char_boundary(col)].chars().count()
+                });
+                format!("{} {}:{}", name, row + 1, col + 1)
+            })
+            .collect::<Vec<String>>();
+        format!("Marks: {}", marks.join(", "))
+    }
+
+    /// Asks for a buffer by its number or part of its name, and switches to it.
+    fn pick_buffer(&mut self) {
+        let labels = (0..self.buffers.len())
+            .map(|idx| self.buffer_label(idx))
+            .collect::<Vec<String>>();
+        let prompt = format!("Buffer ({}): ", labels.join(" "));
+        let mut reply = String::new();
+        while !self.prompt_incremental(&prompt, &mut reply).0 {}
+        if reply.is_empty() {
+            return;
+        }
+        match self.buffer_named(&reply) {
+            Some(idx) => self.switch_buffer(idx),
+            None => self.set_status(format!("No buffer matches {}", reply)),
+        }
+    }
+
+    /// The buffer `name` picks: the one by that number, counting from 1, or else the first
+    /// with a name containing it.
+    fn buffer_named(&self, name: &str) -> Option<usize> {
+        match name.parse::<usize>() {
+            Ok(number) => number
+                .checked_sub(1)
+                .filter(|&idx| idx < self.buffers.len()),
+            Err(_) => (0..self.buffers.len()).find(|&idx| {
+                let label = self.buffer_label(idx);
+                label[label.find(':').map_or(0, |colon| colon + 1)..].contains(name)
+            }),
+        }
+    }
+
+    /// Closes the current buffer, asking first if it has unsaved changes, and shows the next
+    /// one. Closing the last buffer leaves an empty one.
+    fn close_buffer(&mut self) {
+        if self.buffer.is_dirty() {
+            let choice = self.prompt_choice(
+                "Buffer has unsaved changes. (c)lose anyway or (a)bort? ",
+                "ca",
+            );
+            self.set_status(String::new());
+            if choice != Some('c') {
+                return;
+            }
+        }
+        self.leave_buffer(true);
+        self.remember_position();
+        if !self.buffer.is_read_only() {
+            self.buffer.remove_swap();
+        }
+        self.buffers.remove(self.current_buffer);
+        if self.buffers.is_empty() {
+            self.buffers.push((Buffer::new(), 0));
+        }
+        self.current_buffer = self.current_buffer.min(self.buffers.len() - 1);
+        let (buffer, autosaved_at) = std::mem::take(&mut self.buffers[self.current_buffer]);
+        self.buffer = buffer;
+        self.autosaved_at = autosaved_at;
+        let cursor = self.buffer.cursor_position();
+        self.enter_buffer(Some(cursor));
+    }
+
+    /// Has every window remember its view of the buffer's file, before the buffer is replaced.
+    /// Autosave set to save on switching buffers saves it first, unless it's `closing` and its
+    /// changes are being thrown away.
+    fn leave_buffer(&mut self, closing: bool) {
+        if self.swap_files && self.buffer.swap_pending() && !self.buffer.is_read_only() {
+            self.write_swap();
+        }
+        let switching = !closing && self.keep_alive();
+        if switching && self.autosave.buffer_switch && self.is_autosave_pending() {
+            if let Err(err) = self.autosave() {
+                self.set_status(format!("Autosave failed! {}", err));
+            }
+        }
+        let file = match self.buffer.filename() {
+            Some(file) => file.clone(),
+            None => return,
+        };
+        self.windows[self.active].cursor = self.buffer.cursor_position();
+        for window in &mut self.windows {
+            window.remember_view(file.clone(), window.cursor);
+        }
+    }
+
+    /// Gives every window its view of the newly loaded buffer: the one it remembers from
+    /// showing the same file before, or else `fallback`, or else the top of the buffer.
+    fn enter_buffer(&mut self, fallback: Option<Cursor>) {
+        self.cursors.clear();
+        let file = self.buffer.filename().clone();
+        for window in &mut self.windows {
+            window.cursor = file
+                .as_ref()
+                .and_then(|file| window.recall_view(file))
+                .or(fallback)
+                .unwrap_or_default();
+        }
+        self.buffer
+            .set_cursor_position(self.windows[self.active].cursor);
+        self.anchor = None;
+    }
+
+    fn remember_position(&mut self) {
+        if let Some(file) = self
+            .buffer
+            .filename()
+            .as_ref()
+            .filter(|_| self.restore_positions)
+        {
+            if let Err(err) = positions::store(file, self.buffer.cursor_position()) {
+                self.set_status(format!("Can't remember the cursor position: {}", err));
+            }
+        }
+    }
+
+    /// Goes through the buffers with unsaved changes, asking whether to save each one, then
+    /// quits unless that's cancelled. The quit keys pressed again quit without saving the
+    /// rest. With standard output a pipe, the buffer for it goes there.
+    fn quit(&mut self) {
+        let keys = self.action_keys(Action::Quit);
+        let output = self.output.is_some().then(|| {
+            (0..self.buffers.len())
+                .find(|&idx| self.buffer_at(idx).kind() == BufferKind::Stdin)
+                .unwrap_or(self.current_buffer)
+        });
+        for idx in 0..self.buffers.len() {
+            if !self.buffer_at(idx).is_dirty() || output == Some(idx) {
+                continue;
+            }
+            self.switch_buffer(idx);
+            self.set_status(format!(
+                "{} has unsaved changes. (s)ave, (d)iscard or (c)ancel? {} again quits anyway",
+                self.buffer_label(idx),
+                keys
+            ));
+            self.refresh_screen();
+            let key = loop {
+                match self.read_prompt_key() {
+                    key @ (Key::Printable('s' | 'd' | 'c') | Key::Escape) => break key,
+                    key if self.key_bindings.contains(&(key.clone(), Action::Quit)) => break key,
+                    _ => {}
+                }
+            };
+            self.set_status(String::new());
+            match key {
+                Key::Printable('s') => {
+                    if self.save().is_err() || self.buffer.is_dirty() {
+                        return;
+                    }
+                }
+                Key::Printable('d') => {}
+                Key::Printable('c') | Key::Escape => {
+                    return self.set_status("Quit cancelled".to_string());
+                }
+                _ => break,
+            }
+        }
+        if let Some(idx) = output {
+            let written = self.buffer_at(idx).to_bytes().and_then(|bytes| {
+                let output = self.output.as_mut().expect("output to write to");
+                output.write_all(&bytes).and_then(|()| output.flush())
+            });
+            if let Err(err) = written {
+                return self.set_status(format!("Can't write to standard output: {}", err));
+            }
+        }
+        self.quit = true;
+        self.save_session();
+        self.close_files();
+    }
+
+    /// Saves every buffer with unsaved changes, and shows the one that was shown before if
+    /// they all got saved. Returns whether they did.
+    fn save_all(&mut self) -> bool {
+        let shown = self.current_buffer;
+        for idx in 0..self.buffers.len() {
+            if !self.buffer_at(idx).is_dirty() {
+                continue;
+            }
+            self.switch_buffer(idx);
+            if self.save().is_err() || self.buffer.is_dirty() {
+                return false;
+            }
+        }
+        self.switch_buffer(shown);
+        true
+    }
+
+    /// Saves the files open and the windows as the session of the directory the editor was
+    /// started in. Quitting with no files open keeps the session saved before.
+    fn save_session(&mut self) {
+        let Some(dir) = std::env::current_dir().ok().filter(|_| self.sessions) else {
+            return;
+        };
+        let mut files = Vec::new();
+        let mut current = None;
+        for idx in 0..self.buffers.len() {
+            let buffer = self.buffer_at(idx);
+            if let Some(name) = buffer
+                .filename()
+                .as_ref()
+                .filter(|_| buffer.kind().is_file_backed())
+            {
+                if idx == self.current_buffer {
+                    current = Some(files.len());
+                }
+                files.push((self.resolve_path(name), buffer.cursor_position()));
+            }
+        }
+        if files.is_empty() {
+            return;
+        }
+        self.windows[self.active].cursor = self.buffer.cursor_position();
+        let session = Session {
+            cwd: self.cwd.clone(),
+            files,
+            current,
+            layout: self.zoomed.as_ref().unwrap_or(&self.layout).clone(),
+            windows: self
+                .windows
+                .iter()
+                .map(|window| (window.cursor, window.cwd.clone()))
+                .collect(),
+            active: self.active,
+        };
+        if let Err(err) = session::store(&dir, &session) {
+            self.set_status(format!("Can't save the session: {}", err));
+        }
+    }
+
+    /// Opens the files and windows of the session saved on quitting in the directory the
+    /// editor was started in, as `milo --restore` does. Files gone since are left out. Returns
+    /// whether there was a session.
+    pub fn restore_session(&mut self) -> Result<bool> {
+        let Ok(dir) = std::env::current_dir() else {
+            return Ok(false);
+        };
+        let Some(session) = session::load(&dir) else {
+            return Ok(false);
+        };
+        if session.cwd.is_dir() {
+            self.cwd = session.cwd;
+        }
+        let mut current = None;
+        for (idx, (file, cursor)) in session.files.iter().enumerate() {
+            if !file.is_file() {
+                continue;
+            }
+            // Named as they would be on the command line.
+            let name = file.strip_prefix(&dir).unwrap_or(file);
+            self.open(Some(name.to_string_lossy().to_string()))?;
+            self.buffer.set_cursor_position(*cursor);
+            if session.current == Some(idx) {
+                current = Some(self.current_buffer);
+            }
+        }
+        if let Some(idx) = current {
+            self.switch_buffer(idx);
+        }
+        // The windows' views were of the file shown, and are no use if it's gone.
+        let shown = self.buffer.cursor_position();
+        self.windows = session
+            .windows
+            .iter()
+            .map(|(cursor, cwd)| Window {
+                cursor: if current.is_some() { *cursor } else { shown },
+                cwd: cwd.clone(),
+                ..Window::default()
+            })
+            .collect();
+        self.layout = session.layout;
+        self.zoomed = None;
+        self.active = session.active;
+        self.buffer
+            .set_cursor_position(self.windows[self.active].cursor);
+        self.arrange_windows();
+        Ok(true)
+    }
+
+    /// Remembers the cursor position in every buffer's file and deletes its swap file, on the
+    /// way out.
+    fn close_files(&mut self) {
+        for idx in 0..self.buffers.len() {
+            self.switch_buffer(idx);
+            self.remember_position();
+        }
+        // Switching buffers writes their swap files, so these go once all are left.
+        for idx in 0..self.buffers.len() {
+            let buffer = self.buffer_at(idx);
+            if !buffer.is_read_only() {
+                buffer.remove_swap();
+            }
+        }
+    }
+
+    /// Writes the buffer's unsaved changes to its swap file.
+    fn write_swap(&mut self) {
+        if let Err(err) = self.buffer.write_swap() {
+            self.set_status(format!("Can't write the swap file! I/O error: {}", err));
+        }
+    }
+
+    /// Offers to take up the changes in a swap file left behind by an editor that didn't exit
+    /// cleanly, if there's one with text other than the file's.
+    fn offer_recovery(&mut self) {
+        let Some(text) = self
+            .buffer
+            .recovery_path()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+        else {
+            return;
+        };
+        if text == self.buffer.rows_to_string() {
+            return self.buffer.remove_swap();
+        }
+        let choice = self.prompt_choice(
+            "Swap file has unsaved changes. (r)ecover them, (d)elete it or (i)gnore it? ",
+            "rdi",
+        );
+        self.set_status(String::new());
+        match choice {
+            Some('r') => {
+                let rows = self.buffer.restore_text(&text);
+                self.follow_rows(&rows);
+                self.set_status("Recovered unsaved changes. Save to keep them".to_string());
+            }
+            Some('d') => self.buffer.remove_swap(),
+            _ => {}
+        }
+    }
+
+    /// Moves the views of the other windows along with the lines they were on, after the
+    /// text changed as a whole, given the row each line went to.
+    fn follow_rows(&mut self, rows: &[usize]) {
+        let last = self.buffer.line_count().saturating_sub(1);
+        let follow = |row: usize| rows.get(row).map_or(last, |&row| min(row, last));
+        for (idx, window) in self.windows.iter_mut().enumerate() {
+            if idx != self.active {
+                window.cursor.cursor_row = follow(window.cursor.cursor_row);
+                window.cursor.row_offset = follow(window.cursor.row_offset);
+                window.cursor.cursor_col = 0;
+            }
+        }
+    }
+
+    /// Reads the file again, keeping the cursor on the lines that didn't change. Unsaved
+    /// changes are lost, after asking, though undo brings them back.
+    fn reload(&mut self) {
+        let Some(path) = self.buffer.filename().clone() else {
+            return self.set_status("No file to reload".to_string());
+        };
+        if self.buffer.is_dirty() {
+            let choice =
+                self.prompt_choice("Buffer has unsaved changes. Reload anyway? (y/n) ", "yn");
+            self.set_status(String::new());
+            if choice != Some('y') {
+                return;
+            }
+        }
+        match self.read_again(&path) {
+            Ok(()) => self.set_status(format!("Reloaded {}", path.display())),
+            Err(err) => self.set_status(format!("Can't reload! I/O error: {}", err)),
+        }
+    }
+
+    /// Catches up with a change another program made to the file on disk: reloads it if the
+    /// buffer has no unsaved changes, or else asks whether to reload it, keep the buffer as it
+    /// is, or see the differences first. Kept, the buffer overwrites the file when saved.
+    fn check_disk(&mut self) {
+        self.disk_checked_at = Some(Instant::now());
+        let Some(path) = self.buffer.filename().clone() else {
+            return;
+        };
+        if !self.buffer.changed_on_disk() {
+            return;
+        }
+        if self.buffer.is_dirty() {
+            let question = format!(
+                "{} changed on disk. (r)eload, (k)eep the buffer or (d)iff? ",
+                path.display()
+            );
+            loop {
+                match self.prompt_choice(&question, "rkd") {
+                    Some('r') => break,
+                    Some('d') => self.show_diff(),
+                    _ => {
+                        self.buffer.record_disk_state();
+                        return self.set_status(format!("Kept the buffer of {}", path.display()));
+                    }
+                }
+            }
+        }
+        match self.read_again(&path) {
+            Ok(()) => self.set_status(format!("Reloaded {}, changed on disk", path.display())),
+            Err(err) => {
+                self.buffer.record_disk_state();
+                self.set_status(format!("Can't reload! I/O error: {}", err));
+            }
+        }
+    }
+
+    /// Replaces the text with the file's, as unsaved changes would be by a change.
+    fn read_again(&mut self, path: &Path) -> Result<()> {
+        let bytes = std::fs::read(path)?;
+        let encoding = self.encoding.unwrap_or_else(|| Encoding::detect(&bytes));
+        let text = encoding.decode(&bytes);
+        self.buffer.set_encoding(encoding);
+        self.buffer.convert_line_ending(LineEnding::detect(&text));
+        let rows = self.buffer.reload_text(&text);
+        self.follow_rows(&rows);
+        Ok(())
+    }
+
+    /// Replaces the text with what `command` makes of it, given on its standard input, like a
+    /// code formatter. Only lines it changed are touched, so the cursor stays with its line.
+    fn format_with(&mut self, command: &str) {
+        let text = self.buffer.rows_to_string();
+        match filter(self.shell_command(command), &text) {
+            Ok(output) if output == text => self.set_status("Already formatted".to_string()),
+            Ok(output) => {
+                let rows = self.buffer.restore_text(&output);
+                self.follow_rows(&rows);
+                self.set_status(format!("Formatted with {}", command));
+            }
+            Err(err) => self.set_status(format!("{} failed: {}", command, err)),
+        }
+    }
+
+    /// Deletes the blanks at the ends of lines, as one change to undo. A `BufWritePre` hook
+    /// running it trims every file on saving.
+    fn trim_blanks(&mut self) {
+        let text = self.buffer.rows_to_string();
+        let mut trimmed = 0;
+        let lines = text
+            .lines()
+            .map(|line| {
+                let kept = line.trim_end_matches([' ', '\t']);
+                if kept.len() < line.len() {
+                    trimmed += 1;
+                }
+                kept
+            })
+            .collect::<Vec<&str>>();
+        if trimmed == 0 {
+            return;
+        }
+        let rows = self.buffer.restore_text(&(lines.join("\n") + "\n"));
+        self.follow_rows(&rows);
+        self.set_status(format!(
+            "Trimmed {} line{}",
+            trimmed,
+            if trimmed == 1 { "" } else { "s" }
+        ));
+    }
+
+    /// Pipes the selection, or the whole buffer with `whole`, through the shell `command` and
+    /// puts what it prints in its place, as one change to undo.
+    fn filter_through(&mut self, command: &str, whole: bool) {
+        if command.is_empty() {
+            return self.set_status("Usage: %!COMMAND, or !COMMAND over a selection".to_string());
+        }
+        if self.buffer.is_read_only() {
+            return self.set_status("Buffer is read-only!!!".to_string());
+        }
+        let region = self.selection().filter(|_| !whole);
+        let text = match region {
+            Some(region) => self.buffer.region_text(region),
+            None => self.buffer.rows_to_string(),
+        };
+        let output = match filter(self.shell_command(command), &text) {
+            Ok(output) => output,
+            Err(err) => return self.set_status(format!("{} failed: {}", command, err)),
+        };
+        match region {
+            Some(region) => {
+                // Commands end what they print with a line break the selection may not have.
+                let output = match output.strip_suffix('\n') {
+                    Some(output) if !text.ends_with('\n') => output,
+                    _ => output.as_str(),
+                };
+                self.anchor = None;
+                self.buffer.delete_region(region);
+                self.buffer.insert_text(output);
+            }
+            None => {
+                let rows = self.buffer.restore_text(&output);
+                self.follow_rows(&rows);
+            }
+        }
+        self.set_status(format!(
+            "Filtered {} lines through {}",
+            text.lines().count(),
+            command
+        ));
+    }
+
+    /// Character of its line the cursor is on, counting from 1.
+    fn cursor_column(&self) -> usize {
+        let cursor = self.buffer.cursor_position();
+        let before = self.buffer.line(cursor.cursor_row).map_or(0, |line| {
+            let content = line.content();
+            content[..cursor.cursor_col.min(content.len())]
+                .chars()
+                .count()
+        });
+        before + 1
+    }
+
+    /// `command` set up to run in the shell from the working directory, told where the cursor
+    /// is through the environment: `MILO_FILE` has the full path of the file, and `MILO_LINE`
+    /// and `MILO_COLUMN` the line and character, counting from 1. `MILO_PLUGINS` has the
+    /// directory of plugins.
+    fn shell_command(&self, command: &str) -> Command {
+        let row = self.buffer.cursor_position().cursor_row;
+        let mut shell = shell(command);
+        shell
+            .current_dir(self.working_dir())
+            .env("MILO_LINE", (row + 1).to_string())
+            .env("MILO_COLUMN", self.cursor_column().to_string());
+        if let Some(file) = self.buffer.filename() {
+            shell.env("MILO_FILE", self.resolve_path(file));
+        }
+        if let Some(dir) = config::plugins_dir() {
+            shell.env("MILO_PLUGINS", dir);
+        }
+        shell
+    }
+
+    /// Runs `command` in the shell with the buffer as its input, then each line it prints as
+    /// a step of a user command: a command line after a `:`, or else keys. Scripts in any
+    /// language can work the editor this way.
+    fn eval(&mut self, command: &str) {
+        if command.is_empty() {
+            return self.set_status("Usage: eval COMMAND".to_string());
+        }
+        let shell = self.shell_command(command);
+        match filter(shell, &self.buffer.rows_to_string()) {
+            Ok(output) => {
+                let steps = output.lines().map(config::parse_step).collect();
+                self.run_steps(command, steps, &[]);
+            }
+            Err(err) => self.set_status(format!("{} failed: {}", command, err)),
+        }
+    }
+
+    /// Runs the shell `command` with the terminal given back to it, then waits for Enter
+    /// before drawing the editor again, so that what it printed can be read.
+    fn run_shell(&mut self, command: &str) {
+        if command.is_empty() {
+            return self.set_status("Usage: !COMMAND".to_string());
+        }
+        let mut shell = self.shell_command(command);
+        let ran = self.terminal.suspend(|| {
+            let status = shell.status();
+            print!("\n[Press Enter to go back]");
+            let _ = std::io::stdout().flush();
+            let _ = std::io::stdin().read_line(&mut String::new());
+            status
+        });
+        // The command may have committed or checked out something.
+        self.forget_git();
+        match ran.and_then(|status| status) {
+            Ok(status) if status.success() => {}
+            Ok(status) => self.set_status(format!("{} failed: {}", command, status)),
+            Err(err) => self.set_status(format!("Can't run {}: {}", command, err)),
+        }
+    }
+
+    fn save(&mut self) -> Result<()> {
+        if !self.buffer.kind().is_file_backed() {
+            self.set_status(format!(
+                "{} buffers aren't saved",
+                self.buffer.kind().name()
+            ));
+            return Ok(());
+        }
+        if self.buffer.filename().is_none() {
+            let some_name = self
+                .prompt_path("Save as (ESC to cancel, Tab to complete): ")
+                .map(|name| {
+                    self.resolve_path(Path::new(&name))
+                        .to_string_lossy()
+                        .to_string()
+                });
+            self.buffer.set_filename(some_name);
+            if let Some(filetype) = self.buffer.filetype() {
+                self.run_hooks(HookEvent::FileType, Some(filetype.name), &[filetype.name]);
+            }
+        }
+        if self.buffer.is_dirty() && self.buffer.changed_on_disk() {
+            loop {
+                match self.prompt_choice(
+                    "File changed on disk since it was loaded. (o)verwrite, (d)iff or (a)bort? ",
+                    "oda",
+                ) {
+                    Some('o') => break,
+                    Some('d') => self.show_diff(),
+                    _ => {
+                        self.set_status("Save aborted".to_string());
+                        return Ok(());
+                    }
+                }
+            }
+        }
+        if self.buffer.filename().is_some() {
+            self.run_buffer_hooks(HookEvent::BufWritePre);
+        }
+        if let Some(filename) = &self.buffer.filename() {
+            let content = match self.buffer.to_bytes() {
+                Ok(content) => content,
+                Err(err) => {
+                    self.set_status(format!("Can't save! {}", err));
+                    return Ok(());
+                }
+            };
+            if let Err(err) = save::write_file(filename, &content, self.backups) {
+                self.set_status(format!("Can't save! I/O error: {}", err));
+                return Err(err);
+            }
+            self.set_status(format!("{} bytes written to disk", content.len()));
+            self.buffer.not_dirty();
+            self.buffer.record_disk_state();
+            self.relock();
+            self.buffer.remove_swap();
+            self.save_history();
+            self.run_buffer_hooks(HookEvent::BufWritePost);
+        } else {
+            self.set_status("Filename not set!!!".to_string());
+        }
+        Ok(())
+    }
+
+    /// Locks the file just written, new or put in place of the one locked before.
+    fn relock(&mut self) {
+        match self.buffer.relock() {
+            Ok(true) => {}
+            Ok(false) => self.set_status("File is open in another instance".to_string()),
+            Err(err) => self.set_status(format!("Can't lock the file: {}", err)),
+        }
+    }
+
+    /// Writes the buffer's history next to its file, when that's asked for.
+    fn save_history(&mut self) {
+        if !self.undo_files {
+            return;
+        }
+        if let Err(err) = self.buffer.save_history() {
+            self.set_status(format!("Can't save the undo history! I/O error: {}", err));
+        }
+    }
+
+    fn is_autosave_pending(&self) -> bool {
+        self.buffer.is_dirty()
+            && !self.buffer.autosave_off()
+            && self.buffer.filename().is_some()
+            && self.buffer.changes() != self.autosaved_at
+    }
+
+    fn autosave(&mut self) -> Result<()> {
+        self.autosaved_at = self.buffer.changes();
+        if self.autosave.target == AutosaveTarget::File && self.buffer.changed_on_disk() {
+            self.set_status("File changed on disk! Autosave skipped".to_string());
+            return Ok(());
+        }
+        let path = match self.autosave.target {
+            AutosaveTarget::File => self.buffer.filename().clone(),
+            AutosaveTarget::Recovery => self.buffer.recovery_path(),
+        };
+        if let Some(path) = path {
+            // The recovery file keeps the text as the buffer has it, to be read back as that.
+            let content = match self.autosave.target {
+                AutosaveTarget::File => self.buffer.to_bytes(),
+                AutosaveTarget::Recovery => Ok(self.buffer.rows_to_string().into_bytes()),
+            };
+            let backup = self.backups && self.autosave.target == AutosaveTarget::File;
+            let written = content
+                .and_then(|content| save::write_file(&path, &content, backup).map(|()| content));
+            match written {
+                Ok(content) if self.autosave.target == AutosaveTarget::File => {
+                    self.set_status(format!("Autosaved {} bytes", content.len()));
+                    self.buffer.not_dirty();
+                    self.buffer.record_disk_state();
+                    self.relock();
+                    self.save_history();
+                }
+                Ok(_) => {}
+                Err(err) => self.set_status(format!("Autosave failed! {}", err)),
+            }
+        }
+        Ok(())
+    }
+
+    fn extended_command(&mut self) {
+        let key = self.read_chord_key("^X");
+        self.run_extended(key);
+    }
+
+    fn run_extended(&mut self, key: Key) {
+        let rows = self.rows() as isize;
+        match key {
+            Key::Printable('d') => self.show_diff(),
+            Key::Printable('*') => self.search_word_under_cursor(SearchDirection::Forward),
+            Key::Printable('#') => self.search_word_under_cursor(SearchDirection::Reverse),
+            Key::Printable('n') => self.search_next(SearchDirection::Forward),
+            Key::Printable('N') => self.search_next(SearchDirection::Reverse),
+            Key::Printable('a') => self.show_char_info(),
+            Key::Printable(kind @ ('f' | 'F' | 't' | 'T')) => {
+                if let Key::Printable(ch) = self.read_chord_key(&format!("^X {}", kind)) {
+                    let forward = kind.is_lowercase();
+                    let till = kind.eq_ignore_ascii_case(&'t');
+                    self.char_search = Some((ch, forward, till));
+                    self.find_char(ch, forward, till);
+                }
+            }
+            Key::Printable(repeat @ (';' | ',')) => match self.char_search {
+                Some((ch, forward, till)) => self.find_char(ch, forward == (repeat == ';'), till),
+                None => self.set_status("No character searched for yet".to_string()),
+            },
+            Key::Printable('b') => self.pick_buffer(),
+            Key::Printable('k') => self.close_buffer(),
+            Key::Printable('y') => self.yank_line(),
+            Key::Printable('o') => self.pick_options(),
+            Key::Printable('l') => self.cycle_line_numbers(),
+            Key::Printable('p') if self.buffer.is_read_only() => {
+                self.set_status("Buffer is read-only!!!".to_string())
+            }
+            Key::Printable('p') => self.paste(self.paste_selection),
+            Key::Printable('e') => self.expand_selection(),
+            Key::Printable('E') => self.shrink_selection(),
+            Key::Printable('r') => self.toggle_block(),
+            Key::Printable(':') => self.command_line(),
+            Key::Printable('?') => self.show_bindings(),
+            Key::Printable('g') => self.prompt_goto(),
+            Key::Printable('(') => {
+                if let Key::Printable(register) = self.read_chord_key("^X (") {
+                    self.start_recording(register);
+                }
+            }
+            Key::Printable(')') => self.stop_recording(),
+            Key::Printable('@') => {
+                let mut count = 0;
+                let mut key = self.read_chord_key("^X @");
+                while let Key::Printable(digit @ '0'..='9') = key {
+                    count = count * 10 + digit as usize - '0' as usize;
+                    key = self.read_chord_key(&format!("^X @{}", count));
+                }
+                match key {
+                    Key::Printable('@') => self.play_macro(None, count.max(1)),
+                    Key::Printable(register) => self.play_macro(Some(register), count.max(1)),
+                    _ => {}
+                }
+            }
+            Key::Move(Motion::Down) => self.buffer.scroll_view(rows / 2, self.rows()),
+            Key::Move(Motion::Up) => self.buffer.scroll_view(-rows / 2, self.rows()),
+            Key::Move(Motion::PgDn) => self.buffer.scroll_view(rows, self.rows()),
+            Key::Move(Motion::PgUp) => self.buffer.scroll_view(-rows, self.rows()),
+            _ => {}
+        }
+    }
+
+    /// Starts recording the keys typed from here on into the macro in `register`, a letter.
+    fn start_recording(&mut self, register: char) {
+        if let Some((recording, _)) = self.recording {
+            return self.set_status(format!("Already recording into {}", recording));
+        }
+        if !register.is_ascii_alphabetic() {
+            return self.set_status(format!("Macros go in registers a to z, not {}", register));
+        }
+        self.recording = Some((register, Vec::new()));
+        self.set_status(format!("Recording into {}", register));
+    }
+
+    /// Keeps the keys recorded so far as the macro, leaving out those that stopped it.
+    fn stop_recording(&mut self) {
+        let Some((register, mut keys)) = self.recording.take() else {
+            return self.set_status("Not recording".to_string());
+        };
+        keys.truncate(self.recorded_before);
+        self.set_status(format!("Recorded {} keys into {}", keys.len(), register));
+        self.macros.retain(|(existing, _)| *existing != register);
+        self.macros.push((register, keys));
+    }
+
+    /// Replays the keys of the macro in `register`, or of the one played last, `count` times,
+    /// as if typed again. Macros playing macros stop when nested too deeply.
+    fn play_macro(&mut self, register: Option<char>, count: usize) {
+        let Some(register) = register.or(self.last_macro) else {
+            return self.set_status("No macro played yet".to_string());
+        };
+        let Some((_, keys)) = self
+            .macros
+            .iter()
+            .find(|(existing, _)| *existing == register)
+        else {
+            return self.set_status(format!("Nothing recorded into {}", register));
+        };
+        if self.command_depth == MAX_COMMAND_DEPTH {
+            return self.set_status(format!("Macros nested too deeply in {}", register));
+        }
+        let keys = keys.iter().cycle().take(keys.len() * count).cloned();
+        let keys = keys.collect::<Vec<Key>>();
+        self.last_macro = Some(register);
+        // Ahead of the keys left of a macro playing this one, and handled before going back to
+        // those.
+        let after = self.replayed_keys.len();
+        for key in keys.into_iter().rev() {
+            self.replayed_keys.push_front(key);
+        }
+        self.command_depth += 1;
+        while self.replayed_keys.len() > after && self.keep_alive() {
+            let Some(key) = self.replayed_keys.pop_front() else {
+                break;
+            };
+            if let Err(err) = self.handle_key(key) {
+                let left = self.replayed_keys.len().saturating_sub(after);
+                self.replayed_keys.drain(..left);
+                self.set_status(format!("Macro {} failed: {}", register, err));
+            }
+        }
+        self.command_depth -= 1;
+    }
+
+    fn toggle(&self, name: &str) -> bool {
+        match name {
+            "mouse" => self.mouse,
+            "comment_leaders" => self.comment_leaders,
+            "auto_pairs" => self.auto_pairs,
+            "delete_pairs" => self.pair_deletion,
+            "auto_indent" => self.auto_indent,
+            "smart_indent" => self.smart_indent,
+            "expand_tabs" => self.indent_spaces.is_some(),
+            "detect_indent" => self.detect_indent,
+            "restore_positions" => self.restore_positions,
+            "sessions" => self.sessions,
+            "swap_files" => self.swap_files,
+            "undo_files" => self.undo_files,
+            "wrap" => self.wrap,
+            "osc52" => self.osc52,
+            "ansi_colors" => self.ansi_colors,
+            "elastic_tabs" => self.elastic_tabs,
+            "modal" => self.modal.is_some(),
+            "git" => self.git.is_some(),
+            "whitespace" => self.show_whitespace,
+            "trim_on_save" => self.trim_on_save,
+            "smooth_scroll" => self.smooth_scroll,
+            "watch_files" => self.watch_files,
+            "backups" => self.backups,
+            _ => false,
+        }
+    }
+
+    fn set_toggle(&mut self, name: &str, enable: bool) {
+        match name {
+            "mouse" => self.set_mouse(enable),
+            "comment_leaders" => self.set_comment_leaders(enable),
+            "auto_pairs" => self.set_auto_pairs(enable, self.pair_deletion),
+            "delete_pairs" => self.set_auto_pairs(self.auto_pairs, enable),
+            "auto_indent" => self.set_auto_indent(enable, self.smart_indent),
+            "smart_indent" => self.set_auto_indent(self.auto_indent, enable),
+            "expand_tabs" => self.set_indent(enable, self.indent_width),
+            "detect_indent" => self.set_detect_indent(enable),
+            "restore_positions" => self.set_restore_positions(enable),
+            "sessions" => self.set_sessions(enable),
+            "swap_files" => self.set_swap_files(enable),
+            "undo_files" => self.set_undo_files(enable),
+            "wrap" => self.set_wrap(enable),
+            "osc52" => self.set_osc52(enable),
+            "ansi_colors" => self.set_ansi_colors(enable),
+            "elastic_tabs" => self.set_elastic_tabs(enable),
+            "modal" => self.set_modal(enable),
+            "git" => self.set_git(enable),
+            "whitespace" => self.set_show_whitespace(enable),
+            "trim_on_save" => self.set_trim_on_save(enable),
+            "smooth_scroll" => self.set_smooth_scroll(enable),
+            "watch_files" => self.set_watch_files(enable),
+            "backups" => self.set_backups(enable),
+            _ => {}
+        }
+    }
+
+    /// Steps through the themes and the on/off options in the message bar. Themes are shown
+    /// as they're passed over and kept once picked; options flip when picked. Whatever changed
+    /// can be saved to the config file on the way out.
+    fn pick_options(&mut self) {
+        let themes = Theme::all();
+        let choices = themes
+            .iter()
+            .copied()
+            .map(Choice::Theme)
+            .chain(TOGGLES.iter().map(|&name| Choice::Toggle(name)))
+            .collect::<Vec<Choice>>();
+        let original_theme = self.theme;
+        let original_toggles = TOGGLES
+            .iter()
+            .map(|name| self.toggle(name))
+            .collect::<Vec<bool>>();
+        let mut idx = themes
+            .iter()
+            .position(|theme| std::ptr::eq(*theme, self.theme))
+            .unwrap_or(0);
+        let mut chosen = self.theme;
+        loop {
+            let label = match choices[idx] {
+                Choice::Theme(theme) => {
+                    self.set_theme(theme);
+                    let picked = if std::ptr::eq(theme, chosen) {
+                        " (picked)"
+                    } else {
+                        ""
+                    };
+                    format!("Theme {}{}", theme.name, picked)
+                }
+                Choice::Toggle(name) => {
+                    self.set_theme(chosen);
+                    let state = if self.toggle(name) { "on" } else { "off" };
+                    format!("Option {}: {}", name, state)
+                }
+            };
+            self.set_status(format!(
+                "{} ({}/{}, arrows move, Enter picks, Esc closes)",
+                label,
+                idx + 1,
+                choices.len()
+            ));
+            self.refresh_screen();
+            match self.read_prompt_key() {
+                Key::Move(Motion::Up) | Key::Move(Motion::Left) => {
+                    idx = (idx + choices.len() - 1) % choices.len()
+                }
+                Key::Move(Motion::Down) | Key::Move(Motion::Right) | Key::Tab => {
+                    idx = (idx + 1) % choices.len()
+                }
+                Key::Newline | Key::Printable(' ') => match choices[idx] {
+                    Choice::Theme(theme) => chosen = theme,
+                    Choice::Toggle(name) => self.set_toggle(name, !self.toggle(name)),
+                },
+                Key::Escape | Key::Printable('q') => break,
+                _ => {}
+            }
+        }
+        self.set_theme(chosen);
+
+        let mut changes = Vec::new();
+        if !std::ptr::eq(chosen, original_theme) {
+            changes.push(("theme", format!("\"{}\"", chosen.name)));
+        }
+        for (name, was) in TOGGLES.iter().zip(original_toggles) {
+            if self.toggle(name) != was {
+                changes.push((name, self.toggle(name).to_string()));
+            }
+        }
+        self.set_status(String::new());
+        if changes.is_empty() {
+            return;
+        }
+        let prompt = format!(
+            "Save {} changed setting(s) to the config file? (y/n) ",
+            changes.len()
+        );
+        if self.prompt_choice(&prompt, "yn") != Some('y') {
+            return self.set_status(String::new());
+        }
+        for (key, value) in &changes {
+            if let Err(err) = config::save_option(key, value) {
+                return self.set_status(format!("Can't save the config file: {}", err));
+            }
+        }
+        self.set_status("Saved to the config file".to_string());
+    }
+
+    /// Writes the buffer laid out for printing to the file named last in `args`, after the
+    /// options `ExportOptions::parse` reads.
+    fn export(&mut self, args: &[&str]) {
+        let (options, file) = match ExportOptions::parse(args) {
+            Ok((options, [file])) => (options, *file),
+            Ok(_) => {
+                return self
+                    .set_status("Usage: export [-n] [-H] [-w COLS] [-l A-B] FILE".to_string())
+            }
+            Err(err) => return self.set_status(err),
+        };
+        let name = self
+            .buffer
+            .filename()
+            .as_ref()
+            .map_or(self.buffer.kind().name().to_string(), |name| {
+                name.display().to_string()
+            });
+        let lines = (0..self.buffer.line_count())
+            .filter_map(|row| self.buffer.line(row))
+            .map(|line| line.content())
+            .collect::<Vec<&str>>();
+        let text = export::export(&name, &lines, self.buffer.tab_stop(), &options);
+        let path = self.resolve_path(Path::new(file));
+        match std::fs::write(&path, text) {
+            Ok(()) => self.set_status(format!("Exported to {}", path.display())),
+            Err(err) => self.set_status(format!("Can't export to {}: {}", path.display(), err)),
+        }
+    }
+
+    /// Takes the buffer back in time, or forward unless `back`, by the states or the time
+    /// `travel` reads as.
+    fn travel(&mut self, travel: &str, back: bool) {
+        if self.buffer.is_read_only() {
+            return self.set_status("Buffer is read-only!!!".to_string());
+        }
+        let Some(travel) = Travel::parse(travel) else {
+            return self.set_status(format!("Not a count or a time like 5m: {}", travel));
+        };
+        match self.buffer.travel(travel, back) {
+            Some((state, states, age)) => self.set_status(format!(
+                "State {} of {}, from {} ago",
+                state,
+                states,
+                describe_age(age)
+            )),
+            None => self.set_status("No history yet".to_string()),
+        }
+    }
+
+    /// Puts `text` in the register and the kill ring, as a rectangle with `block`.
+    fn keep_kill(&mut self, text: String, block: bool) {
+        self.kills.push(Kill {
+            text: text.clone(),
+            block,
+        });
+        self.register = text;
+        self.register_block = block;
+    }
+
+    /// Puts `text` in the register, and copies it to each of the selections yanks go to. With
+    /// OSC 52 on, the terminal is asked to put it on the system clipboard too, in which case
+    /// the selections are only a bonus.
+    fn yank(&mut self, text: &str, block: bool) {
+        self.keep_kill(text.to_string(), block);
+        if self.osc52 {
+            Terminal::write(&clipboard::osc52(text));
+        }
+        for selection in self.yank_selections.clone() {
+            match clipboard::copy(selection, text) {
+                Err(_) if self.osc52 => break,
+                Err(err) => {
+                    return self.set_status(format!(
+                        "Yanked {} bytes to the register only: {}",
+                        text.len(),
+                        err
+                    ))
+                }
+                Ok(()) => {}
+            }
+        }
+        self.set_status(format!("Yanked {} bytes", text.len()));
+    }
+
+    /// Sets the anchor of a selection of text at the cursor, as started with Shift or the mouse
+    /// if `shifted`.
+    fn start_selection(&mut self, shifted: bool) {
+        let cursor = self.buffer.cursor_position();
+        self.anchor = Some(((cursor.cursor_row, cursor.cursor_col), shifted));
+        self.block = false;
+    }
+
+    /// Switches between selecting a rectangle and a stretch of text, starting to select if
+    /// nothing is.
+    fn toggle_block(&mut self) {
+        if self.anchor.is_none() {
+            self.start_selection(false);
+        }
+        self.block = !self.block;
+        self.set_status(
+            if self.block {
+                "Selecting a rectangle"
+            } else {
+                "Selecting text"
+            }
+            .to_string(),
+        );
+    }
+
+    /// Lines and render columns spanned by the rectangle between the anchor and the cursor,
+    /// when one is selected and has any width.
+    fn rectangle(&self) -> Option<((usize, usize), (usize, usize))> {
+        let ((row, col), _) = self.anchor.filter(|_| self.block)?;
+        let cursor = self.buffer.cursor_position();
+        let render_col = |row: usize, col: usize| {
+            self.buffer
+                .line(row)
+                .map_or(0, |line| line.cursor_to_render_position(col))
+        };
+        let anchor_col = render_col(row, col);
+        let cursor_col = render_col(cursor.cursor_row, cursor.cursor_col);
+        let rows = (min(row, cursor.cursor_row), row.max(cursor.cursor_row));
+        let cols = (min(anchor_col, cursor_col), anchor_col.max(cursor_col));
+        (cols.0 < cols.1).then_some((rows, cols))
+    }
+
+    /// Moves the cursor to byte offset `col` of line `row`.
+    fn place_cursor_at(&mut self, row: usize, col: usize) {
+        let mut cursor = self.buffer.cursor_position();
+        cursor.cursor_row = row;
+        cursor.cursor_col = col;
+        cursor.desired_col = None;
+        self.buffer.set_cursor_position(cursor);
+    }
+
+    /// Moves the cursor to the character at render column `col` of line `row`.
+    fn place_cursor_at_column(&mut self, row: usize, col: usize) {
+        let mut cursor = self.buffer.cursor_position();
+        cursor.cursor_row = row;
+        cursor.cursor_col = self
+            .buffer
+            .line(row)
+            .map_or(0, |line| line.render_to_cursor_position(col));
+        cursor.desired_col = None;
+        self.buffer.set_cursor_position(cursor);
+    }
+
+    /// Puts the selection in the register and the yank selections, taking it out of the
+    /// buffer too with `cut`. A rectangle goes in as a line of text per row.
+    fn copy_selection(&mut self, cut: bool) {
+        if let Some((rows, cols)) = self.rectangle() {
+            self.anchor = None;
+            let text = self.buffer.rectangle_text(rows, cols).join("\n");
+            if cut {
+                self.buffer.delete_rectangle(rows, cols);
+                self.place_cursor_at_column(rows.0, cols.0);
+            }
+            self.yank(&text, true);
+            return;
+        }
+        match self.selection() {
+            Some(selection) => {
+                self.anchor = None;
+                let text = if cut {
+                    self.buffer.delete_region(selection)
+                } else {
+                    self.buffer.region_text(selection)
+                };
+                self.yank(&text, false);
+            }
+            None => self.set_status("Nothing selected".to_string()),
+        }
+    }
+
+    /// Inserts the register in place of the selection, or at the cursor. Text is reindented
+    /// to the cursor line with `reindent`; a rectangle goes in at the same column of the lines
+    /// from there on.
+    fn paste_register(&mut self, reindent: bool) {
+        if let Some((rows, cols)) = self.rectangle() {
+            self.buffer.delete_rectangle(rows, cols);
+            self.place_cursor_at_column(rows.0, cols.0);
+        } else if let Some(selection) = self.selection() {
+            self.buffer.delete_region(selection);
+        }
+        self.anchor = None;
+        let text = self.register.clone();
+        if !self.register_block {
+            let cursor = self.buffer.cursor_position();
+            let start = (cursor.cursor_row, cursor.cursor_col);
+            if reindent {
+                self.buffer.insert_text_reindented(&text);
+            } else {
+                self.buffer.insert_text(&text);
+            }
+            let cursor = self.buffer.cursor_position();
+            let end = (cursor.cursor_row, cursor.cursor_col);
+            let kill = self.kills.iter().position(|kill| kill.text == text);
+            self.last_paste = kill.map(|idx| (Region::new(start, end), self.buffer.changes(), idx));
+            return;
+        }
+        let cursor = self.buffer.cursor_position();
+        let col = self
+            .buffer
+            .line(cursor.cursor_row)
+            .map_or(0, |line| line.cursor_to_render_position(cursor.cursor_col));
+        let rows = text.split('\n').collect::<Vec<&str>>();
+        self.buffer.insert_rectangle(cursor.cursor_row, col, &rows);
+        self.place_cursor_at_column(cursor.cursor_row, col);
+    }
+
+    /// Swaps the text just pasted for the kill before it in the ring, going round to the newest
+    /// after the oldest, and pastes that from then on.
+    fn yank_pop(&mut self) {
+        let cursor = self.buffer.cursor_position();
+        let Some((region, _, idx)) = self.last_paste.filter(|&(region, changes, _)| {
+            changes == self.buffer.changes() && region.end == (cursor.cursor_row, cursor.cursor_col)
+        }) else {
+            return self.set_status("Paste first, then swap it for older kills".to_string());
+        };
+        if self.kills.len() < 2 {
+            return self.set_status("No older kill to swap in".to_string());
+        }
+        self.buffer.delete_region(region);
+        let older = self.kills.older(idx);
+        self.paste_kill(older);
+        self.set_status(format!("Kill {} of {}", older + 1, self.kills.len()));
+    }
+
+    /// Pastes kill `idx` of the ring, and makes it the one pasted from then on.
+    fn paste_kill(&mut self, idx: usize) {
+        if let Some(kill) = self.kills.get(idx) {
+            self.register = kill.text.clone();
+            self.register_block = kill.block;
+            self.paste_register(true);
+        }
+    }
+
+    /// Lists the kill ring over the windows for picking one to paste, with the arrows, Ctrl-N
+    /// and Ctrl-P or Tab, and Enter. Typing narrows them down to those containing the text.
+    fn pick_kill(&mut self) {
+        if self.kills.is_empty() {
+            return self.set_status("Nothing copied to paste".to_string());
+        }
+        let mut query = String::new();
+        let mut selected = 0;
+        let picked = loop {
+            let shown = (0..self.kills.len())
+                .filter(|&idx| {
+                    self.kills
+                        .get(idx)
+                        .is_some_and(|kill| kill.text.contains(&query))
+                })
+                .collect::<Vec<usize>>();
+            selected = min(selected, shown.len().saturating_sub(1));
+            self.overlay = self.kill_lines(&shown, selected);
+            let prompt = format!("Paste ({}/{}): ", shown.len(), self.kills.len());
+            match self.prompt_incremental(&prompt, &mut query) {
+                (true, Some(Key::Escape)) => break None,
+                (true, _) => break shown.get(selected).copied(),
+                (_, Some(Key::Move(Motion::Up) | Key::Control('P'))) => {
+                    selected = selected.saturating_sub(1)
+                }
+                (_, Some(Key::Move(Motion::Down) | Key::Control('N') | Key::Tab)) => selected += 1,
+                (_, None) => selected = 0,
+                _ => {}
+            }
+        };
+        self.overlay.clear();
+        self.terminal.screen().invalidate();
+        match picked {
+            Some(_) if self.buffer.is_read_only() => {
+                self.set_status("Buffer is read-only!!!".to_string())
+            }
+            Some(idx) => self.paste_kill(idx),
+            None => {}
+        }
+    }
+
+    /// Lines of the kill ring picker, the kills of the ring at `shown` on a line each with their
+    /// line breaks as `↵`, bottom up from `selected`.
+    fn kill_lines(&self, shown: &[usize], selected: usize) -> Vec<String> {
+        let height = (self.terminal.rows().saturating_sub(1) / 2).max(1);
+        let width = self.terminal.cols();
+        let first = (selected + 1).saturating_sub(height);
+        let mut lines = shown
+            .iter()
+            .enumerate()
+            .skip(first)
+            .take(height)
+            .filter_map(|(n, &idx)| {
+                let kill = self.kills.get(idx)?;
+                let marker = if n == selected { "> " } else { "  " };
+                let kind = if kill.block { "[rectangle] " } else { "" };
+                let text = format!("{}{}{}", marker, kind, kill.text.replace('\n', "↵"))
+                    .chars()
+                    .map(|ch| if ch.is_control() { ' ' } else { ch })
+                    .chain(std::iter::repeat(' '))
+                    .take(width)
+                    .collect::<String>();
+                let highlight = if n == selected {
+                    Highlight::Selection
+                } else {
+                    Highlight::Normal
+                };
+                Some(highlight::paint(
+                    &text,
+                    &vec![highlight; width],
+                    self.palette,
+                ))
+            })
+            .collect::<Vec<String>>();
+        lines.reverse();
+        lines
+    }
+
+    /// Writes `text` over each row of the selected rectangle, repeated or cut short to fit.
+    fn fill_rectangle(&mut self, text: &str) {
+        let Some((rows, (left, right))) = self.rectangle() else {
+            return self.set_status("No rectangle selected to fill".to_string());
+        };
+        self.anchor = None;
+        let fill = text.chars().cycle().take(right - left).collect::<String>();
+        self.buffer.delete_rectangle(rows, (left, right));
+        let lines = vec![fill.as_str(); rows.1 - rows.0 + 1];
+        self.buffer.insert_rectangle(rows.0, left, &lines);
+        self.place_cursor_at_column(rows.0, left);
+    }
+
+    /// Text between the anchor and the cursor, unless there's no selection or it's empty.
+    fn selection(&self) -> Option<Region> {
+        let (anchor, _) = self.anchor.filter(|_| !self.block)?;
+        let cursor = self.buffer.cursor_position();
+        let cursor = (cursor.cursor_row, cursor.cursor_col);
+        let region = Region::new(min(anchor, cursor), anchor.max(cursor));
+        (!region.is_empty()).then_some(region)
+    }
+
+    /// Lines, words and characters in the selection, plain or rectangular, for the status bar.
+    fn selection_counts(&self) -> Option<String> {
+        let text = match (self.selection(), self.rectangle()) {
+            (Some(region), _) => self.buffer.region_text(region),
+            (None, Some((rows, cols))) => self.buffer.rectangle_text(rows, cols).join("\n"),
+            (None, None) => return None,
+        };
+        let plural = |count: usize, what: &str| {
+            format!("{} {}{}", count, what, if count == 1 { "" } else { "s" })
+        };
+        Some(format!(
+            "{}, {}, {}",
+            plural(text.split('\n').count(), "line"),
+            plural(text.split_whitespace().count(), "word"),
+            plural(text.chars().count(), "char")
+        ))
+    }
+
+    /// Selects the next enclosing syntactic unit around the selection, or around the cursor.
+    fn expand_selection(&mut self) {
+        let cursor = self.buffer.cursor_position();
+        let region = self
+            .selection()
+            .unwrap_or(Region::at((cursor.cursor_row, cursor.cursor_col)));
+        match region::expand(&self.buffer, region) {
+            Some(expanded) => {
+                // A selection made some other way starts the way back afresh.
+                if self.expansions.last() != Some(&region) {
+                    self.expansions = vec![region];
+                }
+                self.expansions.push(expanded);
+                self.select_region(expanded);
+            }
+            None => self.set_status("Nothing bigger to select".to_string()),
+        }
+    }
+
+    /// Goes back to the selection before the last expansion of it, or to the cursor where
+    /// expanding started.
+    fn shrink_selection(&mut self) {
+        let cursor = self.buffer.cursor_position();
+        let region = self
+            .selection()
+            .unwrap_or(Region::at((cursor.cursor_row, cursor.cursor_col)));
+        if self.expansions.len() < 2 || self.expansions.last() != Some(&region) {
+            self.expansions.clear();
+            return self.set_status("Nothing smaller to go back to".to_string());
+        }
+        self.expansions.pop();
+        let previous = self.expansions.last().copied().unwrap_or(region);
+        self.select_region(previous);
+    }
+
+    /// Selects `region`, with the cursor at its end, or puts the cursor there if it's empty.
+    fn select_region(&mut self, region: Region) {
+        self.block = false;
+        self.anchor = (!region.is_empty()).then_some((region.start, false));
+        self.place_cursor_at(region.end.0, region.end.1);
+    }
+
+    /// First and last of the lines selected, plain or as a rectangle, or the cursor's line
+    /// without a selection. A selection ending at the start of a line leaves that line out.
+    fn selected_rows(&self) -> (usize, usize) {
+        if let Some(((top, bottom), _)) = self.rectangle() {
+            return (top, bottom);
+        }
+        match self.selection() {
+            Some(region) if region.end.1 == 0 && region.end.0 > region.start.0 => {
+                (region.start.0, region.end.0 - 1)
+            }
+            Some(region) => (region.start.0, region.end.0),
+            None => {
+                let row = self.buffer.cursor_position().cursor_row;
+                (row, row)
+            }
+        }
+    }
+
+    /// Moves the selected lines up a line, or down, with the selection.
+    fn move_lines(&mut self, up: bool) {
+        let (first, last) = self.selected_rows();
+        if !self.buffer.move_lines(first, last, up) {
+            return;
+        }
+        if let Some((anchor, _)) = &mut self.anchor {
+            anchor.0 = if up { anchor.0 - 1 } else { anchor.0 + 1 };
+        }
+    }
+
+    /// Copies the selected lines below them, selecting the copy.
+    fn duplicate_lines(&mut self) {
+        let (first, last) = self.selected_rows();
+        self.buffer.duplicate_lines(first, last);
+        if let Some((anchor, _)) = &mut self.anchor {
+            anchor.0 += last + 1 - first;
+        }
+    }
+
+    fn join_lines(&mut self) {
+        let (first, last) = self.selected_rows();
+        if self.buffer.join_lines(first, last) {
+            self.anchor = None;
+        } else {
+            self.set_status("No line to join".to_string());
+        }
+    }
+
+    /// Comments out the selected lines with the filetype's comments, or uncomments them.
+    fn toggle_comment(&mut self) {
+        let (first, last) = self.selected_rows();
+        if !self.buffer.toggle_comment(first, last) {
+            return self.set_status("No comments for this filetype".to_string());
+        }
+        // The line the selection starts or ends on may have got shorter.
+        if let Some((anchor, _)) = &mut self.anchor {
+            if let Some(line) = self.buffer.line(anchor.0) {
+                anchor.1 = line.floor_char_boundary(min(anchor.1, line.len()));
+            }
+        }
+    }
+
+    fn yank_line(&mut self) {
+        let row = self.buffer.cursor_position().cursor_row;
+        match self.buffer.line(row) {
+            Some(line) => {
+                let text = format!("{}\n", line.content());
+                self.yank(&text, false);
+            }
+            None => self.set_status("No line to yank".to_string()),
+        }
+    }
+
+    /// Inserts the text in `selection` at the cursor.
+    fn paste(&mut self, selection: Selection) {
+        match clipboard::paste(selection) {
+            Ok(text) => self.buffer.insert_text(&text),
+            Err(err) => self.set_status(format!("Can't paste: {}", err)),
+        }
+    }
+
+    /// Describes the character under the cursor in the message bar, to help untangle encoding
+    /// problems.
+    fn show_char_info(&mut self) {
+        let cursor = self.buffer.cursor_position();
+        let line = match self.buffer.line(cursor.cursor_row) {
+            Some(line) => line,
+            None => return self.set_status("No character under the cursor".to_string()),
+        };
+        let ch = match line.content()[cursor.cursor_col..].chars().next() {
+            Some(ch) => ch,
+            None => return self.set_status("No character under the cursor".to_string()),
+        };
+        let mut bytes = [0; 4];
+        let bytes = ch
+            .encode_utf8(&mut bytes)
+            .bytes()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<Vec<String>>();
+        let width = line.cursor_to_render_position(cursor.cursor_col + ch.len_utf8())
+            - line.cursor_to_render_position(cursor.cursor_col);
+        // Control characters would act on the terminal instead of showing up.
+        let shown = if ch.is_ascii_control() {
+            format!("^{}", ((ch as u8) ^ 0x40) as char)
+        } else if ch.is_control() {
+            ch.escape_unicode().to_string()
+        } else {
+            ch.to_string()
+        };
+        self.set_status(format!(
+            "<{}> {}, U+{:04X}{}, UTF-8 {}, width {}",
+            shown,
+            ch as u32,
+            ch as u32,
+            char_name(ch).map_or(String::new(), |name| format!(" {}", name)),
+            bytes.join(" "),
+            width
+        ));
+    }
+
+    /// Working directory of the focused window, which relative paths are resolved against.
+    fn working_dir(&self) -> &Path {
+        self.windows[self.active].cwd.as_ref().unwrap_or(&self.cwd)
+    }
+
+    fn resolve_path(&self, path: &Path) -> PathBuf {
+        match path.strip_prefix("~") {
+            Ok(rest) => std::env::var_os("HOME").map_or_else(
+                || self.working_dir().join(path),
+                |home| Path::new(&home).join(rest),
+            ),
+            Err(_) => self.working_dir().join(path),
+        }
+    }
+
+    /// Names in the filesystem starting with `partial`, relative to the working directory.
+    /// Directories end in a slash.
+    fn complete_path(&self, partial: &str) -> Vec<String> {
+        let (dir, prefix) = match partial.rfind('/') {
+            Some(idx) => partial.split_at(idx + 1),
+            None => ("", partial),
+        };
+        let entries = match std::fs::read_dir(self.resolve_path(Path::new(dir))) {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new(),
+        };
+        let mut candidates = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let name = entry.file_name().to_string_lossy().to_string();
+                if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.'))
+                {
+                    return None;
+                }
+                let slash = if entry.path().is_dir() { "/" } else { "" };
+                Some(format!("{}{}{}", dir, name, slash))
+            })
+            .collect::<Vec<String>>();
+        candidates.sort();
+        candidates
+    }
+
+    fn command_line(&mut self) {
+        if let Some(command) = self.prompt_completing(":", Self::complete_command) {
+            self.run_command(&command);
+        }
+    }
+
+    /// Candidates for the last word of a command line, and where it starts: command names for
+    /// the first word, and for the one after it what the command takes, or else paths.
+    fn complete_command(&self, line: &str) -> (usize, Vec<String>) {
+        let start = line.rfind(' ').map_or(0, |idx| idx + 1);
+        let partial = &line[start..];
+        let mut words = line[..start].split_whitespace();
+        let candidates = match (words.next(), words.next()) {
+            (None, _) => self.command_names(),
+            (Some("set"), None) => TOGGLES
+                .iter()
+                .flat_map(|name| [name.to_string(), format!("no{}", name)])
+                .chain(config::OPTIONS.iter().map(|name| name.to_string()))
+                .chain(["number", "relativenumber", "nonumber"].map(String::from))
+                .collect(),
+            (Some("theme"), None) => Theme::all()
+                .iter()
+                .map(|theme| theme.name.to_string())
+                .collect(),
+            (Some("filetype"), None) => Filetype::all()
+                .iter()
+                .map(|filetype| filetype.name.to_ascii_lowercase())
+                .collect(),
+            (Some("indent"), None) => vec!["tabs".to_string(), "auto".to_string()],
+            (Some("autosave"), None) => vec!["on".to_string(), "off".to_string()],
+            _ => return (start, self.complete_path(partial)),
+        };
+        let mut candidates = candidates
+            .into_iter()
+            .filter(|candidate| candidate.starts_with(partial))
+            .collect::<Vec<String>>();
+        candidates.sort();
+        candidates.dedup();
+        (start, candidates)
+    }
+
+    /// Everything the command line runs by name: its own commands, the user's, the actions,
+    /// and the window and extended commands.
+    fn command_names(&self) -> Vec<String> {
+        let own = BINDINGS
+            .iter()
+            .filter(|(group, _)| group.starts_with("Commands"))
+            .flat_map(|(_, bindings)| bindings.iter())
+            .filter_map(|(keys, _, _)| keys.split_whitespace().next())
+            .filter(|word| word.chars().all(|ch| ch.is_ascii_lowercase()));
+        let chords = BINDINGS
+            .iter()
+            .flat_map(|(_, bindings)| bindings.iter())
+            .filter(|(_, name, _)| chord_by_name(name).is_some())
+            .map(|(_, name, _)| *name);
+        own.chain(chords)
+            .chain(Action::ALL.iter().map(|action| action.name()))
+            .map(String::from)
+            .chain(self.user_commands.iter().map(|(name, _)| name.clone()))
+            .collect()
+    }
+
+    /// Sets an option from the words after `set`: `NAME` or `noNAME` for those that are on or
+    /// off, and `NAME=VALUE` or `NAME VALUE` for the others, with values as in the config
+    /// file. `number`, `relativenumber` and `nonumber` pick the line numbers.
+    fn set_option(&mut self, args: &str) {
+        let (name, value) = match args.split_once(['=', ' ']) {
+            Some((name, value)) => (name.trim(), Some(value.trim())),
+            None => (args, None),
+        };
+        let line_numbers = match name {
+            "number" => Some(LineNumbers::Absolute),
+            "relativenumber" => Some(LineNumbers::Relative),
+            "nonumber" => Some(LineNumbers::Off),
+            _ => None,
+        };
+        match (line_numbers, value) {
+            (Some(line_numbers), None) => self.set_line_numbers(line_numbers),
+            (None, None) if TOGGLES.contains(&name) => self.set_toggle(name, true),
+            (None, None) => match name.strip_prefix("no") {
+                Some(toggle) if TOGGLES.contains(&toggle) => self.set_toggle(toggle, false),
+                _ => self.set_status(format!("Option {} needs a value", name)),
+            },
+            (_, Some(value)) => {
+                if let Err(err) = config::set_option(self, name, value) {
+                    self.set_status(format!("Can't set {}: {}", name, err));
+                }
+            }
+        }
+    }
+
+    fn run_command(&mut self, command: &str) {
+        if let Some(shell) = command.trim_start().strip_prefix("%!") {
+            return self.filter_through(shell.trim(), true);
+        }
+        if let Some(shell) = command.trim_start().strip_prefix('!') {
+            return match self.selection() {
+                Some(_) => self.filter_through(shell.trim(), false),
+                None => self.run_shell(shell.trim()),
+            };
+        }
+        if let Some(substitute) = command.trim_start().strip_prefix("%s") {
+            return match parse_substitute(substitute) {
+                Some(_) if self.buffer.is_read_only() => {
+                    self.set_status("Buffer is read-only!!!".to_string())
+                }
+                Some((query, replacement, all)) => match Pattern::regex(&query, false) {
+                    Ok(pattern) => self.replace_matches(&pattern, &replacement, !all),
+                    Err(err) => self.set_status(format!("Bad regex: {}", err)),
+                },
+                None => self.set_status("Usage: %s/PATTERN/REPLACEMENT/[a]".to_string()),
+            };
+        }
+        if command
+            .trim_start()
+            .starts_with(|ch: char| ch.is_ascii_digit())
+        {
+            return self.goto_line(command);
+        }
+        if let Some(script) = command.trim_start().strip_prefix("eval ") {
+            return self.eval(script.trim());
+        }
+        if let Some(query) = command.trim_start().strip_prefix("grep") {
+            if query.is_empty() || query.starts_with(char::is_whitespace) {
+                return self.grep(query.trim());
+            }
+        }
+        let mut words = command.split_whitespace();
+        match (words.next(), words.next()) {
+            (Some(cmd @ "cd"), dir) | (Some(cmd @ "lcd"), dir) => {
+                let dir = self.resolve_path(Path::new(dir.unwrap_or("~")));
+                match dir.canonicalize() {
+                    Ok(dir) if dir.is_dir() => {
+                        self.set_status(dir.display().to_string());
+                        if cmd == "cd" {
+                            self.cwd = dir;
+                        } else {
+                            self.windows[self.active].cwd = Some(dir);
+                        }
+                    }
+                    _ => self.set_status(format!("No such directory: {}", dir.display())),
+                }
+            }
+            (Some(cmd @ "earlier"), travel) | (Some(cmd @ "later"), travel) => {
+                self.travel(travel.unwrap_or("1"), cmd == "earlier")
+            }
+            (Some("fill"), _) if self.buffer.is_read_only() => {
+                self.set_status("Buffer is read-only!!!".to_string())
+            }
+            (Some("fill"), _) => match command.trim_start().strip_prefix("fill ") {
+                Some(text) if !text.is_empty() => self.fill_rectangle(text),
+                _ => self.set_status("Usage: fill TEXT".to_string()),
+            },
+            (Some("digraphs"), None) => self.show_digraphs(),
+            (Some("reload"), None) => self.reload(),
+            (Some("trim"), None) if self.buffer.is_read_only() => {
+                self.set_status("Buffer is read-only!!!".to_string())
+            }
+            (Some("trim"), None) => self.trim_blanks(),
+            (Some("format"), _) if self.buffer.is_read_only() => {
+                self.set_status("Buffer is read-only!!!".to_string())
+            }
+            (Some("format"), _) => match command.trim_start().strip_prefix("format ") {
+                Some(formatter) if !formatter.trim().is_empty() => {
+                    self.format_with(formatter.trim())
+                }
+                _ => self.set_status("Usage: format COMMAND".to_string()),
+            },
+            (Some("indent"), None) => {
+                let unit = self.buffer.indent_unit();
+                let what = if unit.starts_with('\t') {
+                    "a tab".to_string()
+                } else {
+                    format!("{} spaces", unit.len())
+                };
+                let from = if self.buffer.has_own_indent() {
+                    " for this buffer"
+                } else {
+                    ""
+                };
+                self.set_status(format!("Indenting with {}{}", what, from));
+            }
+            (Some("indent"), Some(arg)) => {
+                let indent = match arg {
+                    "tabs" => Some("\t".to_string()),
+                    "auto" => None,
+                    _ => match arg.parse::<usize>() {
+                        Ok(width) if width > 0 => Some(" ".repeat(width)),
+                        _ => return self.set_status("Usage: indent [tabs|N|auto]".to_string()),
+                    },
+                };
+                self.buffer.set_own_indent(indent);
+                self.run_command("indent");
+            }
+            (Some("elastic"), None) => {
+                let elastic = !self.buffer.elastic_tabs();
+                self.buffer.set_elastic_tabs(elastic);
+                self.set_status(if elastic {
+                    "Elastic tab stops".to_string()
+                } else {
+                    format!("Tab stops every {} columns", self.buffer.tab_stop())
+                });
+            }
+            (Some("ansi"), None) => match self.buffer.ansi_colors() {
+                Some(colors) => {
+                    self.buffer.show_ansi_colors(!colors);
+                    self.set_status(if colors {
+                        "Showing escape sequences".to_string()
+                    } else {
+                        "Showing colors".to_string()
+                    });
+                }
+                None => self.set_status("No colored output in this buffer".to_string()),
+            },
+            (Some("export"), _) => {
+                let args = command.split_whitespace().skip(1).collect::<Vec<&str>>();
+                self.export(&args)
+            }
+            (Some("autosave"), None) => self.set_status(format!(
+                "Autosave is {} for this buffer",
+                if self.buffer.autosave_off() {
+                    "off"
+                } else {
+                    "on"
+                }
+            )),
+            (Some("autosave"), Some(setting @ ("on" | "off"))) => {
+                self.buffer.set_autosave_off(setting == "off")
+            }
+            (Some("wa"), None) => {
+                if self.save_all() {
+                    self.set_status("All buffers saved".to_string());
+                }
+            }
+            (Some("wqa"), None) => {
+                if self.save_all() {
+                    self.quit();
+                }
+            }
+            (Some("w" | "write"), file) => {
+                if let Some(file) = file {
+                    let path = self.resolve_path(Path::new(file));
+                    self.buffer
+                        .set_filename(Some(path.to_string_lossy().to_string()));
+                }
+                // Saving tells what went wrong itself.
+                let _ = self.run_action(Action::Save);
+            }
+            (Some("q" | "quit"), None) => self.quit(),
+            (Some("wq" | "x"), None) => {
+                if self.run_action(Action::Save).is_ok() && !self.buffer.is_dirty() {
+                    self.quit();
+                }
+            }
+            (Some("e" | "open"), Some(file)) => self.open_path(file),
+            (Some("explore"), dir) => {
+                let dir = match (dir, self.buffer.filename()) {
+                    (Some(dir), _) => self.resolve_path(Path::new(dir)),
+                    (None, Some(file)) if self.buffer.kind() == BufferKind::Directory => {
+                        file.clone()
+                    }
+                    (None, Some(file)) => self
+                        .resolve_path(file)
+                        .parent()
+                        .map_or_else(|| self.working_dir().to_path_buf(), Path::to_path_buf),
+                    (None, None) => self.working_dir().to_path_buf(),
+                };
+                self.explore(&dir);
+            }
+            (Some("set"), None) => self.set_status("Usage: set OPTION[=VALUE]".to_string()),
+            (Some("set"), Some(_)) => {
+                let args = command.trim_start().trim_start_matches("set").trim();
+                self.set_option(args);
+            }
+            (Some("theme"), None) => self.set_status(format!("Theme {}", self.theme.name)),
+            (Some("theme"), Some(name)) => match Theme::by_name(name) {
+                Some(theme) => self.set_theme(theme),
+                None => self.set_status(format!("Unknown theme: {}", name)),
+            },
+            (Some("endings"), None) => {
+                self.set_status(format!("Lines end in {}", self.buffer.line_ending().name()))
+            }
+            (Some("endings"), Some(_)) if self.buffer.is_read_only() => {
+                self.set_status("Buffer is read-only!!!".to_string())
+            }
+            (Some("endings"), Some(name)) => match LineEnding::from_name(name) {
+                Some(ending) => {
+                    self.buffer.convert_line_ending(ending);
+                    self.set_status(format!("Lines end in {} when saved", ending.name()));
+                }
+                None => self.set_status("Usage: endings [lf|crlf]".to_string()),
+            },
+            (Some("view"), None) => {
+                let viewing = !self.buffer.is_viewing();
+                self.buffer.set_viewing(viewing);
+                self.set_status(format!("View mode {}", if viewing { "on" } else { "off" }));
+            }
+            (Some("readonly"), None) if self.buffer.kind().is_read_only() => self.set_status(
+                format!("{} buffers are always read-only", self.buffer.kind().name()),
+            ),
+            (Some("readonly"), None) if self.buffer.ansi_colors() == Some(true) => {
+                self.set_status("Colored output stays read-only while it shows colors".to_string())
+            }
+            (Some("readonly"), None) => {
+                let read_only = !self.buffer.is_read_only();
+                self.buffer.set_read_only(read_only);
+                self.buffer
+                    .set_viewing(self.buffer.is_viewing() && read_only);
+                self.set_status(format!(
+                    "Read-only {}",
+                    if read_only { "on" } else { "off" }
+                ));
+            }
+            (Some("pwd"), None) => self.set_status(self.working_dir().display().to_string()),
+            (Some("kills"), None) => self.pick_kill(),
+            (Some("mark"), Some(name)) => match name.parse::<char>() {
+                Ok(name) => self.set_mark(name),
+                Err(_) => self.set_status("Marks are named by letters".to_string()),
+            },
+            (Some("marks"), None) => self.set_status(self.marks_summary()),
+            (Some("diff"), other) => self.diff_buffer(other),
+            (Some("blame"), None) => self.blame_line(),
+            (Some("filetype"), None) => self.set_status(match self.buffer.filetype() {
+                Some(filetype) => format!("Filetype {}", filetype.name),
+                None => "No filetype".to_string(),
+            }),
+            (Some("filetype"), Some(name)) => match Filetype::by_name(name) {
+                Some(filetype) => {
+                    self.buffer.set_filetype(Some(filetype));
+                    self.run_hooks(HookEvent::FileType, Some(filetype.name), &[filetype.name]);
+                }
+                None if name == "none" => self.buffer.set_filetype(None),
+                None => self.set_status(format!("Unknown filetype: {}", name)),
+            },
+            (Some(name), _)
+                if self
+                    .user_commands
+                    .iter()
+                    .any(|(defined, _)| defined == name) =>
+            {
+                let args = command.split_whitespace().skip(1).collect::<Vec<&str>>();
+                self.run_user_command(name, &args);
+            }
+            (Some(name), None) if Action::from_name(name).is_some() => {
+                let action = Action::from_name(name).expect("checked above");
+                if let Err(err) = self.run_action(action) {
+                    self.set_status(format!("{} failed: {}", name, err));
+                }
+            }
+            (Some(name), None) if chord_by_name(name).is_some() => {
+                let (prefix, key) = chord_by_name(name).expect("checked above");
+                self.replayed_keys.push_front(Key::Printable(key));
+                if let Err(err) = self.run_action(prefix) {
+                    self.set_status(format!("{} failed: {}", name, err));
+                }
+            }
+            _ => self.set_status(format!("Unknown command: {}", command)),
+        }
+    }
+
+    fn run_user_command(&mut self, name: &str, args: &[&str]) {
+        if let Some((_, steps)) = self
+            .user_commands
+            .iter()
+            .find(|(defined, _)| defined == name)
+        {
+            self.run_steps(name, steps.clone(), args);
+        }
+    }
+
+    fn run_hooks(&mut self, event: HookEvent, subject: Option<&str>, args: &[&str]) {
+        // The editor's own features that go by events come ahead of the user's hooks.
+        if event == HookEvent::BufWritePre && self.trim_on_save && !self.buffer.is_read_only() {
+            self.trim_blanks();
+        }
+        if event == HookEvent::BufWritePost {
+            // What git says about the file is out of date once it's written.
+            self.forget_git();
+        }
+        for steps in self.hooks.matching(event, subject) {
+            self.run_steps(event.name(), steps, args);
+        }
+    }
+
+    /// Runs the hooks of a buffer event, with the full path of the buffer's file as their
+    /// subject.
+    fn run_buffer_hooks(&mut self, event: HookEvent) {
+        let file = self
+            .buffer
+            .filename()
+            .as_ref()
+            .map(|file| self.resolve_path(file).to_string_lossy().to_string());
+        let args = file.as_deref().into_iter().collect::<Vec<&str>>();
+        self.run_hooks(event, file.as_deref(), &args);
+    }
+
+    /// Runs the steps of the user command or hook called `name`.
+    fn run_steps(&mut self, name: &str, steps: Vec<CommandStep>, args: &[&str]) {
+        if self.command_depth == MAX_COMMAND_DEPTH {
+            return self.set_status(format!("Commands nested too deeply in {}", name));
+        }
+        self.command_depth += 1;
+        for step in steps {
+            match step {
+                CommandStep::Command(command) => self.run_command(&expand_args(&command, args)),
+                CommandStep::Keys(keys) => {
+                    self.replayed_keys.extend(keys);
+                    while let Some(key) = self.replayed_keys.pop_front() {
+                        if let Err(err) = self.process_key(key) {
+                            self.replayed_keys.clear();
+                            self.set_status(format!("{} failed: {}", name, err));
+                        }
+                    }
+                }
+            }
+            if !self.keep_alive() {
+                break;
+            }
+        }
+        self.command_depth -= 1;
+    }
+
+    /// Pages through every binding, including the insert mappings set up at runtime. The
+    /// global keys are those bound now, action by action.
+    fn show_bindings(&mut self) {
+        let mut lines = Vec::new();
+        for (group, bindings) in BINDINGS {
+            // The table has the default keys; actions may have been bound to others since.
+            let group = group
+                .replace("Ctrl-W", &self.action_keys(Action::WindowCommand))
+                .replace("Ctrl-X", &self.action_keys(Action::ExtendedCommand));
+            lines.push(format!("{}:", group));
+            if group == "Global" {
+                lines.extend(Action::ALL.iter().map(|&action| {
+                    let keys = self
+                        .key_bindings
+                        .iter()
+                        .filter(|&&(_, bound)| bound == action)
+                        .map(|(key, _)| key_name(key))
+                        .collect::<Vec<String>>();
+                    let description = action_description(action).unwrap_or_default();
+                    format!(
+                        "  {:<12}{:<21}{}",
+                        keys.join(" / "),
+                        action.name(),
+                        description
+                    )
+                }));
+            }
+            // Keys with no action behind them, like the mouse, can't be bound elsewhere.
+            let fixed = bindings.iter().filter(|(_, command, _)| {
+                group != "Global"
+                    || row_names(command)
+                        .iter()
+                        .all(|name| Action::from_name(name).is_none())
+            });
+            lines.extend(fixed.map(|(keys, command, description)| {
+                format!("  {:<12}{:<21}{}", keys, command, description)
+            }));
+            lines.push(String::new());
+        }
+        if !self.user_commands.is_empty() {
+            lines.push("User commands:".to_string());
+            lines.extend(self.user_commands.iter().map(|(name, steps)| {
+                let keys = self
+                    .command_keys
+                    .iter()
+                    .filter(|(_, bound)| bound == name)
+                    .map(|(key, _)| key_name(key))
+                    .collect::<Vec<String>>();
+                let keys = if keys.is_empty() {
+                    String::new()
+                } else {
+                    format!(" ({})", keys.join(" / "))
+                };
+                format!("  {:<12}{}{}", name, describe_steps(steps), keys)
+            }));
+            lines.push(String::new());
+        }
+        if !self.hooks.is_empty() {
+            lines.push("Hooks:".to_string());
+            lines.extend(self.hooks.iter().map(|(event, pattern, steps)| {
+                let event = format!("{} {}", event.name(), pattern.unwrap_or("*"));
+                format!("  {:<24}{}", event, describe_steps(steps))
+            }));
+            lines.push(String::new());
+        }
+        if !self.insert_mappings.is_empty() {
+            lines.push("Insert mappings:".to_string());
+            lines.extend(self.insert_mappings.iter().map(|(lhs, rhs)| {
+                let keys = rhs.iter().map(key_name).collect::<Vec<String>>();
+                format!("  {:<12}{}", lhs, keys.join(" "))
+            }));
+        }
+        self.page("Key bindings", &lines, |line| {
+            if line.ends_with(':') {
+                "\x1b[1m"
+            } else {
+                ""
+            }
+        });
+    }
+
+    /// Reads the two characters of a digraph and types the one they name.
+    fn digraph(&mut self) -> Result<()> {
+        let Key::Printable(first) = self.read_chord_key("^D") else {
+            return Ok(());
+        };
+        let Key::Printable(second) = self.read_chord_key(&format!("^D {}", first)) else {
+            return Ok(());
+        };
+        match digraph::lookup(first, second) {
+            Some(ch) => self.process_key(Key::Printable(ch))?,
+            None => self.set_status(format!(
+                "No digraph {}{}, see :digraphs for the list",
+                first, second
+            )),
+        }
+        Ok(())
+    }
+
+    /// Pages through the digraphs and the characters they name.
+    fn show_digraphs(&mut self) {
+        let lines = digraph::DIGRAPHS
+            .chunks(8)
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .map(|(name, ch)| format!("{} {}", name, ch))
+                    .collect::<Vec<String>>()
+                    .join("     ")
+            })
+            .collect::<Vec<String>>();
+        self.page("Digraphs", &lines, |_| "");
+    }
+
+    /// Pages through a unified diff of the file on disk against the buffer.
+    fn show_diff(&mut self) {
+        let Some(lines) = self.diff_lines(None) else {
+            return;
+        };
+        let filename = self.buffer.filename().clone().unwrap_or_default();
+        self.page(&format!("Diff of {}", filename.display()), &lines, |line| {
+            if line.starts_with("---") || line.starts_with("+++") {
+                "\x1b[1m"
+            } else if line.starts_with('+') {
+                "\x1b[32m"
+            } else if line.starts_with('-') {
+                "\x1b[31m"
+            } else if line.starts_with('@') {
+                "\x1b[36m"
+            } else {
+                ""
+            }
+        });
+    }
+
+    /// Lines of a unified diff of the file on disk against the buffer, or with `other` of the
+    /// buffer it names against this one, with the headers naming them. None, saying why, if
+    /// there's nothing to compare or no difference.
+    fn diff_lines(&mut self, other: Option<&str>) -> Option<Vec<String>> {
+        let (old_name, old_text) = match other {
+            None => {
+                let Some(filename) = self.buffer.filename().clone() else {
+                    self.set_status("Buffer has no file to compare against".to_string());
+                    return None;
+                };
+                match std::fs::read_to_string(&filename) {
+                    Ok(saved) => (format!("{} (on disk)", filename.display()), saved),
+                    Err(err) => {
+                        self.set_status(format!("Can't read saved file: {}", err));
+                        return None;
+                    }
+                }
+            }
+            Some(name) => match self.buffer_named(name) {
+                Some(idx) if idx == self.current_buffer => {
+                    self.set_status("That's the buffer shown".to_string());
+                    return None;
+                }
+                Some(idx) => (self.buffer_label(idx), self.buffer_at(idx).rows_to_string()),
+                None => {
+                    self.set_status(format!("No buffer matches {}", name));
+                    return None;
+                }
+            },
+        };
+        let hunks = {
+            let old = old_text.lines().collect::<Vec<&str>>();
+            let current = (0..self.buffer.line_count())
+                .filter_map(|row| self.buffer.line(row))
+                .map(|line| line.content())
+                .collect::<Vec<&str>>();
+            diff::unified(&old, &current, DIFF_CONTEXT)
+        };
+        if hunks.is_empty() {
+            self.set_status(match other {
+                None => "No changes since the file was saved".to_string(),
+                Some(_) => format!("No differences from {}", old_name),
+            });
+            return None;
+        }
+        let new_name = match self.buffer.filename() {
+            Some(filename) if other.is_none() => format!("{} (buffer)", filename.display()),
+            _ => self.buffer_label(self.current_buffer),
+        };
+        let mut lines = vec![format!("--- {}", old_name), format!("+++ {}", new_name)];
+        lines.extend(hunks);
+        Some(lines)
+    }
+
+    /// Opens a unified diff of the file on disk against the buffer, or of the buffer `other`
+    /// names against this one, in a buffer of its own. Lines only on the old side show in red,
+    /// lines only on the new side in green, and lines that replaced others, on both sides, in
+    /// yellow. A diff opened before is brought up to date instead.
+    fn diff_buffer(&mut self, other: Option<&str>) {
+        let Some(lines) = self.diff_lines(other) else {
+            return;
+        };
+        let colored = lines
+            .iter()
+            .zip(diff_colors(&lines))
+            .map(|(line, color)| format!("{}{}\x1b[m", color, line))
+            .collect::<Vec<String>>();
+        let existing =
+            (0..self.buffers.len()).find(|&idx| self.buffer_at(idx).kind() == BufferKind::Diff);
+        match existing {
+            Some(idx) => {
+                self.switch_buffer(idx);
+                self.buffer.reload_text(&colored.join("\n"));
+                self.buffer.goto(1, 1);
+            }
+            None => self.open_buffer(BufferKind::Diff, colored),
+        }
+        self.buffer.interpret_ansi();
+        let hunks = lines.iter().filter(|line| line.starts_with("@@")).count();
+        self.set_status(format!(
+            "{} hunk{}",
+            hunks,
+            if hunks == 1 { "" } else { "s" }
+        ));
+    }
+
+    /// Shows `lines` over the whole screen until dismissed with q or Escape. `style` gives the
+    /// escape sequence to color each line with.
+    fn page(&mut self, title: &str, lines: &[String], style: impl Fn(&str) -> &'static str) {
+        let mut offset = 0;
+        loop {
+            self.terminal.refresh().unwrap_or(false);
+            let rows = self
+                .terminal
+                .rows()
+                .saturating_sub(STATUS_HEIGHT + MESSAGE_HEIGHT);
+            let cols = self.terminal.cols();
+            offset = min(offset, lines.len().saturating_sub(rows));
+
+            self.terminal.append("\x1b[?25l");
+            for n in 0..rows {
+                self.terminal.append(&format!("\x1b[{};1H\x1b[K", n + 1));
+                match lines.get(offset + n) {
+                    Some(line) => {
+                        let rendered = Line::with_tab_stop(line.clone(), self.tab_stop);
+                        let visible = rendered.rendered().chars().take(cols).collect::<String>();
+                        self.terminal
+                            .append(&format!("{}{}\x1b[m", style(line), visible));
+                    }
+                    None => self.terminal.append("~"),
+                }
+            }
+            let status = format!(
+                "{} - {}/{} (arrows/PgUp/PgDn to scroll, q to close)",
+                title,
+                min(offset + rows, lines.len()),
+                lines.len()
+            );
+            self.terminal.append(&format!(
+                "\x1b[{};1H\x1b[7m{:<width$.width$}\x1b[m\x1b[{};1H\x1b[K",
+                rows + 1,
+                status,
+                rows + 2,
+                width = cols
+            ));
+            self.terminal.flush();
+
+            let key = match self.read_event(IDLE_TICK) {
+                Ok(Event::Key(key)) => key,
+                // Redraw at the new size.
+                Ok(_) => continue,
+                Err(_) => Key::Escape,
+            };
+            match key {
+                Key::Move(Motion::Up) => offset = offset.saturating_sub(1),
+                Key::Move(Motion::Down) | Key::Newline => offset += 1,
+                Key::Move(Motion::PgUp) => offset = offset.saturating_sub(rows),
+                Key::Move(Motion::PgDn) | Key::Printable(' ') => offset += rows,
+                Key::Move(Motion::Home) => offset = 0,
+                Key::Move(Motion::End) => offset = lines.len(),
+                Key::Printable('q') | Key::Escape => break,
+                _ => {}
+            }
+        }
+    }
+
+    fn find(&mut self, direction: SearchDirection) {
+        let mut query = String::new();
+        let mut whole_word = false;
+        let mut regex = false;
+        let cursor = self.buffer.cursor_position();
+        loop {
+            let pattern = Pattern::new(&query, whole_word, regex);
+            let prompt = format!(
+                "Search{}{}{} (Use ESC/Arrows/Enter, Ctrl-W = whole word, Ctrl-R = regex): ",
+                if regex { " [regex]" } else { "" },
+                if whole_word { " [word]" } else { "" },
+                match &pattern {
+                    Err(err) => format!(" [{}]", err),
+                    Ok(_) => String::new(),
+                }
+            );
+            self.search_highlight = pattern.as_ref().ok().cloned();
+            let (finished, pending_key) = self.prompt_incremental(&prompt, &mut query);
+            if finished {
+                match pattern {
+                    Ok(pattern) if !query.is_empty() => {
+                        self.search = pattern;
+                        self.push_jump_from((cursor.cursor_row, cursor.cursor_col));
+                    }
+                    Err(err) if !query.is_empty() => {
+                        self.buffer.set_cursor_position(cursor);
+                        self.set_status(format!("Bad regex: {}", err));
+                    }
+                    _ => self.buffer.set_cursor_position(cursor),
+                }
+                break;
+            }
+            if let Some(Key::Control(toggle @ ('W' | 'R'))) = pending_key {
+                // Search again from where the query started, now with the other rule.
+                if toggle == 'W' {
+                    whole_word = !whole_word;
+                } else {
+                    regex = !regex;
+                }
+                self.buffer.set_cursor_position(cursor);
+            }
+            let Ok(pattern) = Pattern::new(&query, whole_word, regex) else {
+                continue;
+            };
+            let (row, col) = match pending_key {
+                Some(Key::Move(Motion::Up)) | Some(Key::Move(Motion::Left)) => {
+                    self.buffer.find_reverse(&pattern, true)
+                }
+                Some(Key::Move(Motion::Down)) | Some(Key::Move(Motion::Right)) => {
+                    self.buffer.find_forward(&pattern, true)
+                }
+                _ => match direction {
+                    SearchDirection::Forward => self.buffer.find_forward(&pattern, false),
+                    SearchDirection::Reverse => self.buffer.find_reverse(&pattern, false),
+                },
+            };
+
+            self.buffer.place_cursor(row, col);
+        }
+        self.search_highlight = None;
+    }
+
+    /// Asks for text to replace and what to replace it with, then goes through the matches.
+    fn replace(&mut self) {
+        let mut query = String::new();
+        while !self.prompt_incremental("Replace: ", &mut query).0 {}
+        if query.is_empty() {
+            return;
+        }
+        let prompt = format!("Replace {} with: ", query);
+        let mut replacement = String::new();
+        loop {
+            match self.prompt_incremental(&prompt, &mut replacement) {
+                (true, Some(Key::Escape)) => return,
+                (true, _) => break,
+                _ => {}
+            }
+        }
+        self.replace_matches(&Pattern::literal(&query, false), &replacement, true);
+    }
+
+    /// Replaces matches of `pattern` with `replacement` from the cursor to the end of the
+    /// buffer, then from the top back to the cursor. With `confirm`, asks about each match,
+    /// with the choice to replace all the rest. Runs as a single key press, so the lot is
+    /// one step back through the history.
+    fn replace_matches(&mut self, pattern: &Pattern, replacement: &str, confirm: bool) {
+        if pattern.is_empty() {
+            return;
+        }
+        let cursor = self.buffer.cursor_position();
+        let start = (cursor.cursor_row, cursor.cursor_col);
+        let mut from = start;
+        // Where matches stop once the search has wrapped around, moving with replacements
+        // before it on its line.
+        let mut limit = None;
+        let mut confirm = confirm;
+        let mut replaced = 0;
+        // Whether the last match was empty, so the next can't be one at the same place.
+        let mut after_empty = false;
+        self.search_highlight = Some(pattern.clone());
+        loop {
+            let found =
+                self.buffer
+                    .find_match(pattern, from, after_empty)
+                    .filter(|&(row, col, end)| {
+                        limit.is_none_or(|limit| (row, col) < limit && (row, end) <= limit)
+                    });
+            let Some((row, col, end)) = found else {
+                if limit.is_some() || start == (0, 0) {
+                    break;
+                }
+                limit = Some(start);
+                from = (0, 0);
+                after_empty = false;
+                continue;
+            };
+            let len = end - col;
+            after_empty = len == 0;
+            if confirm {
+                self.buffer.place_cursor(row, col);
+                match self.prompt_choice(
+                    "Replace this match? (y)es, (n)o, (a)ll the rest, (q)uit",
+                    "ynaq",
+                ) {
+                    Some('y') => {}
+                    Some('n') => {
+                        from = (row, end);
+                        continue;
+                    }
+                    Some('a') => confirm = false,
+                    _ => break,
+                }
+            }
+            self.buffer.replace_text((row, col), len, replacement);
+            replaced += 1;
+            from = (row, col + replacement.len());
+            if let Some(limit) = limit.as_mut().filter(|limit| limit.0 == row) {
+                limit.1 = limit.1 + replacement.len() - len;
+            }
+        }
+        self.search_highlight = None;
+        if replaced == 0 {
+            self.buffer.set_cursor_position(cursor);
+        }
+        self.set_status(format!(
+            "Replaced {} match{}",
+            replaced,
+            if replaced == 1 { "" } else { "es" }
+        ));
+    }
+
+    /// Makes the word under the cursor the search pattern, matching whole words only, and
+    /// jumps to its next occurrence in `direction`.
+    fn search_word_under_cursor(&mut self, direction: SearchDirection) {
+        let cursor = self.buffer.cursor_position();
+        let word = match region::word(&self.buffer, (cursor.cursor_row, cursor.cursor_col)) {
+            Some(word) => word,
+            None => return self.set_status("No word under the cursor".to_string()),
+        };
+        self.search = Pattern::literal(&self.buffer.region_text(word), true);
+        self.buffer.set_cursor_position(Cursor {
+            cursor_col: word.start.1,
+            ..cursor
+        });
+        self.search_next(direction);
     }
 
-    pub fn rows(&self) -> usize {
-        self.terminal.rows() - STATUS_HEIGHT
+    /// Moves the cursor to `ch` on the cursor line, forward or back, or next to it with `till`.
+    /// Any selection stretches to match.
+    fn find_char(&mut self, ch: char, forward: bool, till: bool) {
+        let cursor = self.buffer.cursor_position();
+        let found = self
+            .buffer
+            .line(cursor.cursor_row)
+            .and_then(|line| line.find_char(cursor.cursor_col, ch, forward, till));
+        match found {
+            Some(col) => self.buffer.set_cursor_position(Cursor {
+                cursor_col: col,
+                desired_col: None,
+                ..cursor
+            }),
+            None => self.set_status(format!("{:?} not found on the line", ch)),
+        }
     }
 
-    pub fn cols(&self) -> usize {
-        self.terminal.cols()
+    /// Jumps to the next occurrence of the search pattern in `direction`.
+    fn search_next(&mut self, direction: SearchDirection) {
+        if self.search.is_empty() {
+            return self.set_status("No search pattern yet".to_string());
+        }
+        let (row, col) = match direction {
+            SearchDirection::Forward => self.buffer.find_forward(&self.search, true),
+            SearchDirection::Reverse => self.buffer.find_reverse(&self.search, true),
+        };
+        self.push_jump();
+        self.buffer.place_cursor(row, col);
+        self.set_status(format!(
+            "Search: {}{}{}",
+            self.search.text,
+            if self.search.is_regex() {
+                " (regex)"
+            } else {
+                ""
+            },
+            if self.search.whole_word {
+                " (whole word)"
+            } else {
+                ""
+            }
+        ));
     }
 
-    pub fn keep_alive(&self) -> bool {
-        self.quit_count > 0
+    fn arrange_windows(&mut self) {
+        let screen = Rect {
+            top: 0,
+            left: 0,
+            height: self.terminal.rows().saturating_sub(MESSAGE_HEIGHT),
+            width: self.terminal.cols(),
+        };
+        self.separators = self.layout.arrange(screen, &mut self.windows);
     }
 
-    pub fn set_status(&mut self, msg: String) {
-        self.status_msg = msg;
-        self.status_msg_ts = Instant::now();
+    /// Moves the cursor to the text at screen `row` and `col`, focusing the window there first.
+    /// Drags stay in the focused window, following the pointer only up to its edges.
+    fn click(&mut self, row: usize, col: usize, drag: bool) {
+        if !drag {
+            let clicked = self
+                .layout
+                .windows()
+                .into_iter()
+                .find(|&idx| self.windows[idx].rect.contains(row, col));
+            match clicked {
+                Some(idx) if idx != self.active => self.focus_window(idx),
+                Some(_) => {}
+                None => return,
+            }
+        }
+        let rect = self.windows[self.active].rect;
+        let rows = rect.height.saturating_sub(STATUS_HEIGHT);
+        // Clicks on the status bar only focus.
+        if rows == 0 || rect.width == 0 || (!drag && row >= rect.top + rows) {
+            return;
+        }
+        // Clicks on the gutter go to the start of the line.
+        let gutter = self.gutter_width(rect.width);
+        self.buffer.place_cursor_in_view(
+            row.clamp(rect.top, rect.top + rows - 1) - rect.top,
+            (col.clamp(rect.left, rect.left + rect.width - 1) - rect.left).saturating_sub(gutter),
+            rect.width - gutter,
+        );
     }
 
-    pub fn process_keypress(&mut self) -> Result<()> {
-        let key = self.terminal.read_key()?;
+    fn focus_window(&mut self, idx: usize) {
+        self.anchor = None;
+        self.cursors.clear();
+        self.windows[self.active].cursor = self.buffer.cursor_position();
+        self.active = idx;
+        self.buffer.set_cursor_position(self.windows[idx].cursor);
+    }
 
+    fn window_command(&mut self) {
+        let mut count = 0;
+        let mut key = self.read_chord_key("^W");
+        while let Key::Printable(digit @ '0'..='9') = key {
+            count = count * 10 + digit as isize - '0' as isize;
+            key = self.read_chord_key(&format!("^W{}", count));
+        }
+        let count = count.max(1);
+        if key == Key::Printable('z') {
+            return self.toggle_zoom();
+        }
+        if let Some(layout) = self.zoomed.take() {
+            self.layout = layout;
+        }
         match key {
-            Key::Control('Q') => {
-                if self.buffer.is_dirty() && self.quit_count > 0 {
-                    self.quit_count -= 1;
-                    self.set_status(format!(
-                        "WARNING!!! Press Ctrl-Q {} more times to quit. File has unsaved changes.",
-                        self.quit_count
-                    ));
-                } else {
-                    self.quit_count = 0;
-                }
-                return Ok(()); // To prevent resetting QUIT_COUNT
+            Key::Printable('s') | Key::Control('S') => self.split_window(Split::Horizontal),
+            Key::Printable('v') | Key::Control('V') => self.split_window(Split::Vertical),
+            Key::Printable('w') | Key::Control('W') => self.cycle_window(true),
+            Key::Printable('W') => self.cycle_window(false),
+            Key::Printable('c') | Key::Printable('q') => self.close_window(),
+            Key::Printable('+') => self.resize_window(Split::Horizontal, count),
+            Key::Printable('-') => self.resize_window(Split::Horizontal, -count),
+            Key::Printable('>') => self.resize_window(Split::Vertical, count),
+            Key::Printable('<') => self.resize_window(Split::Vertical, -count),
+            Key::Printable('=') => {
+                self.layout.equalize();
+                self.arrange_windows();
             }
-            Key::Control('S') => self.save()?,
-            Key::Control('F') => self.find(SearchDirection::Forward),
-            Key::Control('G') => self.find(SearchDirection::Reverse),
-            Key::Move(motion) => self.buffer.move_cursor(motion, self.rows(), self.cols()),
-            Key::Printable(ch) => self.buffer.insert_char(ch),
-            Key::Tab => self.buffer.insert_char('\t'),
-            Key::Newline => self.buffer.insert_new_line(),
-            Key::Escape | Key::Control('L') => {}
-            Key::Backspace | Key::Control('H') => self.buffer.delete_char(),
-            Key::Delete => {
-                self.buffer
-                    .move_cursor(Motion::Right, self.rows(), self.cols());
-                self.buffer.delete_char();
+            Key::Printable('b') => {
+                let window = &mut self.windows[self.active];
+                window.scroll_bind = !window.scroll_bind;
+                let state = if window.scroll_bind { "on" } else { "off" };
+                self.set_status(format!("Scroll binding {} for this window", state));
             }
-            _key => {}
+            _ => {}
+        }
+    }
+
+    /// Gives the whole screen to the focused window, or brings back the layout it replaced.
+    fn toggle_zoom(&mut self) {
+        if let Some(layout) = self.zoomed.take() {
+            self.layout = layout;
+        } else if self.windows.len() > 1 {
+            self.zoomed = Some(std::mem::replace(
+                &mut self.layout,
+                Layout::new(self.active),
+            ));
+            self.set_status("Window zoomed. Ctrl-W z to restore".to_string());
+        }
+        self.arrange_windows();
+    }
+
+    fn split_window(&mut self, split: Split) {
+        let rect = self.windows[self.active].rect;
+        let size = match split {
+            Split::Horizontal => rect.height,
+            Split::Vertical => rect.width.saturating_sub(1),
         };
-        self.quit_count = TOTAL_QUIT_COUNT;
-        Ok(())
+        if size / 2 < MIN_SIZE + STATUS_HEIGHT {
+            self.set_status("Not enough room to split!!!".to_string());
+            return;
+        }
+        self.windows.push(Window {
+            cursor: self.buffer.cursor_position(),
+            cwd: self.windows[self.active].cwd.clone(),
+            views: self.windows[self.active].views.clone(),
+            ..Window::default()
+        });
+        let window = self.windows.len() - 1;
+        self.layout.split(self.active, window, split);
+        self.focus_window(window);
+        self.arrange_windows();
     }
 
-    pub fn open(&mut self, file_arg: Option<String>) -> Result<()> {
-        if let Some(file) = file_arg {
-            self.buffer.set_filename(Some(file.clone()));
-            let line_iter = BufReader::new(File::open(file)?).lines();
-            for line in line_iter {
-                self.buffer.append_row(line?);
-            }
+    fn resize_window(&mut self, split: Split, delta: isize) {
+        self.layout.resize(self.active, split, delta);
+        self.arrange_windows();
+    }
+
+    fn cycle_window(&mut self, forward: bool) {
+        let order = self.layout.windows();
+        let pos = order
+            .iter()
+            .position(|&idx| idx == self.active)
+            .unwrap_or(0);
+        let next = if forward {
+            (pos + 1) % order.len()
+        } else {
+            (pos + order.len() - 1) % order.len()
+        };
+        self.focus_window(order[next]);
+    }
+
+    fn close_window(&mut self) {
+        if self.windows.len() == 1 {
+            self.set_status("Can't close the last window!!!".to_string());
+            return;
         }
-        self.buffer.not_dirty();
-        Ok(())
+        self.layout.remove(self.active);
+        self.windows.remove(self.active);
+        self.active = min(self.active, self.windows.len() - 1);
+        self.buffer
+            .set_cursor_position(self.windows[self.active].cursor);
+        self.arrange_windows();
     }
 
-    fn save(&mut self) -> Result<()> {
-        if self.buffer.filename().is_none() {
-            let some_name = self.prompt("Save as (ESC to cancel): ");
-            self.buffer.set_filename(some_name);
+    /// Scrolls the focused window to its cursor, dragging scroll-bound windows along by the
+    /// same number of rows.
+    fn scroll_windows(&mut self) {
+        let before = self.windows[self.active].cursor.row_offset;
+        if self.rows() > 0 {
+            self.buffer.scroll(self.rows(), self.cols());
         }
-        if let Some(filename) = &self.buffer.filename() {
-            let content = self.buffer.rows_to_string();
-            if let Err(err) = std::fs::write(filename, content.as_bytes()) {
-                self.set_status(format!("Can't save! I/O error: {}", err));
-                return Err(err);
+        self.windows[self.active].cursor = self.buffer.cursor_position();
+        let after = self.buffer.cursor_position().row_offset;
+        if before == after || !self.windows[self.active].scroll_bind {
+            return;
+        }
+
+        let last_row = self.buffer.line_count().saturating_sub(1);
+        for (idx, window) in self.windows.iter_mut().enumerate() {
+            if idx == self.active || !window.scroll_bind {
+                continue;
             }
-            self.set_status(format!("{} bytes written to disk", content.len()));
-            self.buffer.not_dirty();
-        } else {
-            self.set_status("Filename not set!!!".to_string());
+            let rows = window.rect.height.saturating_sub(STATUS_HEIGHT).max(1);
+            let cursor = &mut window.cursor;
+            cursor.row_offset = if after > before {
+                min(cursor.row_offset + (after - before), last_row)
+            } else {
+                cursor.row_offset.saturating_sub(before - after)
+            };
+            cursor.cursor_row = cursor
+                .cursor_row
+                .max(cursor.row_offset)
+                .min(cursor.row_offset + rows - 1);
         }
-        Ok(())
     }
 
-    fn find(&mut self, direction: SearchDirection) {
-        let mut query = String::new();
+    fn draw_window(&mut self, idx: usize) {
+        let rect = self.windows[idx].rect;
+        let rows = rect.height.saturating_sub(STATUS_HEIGHT);
+        if rect.height == 0 || rect.width == 0 {
+            return;
+        }
+        let focused = self.buffer.cursor_position();
+        if idx != self.active {
+            self.buffer.set_cursor_position(self.windows[idx].cursor);
+        }
+        let gutter = self.gutter_width(rect.width);
+        let cols = rect.width - gutter;
+        if rows > 0 {
+            self.buffer.scroll(rows, cols);
+        }
+
+        let (content, mut highlights) = if self.buffer.is_empty() {
+            (crate::editor_home_screen(rows, rect.width), Vec::new())
+        } else {
+            (
+                self.buffer.frame_content(rows, cols),
+                self.buffer
+                    .frame_highlights(rows, cols, self.search_highlight.as_ref()),
+            )
+        };
         let cursor = self.buffer.cursor_position();
-        loop {
-            let (finished, pending_key) =
-                self.prompt_incremental("Search (Use ESC/Arrows/Enter): ", &mut query);
-            if finished {
-                break;
-            }
-            let (row, col) = match pending_key {
-                Some(Key::Move(Motion::Up)) | Some(Key::Move(Motion::Left)) => {
-                    self.buffer.find_reverse(&query, true)
+        let frame = self.buffer.frame_rows(rows, cols);
+        let brackets = self.bracket_pair().filter(|_| idx == self.active);
+        let (signs, changes) = if gutter == 0 {
+            (Vec::new(), Vec::new())
+        } else {
+            (self.diagnostic_signs(), self.git_changes())
+        };
+        for (n, mut line) in content.into_iter().enumerate() {
+            // Only the first row of a wrapped line gets its number.
+            let shown = frame.get(n).copied().flatten();
+            let label = match shown {
+                _ if gutter == 0 => String::new(),
+                Some((row, _, _))
+                    if n == 0 || frame[n - 1].is_none_or(|(prev, _, _)| prev != row) =>
+                {
+                    let mut label = self.line_numbers.label(row, cursor.cursor_row, gutter);
+                    // A diagnostic's sign takes the place of the space after the number, or
+                    // else the mark of a change since the last commit.
+                    let sign = signs
+                        .iter()
+                        .find(|&&(at, _)| at == row)
+                        .map(|&(_, sign)| {
+                            let color = match sign {
+                                'E' => self.palette.overflow,
+                                'W' => self.palette.search_match,
+                                _ => "",
+                            };
+                            (sign, color)
+                        })
+                        .or_else(|| {
+                            let change = changes.iter().find(|&&(at, _)| at == row);
+                            change.map(|&(_, change)| change.sign())
+                        })
+                        .map(|(sign, color)| {
+                            label.pop();
+                            format!("{}{}\x1b[m", color, sign)
+                        });
+                    let sign = sign.unwrap_or_default();
+                    if row == cursor.cursor_row {
+                        label + &sign
+                    } else {
+                        format!("{}{}\x1b[m{}", self.palette.line_number, label, sign)
+                    }
                 }
-                Some(Key::Move(Motion::Down)) | Some(Key::Move(Motion::Right)) => {
-                    self.buffer.find_forward(&query, true)
+                _ => " ".repeat(gutter),
+            };
+            let painted = match highlights.get_mut(n) {
+                Some(highlights) => {
+                    if let (Some(width), Some((_, start, _))) = (self.text_width, shown) {
+                        for highlight in highlights
+                            .iter_mut()
+                            .skip(width.saturating_sub(start))
+                            .filter(|highlight| **highlight != Highlight::Match)
+                        {
+                            *highlight = Highlight::Overflow;
+                        }
+                    }
+                    if let Some((row, start, width)) = shown.filter(|_| self.show_whitespace) {
+                        self.mark_blanks(row, start, width, &mut line, highlights);
+                    }
+                    let mut past_end = false;
+                    if let Some((row, start, _)) = shown.filter(|_| idx == self.active) {
+                        self.mark_selection(row, start, highlights);
+                        past_end = self.mark_cursors(row, start, highlights);
+                        for bracket in brackets.into_iter().flat_map(|(at, other)| [at, other]) {
+                            self.mark_bracket(bracket, row, start, highlights);
+                        }
+                    }
+                    if past_end && line.chars().count() < cols {
+                        highlights.resize(line.chars().count(), Highlight::Normal);
+                        highlights.push(Highlight::Cursor);
+                        highlight::paint(&format!("{} ", line), highlights, self.palette)
+                    } else {
+                        highlight::paint(&line, highlights, self.palette)
+                    }
                 }
-                _ => match direction {
-                    SearchDirection::Forward => self.buffer.find_forward(&query, false),
-                    SearchDirection::Reverse => self.buffer.find_reverse(&query, false),
-                },
+                None => line.clone(),
             };
+            let row = format!(
+                "{}{}{:pad$}",
+                label,
+                painted,
+                "",
+                pad = cols.saturating_sub(line.chars().count())
+            );
+            self.terminal
+                .screen()
+                .draw_at(rect.top + n, rect.left, &row);
+        }
+        let overlong = match self.text_width {
+            Some(width) if !self.buffer.is_empty() => self.buffer.overlong_lines(width),
+            _ => 0,
+        };
+        let status = self.draw_status_bar(rect.width, idx == self.active, overlong);
+        self.terminal
+            .screen()
+            .draw_at(rect.top + rows, rect.left, &status);
 
-            self.buffer.place_cursor(row, col);
+        if idx != self.active {
+            self.windows[idx].cursor = self.buffer.cursor_position();
+            self.buffer.set_cursor_position(focused);
         }
-        if query.is_empty() {
-            self.buffer.set_cursor_position(cursor);
+    }
+
+    /// Draws the blanks of line `row` in `line`, the `width` columns of it shown from render
+    /// column `start`, so that they stand out, and marks them in its `highlights`.
+    fn mark_blanks(
+        &self,
+        row: usize,
+        start: usize,
+        width: usize,
+        line: &mut String,
+        highlights: &mut Vec<Highlight>,
+    ) {
+        let Some(blanks) = self.buffer.line(row).map(|text| text.blanks(start, width)) else {
+            return;
+        };
+        if blanks.is_empty() {
+            return;
+        }
+        let mut chars = line.chars().collect::<Vec<char>>();
+        if highlights.len() < chars.len() {
+            highlights.resize(chars.len(), Highlight::Normal);
+        }
+        for blank in blanks {
+            let highlight = match blank.ch {
+                _ if blank.trailing => Highlight::Trailing,
+                '\t' => Highlight::Tab,
+                _ => Highlight::NoBreakSpace,
+            };
+            for col in blank.col..blank.col + blank.width {
+                let Some(idx) = col.checked_sub(start).filter(|&idx| idx < chars.len()) else {
+                    continue;
+                };
+                highlights[idx] = highlight;
+                if col == blank.col {
+                    match blank.ch {
+                        '\t' => chars[idx] = '»',
+                        '\u{a0}' => chars[idx] = '␣',
+                        _ => {}
+                    }
+                }
+            }
         }
+        *line = chars.into_iter().collect();
     }
 
-    fn draw_content(&self) -> String {
-        if self.buffer.is_empty() {
-            crate::editor_home_screen(self.rows(), self.cols())
+    /// Marks the part of the selection on line `row` in the `highlights` of the text shown
+    /// from render column `start`.
+    fn mark_selection(&self, row: usize, start: usize, highlights: &mut [Highlight]) {
+        let Some(line) = self.buffer.line(row) else {
+            return;
+        };
+        let (from, to) = if let Some(((top, bottom), cols)) = self.rectangle() {
+            if row < top || row > bottom {
+                return;
+            }
+            cols
+        } else if let Some(selection) = self.selection() {
+            if row < selection.start.0 || row > selection.end.0 {
+                return;
+            }
+            let from = if row == selection.start.0 {
+                line.cursor_to_render_position(selection.start.1)
+            } else {
+                0
+            };
+            let to = if row == selection.end.0 {
+                line.cursor_to_render_position(selection.end.1)
+            } else {
+                usize::MAX
+            };
+            (from, to)
         } else {
-            self.buffer.frame_content(self.rows(), self.cols())
+            return;
+        };
+        for (col, highlight) in (start..).zip(highlights.iter_mut()) {
+            if (from..to).contains(&col) {
+                *highlight = Highlight::Selection;
+            }
+        }
+    }
+
+    /// Marks the bracket at `pos`, if it's on line `row`, in the `highlights` of the text shown
+    /// from render column `start`.
+    fn mark_bracket(&self, pos: Position, row: usize, start: usize, highlights: &mut [Highlight]) {
+        let Some(line) = self.buffer.line(row).filter(|_| pos.0 == row) else {
+            return;
+        };
+        let col = pos.1;
+        let render = line.cursor_to_render_position(col);
+        if let Some(highlight) = render
+            .checked_sub(start)
+            .and_then(|idx| highlights.get_mut(idx))
+        {
+            *highlight = Highlight::Bracket;
+        }
+    }
+
+    /// Marks the cursors besides the main one on line `row`, and what they select, in the
+    /// `highlights` of the text shown from render column `start`. Returns whether one is at the
+    /// end of the line, just past the text shown.
+    fn mark_cursors(&self, row: usize, start: usize, highlights: &mut [Highlight]) -> bool {
+        let Some(line) = self.buffer.line(row) else {
+            return false;
+        };
+        let shown = highlights.len();
+        let mut mark = |from: usize, to: usize, highlight: Highlight| {
+            let from = from.saturating_sub(start);
+            let to = min(to.saturating_sub(start), highlights.len());
+            for cell in highlights.iter_mut().take(to).skip(from) {
+                *cell = highlight;
+            }
+        };
+        let mut past_end = false;
+        for (cursor, anchor) in &self.cursors {
+            let at = (cursor.cursor_row, cursor.cursor_col);
+            if let Some((anchor, _)) = anchor {
+                let (first, last) = (min(*anchor, at), (*anchor).max(at));
+                if (first.0..=last.0).contains(&row) {
+                    let from = if row == first.0 {
+                        line.cursor_to_render_position(first.1)
+                    } else {
+                        0
+                    };
+                    let to = if row == last.0 {
+                        line.cursor_to_render_position(last.1)
+                    } else {
+                        usize::MAX
+                    };
+                    mark(from, to, Highlight::Selection);
+                }
+            }
+            if cursor.cursor_row == row {
+                let col = line.cursor_to_render_position(cursor.cursor_col);
+                past_end |= col == line.columns() && col.checked_sub(start) == Some(shown);
+                mark(col, col + 1, Highlight::Cursor);
+            }
         }
+        past_end
     }
 
-    fn draw_status_bar(&self) -> String {
+    /// Status bar of a window, mentioning `overlong` lines that don't fit the text width.
+    fn draw_status_bar(&self, width: usize, focused: bool, overlong: usize) -> String {
         let filename = self
             .buffer
             .filename()
             .as_ref()
             .map(|file| file.to_str().unwrap_or("<file-name-not-utf8>"))
-            .unwrap_or("[No Name]");
+            .unwrap_or(self.buffer.kind().name());
+        let number = if self.buffers.len() > 1 {
+            format!("[{}/{}] ", self.current_buffer + 1, self.buffers.len())
+        } else {
+            String::new()
+        };
         let status_left = format!(
-            "{name:<.*} - {lc} lines {dirty}",
+            "{number}{name:<.*} - {lc} lines {dirty}",
             FILE_NAME_WIDTH,
+            number = number,
             name = filename,
             lc = self.buffer.line_count(),
             dirty = if self.buffer.is_dirty() {
                 "(modified)"
+            } else if self.buffer.is_viewing() {
+                "(view)"
+            } else if self.buffer.is_read_only() {
+                "(read-only)"
             } else {
                 ""
             },
         );
         let c_row = self.buffer.cursor_position().cursor_row;
-        let status_right = format!("{}/{}", c_row + 1, self.buffer.line_count());
-        let num_spaces = self
-            .cols()
-            .saturating_sub(status_left.len())
-            .saturating_sub(status_right.len());
+        let pending = if focused && !self.pending_keys.is_empty() {
+            format!("{} ", self.pending_keys)
+        } else {
+            String::new()
+        };
+        let selected = if focused {
+            self.selection_counts()
+                .map(|counts| format!("{} | ", counts))
+                .unwrap_or_default()
+        } else {
+            String::new()
+        };
+        let signs = self.diagnostic_signs();
+        let errors = signs.iter().filter(|&&(_, sign)| sign == 'E').count();
+        let warnings = signs.iter().filter(|&&(_, sign)| sign == 'W').count();
+        let branch = match (self.git_file(), &self.git) {
+            (Some(_), Some(tracker)) => tracker.status().map(|status| {
+                format!(
+                    "{}{} | ",
+                    status.branch,
+                    if status.dirty { "*" } else { "" }
+                )
+            }),
+            _ => None,
+        };
+        let status_right = format!(
+            "{}{}{}{}{}{}{}{}{}{}{}{}/{}",
+            pending,
+            if focused && self.overwrite {
+                "OVR "
+            } else {
+                ""
+            },
+            match &self.modal {
+                Some(modal) if focused => format!("{} ", modal.mode().name()),
+                _ => String::new(),
+            },
+            match self.recording {
+                Some((register, _)) => format!("REC {} ", register),
+                None => String::new(),
+            },
+            match self.cursors.len() {
+                0 => String::new(),
+                _ if !focused => String::new(),
+                extra => format!("{} cursors ", extra + 1),
+            },
+            selected,
+            match overlong {
+                0 => String::new(),
+                count => format!("{} long | ", count),
+            },
+            match (errors, warnings) {
+                (0, 0) => String::new(),
+                (errors, warnings) => format!("E{} W{} | ", errors, warnings),
+            },
+            branch.unwrap_or_default(),
+            self.buffer
+                .filetype()
+                .map(|filetype| format!("{} | ", filetype.name))
+                .unwrap_or_default(),
+            if self.buffer.kind().is_file_backed() {
+                format!(
+                    "{} | {} | ",
+                    self.buffer.encoding().name(),
+                    self.buffer.line_ending().name()
+                )
+            } else {
+                String::new()
+            },
+            c_row + 1,
+            self.buffer.line_count()
+        );
+        let num_spaces = width
+            .saturating_sub(status_left.chars().count())
+            .saturating_sub(status_right.chars().count());
 
-        format!(
-            "\x1b[7m{left}{:spaces$}{right}\x1b[m\r\n",
+        let status = format!(
+            "{left}{:spaces$}{right}",
             STATUS_LINE_BLANK,
             spaces = num_spaces,
             left = status_left,
             right = status_right
+        );
+        format!(
+            "{}{}\x1b[m",
+            self.palette.status_bar,
+            status.chars().take(width).collect::<String>()
         )
     }
 
+    /// Draws the overlay over the windows, which were drawn in full under it.
+    fn draw_overlay(&mut self) {
+        let top = self.terminal.rows().saturating_sub(self.overlay.len() + 1);
+        for (n, line) in self.overlay.iter().enumerate() {
+            self.terminal
+                .append(&format!("\x1b[{};1H\x1b[K{}", top + n + 1, line));
+        }
+    }
+
+    /// Draws the popup below the cursor, or above it where there's no room below, starting
+    /// its offset to the left of it. The windows were drawn in full under it.
+    fn draw_popup(&mut self) {
+        let (lines, width, shift) = &self.popup;
+        if lines.is_empty() {
+            return;
+        }
+        let (row, col) = self.cursor_on_screen();
+        let height = lines.len();
+        // Rows from 1, leaving out the message bar.
+        let top = if row + height < self.terminal.rows() {
+            row + 1
+        } else {
+            row.saturating_sub(height).max(1)
+        };
+        let left = col
+            .saturating_sub(*shift)
+            .min(self.terminal.cols().saturating_sub(*width) + 1)
+            .max(1);
+        let popup = lines
+            .iter()
+            .enumerate()
+            .map(|(n, line)| format!("\x1b[{};{}H{}", top + n, left, line))
+            .collect::<String>();
+        self.terminal.append(&popup);
+    }
+
     fn draw_message_bar(&mut self) {
-        self.terminal.append("\x1b[K");
-        if self.status_msg_ts.elapsed().as_secs() < 5 {
-            self.status_msg.truncate(self.cols());
-            let msg = self.status_msg.clone();
+        self.terminal
+            .append(format!("\x1b[{};1H\x1b[K", self.terminal.rows()).as_str());
+        if self.status_msg_ts.elapsed() < self.status_msg_timeout {
+            let msg = self
+                .status_msg
+                .chars()
+                .take(self.terminal.cols())
+                .collect::<String>();
             self.terminal.append(msg.as_str());
         }
     }
 
     pub fn refresh_screen(&mut self) {
-        self.terminal.refresh().unwrap_or(());
-        self.buffer.scroll(self.rows(), self.cols());
+        if self.terminal.refresh().unwrap_or(false) {
+            // Nothing drawn at the old size is in the right place anymore.
+            self.terminal.screen().clear();
+        }
+        self.update_git();
+        self.arrange_windows();
+        self.scroll_windows();
 
+        // What an overlay covered isn't known to have changed, nor where a shorter one leaves
+        // off.
+        if !self.overlay.is_empty() || !self.popup.0.is_empty() {
+            self.terminal.screen().invalidate();
+        }
         self.terminal.append("\x1b[?25l");
-        self.terminal.append("\x1b[H");
-
-        self.terminal.append(&self.draw_content());
-        self.terminal.append(&self.draw_status_bar());
+        for idx in self.layout.windows() {
+            if idx != self.active {
+                self.draw_window(idx);
+            }
+        }
+        let active = self.windows[self.active].rect;
+        for separator in self.separators.clone() {
+            let beside_active =
+                separator.left + 1 == active.left || separator.left == active.left + active.width;
+            for row in separator.top..separator.top + separator.height {
+                let along_active =
+                    beside_active && (active.top..active.top + active.height).contains(&row);
+                let border = format!(
+                    "{}{}\x1b[m",
+                    self.border_style.attributes(along_active),
+                    self.border_style.vertical()
+                );
+                self.terminal.screen().draw_at(row, separator.left, &border);
+            }
+        }
+        self.draw_window(self.active);
+        self.draw_overlay();
+        self.draw_popup();
         self.draw_message_bar();
 
-        let (c_row, c_col) = self.buffer.cursor_placement();
+        let (row, col) = self.cursor_on_screen();
         self.terminal
-            .append(format!("\x1b[{};{}H", c_row, c_col).as_str());
+            .append(format!("\x1b[{};{}H", row, col).as_str());
         self.terminal.append("\x1b[?25h");
         self.terminal.flush();
     }
 
+    /// Row and column of the cursor on the screen, counting from 1.
+    fn cursor_on_screen(&self) -> (usize, usize) {
+        let rect = self.windows[self.active].rect;
+        let (c_row, c_col) = self.buffer.cursor_placement(self.cols());
+        // A wrapped line taller than the window can leave the cursor below it.
+        let c_row = min(c_row, self.rows().max(1));
+        let c_col = c_col + self.gutter_width(rect.width);
+        (rect.top + c_row, rect.left + c_col)
+    }
+
+    /// Reads a key while a prompt is shown on the regular screen, redrawing the screen when
+    /// the terminal is resized in the meantime.
+    fn read_prompt_key(&mut self) -> Key {
+        loop {
+            match self.read_event(IDLE_TICK) {
+                Ok(Event::Key(key)) => return key,
+                Ok(Event::Resize(..)) => self.refresh_screen(),
+                Ok(Event::Tick) => {}
+                Err(_) => return Key::Escape,
+            }
+        }
+    }
+
     fn prompt_incremental(
         &mut self,
         prompt: &str,
         incremental: &mut String,
     ) -> (bool, Option<Key>) {
-        self.set_status(format!("{}{}", prompt, incremental));
+        self.prompt_hinted(prompt, incremental, "")
+    }
+
+    /// `prompt_incremental` with `hint` shown after the reply.
+    fn prompt_hinted(
+        &mut self,
+        prompt: &str,
+        incremental: &mut String,
+        hint: &str,
+    ) -> (bool, Option<Key>) {
+        self.set_status(format!("{}{}{}", prompt, incremental, hint));
         self.refresh_screen();
-        match self.terminal.read_key().unwrap_or(Key::Escape) {
+        match self.read_prompt_key() {
             Key::Printable(ch) => {
                 incremental.push(ch);
                 (false, None)
             }
             Key::Newline => {
-                self.set_status(format!(""));
+                self.set_status(String::new());
                 (true, None)
             }
             Key::Escape => {
                 incremental.clear();
-                self.set_status(format!(""));
-                (true, None)
+                self.set_status(String::new());
+                (true, Some(Key::Escape))
             }
             Key::Delete | Key::Backspace | Key::Control('H') => {
                 incremental.pop();
                 (false, None)
             }
+            Key::Paste(text) => {
+                incremental.push_str(text.lines().next().unwrap_or_default());
+                (false, None)
+            }
             key => (false, Some(key)),
         }
     }
 
-    fn prompt(&mut self, prompt: &str) -> Option<String> {
+    /// Asks for a single key out of `choices`. Returns None if the prompt is escaped.
+    fn prompt_choice(&mut self, prompt: &str, choices: &str) -> Option<char> {
+        self.set_status(prompt.to_string());
+        self.refresh_screen();
+        loop {
+            match self.read_prompt_key() {
+                Key::Printable(ch) if choices.contains(ch) => return Some(ch),
+                Key::Escape => return None,
+                _ => {}
+            }
+        }
+    }
+
+    /// Prompt for a path, where Tab completes it and pressing it again cycles through the
+    /// other candidates.
+    fn prompt_path(&mut self, prompt: &str) -> Option<String> {
+        self.prompt_completing(prompt, |editor, reply| (0, editor.complete_path(reply)))
+    }
+
+    /// Prompt where Tab completes the reply, and pressing it again cycles through the other
+    /// candidates, listed after the reply. `complete` gives those for a reply, and where in it
+    /// they go.
+    fn prompt_completing(
+        &mut self,
+        prompt: &str,
+        complete: impl Fn(&Self, &str) -> (usize, Vec<String>),
+    ) -> Option<String> {
         let mut reply = String::new();
+        // Text before the completion, the candidates and the one shown, while Tab is being
+        // pressed.
+        let mut completion: Option<(String, Vec<String>, usize)> = None;
         loop {
-            if self.prompt_incremental(prompt, &mut reply).0 {
+            // Candidates by the last part of their paths, the one shown in brackets.
+            let hint = match &completion {
+                Some((_, candidates, shown)) if candidates.len() > 1 => candidates
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, candidate)| {
+                        let start = candidate
+                            .trim_end_matches('/')
+                            .rfind('/')
+                            .map_or(0, |idx| idx + 1);
+                        if idx == *shown {
+                            format!(" [{}]", &candidate[start..])
+                        } else {
+                            format!(" {}", &candidate[start..])
+                        }
+                    })
+                    .collect::<String>(),
+                _ => String::new(),
+            };
+            let (finished, pending_key) = self.prompt_hinted(prompt, &mut reply, &hint);
+            if finished {
                 return if reply.is_empty() { None } else { Some(reply) };
             }
+            if pending_key != Some(Key::Tab) {
+                completion = None;
+                continue;
+            }
+            let (before, candidates, shown) = completion.get_or_insert_with(|| {
+                let (start, candidates) = complete(self, &reply);
+                (reply[..start].to_string(), candidates, usize::MAX)
+            });
+            if candidates.is_empty() {
+                continue;
+            }
+            *shown = shown.wrapping_add(1) % candidates.len();
+            reply = format!("{}{}", before, candidates[*shown]);
+            // With nothing to cycle through, the next Tab goes on from there, like into a
+            // directory.
+            if candidates.len() == 1 {
+                completion = None;
+            }
+        }
+    }
+}
+
+/// Entries of `dir` as a directory listing shows them: `../` to go up, then the directories
+/// with a slash after them, then the other files, each by name.
+fn list_dir(dir: &Path) -> Result<Vec<String>> {
+    let mut dirs = Vec::new();
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if entry.path().is_dir() {
+            dirs.push(format!("{}/", name));
+        } else {
+            files.push(name);
+        }
+    }
+    dirs.sort();
+    files.sort();
+    let up = dir.parent().map(|_| "../".to_string());
+    Ok(up.into_iter().chain(dirs).chain(files).collect())
+}
+
+/// The names a row of the bindings page stands for: its own, or both of a pair like
+/// `jump-back/forward`, whose second takes the place of the last word of the first.
+fn row_names(names: &str) -> Vec<String> {
+    match names.split_once('/') {
+        Some((first, second)) => {
+            let second = match first.rsplit_once('-') {
+                Some((stem, _)) => format!("{}-{}", stem, second),
+                None => second.to_string(),
+            };
+            vec![first.to_string(), second]
+        }
+        None => vec![names.to_string()],
+    }
+}
+
+/// What `action` does, as its row on the bindings page says.
+fn action_description(action: Action) -> Option<&'static str> {
+    BINDINGS
+        .iter()
+        .flat_map(|(_, bindings)| bindings.iter())
+        .find(|(_, names, _)| row_names(names).iter().any(|name| name == action.name()))
+        .map(|&(_, _, description)| description)
+}
+
+/// The window or extended command called `name` on the bindings page, as the action starting
+/// it and the key that follows. Only those run by a single key have one.
+fn chord_by_name(name: &str) -> Option<(Action, char)> {
+    BINDINGS.iter().find_map(|(group, bindings)| {
+        let prefix = if group.starts_with("Windows") {
+            Action::WindowCommand
+        } else if group.starts_with("Extended") {
+            Action::ExtendedCommand
+        } else {
+            return None;
+        };
+        bindings.iter().find_map(|(keys, bound, _)| {
+            let mut chars = keys.chars();
+            match (chars.next(), chars.next()) {
+                (Some(key), None) if *bound == name => Some((prefix, key)),
+                _ => None,
+            }
+        })
+    })
+}
+
+/// Pattern, replacement and whether to replace all matches without asking, out of the part
+/// of a `%s/PATTERN/REPLACEMENT/[a]` command after `%s`. Any character can stand in for the
+/// slashes, and a backslash before it makes it part of the text.
+fn parse_substitute(text: &str) -> Option<(String, String, bool)> {
+    let mut chars = text.chars();
+    let delimiter = chars
+        .next()
+        .filter(|ch| !ch.is_alphanumeric() && *ch != ' ')?;
+    let mut parts = vec![String::new()];
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => match chars.next() {
+                Some(next) if next == delimiter => parts.last_mut()?.push(next),
+                Some(next) => {
+                    parts.last_mut()?.push('\\');
+                    parts.last_mut()?.push(next);
+                }
+                None => parts.last_mut()?.push('\\'),
+            },
+            ch if ch == delimiter => parts.push(String::new()),
+            ch => parts.last_mut()?.push(ch),
+        }
+    }
+    match parts.as_slice() {
+        [query, ..] if query.is_empty() => None,
+        [query, replacement] => Some((query.clone(), replacement.clone(), false)),
+        [query, replacement, flags] if flags.is_empty() || flags == "a" => {
+            Some((query.clone(), replacement.clone(), flags == "a"))
+        }
+        _ => None,
+    }
+}
+
+/// `age` in the largest unit it's at least one of, like `3 minutes`.
+fn describe_age(age: Duration) -> String {
+    let secs = age.as_secs();
+    let (count, unit) = match secs {
+        0..60 => (secs, "second"),
+        60..3600 => (secs / 60, "minute"),
+        3600..86400 => (secs / 3600, "hour"),
+        _ => (secs / 86400, "day"),
+    };
+    format!("{} {}{}", count, unit, if count == 1 { "" } else { "s" })
+}
+
+/// `command` with the `$` placeholders of a user command replaced by its arguments. Missing
+/// arguments are left empty.
+fn expand_args(command: &str, args: &[&str]) -> String {
+    let mut expanded = String::with_capacity(command.len());
+    let mut chars = command.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match (ch, chars.peek()) {
+            ('$', Some('*')) => expanded.push_str(&args.join(" ")),
+            ('$', Some('$')) => expanded.push('$'),
+            ('$', Some(&digit @ '1'..='9')) => {
+                let idx = digit as usize - '1' as usize;
+                expanded.push_str(args.get(idx).copied().unwrap_or(""));
+            }
+            _ => {
+                expanded.push(ch);
+                continue;
+            }
+        }
+        chars.next();
+    }
+    expanded
+}
+
+fn describe_steps(steps: &[CommandStep]) -> String {
+    steps
+        .iter()
+        .map(|step| match step {
+            CommandStep::Command(command) => format!(":{}", command),
+            CommandStep::Keys(keys) => keys.iter().map(key_name).collect::<Vec<String>>().join(" "),
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// The shell set up to run `command`.
+fn shell(command: &str) -> Command {
+    #[cfg(unix)]
+    let mut shell = Command::new("sh");
+    #[cfg(unix)]
+    shell.arg("-c");
+    #[cfg(windows)]
+    let mut shell = Command::new("cmd");
+    #[cfg(windows)]
+    shell.arg("/C");
+    shell.arg(command);
+    shell
+}
+
+/// What the command `shell` runs prints given `input`, or the first line of what it complains
+/// about if it fails.
+fn filter(mut shell: Command, input: &str) -> Result<String> {
+    let mut child = shell
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // Written from a thread of its own, so that a command printing as it reads can't fill its
+    // output pipe while the input is still going in.
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let input = input.to_string();
+    let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = child.wait_with_output()?;
+    writer.join().unwrap_or(Ok(()))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stderr
+            .lines()
+            .next()
+            .map_or(output.status.to_string(), str::to_string);
+        return Err(std::io::Error::other(reason));
+    }
+    String::from_utf8(output.stdout).map_err(|_| std::io::Error::other("output isn't UTF-8"))
+}
+
+fn key_name(key: &Key) -> String {
+    match key {
+        Key::Printable(ch) => ch.to_string(),
+        Key::Control('@') => "Ctrl-Space".to_string(),
+        Key::Control(ch) => format!("Ctrl-{}", ch),
+        Key::Move(motion) => format!("{:?}", motion),
+        Key::Select(motion) => format!("Shift-{:?}", motion),
+        Key::Modified(motion, modifiers) => {
+            let mut name = String::new();
+            for (modifier, prefix) in [
+                (Modifiers::CTRL, "Ctrl-"),
+                (Modifiers::ALT, "Alt-"),
+                (Modifiers::SHIFT, "Shift-"),
+            ] {
+                if modifiers.contains(modifier) {
+                    name.push_str(prefix);
+                }
+            }
+            format!("{}{:?}", name, motion)
+        }
+        Key::Alt(ch) => format!("Alt-{}", ch),
+        Key::Function(number) => format!("F{}", number),
+        key => format!("{:?}", key),
+    }
+}
+
+/// Escape sequence coloring each line of a unified diff. Lines taken out right before others
+/// go in, changes to the same lines, are yellow on both sides. The first two lines are the
+/// headers naming the two sides.
+fn diff_colors(lines: &[String]) -> Vec<&'static str> {
+    let mut colors = Vec::with_capacity(lines.len());
+    let mut idx = 0;
+    while idx < lines.len() {
+        let line = &lines[idx];
+        if idx < 2 {
+            colors.push("\x1b[1m");
+        } else if line.starts_with("@@") {
+            colors.push("\x1b[36m");
+        } else if line.starts_with('-') || line.starts_with('+') {
+            let removed = lines[idx..]
+                .iter()
+                .take_while(|line| line.starts_with('-'))
+                .count();
+            let added = lines[idx + removed..]
+                .iter()
+                .take_while(|line| line.starts_with('+'))
+                .count();
+            let (removed_color, added_color) = if removed > 0 && added > 0 {
+                ("\x1b[33m", "\x1b[33m")
+            } else {
+                ("\x1b[31m", "\x1b[32m")
+            };
+            colors.extend(std::iter::repeat_n(removed_color, removed));
+            colors.extend(std::iter::repeat_n(added_color, added));
+            idx += removed + added;
+            continue;
+        } else {
+            colors.push("");
+        }
+        idx += 1;
+    }
+    colors
+}
+
+/// Unicode names of the characters most likely to need telling apart from others that look
+/// the same. The full name table is too big to carry around.
+fn char_name(ch: char) -> Option<&'static str> {
+    const CONTROLS: [&str; 32] = [
+        "NULL",
+        "START OF HEADING",
+        "START OF TEXT",
+        "END OF TEXT",
+        "END OF TRANSMISSION",
+        "ENQUIRY",
+        "ACKNOWLEDGE",
+        "BELL",
+        "BACKSPACE",
+        "CHARACTER TABULATION",
+        "LINE FEED",
+        "LINE TABULATION",
+        "FORM FEED",
+        "CARRIAGE RETURN",
+        "SHIFT OUT",
+        "SHIFT IN",
+        "DATA LINK ESCAPE",
+        "DEVICE CONTROL ONE",
+        "DEVICE CONTROL TWO",
+        "DEVICE CONTROL THREE",
+        "DEVICE CONTROL FOUR",
+        "NEGATIVE ACKNOWLEDGE",
+        "SYNCHRONOUS IDLE",
+        "END OF TRANSMISSION BLOCK",
+        "CANCEL",
+        "END OF MEDIUM",
+        "SUBSTITUTE",
+        "ESCAPE",
+        "INFORMATION SEPARATOR FOUR",
+        "INFORMATION SEPARATOR THREE",
+        "INFORMATION SEPARATOR TWO",
+        "INFORMATION SEPARATOR ONE",
+    ];
+    match ch {
+        '\0'..='\x1f' => Some(CONTROLS[ch as usize]),
+        ' ' => Some("SPACE"),
+        '\x7f' => Some("DELETE"),
+        '\u{a0}' => Some("NO-BREAK SPACE"),
+        '\u{ad}' => Some("SOFT HYPHEN"),
+        '\u{2002}' => Some("EN SPACE"),
+        '\u{2003}' => Some("EM SPACE"),
+        '\u{2009}' => Some("THIN SPACE"),
+        '\u{200b}' => Some("ZERO WIDTH SPACE"),
+        '\u{200c}' => Some("ZERO WIDTH NON-JOINER"),
+        '\u{200d}' => Some("ZERO WIDTH JOINER"),
+        '\u{2010}' => Some("HYPHEN"),
+        '\u{2011}' => Some("NON-BREAKING HYPHEN"),
+        '\u{2013}' => Some("EN DASH"),
+        '\u{2014}' => Some("EM DASH"),
+        '\u{2018}' => Some("LEFT SINGLE QUOTATION MARK"),
+        '\u{2019}' => Some("RIGHT SINGLE QUOTATION MARK"),
+        '\u{201c}' => Some("LEFT DOUBLE QUOTATION MARK"),
+        '\u{201d}' => Some("RIGHT DOUBLE QUOTATION MARK"),
+        '\u{2028}' => Some("LINE SEPARATOR"),
+        '\u{2029}' => Some("PARAGRAPH SEPARATOR"),
+        '\u{202f}' => Some("NARROW NO-BREAK SPACE"),
+        '\u{2060}' => Some("WORD JOINER"),
+        '\u{3000}' => Some("IDEOGRAPHIC SPACE"),
+        '\u{feff}' => Some("ZERO WIDTH NO-BREAK SPACE"),
+        '\u{fffd}' => Some("REPLACEMENT CHARACTER"),
+        _ => None,
+    }
+}
+
+/// Whether `path` can be written, or doesn't exist yet to tell. Write permission is looked at
+/// as well as tried, since the superuser can write to a file nobody has permission to.
+fn is_writable(path: &Path) -> bool {
+    match std::fs::metadata(path) {
+        Ok(meta) if meta.is_file() => {
+            !meta.permissions().readonly()
+                && std::fs::OpenOptions::new().write(true).open(path).is_ok()
         }
+        _ => true,
     }
 }
@@ -0,0 +1,111 @@
+use std::convert::TryFrom;
+
+/// How the text of a file is stored as bytes. Buffers always hold UTF-8; files in another
+/// encoding are turned into it when read and back when saved.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    /// UTF-8 starting with a byte order mark, as some Windows programs write it.
+    Utf8Bom,
+    Utf16Le,
+    Utf16Be,
+    /// ISO 8859-1, one byte for each of the first 256 characters.
+    Latin1,
+}
+
+const UTF8_BOM: &[u8] = &[0xef, 0xbb, 0xbf];
+const UTF16LE_BOM: &[u8] = &[0xff, 0xfe];
+const UTF16BE_BOM: &[u8] = &[0xfe, 0xff];
+
+impl Encoding {
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "UTF-8",
+            Encoding::Utf8Bom => "UTF-8 BOM",
+            Encoding::Utf16Le => "UTF-16LE",
+            Encoding::Utf16Be => "UTF-16BE",
+            Encoding::Latin1 => "Latin-1",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Encoding> {
+        match name.to_ascii_lowercase().replace('_', "-").as_str() {
+            "utf-8" | "utf8" => Some(Encoding::Utf8),
+            "utf-8-bom" | "utf8-bom" => Some(Encoding::Utf8Bom),
+            "utf-16" | "utf-16le" | "utf16le" => Some(Encoding::Utf16Le),
+            "utf-16be" | "utf16be" => Some(Encoding::Utf16Be),
+            "latin-1" | "latin1" | "iso-8859-1" => Some(Encoding::Latin1),
+            _ => None,
+        }
+    }
+
+    /// The encoding `bytes` look to be in: the one its byte order mark says, UTF-8 if they're
+    /// valid as that, or else Latin-1, which any bytes are.
+    pub fn detect(bytes: &[u8]) -> Encoding {
+        if bytes.starts_with(UTF8_BOM) {
+            Encoding::Utf8Bom
+        } else if bytes.starts_with(UTF16LE_BOM) {
+            Encoding::Utf16Le
+        } else if bytes.starts_with(UTF16BE_BOM) {
+            Encoding::Utf16Be
+        } else if std::str::from_utf8(bytes).is_ok() {
+            Encoding::Utf8
+        } else {
+            Encoding::Latin1
+        }
+    }
+
+    /// The text `bytes` hold in this encoding, leaving out the byte order mark. Bytes that
+    /// don't make a character become U+FFFD.
+    pub fn decode(self, bytes: &[u8]) -> String {
+        match self {
+            Encoding::Utf8 | Encoding::Utf8Bom => {
+                let bytes = bytes.strip_prefix(UTF8_BOM).unwrap_or(bytes);
+                String::from_utf8_lossy(bytes).into_owned()
+            }
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                let bom = if self == Encoding::Utf16Le {
+                    UTF16LE_BOM
+                } else {
+                    UTF16BE_BOM
+                };
+                let bytes = bytes.strip_prefix(bom).unwrap_or(bytes);
+                let units = bytes
+                    .chunks(2)
+                    .map(|pair| match (self, pair) {
+                        (Encoding::Utf16Le, &[low, high]) => u16::from_le_bytes([low, high]),
+                        (_, &[high, low]) => u16::from_be_bytes([high, low]),
+                        // An odd byte at the end is half a character.
+                        _ => 0xfffd,
+                    })
+                    .collect::<Vec<u16>>();
+                String::from_utf16_lossy(&units)
+            }
+            Encoding::Latin1 => bytes.iter().map(|&byte| char::from(byte)).collect(),
+        }
+    }
+
+    /// `text` as bytes in this encoding, with the byte order mark it calls for, or the first
+    /// character it has no bytes for.
+    pub fn encode(self, text: &str) -> Result<Vec<u8>, char> {
+        match self {
+            Encoding::Utf8 => Ok(text.as_bytes().to_vec()),
+            Encoding::Utf8Bom => Ok([UTF8_BOM, text.as_bytes()].concat()),
+            Encoding::Utf16Le => {
+                let mut bytes = UTF16LE_BOM.to_vec();
+                bytes.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+                Ok(bytes)
+            }
+            Encoding::Utf16Be => {
+                let mut bytes = UTF16BE_BOM.to_vec();
+                bytes.extend(text.encode_utf16().flat_map(u16::to_be_bytes));
+                Ok(bytes)
+            }
+            Encoding::Latin1 => text
+                .chars()
+                .map(|ch| u8::try_from(ch).map_err(|_| ch))
+                .collect(),
+        }
+    }
+}
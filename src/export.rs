@@ -0,0 +1,126 @@
+use crate::line::{Line, TAB_STOP};
+use std::io::{Error, Result, Write};
+
+/// How text is laid out for printing or sharing.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ExportOptions {
+    /// Number each line in a gutter.
+    pub numbers: bool,
+    /// Start with a line naming the file and the lines exported.
+    pub header: bool,
+    /// Break lines to fit this many columns, numbers included, carrying on below under the
+    /// text.
+    pub wrap: Option<usize>,
+    /// First and last line to export, counting from 1. All of them when left out.
+    pub lines: Option<(usize, usize)>,
+}
+
+impl ExportOptions {
+    /// Reads the options at the start of `args`: `-n` for numbers, `-H` for the header,
+    /// `-w COLS` to wrap and `-l FIRST-LAST` for a range of lines. Returns them with the rest
+    /// of the arguments.
+    pub fn parse<'a>(args: &'a [&'a str]) -> std::result::Result<(Self, &'a [&'a str]), String> {
+        let mut options = Self::default();
+        let mut rest = args;
+        while let Some((&arg, tail)) = rest.split_first() {
+            match arg {
+                "-n" => options.numbers = true,
+                "-H" => options.header = true,
+                "-w" | "-l" => {
+                    let value = *tail
+                        .first()
+                        .ok_or_else(|| format!("{} needs a value", arg))?;
+                    if arg == "-w" {
+                        let cols = value.parse().ok().filter(|&cols| cols > 0);
+                        options.wrap = Some(cols.ok_or_else(|| format!("Bad width: {}", value))?);
+                    } else {
+                        options.lines = Some(parse_range(value)?);
+                    }
+                    rest = &tail[1..];
+                    continue;
+                }
+                _ => break,
+            }
+            rest = tail;
+        }
+        Ok((options, rest))
+    }
+}
+
+/// `FIRST-LAST`, or a single line number, as a range of lines counting from 1.
+fn parse_range(text: &str) -> std::result::Result<(usize, usize), String> {
+    let (first, last) = text.split_once('-').unwrap_or((text, text));
+    match (first.parse::<usize>(), last.parse::<usize>()) {
+        (Ok(first), Ok(last)) if first >= 1 && first <= last => Ok((first, last)),
+        _ => Err(format!("Bad line range: {}", text)),
+    }
+}
+
+/// `lines` of the file called `name` laid out by `options`, with tabs expanded to every
+/// `tab_stop` columns.
+pub fn export(name: &str, lines: &[&str], tab_stop: usize, options: &ExportOptions) -> String {
+    let (first, last) = options.lines.unwrap_or((1, lines.len()));
+    let last = last.min(lines.len());
+    let gutter = if options.numbers {
+        last.to_string().len()
+    } else {
+        0
+    };
+    // Numbers are followed by ` | `.
+    let text_cols = options
+        .wrap
+        .map(|cols| cols.saturating_sub(if options.numbers { gutter + 3 } else { 0 }));
+    let mut out = String::new();
+    if options.header {
+        out.push_str(&format!(
+            "{}, lines {}-{} of {}\n\n",
+            name,
+            first.min(last),
+            last,
+            lines.len()
+        ));
+    }
+    for (idx, line) in lines.iter().enumerate().take(last).skip(first - 1) {
+        let line = Line::with_tab_stop(line.to_string(), tab_stop);
+        let rendered = line.rendered().chars().collect::<Vec<char>>();
+        let mut starts = match text_cols {
+            Some(cols) => line.wrap_starts(cols),
+            None => vec![0],
+        };
+        // Wrapping leaves room for a cursor past the end, which has no place on paper.
+        starts.retain(|&start| start == 0 || start < rendered.len());
+        for (n, &start) in starts.iter().enumerate() {
+            let end = starts.get(n + 1).copied().unwrap_or(rendered.len());
+            if options.numbers && n == 0 {
+                out.push_str(&format!("{:>gutter$} | ", idx + 1));
+            } else if options.numbers {
+                out.push_str(&format!("{:gutter$} | ", ""));
+            }
+            let text = rendered[start..end].iter().collect::<String>();
+            out.push_str(text.trim_end());
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// Exports files named in `args` to standard output, after the options `ExportOptions::parse`
+/// reads.
+pub fn run(args: &[String]) -> Result<()> {
+    let args = args.iter().map(String::as_str).collect::<Vec<&str>>();
+    let (options, files) = ExportOptions::parse(&args).map_err(Error::other)?;
+    if files.is_empty() {
+        return Err(Error::other("Nothing to export: no files given"));
+    }
+    let mut stdout = std::io::stdout().lock();
+    for (n, file) in files.iter().enumerate() {
+        let text = std::fs::read_to_string(file)
+            .map_err(|err| Error::new(err.kind(), format!("{}: {}", file, err)))?;
+        if n > 0 {
+            stdout.write_all(b"\n")?;
+        }
+        let lines = text.lines().collect::<Vec<&str>>();
+        stdout.write_all(export(file, &lines, TAB_STOP, &options).as_bytes())?;
+    }
+    Ok(())
+}
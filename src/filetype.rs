@@ -0,0 +1,311 @@
+use std::path::Path;
+use std::sync::RwLock;
+
+/// What the editor knows about a kind of file.
+pub struct Filetype {
+    pub name: &'static str,
+    pub extensions: &'static [&'static str],
+    /// Whole file names that have no telling extension, like `Makefile`.
+    pub filenames: &'static [&'static str],
+    /// Programs named on the `#!` line of scripts of this type.
+    pub interpreters: &'static [&'static str],
+    /// One level of indentation.
+    pub indent: &'static str,
+    /// Characters that, ending a line, indent the line after it by another level.
+    pub indent_after: &'static [char],
+    pub line_comment: Option<&'static str>,
+    /// Opening and closing delimiters of block comments.
+    pub block_comment: Option<(&'static str, &'static str)>,
+    /// Reserved words and built-in types, highlighted as keywords.
+    pub keywords: &'static [&'static str],
+    /// Characters that start and end strings. Single quotes only make strings of up to a few
+    /// characters, so that they don't take Rust lifetimes or apostrophes for one.
+    pub quotes: &'static [char],
+    /// Delimiters of strings that may go on over several lines, ahead of `quotes`, and
+    /// whether backslashes escape characters in them.
+    pub long_strings: &'static [(&'static str, &'static str, bool)],
+}
+
+/// Filetypes defined in the user's syntax files, looked at before the built-in ones.
+static USER_FILETYPES: RwLock<Vec<&'static Filetype>> = RwLock::new(Vec::new());
+
+static FILETYPES: [Filetype; 7] = [
+    Filetype {
+        name: "Rust",
+        extensions: &["rs"],
+        filenames: &[],
+        interpreters: &[],
+        indent: "    ",
+        indent_after: &['{', '(', '['],
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+        keywords: &[
+            "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum",
+            "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod",
+            "move", "mut", "pub", "ref", "return", "self", "Self", "static", "struct", "super",
+            "trait", "true", "type", "unsafe", "use", "where", "while", "bool", "char", "str",
+            "u8", "u16", "u32", "u64", "u128", "usize", "i8", "i16", "i32", "i64", "i128", "isize",
+            "f32", "f64", "String", "Vec", "Option", "Result", "Some", "None", "Ok", "Err", "Box",
+        ],
+        quotes: &['"', '\''],
+        long_strings: &[
+            ("\"", "\"", true),
+            ("r\"", "\"", false),
+            ("r#\"", "\"#", false),
+            ("r##\"", "\"##", false),
+            ("br\"", "\"", false),
+            ("br#\"", "\"#", false),
+        ],
+    },
+    Filetype {
+        name: "C",
+        extensions: &["c", "h", "cc", "cpp", "hpp"],
+        filenames: &[],
+        interpreters: &[],
+        indent: "    ",
+        indent_after: &['{', '(', '['],
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+        keywords: &[
+            "auto", "break", "case", "const", "continue", "default", "do", "else", "enum",
+            "extern", "for", "goto", "if", "inline", "register", "restrict", "return", "sizeof",
+            "static", "struct", "switch", "typedef", "union", "volatile", "while", "char",
+            "double", "float", "int", "long", "short", "signed", "unsigned", "void", "bool",
+            "size_t", "NULL", "true", "false", "#include", "#define", "#if", "#ifdef", "#ifndef",
+            "#else", "#endif",
+        ],
+        quotes: &['"', '\''],
+        long_strings: &[],
+    },
+    Filetype {
+        name: "JavaScript",
+        extensions: &["js", "ts"],
+        filenames: &[],
+        interpreters: &["node", "deno"],
+        indent: "  ",
+        indent_after: &['{', '(', '['],
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+        keywords: &[
+            "async",
+            "await",
+            "break",
+            "case",
+            "catch",
+            "class",
+            "const",
+            "continue",
+            "default",
+            "delete",
+            "do",
+            "else",
+            "export",
+            "extends",
+            "false",
+            "finally",
+            "for",
+            "function",
+            "if",
+            "import",
+            "in",
+            "instanceof",
+            "let",
+            "new",
+            "null",
+            "return",
+            "switch",
+            "this",
+            "throw",
+            "true",
+            "try",
+            "typeof",
+            "undefined",
+            "var",
+            "while",
+            "yield",
+        ],
+        quotes: &['"', '\'', '`'],
+        long_strings: &[("`", "`", true)],
+    },
+    Filetype {
+        name: "Python",
+        extensions: &["py"],
+        filenames: &[],
+        interpreters: &["python", "python2", "python3"],
+        indent: "    ",
+        indent_after: &[':', '(', '[', '{'],
+        line_comment: Some("#"),
+        block_comment: None,
+        keywords: &[
+            "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del",
+            "elif", "else", "except", "False", "finally", "for", "from", "global", "if", "import",
+            "in", "is", "lambda", "None", "nonlocal", "not", "or", "pass", "raise", "return",
+            "True", "try", "while", "with", "yield",
+        ],
+        quotes: &['"', '\''],
+        long_strings: &[("\"\"\"", "\"\"\"", true), ("'''", "'''", true)],
+    },
+    Filetype {
+        name: "Shell",
+        extensions: &["sh", "bash", "zsh"],
+        filenames: &[".bashrc", ".bash_profile", ".profile", ".zshrc", "PKGBUILD"],
+        interpreters: &["sh", "bash", "zsh", "dash", "ksh"],
+        indent: "    ",
+        indent_after: &['{', '('],
+        line_comment: Some("#"),
+        block_comment: None,
+        keywords: &[
+            "case", "do", "done", "elif", "else", "esac", "export", "fi", "for", "function", "if",
+            "in", "local", "return", "then", "until", "while",
+        ],
+        quotes: &['"', '\''],
+        long_strings: &[("\"", "\"", true), ("'", "'", false)],
+    },
+    Filetype {
+        name: "TOML",
+        extensions: &["toml"],
+        filenames: &["Cargo.lock"],
+        interpreters: &[],
+        indent: "    ",
+        indent_after: &['[', '{'],
+        line_comment: Some("#"),
+        block_comment: None,
+        keywords: &["true", "false"],
+        quotes: &['"', '\''],
+        long_strings: &[("\"\"\"", "\"\"\"", true), ("'''", "'''", false)],
+    },
+    Filetype {
+        name: "Make",
+        extensions: &["mk"],
+        filenames: &["Makefile", "makefile", "GNUmakefile"],
+        interpreters: &["make"],
+        // Recipe lines have to start with a tab.
+        indent: "\t",
+        indent_after: &[],
+        line_comment: Some("#"),
+        block_comment: None,
+        keywords: &[
+            "define", "endef", "else", "endif", "export", "ifdef", "ifeq", "ifndef", "ifneq",
+            "include", "override", "unexport",
+        ],
+        quotes: &['"', '\''],
+        long_strings: &[],
+    },
+];
+
+impl Filetype {
+    /// Adds `filetype` to the known ones, ahead of any built-in or earlier one with the same
+    /// name, extensions or file names. It lives as long as the editor does.
+    pub fn register(filetype: Filetype) {
+        let filetype: &'static Filetype = Box::leak(Box::new(filetype));
+        let mut user = USER_FILETYPES
+            .write()
+            .unwrap_or_else(|err| err.into_inner());
+        user.insert(0, filetype);
+    }
+
+    /// Every known filetype, the user's first.
+    pub fn all() -> Vec<&'static Filetype> {
+        let user = USER_FILETYPES.read().unwrap_or_else(|err| err.into_inner());
+        user.iter().copied().chain(FILETYPES.iter()).collect()
+    }
+
+    /// Works out the type of a file from its name, or else from its first line: the program
+    /// on a `#!` line, or a Vim or Emacs modeline naming the type.
+    pub fn detect(path: Option<&Path>, first_line: Option<&str>) -> Option<&'static Filetype> {
+        path.and_then(Filetype::from_path)
+            .or_else(|| first_line.and_then(Filetype::from_first_line))
+    }
+
+    /// Looks a filetype up by its name or one of its extensions, ignoring case.
+    pub fn by_name(name: &str) -> Option<&'static Filetype> {
+        let name = name.to_ascii_lowercase();
+        Filetype::all().into_iter().find(|filetype| {
+            filetype.name.to_ascii_lowercase() == name || filetype.extensions.contains(&&*name)
+        })
+    }
+
+    fn from_path(path: &Path) -> Option<&'static Filetype> {
+        let filename = path.file_name()?.to_str()?;
+        let extension = path.extension().and_then(|extension| extension.to_str());
+        let all = Filetype::all();
+        all.iter()
+            .find(|filetype| filetype.filenames.contains(&filename))
+            .or_else(|| {
+                all.iter().find(|filetype| {
+                    extension.is_some_and(|ext| filetype.extensions.contains(&ext))
+                })
+            })
+            .copied()
+    }
+
+    fn from_first_line(line: &str) -> Option<&'static Filetype> {
+        if let Some(command) = line.strip_prefix("#!") {
+            let mut words = command.split_whitespace();
+            let mut program = words.next()?.rsplit('/').next()?;
+            if program == "env" {
+                program = words.find(|word| !word.starts_with('-'))?;
+            }
+            // Versioned names like `python3.12` count as the plain program.
+            let program = program.trim_end_matches(|ch: char| ch.is_ascii_digit() || ch == '.');
+            return Filetype::all().into_iter().find(|filetype| {
+                filetype.interpreters.iter().any(|interpreter| {
+                    interpreter.trim_end_matches(|ch: char| ch.is_ascii_digit()) == program
+                })
+            });
+        }
+        // `vim: set ft=rust:`, `vim: filetype=rust` or `-*- mode: rust -*-`.
+        let name = if let Some(idx) = line.find("-*-") {
+            let settings = &line[idx + 3..];
+            let settings = &settings[..settings.find("-*-")?];
+            settings
+                .split(';')
+                .find_map(|setting| setting.trim().strip_prefix("mode:"))
+                .or_else(|| Some(settings).filter(|settings| !settings.contains(':')))?
+        } else {
+            let settings = &line[line.find("vim:").or_else(|| line.find("vi:"))?..];
+            settings.split([' ', ':']).find_map(|setting| {
+                setting
+                    .strip_prefix("ft=")
+                    .or_else(|| setting.strip_prefix("filetype="))
+            })?
+        };
+        Filetype::by_name(name.trim())
+    }
+
+    /// Leader to start the line after `line` with, when `line` is inside a comment: the
+    /// indentation and comment marker of a line comment, or the ` * ` of a block comment.
+    pub fn comment_leader(&self, line: &str) -> Option<String> {
+        let body = line.trim_start_matches([' ', '\t']);
+        let indent = &line[..line.len() - body.len()];
+
+        if let Some(marker) = self.line_comment {
+            if let Some(rest) = body.strip_prefix(marker) {
+                // Doc comments like `///` or `//!` keep their extra punctuation.
+                let extra = rest.len()
+                    - rest
+                        .trim_start_matches(|ch: char| ch.is_ascii_punctuation())
+                        .len();
+                let text = &rest[extra..];
+                // Markers glued to text, like a `#!/bin/sh` shebang, aren't comments.
+                if !text.is_empty() && !text.starts_with(char::is_whitespace) {
+                    return None;
+                }
+                let space = text.len() - text.trim_start().len();
+                return Some(format!("{}{}{}", indent, marker, &rest[..extra + space]));
+            }
+        }
+
+        let (open, close) = self.block_comment?;
+        if body.contains(close) {
+            return None;
+        }
+        if body.starts_with(open) {
+            Some(format!("{} * ", indent))
+        } else if body.starts_with('*') {
+            Some(format!("{}* ", indent))
+        } else {
+            None
+        }
+    }
+}
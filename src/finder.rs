@@ -0,0 +1,244 @@
+use crate::hook::glob_match;
+use std::fs;
+use std::path::Path;
+
+/// Files to pick from, and the ones matching the query so far, best first.
+pub struct Finder {
+    files: Vec<String>,
+    query: String,
+    /// Indices into `files` of the matches, with their scores and the indices of the
+    /// characters matched.
+    matches: Vec<(usize, i64, Vec<usize>)>,
+}
+
+impl Finder {
+    pub fn new(files: Vec<String>) -> Self {
+        let matches = (0..files.len()).map(|idx| (idx, 0, Vec::new())).collect();
+        Self {
+            files,
+            query: String::new(),
+            matches,
+        }
+    }
+
+    /// Keeps the files `query` matches. Typing more only narrows the matches down, so then
+    /// only those are looked at again.
+    pub fn set_query(&mut self, query: &str) {
+        if query == self.query {
+            return;
+        }
+        let candidates = if query.starts_with(self.query.as_str()) {
+            self.matches.iter().map(|&(idx, _, _)| idx).collect()
+        } else {
+            (0..self.files.len()).collect::<Vec<usize>>()
+        };
+        let files = &self.files;
+        self.matches = candidates
+            .into_iter()
+            .filter_map(|idx| {
+                fuzzy_match(query, &files[idx]).map(|(score, matched)| (idx, score, matched))
+            })
+            .collect();
+        // Between matches as good, shorter paths first.
+        self.matches
+            .sort_by_key(|&(idx, score, _)| (-score, files[idx].len()));
+        self.query = query.to_string();
+    }
+
+    /// The matching files, best first, with the indices of the characters matched.
+    pub fn matches(&self) -> impl Iterator<Item = (&str, &[usize])> {
+        self.matches
+            .iter()
+            .map(move |(idx, _, matched)| (self.files[*idx].as_str(), matched.as_slice()))
+    }
+
+    pub fn match_count(&self) -> usize {
+        self.matches.len()
+    }
+
+    pub fn file_count(&self) -> usize {
+        self.files.len()
+    }
+}
+
+/// How well `candidate` matches `query`, with the indices of the characters matched, if it
+/// has all the characters of the query in order. Matches at the start of words, runs of
+/// them and ones in the file name score higher, gaps between them lower. Case only counts
+/// when the query has capitals in it.
+pub fn fuzzy_match(query: &str, candidate: &str) -> Option<(i64, Vec<usize>)> {
+    let case_sensitive = query.chars().any(char::is_uppercase);
+    let fold = |ch: char| {
+        if case_sensitive {
+            ch
+        } else {
+            ch.to_ascii_lowercase()
+        }
+    };
+    let query = query
+        .chars()
+        .filter(|ch| !ch.is_whitespace())
+        .map(fold)
+        .collect::<Vec<char>>();
+    if query.is_empty() {
+        return Some((0, Vec::new()));
+    }
+    let text = candidate.chars().collect::<Vec<char>>();
+    // The earliest the match can end, then back from there for the tightest one ending there.
+    let mut next = 0;
+    let end = text.iter().position(|&ch| {
+        if fold(ch) == query[next] {
+            next += 1;
+        }
+        next == query.len()
+    })?;
+    let mut matched = vec![0; query.len()];
+    let mut left = query.len();
+    for idx in (0..=end).rev() {
+        if fold(text[idx]) == query[left - 1] {
+            left -= 1;
+            matched[left] = idx;
+            if left == 0 {
+                break;
+            }
+        }
+    }
+
+    let name_start = text
+        .iter()
+        .rposition(|&ch| ch == '/')
+        .map_or(0, |idx| idx + 1);
+    let mut score = 0;
+    for (n, &idx) in matched.iter().enumerate() {
+        score += 16;
+        score += match idx.checked_sub(1).map(|prev| text[prev]) {
+            None | Some('/') => 12,
+            Some(prev) if !prev.is_alphanumeric() => 8,
+            Some(prev) if prev.is_lowercase() && text[idx].is_uppercase() => 8,
+            _ => 0,
+        };
+        if idx >= name_start {
+            score += 2;
+        }
+        if n > 0 {
+            score += match idx - matched[n - 1] - 1 {
+                0 => 8,
+                gap => -(gap.min(16) as i64),
+            };
+        }
+    }
+    Some((score, matched))
+}
+
+/// Files under `root`, as paths relative to it, leaving out `.git` and whatever the
+/// `.gitignore` files along the way ignore. Stops after `limit` files, for when the
+/// directory turns out to be huge.
+pub fn walk(root: &Path, limit: usize) -> Vec<String> {
+    let mut files = Vec::new();
+    walk_dir(root, "", &mut Vec::new(), &mut files, limit);
+    files
+}
+
+fn walk_dir(root: &Path, dir: &str, rules: &mut Vec<Rule>, files: &mut Vec<String>, limit: usize) {
+    let full = root.join(dir);
+    let inherited = rules.len();
+    if let Ok(text) = fs::read_to_string(full.join(".gitignore")) {
+        rules.extend(text.lines().filter_map(|line| Rule::parse(line, dir)));
+    }
+    let mut entries = match fs::read_dir(&full) {
+        Ok(entries) => entries.filter_map(|entry| entry.ok()).collect::<Vec<_>>(),
+        Err(_) => Vec::new(),
+    };
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        if files.len() >= limit {
+            break;
+        }
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name == ".git" {
+            continue;
+        }
+        let path = if dir.is_empty() {
+            name
+        } else {
+            format!("{}/{}", dir, name)
+        };
+        // Links to directories aren't followed, so that a loop of them can't trap the walk.
+        let is_dir = entry.file_type().is_ok_and(|kind| kind.is_dir());
+        if ignored(rules, &path, is_dir) {
+            continue;
+        }
+        if is_dir {
+            walk_dir(root, &path, rules, files, limit);
+        } else {
+            files.push(path);
+        }
+    }
+    rules.truncate(inherited);
+}
+
+/// A line of a `.gitignore` file.
+struct Rule {
+    /// Directory of the file, relative to the root and ending in a slash, or empty for the
+    /// root itself.
+    base: String,
+    pattern: String,
+    /// Whether a match brings the path back instead of ignoring it.
+    negated: bool,
+    dir_only: bool,
+    /// Whether the pattern goes by the whole path under `base`, instead of the last part.
+    anchored: bool,
+}
+
+impl Rule {
+    fn parse(line: &str, dir: &str) -> Option<Rule> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        // A backslash lets a pattern start with # or !.
+        let line = line.strip_prefix('\\').unwrap_or(line);
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let line = line.strip_prefix("**/").unwrap_or(line);
+        Some(Rule {
+            base: if dir.is_empty() {
+                String::new()
+            } else {
+                format!("{}/", dir)
+            },
+            pattern: line.strip_prefix('/').unwrap_or(line).to_string(),
+            negated,
+            dir_only,
+            anchored: line.contains('/'),
+        })
+    }
+
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let Some(rel) = path.strip_prefix(self.base.as_str()) else {
+            return false;
+        };
+        if self.anchored {
+            glob_match(&self.pattern, rel)
+        } else {
+            glob_match(&self.pattern, rel.rsplit('/').next().unwrap_or(rel))
+        }
+    }
+}
+
+/// Whether the last of `rules` matching `path` ignores it.
+fn ignored(rules: &[Rule], path: &str, is_dir: bool) -> bool {
+    rules
+        .iter()
+        .rev()
+        .find(|rule| rule.matches(path, is_dir))
+        .is_some_and(|rule| !rule.negated)
+}
@@ -0,0 +1,393 @@
+use crate::diff;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How a line of a file differs from the version of it in the last commit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LineChange {
+    Added,
+    Modified,
+    /// Lines right above this one were deleted, or below it for the last line.
+    Deleted,
+}
+
+impl LineChange {
+    /// Mark of the change in the gutter, and its color.
+    pub fn sign(self) -> (char, &'static str) {
+        match self {
+            LineChange::Added => ('+', "\x1b[32m"),
+            LineChange::Modified => ('~', "\x1b[33m"),
+            LineChange::Deleted => ('-', "\x1b[31m"),
+        }
+    }
+}
+
+/// The lines of `current` that differ from `base`, by row, in order.
+pub fn line_changes(
+    base: &[impl AsRef<str>],
+    current: &[impl AsRef<str>],
+) -> Vec<(usize, LineChange)> {
+    let base = base.iter().map(AsRef::as_ref).collect::<Vec<&str>>();
+    let current = current.iter().map(AsRef::as_ref).collect::<Vec<&str>>();
+    let mut marks = Vec::new();
+    for (old, new) in diff::changes(&diff::diff(&base, &current)) {
+        if new.is_empty() {
+            if !current.is_empty() {
+                marks.push((new.start.min(current.len() - 1), LineChange::Deleted));
+            }
+        } else {
+            let change = if old.is_empty() {
+                LineChange::Added
+            } else {
+                LineChange::Modified
+            };
+            marks.extend(new.map(|row| (row, change)));
+        }
+    }
+    marks
+}
+
+/// Branch checked out in a repository, and whether tracked files have changes not committed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Status {
+    pub branch: String,
+    pub dirty: bool,
+}
+
+impl Status {
+    /// Reads the output of `git status --porcelain --branch`.
+    fn parse(output: &str) -> Option<Status> {
+        let mut lines = output.lines();
+        let header = lines.next()?.strip_prefix("## ")?;
+        let header = header.strip_prefix("No commits yet on ").unwrap_or(header);
+        let branch = if header.starts_with("HEAD (no branch)") {
+            "detached".to_string()
+        } else {
+            let end = header.find("...").or_else(|| header.find(' '));
+            header[..end.unwrap_or(header.len())].to_string()
+        };
+        Some(Status {
+            branch,
+            dirty: lines.any(|line| !line.is_empty()),
+        })
+    }
+}
+
+/// Who last changed a line, and in what commit.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Blame {
+    /// Abbreviated hash, or None for a line not committed yet.
+    pub commit: Option<String>,
+    pub author: String,
+    /// Seconds since the epoch.
+    pub time: u64,
+    pub summary: String,
+}
+
+impl Blame {
+    /// Reads the output of `git blame --porcelain` for a single line.
+    fn parse(output: &str) -> Option<Blame> {
+        let mut lines = output.lines();
+        let hash = lines.next()?.split(' ').next()?;
+        // Lines not committed yet are blamed on a hash of all zeros.
+        let committed = hash.chars().any(|ch| ch != '0');
+        let mut blame = Blame {
+            commit: Some(hash.chars().take(8).collect()).filter(|_| committed),
+            author: String::new(),
+            time: 0,
+            summary: String::new(),
+        };
+        for line in lines {
+            if let Some(author) = line.strip_prefix("author ") {
+                blame.author = author.to_string();
+            } else if let Some(time) = line.strip_prefix("author-time ") {
+                blame.time = time.parse().unwrap_or_default();
+            } else if let Some(summary) = line.strip_prefix("summary ") {
+                blame.summary = summary.to_string();
+            }
+        }
+        Some(blame)
+    }
+
+    /// The blame for the message bar, with how long ago the commit was made.
+    pub fn describe(&self) -> String {
+        let Some(commit) = &self.commit else {
+            return "Not committed yet".to_string();
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        format!(
+            "{} {}, {}: {}",
+            commit,
+            self.author,
+            ago(now.saturating_sub(self.time)),
+            self.summary
+        )
+    }
+}
+
+/// `secs` as a rough age, like "3 days ago".
+fn ago(secs: u64) -> String {
+    let (count, unit) = match secs {
+        0..=59 => return "just now".to_string(),
+        60..=3_599 => (secs / 60, "minute"),
+        3_600..=86_399 => (secs / 3_600, "hour"),
+        86_400..=2_629_799 => (secs / 86_400, "day"),
+        2_629_800..=31_557_599 => (secs / 2_629_800, "month"),
+        _ => (secs / 31_557_600, "year"),
+    };
+    format!(
+        "{} {}{} ago",
+        count,
+        unit,
+        if count == 1 { "" } else { "s" }
+    )
+}
+
+/// What `git` prints for `args`, run in `dir` and given `input`, or None if it fails, as it
+/// does outside a repository or where git isn't installed.
+fn git(dir: &Path, args: &[&str], input: Option<&str>) -> Option<String> {
+    let mut child = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        let input = input.to_string();
+        std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+    }
+    let output = child.wait_with_output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Directory and name of `file`, for running git next to it.
+fn split(file: &Path) -> Option<(&Path, String)> {
+    let dir = file.parent().filter(|dir| !dir.as_os_str().is_empty())?;
+    let name = file.file_name()?.to_string_lossy().to_string();
+    Some((dir, name))
+}
+
+/// The lines of `file` as of the last commit, or None if git doesn't track it.
+pub fn head_lines(file: &Path) -> Option<Vec<String>> {
+    let (dir, name) = split(file)?;
+    let text = git(dir, &["show", &format!("HEAD:./{}", name)], None)?;
+    Some(text.lines().map(str::to_string).collect())
+}
+
+/// Branch and state of the repository holding `dir`, if there's one.
+pub fn status(dir: &Path) -> Option<Status> {
+    let output = git(
+        dir,
+        &["status", "--porcelain", "--branch", "--untracked-files=no"],
+        None,
+    )?;
+    Status::parse(&output)
+}
+
+/// Who last changed line `row`, from 0, of `file` as it reads in `text`, which may have
+/// changes not saved yet.
+pub fn blame(file: &Path, row: usize, text: &str) -> Option<Blame> {
+    let (dir, name) = split(file)?;
+    let range = format!("{},{}", row + 1, row + 1);
+    let args = [
+        "blame",
+        "--porcelain",
+        "-L",
+        &range,
+        "--contents",
+        "-",
+        "--",
+        &name,
+    ];
+    Blame::parse(&git(dir, &args, Some(text))?)
+}
+
+/// How long what git said is trusted for, to catch commits and checkouts made outside.
+const STALE_AFTER: Duration = Duration::from_secs(5);
+
+/// What git said about a file, and when it was asked.
+struct Answer {
+    file: PathBuf,
+    asked_at: Instant,
+    /// Committed lines of the file, or None if git doesn't track it.
+    base: Option<Vec<String>>,
+    /// Status of the repository holding the file.
+    status: Option<Status>,
+}
+
+impl Answer {
+    /// Asks git about `file`, which can take a while in a big repository.
+    fn ask(file: PathBuf) -> Answer {
+        let asked_at = Instant::now();
+        let status = file.parent().and_then(status);
+        let base = head_lines(&file);
+        Answer {
+            file,
+            asked_at,
+            base,
+            status,
+        }
+    }
+}
+
+/// What git says about the files shown. Git runs on a thread of its own, since it can take
+/// longer than a frame, and is asked again after `forget` or once what it said is stale.
+#[derive(Default)]
+pub struct Tracker {
+    answers: Vec<Answer>,
+    /// File git is being asked about, and where the answer comes.
+    query: Option<(PathBuf, Receiver<Answer>)>,
+    /// When `forget` was last called, making what git said before then stale.
+    forgotten_at: Option<Instant>,
+    /// File last updated for, whose repository's status is shown.
+    shown: Option<PathBuf>,
+    /// The file changes were last worked out for, and its count of changes then.
+    marked: Option<(PathBuf, usize)>,
+    marks: Vec<(usize, LineChange)>,
+}
+
+impl Tracker {
+    /// Has everything asked of git again, after something that may have changed the files or
+    /// the repository, like saving or running a command. What it said shows until then.
+    pub fn forget(&mut self) {
+        self.forgotten_at = Some(Instant::now());
+    }
+
+    /// Whether git is being asked something, for `update` to take the answer when it comes.
+    pub fn is_asking(&self) -> bool {
+        self.query.is_some()
+    }
+
+    /// Brings what's known up to date for `file` with its `lines`, which only get fetched if
+    /// its count of `changes` is new. Asks git again if what it said is stale, taking the
+    /// answer in a later update.
+    pub fn update<'a>(
+        &mut self,
+        file: &Path,
+        changes: usize,
+        lines: impl FnOnce() -> Vec<&'a str>,
+    ) {
+        self.receive();
+        self.shown = Some(file.to_path_buf());
+        let stale = self.answer(file).is_none_or(|answer| {
+            answer.asked_at.elapsed() >= STALE_AFTER
+                || self.forgotten_at.is_some_and(|at| answer.asked_at < at)
+        });
+        if stale && self.query.as_ref().is_none_or(|(asked, _)| asked != file) {
+            let (sender, answers) = mpsc::channel();
+            let asked = file.to_path_buf();
+            thread::spawn(move || sender.send(Answer::ask(asked)));
+            self.query = Some((file.to_path_buf(), answers));
+        }
+        let marked = (file.to_path_buf(), changes);
+        if self.marked.as_ref() == Some(&marked) {
+            return;
+        }
+        let Some(answer) = self.answer(file) else {
+            return;
+        };
+        self.marks = match &answer.base {
+            Some(base) => line_changes(base, &lines()),
+            None => Vec::new(),
+        };
+        self.marked = Some(marked);
+    }
+
+    /// Takes the answer to the query running, if it came.
+    fn receive(&mut self) {
+        let Some((_, answers)) = &self.query else {
+            return;
+        };
+        let answer = match answers.try_recv() {
+            Ok(answer) => answer,
+            Err(TryRecvError::Empty) => return,
+            Err(TryRecvError::Disconnected) => {
+                self.query = None;
+                return;
+            }
+        };
+        self.query = None;
+        self.answers.retain(|known| known.file != answer.file);
+        if self
+            .marked
+            .as_ref()
+            .is_some_and(|(file, _)| *file == answer.file)
+        {
+            self.marked = None;
+        }
+        self.answers.push(answer);
+    }
+
+    fn answer(&self, file: &Path) -> Option<&Answer> {
+        self.answers.iter().find(|answer| answer.file == file)
+    }
+
+    /// Changed lines of `file`, as of the last `update` for it.
+    pub fn marks(&self, file: &Path) -> &[(usize, LineChange)] {
+        match &self.marked {
+            Some((known, _)) if known == file => &self.marks,
+            _ => &[],
+        }
+    }
+
+    /// Status of the repository of the file last updated for.
+    pub fn status(&self) -> Option<&Status> {
+        self.answer(self.shown.as_deref()?)?.status.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changed_lines() {
+        let base = ["a", "b", "c", "d"];
+        let current = ["a", "B", "c", "new", "d"];
+        assert_eq!(
+            line_changes(&base, &current),
+            vec![(1, LineChange::Modified), (3, LineChange::Added)]
+        );
+        assert_eq!(
+            line_changes(&base, &["a", "d"]),
+            vec![(1, LineChange::Deleted)]
+        );
+        assert_eq!(line_changes(&base, &["a"]), vec![(0, LineChange::Deleted)]);
+    }
+
+    #[test]
+    fn porcelain_output() {
+        let status = Status::parse("## main...origin/main [ahead 1]\n M src/lib.rs\n");
+        assert_eq!(
+            status,
+            Some(Status {
+                branch: "main".to_string(),
+                dirty: true
+            })
+        );
+        let status = Status::parse("## No commits yet on trunk\n").unwrap();
+        assert_eq!((status.branch.as_str(), status.dirty), ("trunk", false));
+        let blame = Blame::parse(
+            "0123456789abcdef 3 3 1\nauthor Ada\nauthor-time 1700000000\nsummary Fix it\n",
+        )
+        .unwrap();
+        assert_eq!(blame.commit.as_deref(), Some("01234567"));
+        assert_eq!((blame.author.as_str(), blame.time), ("Ada", 1_700_000_000));
+        assert_eq!(blame.summary, "Fix it");
+    }
+}
@@ -0,0 +1,100 @@
+use crate::pattern::Pattern;
+use std::fs;
+use std::path::Path;
+use std::thread;
+
+/// A line of a file with a match in it.
+#[derive(Debug, PartialEq)]
+pub struct Match {
+    /// Path of the file, as it was given to `search`.
+    pub file: String,
+    /// Line and character of the first match on it, counting from 1.
+    pub row: usize,
+    pub col: usize,
+    pub text: String,
+}
+
+impl Match {
+    /// The match as a line of results, `FILE:LINE:COLUMN: TEXT`.
+    pub fn to_line(&self) -> String {
+        format!("{}:{}:{}: {}", self.file, self.row, self.col, self.text)
+    }
+}
+
+/// File, line and column of a line of results, as `Match::to_line` writes it.
+pub fn location(line: &str) -> Option<(&str, usize, usize)> {
+    // File names can have colons too, so the first place the numbers follow goes.
+    line.match_indices(':').find_map(|(idx, _)| {
+        let mut numbers = line[idx + 1..].splitn(3, ':');
+        let row = numbers.next()?.parse().ok()?;
+        let col = numbers.next()?.parse().ok()?;
+        numbers
+            .next()?
+            .starts_with(' ')
+            .then_some((&line[..idx], row, col))
+    })
+}
+
+/// Lines of `files`, paths relative to `root`, that `pattern` matches, file by file in the
+/// order given. The files are shared out between as many threads as there are processors.
+/// Files that aren't text are skipped. Stops after `limit` matches.
+pub fn search(root: &Path, files: &[String], pattern: &Pattern, limit: usize) -> Vec<Match> {
+    let threads = thread::available_parallelism().map_or(1, |count| count.get());
+    let chunk = files.len().div_ceil(threads).max(1);
+    let mut found = thread::scope(|scope| {
+        let workers = files
+            .chunks(chunk)
+            .map(|files| scope.spawn(move || search_files(root, files, pattern, limit)))
+            .collect::<Vec<_>>();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap_or_default())
+            .collect::<Vec<Match>>()
+    });
+    found.truncate(limit);
+    found
+}
+
+fn search_files(root: &Path, files: &[String], pattern: &Pattern, limit: usize) -> Vec<Match> {
+    let mut found = Vec::new();
+    for file in files {
+        let Ok(text) = fs::read_to_string(root.join(file)) else {
+            continue;
+        };
+        if text.contains('\0') {
+            continue;
+        }
+        for (row, line) in text.lines().enumerate() {
+            if let Some(&(start, _)) = pattern.find_iter(line).first() {
+                found.push(Match {
+                    file: file.clone(),
+                    row: row + 1,
+                    col: line[..start].chars().count() + 1,
+                    text: line.to_string(),
+                });
+                if found.len() >= limit {
+                    return found;
+                }
+            }
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn result_lines() {
+        let found = Match {
+            file: "src/a:b.rs".to_string(),
+            row: 12,
+            col: 5,
+            text: "let x = 1: y;".to_string(),
+        };
+        assert_eq!(found.to_line(), "src/a:b.rs:12:5: let x = 1: y;");
+        assert_eq!(location(&found.to_line()), Some(("src/a:b.rs", 12, 5)));
+        assert_eq!(location("no numbers: here"), None);
+    }
+}
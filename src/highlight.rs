@@ -0,0 +1,490 @@
+use crate::ansi::{self, ColorDepth, Style};
+use crate::filetype::Filetype;
+use crate::line::Line;
+use crate::region::is_word_char;
+use std::cmp::min;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::RwLock;
+use std::thread;
+
+/// Lines above which a buffer's line states are worked out on a thread of their own.
+pub const BACKGROUND_LINES: usize = 5_000;
+/// Lines a background pass reports on at a time.
+const BATCH_LINES: usize = 1_000;
+/// Columns of a line that get highlighted. Past them, as in a minified file's one long line,
+/// text is drawn plain and leaves the state of the line alone, so that highlighting takes no
+/// longer than for a line of this many.
+pub const MAX_COLUMNS: usize = 3_000;
+
+/// How a rendered character is drawn.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Highlight {
+    Normal,
+    Number,
+    String,
+    Comment,
+    Keyword,
+    /// Part of a search match.
+    Match,
+    /// Past the text width.
+    Overflow,
+    /// Selected.
+    Selection,
+    /// Under a cursor besides the main one.
+    Cursor,
+    /// A bracket at the cursor, or the one it pairs with.
+    Bracket,
+    /// A tab, with whitespace shown.
+    Tab,
+    /// A no-break space, with whitespace shown.
+    NoBreakSpace,
+    /// A blank at the end of a line, with whitespace shown.
+    Trailing,
+    /// In a style colored output asked for.
+    Ansi(Style),
+}
+
+/// Colors for each kind of highlight and for the parts of the screen around the text, as the
+/// escape sequences that switch to them.
+#[derive(Clone)]
+pub struct Theme {
+    pub name: &'static str,
+    pub number: &'static str,
+    pub string: &'static str,
+    pub comment: &'static str,
+    pub keyword: &'static str,
+    pub search_match: &'static str,
+    pub overflow: &'static str,
+    pub selection: &'static str,
+    pub status_bar: &'static str,
+    /// Numbers of the lines other than the cursor's.
+    pub line_number: &'static str,
+    /// Tabs, no-break spaces and blanks ending lines, with whitespace shown.
+    pub tab: &'static str,
+    pub nbsp: &'static str,
+    pub trailing: &'static str,
+}
+
+/// Themes defined in the user's config file, looked at before the built-in ones.
+static USER_THEMES: RwLock<Vec<&'static Theme>> = RwLock::new(Vec::new());
+
+pub static THEMES: [Theme; 6] = [
+    Theme {
+        name: "default",
+        number: "\x1b[31m",
+        string: "\x1b[35m",
+        comment: "\x1b[36m",
+        keyword: "\x1b[33m",
+        search_match: "\x1b[30;43m",
+        overflow: "\x1b[37;41m",
+        selection: "\x1b[7m",
+        status_bar: "\x1b[7m",
+        line_number: "\x1b[2m",
+        tab: "\x1b[2m",
+        nbsp: "\x1b[4;33m",
+        trailing: "\x1b[41m",
+    },
+    Theme {
+        name: "bright",
+        number: "\x1b[91m",
+        string: "\x1b[95m",
+        comment: "\x1b[96m",
+        keyword: "\x1b[93m",
+        search_match: "\x1b[30;103m",
+        overflow: "\x1b[97;101m",
+        selection: "\x1b[30;107m",
+        status_bar: "\x1b[30;107m",
+        line_number: "\x1b[90m",
+        tab: "\x1b[90m",
+        nbsp: "\x1b[4;93m",
+        trailing: "\x1b[101m",
+    },
+    Theme {
+        name: "ocean",
+        number: "\x1b[34m",
+        string: "\x1b[32m",
+        comment: "\x1b[90m",
+        keyword: "\x1b[36m",
+        search_match: "\x1b[30;46m",
+        overflow: "\x1b[37;44m",
+        selection: "\x1b[30;47m",
+        status_bar: "\x1b[30;46m",
+        line_number: "\x1b[2;34m",
+        tab: "\x1b[2;34m",
+        nbsp: "\x1b[4;35m",
+        trailing: "\x1b[41m",
+    },
+    // For terminals without colors, or people who'd rather not have them.
+    Theme {
+        name: "mono",
+        number: "",
+        string: "\x1b[3m",
+        comment: "\x1b[2m",
+        keyword: "\x1b[1m",
+        search_match: "\x1b[7m",
+        overflow: "\x1b[4m",
+        selection: "\x1b[7m",
+        status_bar: "\x1b[7m",
+        line_number: "\x1b[2m",
+        tab: "\x1b[2m",
+        nbsp: "\x1b[4m",
+        trailing: "\x1b[7m",
+    },
+    // From the 256 numbered colors.
+    Theme {
+        name: "slate",
+        number: "\x1b[38;5;173m",
+        string: "\x1b[38;5;108m",
+        comment: "\x1b[38;5;244m",
+        keyword: "\x1b[38;5;110m",
+        search_match: "\x1b[38;5;16;48;5;179m",
+        overflow: "\x1b[38;5;231;48;5;131m",
+        selection: "\x1b[48;5;238m",
+        status_bar: "\x1b[38;5;252;48;5;240m",
+        line_number: "\x1b[38;5;242m",
+        tab: "\x1b[38;5;240m",
+        nbsp: "\x1b[4;38;5;179m",
+        trailing: "\x1b[48;5;131m",
+    },
+    // In exact colors, brought down to the nearest ones on terminals without them.
+    Theme {
+        name: "gruvbox",
+        number: "\x1b[38;2;211;134;155m",
+        string: "\x1b[38;2;184;187;38m",
+        comment: "\x1b[3;38;2;146;131;116m",
+        keyword: "\x1b[38;2;251;73;52m",
+        search_match: "\x1b[38;2;40;40;40;48;2;250;189;47m",
+        overflow: "\x1b[38;2;235;219;178;48;2;157;0;6m",
+        selection: "\x1b[48;2;80;73;69m",
+        status_bar: "\x1b[38;2;235;219;178;48;2;80;73;69m",
+        line_number: "\x1b[38;2;124;111;100m",
+        tab: "\x1b[38;2;102;92;84m",
+        nbsp: "\x1b[4;38;2;254;128;25m",
+        trailing: "\x1b[48;2;157;0;6m",
+    },
+];
+
+impl Theme {
+    /// Makes `theme` known, ahead of any built-in or earlier one of the same name. It lives as
+    /// long as the editor does.
+    pub fn register(theme: Theme) {
+        let theme: &'static Theme = Box::leak(Box::new(theme));
+        let mut user = USER_THEMES.write().unwrap_or_else(|err| err.into_inner());
+        user.insert(0, theme);
+    }
+
+    /// Every known theme, the user's first.
+    pub fn all() -> Vec<&'static Theme> {
+        let user = USER_THEMES.read().unwrap_or_else(|err| err.into_inner());
+        user.iter().copied().chain(THEMES.iter()).collect()
+    }
+
+    pub fn by_name(name: &str) -> Option<&'static Theme> {
+        Theme::all().into_iter().find(|theme| theme.name == name)
+    }
+
+    /// The escape sequence of the color named `key`, as the config file names them: the field
+    /// it's kept in.
+    pub fn color_mut(&mut self, key: &str) -> Option<&mut &'static str> {
+        IntoIterator::into_iter(self.colors_mut())
+            .find(|(name, _)| *name == key)
+            .map(|(_, color)| color)
+    }
+
+    fn colors_mut(&mut self) -> [(&'static str, &mut &'static str); 12] {
+        [
+            ("number", &mut self.number),
+            ("string", &mut self.string),
+            ("comment", &mut self.comment),
+            ("keyword", &mut self.keyword),
+            ("search_match", &mut self.search_match),
+            ("overflow", &mut self.overflow),
+            ("selection", &mut self.selection),
+            ("status_bar", &mut self.status_bar),
+            ("line_number", &mut self.line_number),
+            ("tab", &mut self.tab),
+            ("nbsp", &mut self.nbsp),
+            ("trailing", &mut self.trailing),
+        ]
+    }
+
+    /// This theme with its colors brought down to the nearest ones a terminal showing `depth`
+    /// has. Worked out once for each theme and depth.
+    pub fn for_colors(&'static self, depth: ColorDepth) -> &'static Theme {
+        static REDUCED: RwLock<Vec<(usize, ColorDepth, &'static Theme)>> = RwLock::new(Vec::new());
+        if depth == ColorDepth::TrueColor {
+            return self;
+        }
+        let key = self as *const Theme as usize;
+        let mut reduced = REDUCED.write().unwrap_or_else(|err| err.into_inner());
+        if let Some(&(_, _, theme)) = reduced
+            .iter()
+            .find(|&&(theme, colors, _)| theme == key && colors == depth)
+        {
+            return theme;
+        }
+        let mut theme = self.clone();
+        for (_, color) in theme.colors_mut() {
+            *color = Box::leak(ansi::reduce(color, depth).into_boxed_str());
+        }
+        let theme: &'static Theme = Box::leak(Box::new(theme));
+        reduced.push((key, depth, theme));
+        theme
+    }
+
+    /// Escape sequence switching the terminal to `highlight`. Nothing for the styles of colored
+    /// output, which bring their own.
+    pub fn attributes(&self, highlight: Highlight) -> &'static str {
+        match highlight {
+            Highlight::Normal => "\x1b[m",
+            Highlight::Number => self.number,
+            Highlight::String => self.string,
+            Highlight::Comment => self.comment,
+            Highlight::Keyword => self.keyword,
+            Highlight::Match => self.search_match,
+            Highlight::Overflow => self.overflow,
+            Highlight::Selection => self.selection,
+            // Like the terminal's own block cursor, whatever the theme.
+            Highlight::Cursor => "\x1b[7m",
+            Highlight::Bracket => "\x1b[1;4m",
+            Highlight::Tab => self.tab,
+            Highlight::NoBreakSpace => self.nbsp,
+            Highlight::Trailing => self.trailing,
+            Highlight::Ansi(_) => "",
+        }
+    }
+}
+
+/// How far ahead a single quote looks for its closing quote before it's taken for something
+/// other than a character literal, like a Rust lifetime.
+const CHAR_LITERAL_LEN: usize = 12;
+
+/// What a line starts inside of, carried over from the lines before it.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LineState {
+    #[default]
+    Code,
+    Comment,
+    /// A string of the filetype's `long_strings`, by its index there.
+    String(usize),
+}
+
+/// Classes for the characters of the rendered `line` up to `MAX_COLUMNS`, given the state it
+/// starts in. Also returns the state it ends in, for the line after it.
+pub fn highlight_line(
+    line: &str,
+    filetype: &Filetype,
+    state: LineState,
+) -> (Vec<Highlight>, LineState) {
+    let chars = line.chars().take(MAX_COLUMNS).collect::<Vec<char>>();
+    let mut highlights = vec![Highlight::Normal; chars.len()];
+    let starts_with = |idx: usize, text: &str| {
+        text.chars()
+            .enumerate()
+            .all(|(n, ch)| chars.get(idx + n) == Some(&ch))
+    };
+    let mut state = state;
+    let mut idx = 0;
+    while idx < chars.len() {
+        let after_word = idx > 0 && is_word_char(chars[idx - 1]);
+        if state == LineState::Code {
+            if let Some((open, _)) = filetype.block_comment {
+                if starts_with(idx, open) {
+                    let end = idx + open.chars().count();
+                    highlights[idx..end].fill(Highlight::Comment);
+                    (state, idx) = (LineState::Comment, end);
+                    continue;
+                }
+            }
+            if let Some(marker) = filetype.line_comment {
+                if starts_with(idx, marker) {
+                    highlights[idx..].fill(Highlight::Comment);
+                    break;
+                }
+            }
+            // The longest opener, so that `"""` isn't taken for an empty string and a quote.
+            let long_string = filetype
+                .long_strings
+                .iter()
+                .enumerate()
+                .filter(|(_, (open, _, _))| {
+                    starts_with(idx, open) && !(after_word && open.starts_with(is_word_char))
+                })
+                .max_by_key(|(_, (open, _, _))| open.len());
+            if let Some((n, (open, _, _))) = long_string {
+                let end = idx + open.chars().count();
+                highlights[idx..end].fill(Highlight::String);
+                (state, idx) = (LineState::String(n), end);
+                continue;
+            }
+        }
+        match state {
+            LineState::Comment => {
+                let close = filetype.block_comment.map_or("", |(_, close)| close);
+                let end = (idx..chars.len())
+                    .find(|&n| starts_with(n, close))
+                    .map(|n| n + close.chars().count());
+                let end = end
+                    .inspect(|_| state = LineState::Code)
+                    .unwrap_or(chars.len());
+                highlights[idx..end].fill(Highlight::Comment);
+                idx = end;
+                continue;
+            }
+            LineState::String(n) => {
+                let (_, close, escapes) = filetype.long_strings[n];
+                let mut end = idx;
+                while end < chars.len() {
+                    if escapes && chars[end] == '\\' {
+                        end += 2;
+                    } else if starts_with(end, close) {
+                        end += close.chars().count();
+                        state = LineState::Code;
+                        break;
+                    } else {
+                        end += 1;
+                    }
+                }
+                let end = min(end, chars.len());
+                highlights[idx..end].fill(Highlight::String);
+                idx = end;
+                continue;
+            }
+            LineState::Code => {}
+        }
+
+        let ch = chars[idx];
+        if filetype.quotes.contains(&ch) {
+            let close = (idx + 1..chars.len())
+                .scan(false, |escaped, n| {
+                    let found = chars[n] == ch && !*escaped;
+                    *escaped = chars[n] == '\\' && !*escaped;
+                    Some((n, found))
+                })
+                .find(|&(_, found)| found)
+                .map(|(n, _)| n);
+            let end = match close {
+                Some(close) if ch != '\'' || close - idx <= CHAR_LITERAL_LEN => close + 1,
+                None if ch != '\'' => chars.len(),
+                _ => idx + 1,
+            };
+            if ch != '\'' || end > idx + 1 {
+                highlights[idx..end].fill(Highlight::String);
+            }
+            idx = end;
+        } else if ch.is_ascii_digit() && !after_word {
+            let end = (idx..chars.len())
+                .find(|&n| !(is_word_char(chars[n]) || chars[n] == '.'))
+                .unwrap_or(chars.len());
+            highlights[idx..end].fill(Highlight::Number);
+            idx = end;
+        } else if let Some(keyword) = filetype.keywords.iter().find(|keyword| {
+            !after_word
+                && starts_with(idx, keyword)
+                && !chars
+                    .get(idx + keyword.chars().count())
+                    .is_some_and(|&next| is_word_char(next))
+        }) {
+            let end = idx + keyword.chars().count();
+            highlights[idx..end].fill(Highlight::Keyword);
+            idx = end;
+        } else {
+            idx += 1;
+            // Skip the rest of a word so keywords only match whole words.
+            if is_word_char(ch) {
+                while idx < chars.len() && is_word_char(chars[idx]) {
+                    idx += 1;
+                }
+            }
+        }
+    }
+    (highlights, state)
+}
+
+/// A pass over a snapshot of a buffer on another thread, working out the state each line
+/// starts in. Dropping it stops the pass.
+pub struct Background {
+    results: Receiver<Vec<LineState>>,
+}
+
+impl Background {
+    /// Starts a pass over `lines`, the first of which starts in `state`, with tabs expanded to
+    /// every `tab_stop` columns.
+    pub fn spawn(
+        lines: Vec<String>,
+        tab_stop: usize,
+        filetype: &'static Filetype,
+        state: LineState,
+    ) -> Self {
+        let (sender, results) = mpsc::channel();
+        thread::spawn(move || {
+            let mut state = state;
+            for batch in lines.chunks(BATCH_LINES) {
+                let starts = batch
+                    .iter()
+                    .map(|line| {
+                        let start = state;
+                        let line = Line::with_tab_stop(line.clone(), tab_stop);
+                        state = highlight_line(line.rendered(), filetype, state).1;
+                        start
+                    })
+                    .collect();
+                // Nobody is listening once the buffer has moved on.
+                if sender.send(starts).is_err() {
+                    return;
+                }
+            }
+        });
+        Self { results }
+    }
+
+    /// States of the lines reported since the last call, following on from those.
+    pub fn take(&self) -> Vec<LineState> {
+        self.results.try_iter().flatten().collect()
+    }
+}
+
+/// Marks the characters of the rendered `line` covered by `matches`, given in order as byte
+/// offsets into the line and the text matched there.
+pub fn highlight_matches(line: &str, highlights: &mut [Highlight], matches: &[(usize, &str)]) {
+    // Characters are counted on from the last match, not from the start each time.
+    let (mut counted, mut first) = (0, 0);
+    for (start, found) in matches {
+        first += line[counted..*start].chars().count();
+        counted = *start;
+        for highlight in highlights
+            .iter_mut()
+            .skip(first)
+            .take(found.chars().count())
+        {
+            *highlight = Highlight::Match;
+        }
+    }
+}
+
+/// `text` with escape sequences switching between its characters' `highlights` in the colors
+/// of `theme`, ending with the terminal back to normal.
+pub fn paint(text: &str, highlights: &[Highlight], theme: &Theme) -> String {
+    let mut painted = String::with_capacity(text.len());
+    let mut current = Highlight::Normal;
+    for (ch, &highlight) in text.chars().zip(
+        highlights
+            .iter()
+            .chain(std::iter::repeat(&Highlight::Normal)),
+    ) {
+        if highlight != current {
+            painted.push_str(theme.attributes(Highlight::Normal));
+            match highlight {
+                Highlight::Normal => {}
+                Highlight::Ansi(style) => painted.push_str(&style.escape()),
+                _ => painted.push_str(theme.attributes(highlight)),
+            }
+            current = highlight;
+        }
+        painted.push(ch);
+    }
+    if current != Highlight::Normal {
+        painted.push_str(theme.attributes(Highlight::Normal));
+    }
+    painted
+}
@@ -0,0 +1,399 @@
+use std::cmp::min;
+use std::fs;
+use std::io::{Error, Result};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// States recorded this soon after the previous one replace it, so a burst of typing makes a
+/// single step through the history.
+const COALESCE: Duration = Duration::from_secs(5);
+
+/// Bytes of changed lines kept in memory, past which the oldest states are dropped.
+const MAX_BYTES: usize = 16 << 20;
+
+/// Bytes of changed lines written out by `History::save`, for the latest states only.
+const SAVED_BYTES: usize = 1 << 20;
+
+/// First line of a saved history, naming the format.
+const HEADER: &str = "milo history 2";
+
+/// What takes the text of a state back to the one before it: `lines` in place of the `len`
+/// lines from `row` on. Empty for the oldest state.
+#[derive(Default)]
+struct Delta {
+    row: usize,
+    len: usize,
+    lines: Vec<String>,
+}
+
+impl Delta {
+    /// Delta taking `new` back to `old`, covering the lines between what they start and end
+    /// with alike.
+    fn between<S: AsRef<str>>(old: &[String], new: &[S]) -> Delta {
+        let same = |(old, new): (&String, &S)| old.as_str() == new.as_ref();
+        let prefix = old.iter().zip(new).take_while(|&pair| same(pair)).count();
+        let suffix = old[prefix..]
+            .iter()
+            .rev()
+            .zip(new[prefix..].iter().rev())
+            .take_while(|&pair| same(pair))
+            .count();
+        Delta {
+            row: prefix,
+            len: new.len() - prefix - suffix,
+            lines: old[prefix..old.len() - suffix].to_vec(),
+        }
+    }
+
+    /// Turns `lines` into the text before the change, returning what `lines` had there.
+    fn revert(&self, lines: &mut Vec<String>) -> Vec<String> {
+        lines
+            .splice(self.row..self.row + self.len, self.lines.iter().cloned())
+            .collect()
+    }
+
+    /// Bytes it keeps, counting line breaks.
+    fn bytes(&self) -> usize {
+        self.lines.iter().map(|line| line.len() + 1).sum()
+    }
+}
+
+/// A state of a buffer's text, with when it came about and where its cursor was.
+struct Snapshot {
+    time: Instant,
+    delta: Delta,
+    cursor: (usize, usize),
+}
+
+/// How far to go through the history.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Travel {
+    /// A number of recorded states.
+    Steps(usize),
+    /// A stretch of time.
+    Time(Duration),
+}
+
+impl Travel {
+    /// Reads `10` as ten states, and `30s`, `5m`, `2h` or `1d` as that much time.
+    pub fn parse(text: &str) -> Option<Travel> {
+        let unit = match text.chars().last()? {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            _ => return text.parse().ok().map(Travel::Steps),
+        };
+        let count = text[..text.len() - 1].parse::<u64>().ok()?;
+        Some(Travel::Time(Duration::from_secs(count * unit)))
+    }
+}
+
+/// Every state a buffer went through, oldest first, so it can be taken back to how it was
+/// some time ago. States are kept in the order they came about rather than as a tree: going
+/// back and editing from there adds the new states after the ones left behind, which stay
+/// reachable. Only the latest state's text is kept whole; the others are what changed since
+/// the one before, as far back as `MAX_BYTES` of those reach.
+#[derive(Default)]
+pub struct History {
+    snapshots: Vec<Snapshot>,
+    /// Text of the latest state.
+    latest: Vec<String>,
+    /// Bytes the deltas keep.
+    bytes: usize,
+    /// State the buffer was last taken to, until it changes again.
+    current: Option<usize>,
+    /// Buffer's change count when it was last recorded or taken to a state.
+    seen: Option<usize>,
+}
+
+impl History {
+    /// Whether the buffer at change count `changes` is already in the history.
+    pub fn has_seen(&self, changes: usize) -> bool {
+        self.seen == Some(changes)
+    }
+
+    /// Adds `lines` with the `cursor` as the latest state, at change count `changes`. Replaces
+    /// the latest state instead when that was recorded moments ago, other than the first.
+    pub fn record(&mut self, lines: &[&str], cursor: (usize, usize), changes: usize) {
+        let now = Instant::now();
+        self.seen = Some(changes);
+        let coalesce = self.current.is_none() && self.snapshots.len() > 1;
+        self.current = None;
+        if let Some(last) = self.snapshots.last_mut() {
+            if coalesce && now.duration_since(last.time) < COALESCE {
+                // The state before the latest is what the new one changes.
+                let delta = std::mem::take(&mut last.delta);
+                self.bytes -= delta.bytes();
+                delta.revert(&mut self.latest);
+                last.delta = Delta::between(&self.latest, lines);
+                last.cursor = cursor;
+                self.bytes += last.delta.bytes();
+                self.latest = lines.iter().map(|line| line.to_string()).collect();
+                return;
+            }
+        }
+        let delta = if self.snapshots.is_empty() {
+            Delta::default()
+        } else {
+            Delta::between(&self.latest, lines)
+        };
+        self.bytes += delta.bytes();
+        self.snapshots.push(Snapshot {
+            time: now,
+            delta,
+            cursor,
+        });
+        self.latest = lines.iter().map(|line| line.to_string()).collect();
+        while self.bytes > MAX_BYTES && self.snapshots.len() > 1 {
+            self.forget_oldest();
+        }
+    }
+
+    /// Drops the oldest state, leaving the one after it as the oldest.
+    fn forget_oldest(&mut self) {
+        self.snapshots.remove(0);
+        let delta = std::mem::take(&mut self.snapshots[0].delta);
+        self.bytes -= delta.bytes();
+        self.current = self.current.map(|idx| idx.saturating_sub(1));
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    /// Index of the state that `travel` leads to, backward in time with `back`, from the one
+    /// the buffer is at. Going back further than the history reaches stops at its oldest
+    /// state, and going forward past the present stops at the latest.
+    pub fn destination(&self, travel: Travel, back: bool) -> Option<usize> {
+        let latest = self.snapshots.len().checked_sub(1)?;
+        let here = self.current.unwrap_or(latest);
+        let idx = match travel {
+            Travel::Steps(steps) if back => here.saturating_sub(steps),
+            Travel::Steps(steps) => min(here.saturating_add(steps), latest),
+            Travel::Time(span) => {
+                let now = match self.current {
+                    Some(idx) => self.snapshots[idx].time,
+                    None => Instant::now(),
+                };
+                let target = if back {
+                    now.checked_sub(span)
+                } else {
+                    now.checked_add(span)
+                };
+                target.map_or(0, |target| {
+                    self.snapshots
+                        .iter()
+                        .rposition(|snapshot| snapshot.time <= target)
+                        .unwrap_or(0)
+                })
+            }
+        };
+        Some(idx)
+    }
+
+    /// Marks state `idx` as the one the buffer was taken to, now at change count `changes`,
+    /// and returns its lines and cursor.
+    pub fn arrive(&mut self, idx: usize, changes: usize) -> (Vec<String>, (usize, usize)) {
+        self.current = Some(idx);
+        self.seen = Some(changes);
+        let mut lines = self.latest.clone();
+        for snapshot in self.snapshots[idx + 1..].iter().rev() {
+            snapshot.delta.revert(&mut lines);
+        }
+        (lines, self.snapshots[idx].cursor)
+    }
+
+    /// How long ago state `idx` was recorded.
+    pub fn age(&self, idx: usize) -> Duration {
+        self.snapshots[idx].time.elapsed()
+    }
+
+    /// Writes the latest states to `path`: a line with the number of lines of the latest text
+    /// and a checksum of it, then for each state a line with when it was recorded in seconds
+    /// since the epoch, the cursor row and column, and the row, length and number of lines of
+    /// its delta, followed by those lines. The text itself is left to the file.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut out = format!(
+            "{}\n{} {}\n",
+            HEADER,
+            self.latest.len(),
+            checksum(&self.latest)
+        );
+        let mut bytes = 0;
+        let skip = self
+            .snapshots
+            .iter()
+            .rposition(|snapshot| {
+                bytes += snapshot.delta.bytes();
+                bytes > SAVED_BYTES
+            })
+            .map_or(0, |idx| idx + 1);
+        for (idx, snapshot) in self.snapshots.iter().enumerate().skip(skip) {
+            let time = SystemTime::now()
+                .checked_sub(snapshot.time.elapsed())
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .unwrap_or_default();
+            // The oldest saved state has nothing before it to go back to.
+            let empty = Delta::default();
+            let delta = if idx == skip { &empty } else { &snapshot.delta };
+            out.push_str(&format!(
+                "{} {} {} {} {} {}\n",
+                time.as_secs(),
+                snapshot.cursor.0,
+                snapshot.cursor.1,
+                delta.row,
+                delta.len,
+                delta.lines.len()
+            ));
+            for line in &delta.lines {
+                out.push_str(line);
+                out.push('\n');
+            }
+        }
+        fs::write(path, out)
+    }
+
+    /// History saved at `path`, for a buffer at change count `changes`. Only taken when its
+    /// latest state has the same text as `lines`, since otherwise the file changed without it
+    /// and going back would skip over those changes.
+    pub fn load(path: &Path, lines: &[String], changes: usize) -> Result<History> {
+        let bad = || Error::other(format!("{}: not a saved history", path.display()));
+        let text = fs::read_to_string(path)?;
+        let mut rows = text.lines();
+        if rows.next() != Some(HEADER) {
+            return Err(bad());
+        }
+        let numbers = |row: &str| {
+            row.split(' ')
+                .map(|field| field.parse::<u64>().ok())
+                .collect::<Option<Vec<u64>>>()
+        };
+        let Some(&[count, sum]) = rows.next().and_then(numbers).as_deref() else {
+            return Err(bad());
+        };
+        if count != lines.len() as u64 || sum != checksum(lines) {
+            return Err(Error::other("the file changed since its history was saved"));
+        }
+        let mut history = History {
+            latest: lines.to_vec(),
+            seen: Some(changes),
+            ..History::default()
+        };
+        while let Some(row) = rows.next() {
+            let Some(&[secs, row, col, at, len, count]) = numbers(row).as_deref() else {
+                return Err(bad());
+            };
+            let delta = Delta {
+                row: at as usize,
+                len: len as usize,
+                lines: rows
+                    .by_ref()
+                    .take(count as usize)
+                    .map(str::to_string)
+                    .collect(),
+            };
+            if delta.lines.len() as u64 != count {
+                return Err(bad());
+            }
+            let age = SystemTime::now()
+                .duration_since(UNIX_EPOCH + Duration::from_secs(secs))
+                .unwrap_or_default();
+            history.bytes += delta.bytes();
+            history.snapshots.push(Snapshot {
+                time: Instant::now().checked_sub(age).unwrap_or_else(Instant::now),
+                delta,
+                cursor: (row as usize, col as usize),
+            });
+        }
+        // Each delta has to fit the text it takes back, or the file doesn't hold this history.
+        let mut text = history.latest.clone();
+        for snapshot in history.snapshots.iter().rev() {
+            if snapshot.delta.row + snapshot.delta.len > text.len() {
+                return Err(bad());
+            }
+            snapshot.delta.revert(&mut text);
+        }
+        Ok(history)
+    }
+}
+
+/// FNV-1a hash of `lines`, to tell whether a saved history leads up to them.
+fn checksum(lines: &[String]) -> u64 {
+    lines
+        .iter()
+        .flat_map(|line| line.bytes().chain(Some(b'\n')))
+        .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records `text` as a state of its own, as if typed long after the one before.
+    fn record(history: &mut History, text: &str, changes: usize) {
+        if let Some(last) = history.snapshots.last_mut() {
+            last.time = Instant::now()
+                .checked_sub(COALESCE * 2)
+                .unwrap_or(last.time);
+        }
+        let lines = text.lines().collect::<Vec<&str>>();
+        history.record(&lines, (changes, 0), changes);
+    }
+
+    /// Text of state `idx`.
+    fn text_at(history: &mut History, idx: usize) -> String {
+        let (lines, _) = history.arrive(idx, 0);
+        lines.join("\n")
+    }
+
+    #[test]
+    fn states_from_deltas() {
+        let mut history = History::default();
+        let texts = ["a\nb\nc", "a\nB\nc", "a\nB\nc\nd", "d", "", "x\ny"];
+        for (changes, text) in texts.iter().enumerate() {
+            record(&mut history, text, changes);
+        }
+        // A change within moments of the last replaces it.
+        history.record(&["x", "z"], (0, 0), 10);
+        assert_eq!(history.len(), texts.len());
+        assert_eq!(text_at(&mut history, texts.len() - 1), "x\nz");
+        for (idx, text) in texts.iter().enumerate().rev().skip(1) {
+            assert_eq!(text_at(&mut history, idx), *text);
+        }
+    }
+
+    #[test]
+    fn limits_and_saving() {
+        let mut history = History::default();
+        let line = "x".repeat(1 << 20);
+        for changes in 0..40 {
+            record(&mut history, &format!("{}{}", changes, line), changes);
+        }
+        assert!(history.bytes <= MAX_BYTES);
+        assert!(history.len() < 40);
+        assert_eq!(
+            text_at(&mut history, 0),
+            format!("{}{}", 40 - history.len(), line)
+        );
+
+        let mut history = History::default();
+        for changes in 0..5 {
+            record(&mut history, &format!("kept\n{}", changes), changes);
+        }
+        let path = std::env::temp_dir().join(format!("milo-history-{}", std::process::id()));
+        history.save(&path).unwrap();
+        let latest = history.latest.clone();
+        let mut loaded = History::load(&path, &latest, 0).unwrap();
+        assert_eq!(loaded.len(), 5);
+        assert_eq!(text_at(&mut loaded, 1), "kept\n1");
+        assert!(History::load(&path, &["kept".to_string()], 0).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
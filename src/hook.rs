@@ -0,0 +1,116 @@
+use crate::editor::CommandStep;
+
+/// Points in the editor's life that hooks can run at.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HookEvent {
+    /// A file was loaded into the buffer, or an unnamed buffer was started.
+    BufOpen,
+    /// The buffer is about to be written. Changes made by the hook go into the file.
+    BufWritePre,
+    /// The buffer was written to its file.
+    BufWritePost,
+    /// The buffer got a filetype, on opening a file or on saving it under a new name.
+    FileType,
+    /// The terminal window changed size.
+    Resize,
+    /// Modal editing went into another mode. Hooks get the new mode's name, like `INSERT`, as
+    /// their subject.
+    ModeChange,
+    /// A key left the cursor somewhere else.
+    CursorMove,
+}
+
+impl HookEvent {
+    pub fn name(self) -> &'static str {
+        match self {
+            HookEvent::BufOpen => "BufOpen",
+            HookEvent::BufWritePre => "BufWritePre",
+            HookEvent::BufWritePost => "BufWritePost",
+            HookEvent::FileType => "FileType",
+            HookEvent::Resize => "Resize",
+            HookEvent::ModeChange => "ModeChange",
+            HookEvent::CursorMove => "CursorMove",
+        }
+    }
+}
+
+struct Hook {
+    event: HookEvent,
+    pattern: Option<String>,
+    steps: Vec<CommandStep>,
+}
+
+/// Steps to run at each event, in the order they were added.
+#[derive(Default)]
+pub struct Hooks {
+    hooks: Vec<Hook>,
+}
+
+impl Hooks {
+    /// Adds `steps` to run at `event`. With a `pattern`, they only run when it matches the
+    /// subject of the event: the file's path for buffer events and the filetype's name for
+    /// `FileType`. Events without a subject only run hooks without a pattern.
+    pub fn add(&mut self, event: HookEvent, pattern: Option<&str>, steps: Vec<CommandStep>) {
+        self.hooks.push(Hook {
+            event,
+            pattern: pattern.map(str::to_string),
+            steps,
+        });
+    }
+
+    /// Steps of every hook to run at `event` with `subject`.
+    pub fn matching(&self, event: HookEvent, subject: Option<&str>) -> Vec<Vec<CommandStep>> {
+        self.hooks
+            .iter()
+            .filter(|hook| hook.event == event)
+            .filter(|hook| match (&hook.pattern, subject) {
+                (None, _) => true,
+                (Some(pattern), Some(subject)) => glob_match(pattern, subject),
+                (Some(_), None) => false,
+            })
+            .map(|hook| hook.steps.clone())
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    /// Every hook as its event, pattern and steps.
+    pub fn iter(&self) -> impl Iterator<Item = (HookEvent, Option<&str>, &[CommandStep])> {
+        self.hooks
+            .iter()
+            .map(|hook| (hook.event, hook.pattern.as_deref(), hook.steps.as_slice()))
+    }
+}
+
+/// Whether `text` matches `pattern` as a whole, where `*` stands for any run of characters,
+/// slashes included, and `?` for any single character.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<char>>();
+    let text = text.chars().collect::<Vec<char>>();
+    let (mut p, mut t) = (0, 0);
+    // Position of the last star and of the text it was tried against, to backtrack to.
+    let mut star = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&ch) if ch == '?' || ch == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    star = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&ch| ch == '*')
+}
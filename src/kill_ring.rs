@@ -0,0 +1,75 @@
+/// Text cut or copied, to paste again.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Kill {
+    pub text: String,
+    /// Whether it's a rectangle, a line of text per row.
+    pub block: bool,
+}
+
+/// The last few kills, newest first, for pasting and then swapping for older ones.
+pub struct KillRing {
+    kills: Vec<Kill>,
+    capacity: usize,
+}
+
+impl KillRing {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            kills: Vec::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Adds `kill` as the newest. One with the same text as one in the ring moves that one
+    /// up, instead of going in twice. The oldest goes once there are too many.
+    pub fn push(&mut self, kill: Kill) {
+        self.kills.retain(|known| known.text != kill.text);
+        self.kills.insert(0, kill);
+        self.kills.truncate(self.capacity);
+    }
+
+    pub fn get(&self, idx: usize) -> Option<&Kill> {
+        self.kills.get(idx)
+    }
+
+    pub fn len(&self) -> usize {
+        self.kills.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.kills.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Kill> {
+        self.kills.iter()
+    }
+
+    /// Index of the kill older than the one at `idx`, going round to the newest after the
+    /// oldest.
+    pub fn older(&self, idx: usize) -> usize {
+        (idx + 1) % self.kills.len().max(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn newest_first() {
+        let kill = |text: &str| Kill {
+            text: text.to_string(),
+            block: false,
+        };
+        let mut ring = KillRing::new(3);
+        for text in ["a", "b", "c", "a", "d"] {
+            ring.push(kill(text));
+        }
+        let texts = ring
+            .iter()
+            .map(|kill| kill.text.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(texts, ["d", "a", "c"]);
+        assert_eq!((ring.older(0), ring.older(2)), (1, 0));
+    }
+}
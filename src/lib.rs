@@ -1,9 +1,70 @@
+//! A small terminal text editor, and the pieces it's made of.
+//!
+//! The `terminal` module works on its own, for tools that want raw mode, decoded keys and
+//! buffered drawing without the rest of the editor:
+//!
+//! ```no_run
+//! use milo::{Event, Key, Terminal};
+//! use std::time::Duration;
+//!
+//! let mut terminal = Terminal::new()?;
+//! loop {
+//!     match terminal.read_event(Duration::from_secs(1))? {
+//!         Event::Key(Key::Control('Q')) => break,
+//!         Event::Key(key) => {
+//!             terminal.screen().move_to(0, 0);
+//!             terminal.screen().append(&format!("\x1b[K{:?}", key));
+//!             terminal.screen().flush();
+//!         }
+//!         Event::Resize(rows, cols) => eprintln!("{}x{}", cols, rows),
+//!         Event::Tick => {}
+//!     }
+//! }
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+pub mod ansi;
+pub mod backend;
+pub mod batch;
 pub mod buffer;
+pub mod clipboard;
+pub mod complete;
+pub mod config;
+pub mod diff;
+pub mod digraph;
 pub mod editor;
+pub mod encoding;
+pub mod export;
+pub mod filetype;
+pub mod finder;
+pub mod git;
+pub mod grep;
+pub mod highlight;
+pub mod history;
+pub mod hook;
+pub mod kill_ring;
 pub mod line;
+#[cfg(feature = "lsp")]
+pub mod lsp;
+pub mod modal;
+pub mod pattern;
+pub mod positions;
+pub mod region;
+pub mod save;
+pub mod session;
+pub mod storage;
+#[cfg(feature = "syntax-backend")]
+pub mod syntax;
+#[cfg(feature = "async")]
+pub mod task;
 pub mod terminal;
+pub mod terminfo;
+pub mod window;
+
+pub use editor::Editor;
+pub use terminal::{Event, Key, Modifiers, Motion, MouseButton, MouseEvent, Screen, Terminal};
 
-pub fn editor_home_screen(rows: usize, cols: usize) -> String {
+pub fn editor_home_screen(rows: usize, cols: usize) -> Vec<String> {
     let mut banner = format!(
         "{} -- version {}",
         env!("CARGO_PKG_NAME"),
@@ -15,21 +76,19 @@ pub fn editor_home_screen(rows: usize, cols: usize) -> String {
         banner
     } else {
         let mut centered = "~".to_string();
-        centered.extend(std::iter::repeat(" ").take(padding - 1));
+        centered.extend(std::iter::repeat_n(" ", padding - 1));
         centered.push_str(banner.as_str());
         centered
     };
 
-    std::iter::repeat("~")
-        .take(rows)
+    std::iter::repeat_n("~", rows)
         .enumerate()
         .map(|(n, buf)| {
             if n == rows / 3 {
-                banner.chars()
+                banner.clone()
             } else {
-                buf.chars()
+                buf.to_string()
             }
         })
-        .flat_map(|buf| buf.chain("\x1b[K\r\n".chars()))
         .collect()
 }
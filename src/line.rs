@@ -1,18 +1,70 @@
-const TAB_STOP: usize = 8;
+use crate::pattern::Pattern;
+use std::cell::OnceCell;
+use std::cmp::min;
+
+/// Columns between tab stops, unless configured otherwise.
+pub const TAB_STOP: usize = 8;
+
+/// A blank drawn differently when whitespace is shown.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Blank {
+    /// Render column it starts at, and columns it takes.
+    pub col: usize,
+    pub width: usize,
+    pub ch: char,
+    /// Whether it's among the blanks the line ends in.
+    pub trailing: bool,
+}
 
 pub struct Line {
     actual: String,
-    rendered: String,
+    /// The text as drawn, whether it's all ASCII so that columns and byte offsets coincide,
+    /// and whether it's the text itself, so that the text's byte offsets are its columns too.
+    /// Worked out the first time it's needed, so lines of a big file that are never shown or
+    /// searched cost no more than their text.
+    rendered: OnceCell<(String, bool, bool)>,
+    /// Columns between the tab stops tabs are expanded to.
+    tab_stop: usize,
+    /// Render columns tabs go to before the fixed tab stops take over, set by the buffer to
+    /// line cells up with the lines around when tabs are elastic.
+    elastic_stops: Vec<usize>,
 }
 
 impl Line {
     pub fn new(actual: String) -> Self {
-        let mut ret = Self {
+        Self::with_tab_stop(actual, TAB_STOP)
+    }
+
+    pub fn with_tab_stop(actual: String, tab_stop: usize) -> Self {
+        Self {
             actual,
-            rendered: String::new(),
-        };
-        ret.update();
-        ret
+            rendered: OnceCell::new(),
+            tab_stop: tab_stop.max(1),
+            elastic_stops: Vec::new(),
+        }
+    }
+
+    pub fn set_tab_stop(&mut self, tab_stop: usize) {
+        self.tab_stop = tab_stop.max(1);
+        self.update();
+    }
+
+    /// Makes tabs go to the render columns in `stops`, in order, as elastic tab stops.
+    pub fn set_elastic_stops(&mut self, stops: Vec<usize>) {
+        if stops != self.elastic_stops {
+            self.elastic_stops = stops;
+            self.update();
+        }
+    }
+
+    /// Widths of the cells of the line that end in a tab, as elastic tab stops line them up.
+    pub fn cell_widths(&self) -> Vec<usize> {
+        let mut cells = self.actual.split('\t').collect::<Vec<&str>>();
+        cells.pop();
+        cells
+            .iter()
+            .map(|cell| cell.chars().map(|ch| self.width(ch, 0)).sum())
+            .collect()
     }
 
     pub fn len(&self) -> usize {
@@ -38,44 +90,236 @@ impl Line {
         self.update();
     }
 
+    pub fn insert_str(&mut self, pos: usize, content: &str) {
+        self.actual.insert_str(pos, content);
+        self.update();
+    }
+
+    pub fn indent(&self) -> &str {
+        let indent = self.actual.len() - self.actual.trim_start_matches([' ', '\t']).len();
+        &self.actual[..indent]
+    }
+
     pub fn content(&self) -> &str {
         self.actual.as_str()
     }
 
     pub fn rendered(&self) -> &str {
-        self.rendered.as_str()
+        &self.render().0
     }
 
-    pub fn match_indices(&self, query: &str) -> Vec<(usize, &str)> {
-        self.rendered.match_indices(query).collect()
+    /// Whether the rendered text is all ASCII.
+    fn ascii(&self) -> bool {
+        self.render().1
     }
 
-    pub fn cursor_to_render_position(&self, pos: usize) -> usize {
-        self.actual.chars().take(pos).fold(0, |rx, c| {
-            if c == '\t' {
-                rx + TAB_STOP - (rx % TAB_STOP)
-            } else {
-                rx + 1
+    /// Whether each byte of the text is drawn as one column, so positions in it need no
+    /// walking over the characters before them.
+    fn plain(&self) -> bool {
+        self.render().2
+    }
+
+    /// Columns the line takes when drawn.
+    pub fn columns(&self) -> usize {
+        if self.ascii() {
+            self.rendered().len()
+        } else {
+            self.rendered().chars().count()
+        }
+    }
+
+    /// Tabs, no-break spaces and blanks at the end of the line in the `width` render columns
+    /// from `start`, and those of them partly in.
+    pub fn blanks(&self, start: usize, width: usize) -> Vec<Blank> {
+        let end = start.saturating_add(width);
+        let trailing_from = self.actual.trim_end_matches([' ', '\t', '\u{a0}']).len();
+        let mut blanks = Vec::new();
+        if self.plain() {
+            // Every character is a byte and a column, and none is a no-break space.
+            let bytes = self.actual.as_bytes().iter().enumerate();
+            for (col, &byte) in bytes.take(end).skip(start) {
+                if byte == b'\t' || col >= trailing_from {
+                    blanks.push(Blank {
+                        col,
+                        width: 1,
+                        ch: byte as char,
+                        trailing: col >= trailing_from,
+                    });
+                }
             }
-        })
+            return blanks;
+        }
+        let mut rx = 0;
+        for (idx, ch) in self.actual.char_indices() {
+            if rx >= end {
+                break;
+            }
+            let width = self.width(ch, rx);
+            let trailing = idx >= trailing_from;
+            if rx + width > start && (trailing || ch == '\t' || ch == '\u{a0}') {
+                blanks.push(Blank {
+                    col: rx,
+                    width,
+                    ch,
+                    trailing,
+                });
+            }
+            rx += width;
+        }
+        blanks
+    }
+
+    /// Up to `width` rendered columns starting at column `start`. Takes time proportional to
+    /// `width` rather than to the length of the line when the line is plain ASCII.
+    pub fn rendered_slice(&self, start: usize, width: usize) -> &str {
+        let rendered = self.rendered();
+        if self.ascii() {
+            let start = min(start, rendered.len());
+            let end = min(start.saturating_add(width), rendered.len());
+            return &rendered[start..end];
+        }
+        let mut indices = rendered
+            .char_indices()
+            .map(|(idx, _)| idx)
+            .chain(std::iter::once(rendered.len()))
+            .skip(start);
+        let begin = indices.next().unwrap_or(rendered.len());
+        let end = indices
+            .nth(width.saturating_sub(1))
+            .unwrap_or(rendered.len());
+        &rendered[begin..if width == 0 { begin } else { end }]
     }
 
+    /// Render columns that each screen row starts at when the line is wrapped to `cols`
+    /// columns. Rows break after the last space that fits, or at `cols` in a word longer than
+    /// that. Room is kept for the cursor past the end of the line, so a line filling its last
+    /// row exactly gets an empty row after it.
+    pub fn wrap_starts(&self, cols: usize) -> Vec<usize> {
+        if self.ascii() {
+            let bytes = self.rendered().as_bytes();
+            wrap_starts(bytes.len(), |idx| bytes[idx] == b' ', cols)
+        } else {
+            let chars = self.rendered().chars().collect::<Vec<char>>();
+            wrap_starts(chars.len(), |idx| chars[idx] == ' ', cols)
+        }
+    }
+
+    /// Render positions of `pattern`'s matches in the line, with the text matched there.
+    pub fn match_indices(&self, pattern: &Pattern) -> Vec<(usize, &str)> {
+        self.match_indices_before(pattern, usize::MAX)
+    }
+
+    /// Render positions of `pattern`'s matches in the first `cols` columns of the line, with
+    /// the text matched there, so that a view of the start of a long line doesn't search all
+    /// of it.
+    pub fn match_indices_before(&self, pattern: &Pattern, cols: usize) -> Vec<(usize, &str)> {
+        let rendered = self.rendered();
+        let end = if self.ascii() {
+            min(cols, rendered.len())
+        } else {
+            rendered
+                .char_indices()
+                .nth(cols)
+                .map_or(rendered.len(), |(idx, _)| idx)
+        };
+        pattern
+            .find_iter(&rendered[..end])
+            .into_iter()
+            .map(|(start, end)| (start, &rendered[start..end]))
+            .collect()
+    }
+
+    /// Render column of the character at byte offset `pos`.
+    pub fn cursor_to_render_position(&self, pos: usize) -> usize {
+        if self.plain() {
+            return min(pos, self.actual.len());
+        }
+        self.actual
+            .char_indices()
+            .take_while(|&(idx, _)| idx < pos)
+            .fold(0, |rx, (_, ch)| rx + self.width(ch, rx))
+    }
+
+    /// Byte offset of the character drawn at render column `pos`, or the length of the line
+    /// if it ends before.
     pub fn render_to_cursor_position(&self, pos: usize) -> usize {
-        match self.actual.chars().enumerate().try_fold(0, |rx, (n, ch)| {
-            let rx = if ch == '\t' {
-                rx + TAB_STOP - (rx % TAB_STOP)
+        if self.plain() {
+            return min(pos, self.actual.len());
+        }
+        let mut rx = 0;
+        for (idx, ch) in self.actual.char_indices() {
+            rx += self.width(ch, rx);
+            if rx > pos {
+                return idx;
+            }
+        }
+        self.actual.len()
+    }
+
+    /// Render column of byte offset `pos` in the rendered text.
+    pub fn rendered_column(&self, pos: usize) -> usize {
+        if self.ascii() {
+            pos
+        } else {
+            self.rendered()[..pos].chars().count()
+        }
+    }
+
+    /// Byte offset of the character before the one at `pos`.
+    pub fn prev_char_boundary(&self, pos: usize) -> usize {
+        self.actual[..pos]
+            .char_indices()
+            .next_back()
+            .map_or(0, |(idx, _)| idx)
+    }
+
+    /// Byte offset of the character after the one at `pos`, or the length of the line at its
+    /// end.
+    pub fn next_char_boundary(&self, pos: usize) -> usize {
+        self.actual[pos..]
+            .chars()
+            .next()
+            .map_or(pos, |ch| pos + ch.len_utf8())
+    }
+
+    /// Byte offset the cursor at `pos` moves to when looking for `ch` on the line, forward or
+    /// back. It lands on the character, or next to it on the side it came from with `till`.
+    /// A `till` search skips a character already next to the cursor so repeating it moves on.
+    pub fn find_char(&self, pos: usize, ch: char, forward: bool, till: bool) -> Option<usize> {
+        if forward {
+            let from = if till {
+                self.next_char_boundary(self.next_char_boundary(pos))
             } else {
-                rx + 1
+                self.next_char_boundary(pos)
             };
-            if rx > pos {
-                Err(n)
+            let found = from + self.actual.get(from..)?.find(ch)?;
+            Some(if till {
+                self.prev_char_boundary(found)
             } else {
-                Ok(rx)
-            }
-        }) {
-            Ok(v) => v,
-            Err(v) => v,
+                found
+            })
+        } else {
+            let to = if till {
+                self.prev_char_boundary(pos)
+            } else {
+                pos
+            };
+            let found = self.actual[..to].rfind(ch)?;
+            Some(if till {
+                self.next_char_boundary(found)
+            } else {
+                found
+            })
+        }
+    }
+
+    /// `pos` clamped to the line and moved back to the start of the character it falls in.
+    pub fn floor_char_boundary(&self, pos: usize) -> usize {
+        let mut pos = min(pos, self.actual.len());
+        while !self.actual.is_char_boundary(pos) {
+            pos -= 1;
         }
+        pos
     }
 
     pub fn split_off(&mut self, index: usize) -> String {
@@ -84,17 +328,142 @@ impl Line {
         tail
     }
 
+    /// Forgets the rendered text after a change, to be worked out again when needed.
     fn update(&mut self) {
-        self.rendered.clear();
-        for ch in self.actual.chars() {
-            if ch == '\t' {
-                self.rendered.push(' ');
-                while self.rendered.len() % TAB_STOP != 0 {
-                    self.rendered.push(' ');
-                }
-            } else {
-                self.rendered.push(ch);
+        self.rendered.take();
+    }
+
+    /// Columns `ch` takes when drawn at render column `rx`. Control characters other than tabs
+    /// are drawn as `^X`, so that they show up instead of acting on the terminal.
+    fn width(&self, ch: char, rx: usize) -> usize {
+        if ch == '\t' {
+            match self.elastic_stops.iter().find(|&&stop| stop > rx) {
+                Some(stop) => stop - rx,
+                None => self.tab_stop - (rx % self.tab_stop),
             }
+        } else if ch.is_ascii_control() {
+            2
+        } else {
+            1
         }
     }
+
+    fn render(&self) -> &(String, bool, bool) {
+        self.rendered.get_or_init(|| {
+            let mut rendered = String::with_capacity(self.actual.len());
+            let mut rx = 0;
+            for ch in self.actual.chars() {
+                let width = self.width(ch, rx);
+                if ch == '\t' {
+                    rendered.extend(std::iter::repeat_n(' ', width));
+                } else if ch.is_ascii_control() {
+                    rendered.push('^');
+                    rendered.push(((ch as u8) ^ 0x40) as char);
+                } else {
+                    rendered.push(ch);
+                }
+                rx += width;
+            }
+            let ascii = rendered.is_ascii();
+            // Tabs one column wide leave the length alone, and are still a column a byte.
+            let plain = ascii && rendered.len() == self.actual.len();
+            (rendered, ascii, plain)
+        })
+    }
+}
+
+/// Columns that rows of `len` columns wrapped to `cols` start at, given which are spaces.
+fn wrap_starts(len: usize, is_space: impl Fn(usize) -> bool, cols: usize) -> Vec<usize> {
+    let cols = cols.max(1);
+    let mut starts = vec![0];
+    let mut start = 0;
+    while len + 1 - start > cols {
+        let end = start + cols;
+        start = (start + 1..=end)
+            .rev()
+            .find(|&next| is_space(next - 1))
+            .unwrap_or(end);
+        starts.push(start);
+    }
+    starts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filetype::Filetype;
+    use crate::highlight::{self, MAX_COLUMNS};
+
+    /// A line like a minified file's, far longer than any screen.
+    fn long_line() -> Line {
+        Line::new("var a=1;".repeat(500_000))
+    }
+
+    #[test]
+    fn positions_in_plain_long_line() {
+        let line = long_line();
+        assert_eq!(line.columns(), 4_000_000);
+        assert_eq!(line.cursor_to_render_position(3_999_999), 3_999_999);
+        assert_eq!(line.render_to_cursor_position(5_000_000), 4_000_000);
+        assert_eq!(line.rendered_slice(3_999_996, 80), "a=1;");
+    }
+
+    #[test]
+    fn positions_past_tabs_and_wide_characters() {
+        let line = Line::new(format!("\té{}", "x".repeat(100_000)));
+        assert_eq!(line.columns(), 100_009);
+        assert_eq!(line.cursor_to_render_position(3), 9);
+        assert_eq!(line.render_to_cursor_position(9), 3);
+        assert_eq!(line.rendered_slice(100_007, 80), "xx");
+    }
+
+    #[test]
+    fn blanks_shown() {
+        let blank = |col, width, ch, trailing| Blank {
+            col,
+            width,
+            ch,
+            trailing,
+        };
+        let line = Line::new("a\tb\u{a0}c \t".to_string());
+        assert_eq!(
+            line.blanks(0, 80),
+            vec![
+                blank(1, 7, '\t', false),
+                blank(9, 1, '\u{a0}', false),
+                blank(11, 1, ' ', true),
+                blank(12, 4, '\t', true),
+            ]
+        );
+        assert_eq!(
+            line.blanks(4, 6),
+            vec![blank(1, 7, '\t', false), blank(9, 1, '\u{a0}', false)]
+        );
+        let line = Line::new("x = 1;  ".to_string());
+        assert_eq!(line.blanks(7, 80), vec![blank(7, 1, ' ', true)]);
+    }
+
+    #[test]
+    fn wrapping_long_line() {
+        let starts = long_line().wrap_starts(80);
+        assert_eq!(starts.len(), 50_001);
+        assert!(starts.windows(2).all(|pair| pair[1] - pair[0] <= 80));
+    }
+
+    #[test]
+    fn highlighting_stops_at_max_columns() {
+        let line = long_line();
+        let filetype = Filetype::by_name("JavaScript").expect("a JavaScript filetype");
+        let (highlights, _) =
+            highlight::highlight_line(line.rendered(), filetype, Default::default());
+        assert_eq!(highlights.len(), MAX_COLUMNS);
+    }
+
+    #[test]
+    fn matching_start_of_long_line() {
+        let line = long_line();
+        let pattern = Pattern::literal("a=1", false);
+        assert_eq!(line.match_indices_before(&pattern, 80).len(), 10);
+        assert_eq!(line.match_indices(&pattern).len(), 500_000);
+    }
 }
@@ -0,0 +1,946 @@
+use std::fmt;
+use std::io::{BufRead, BufReader, Error, Result, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+
+/// A JSON value, the form language servers take and give everything in. Objects keep their
+/// members in order, which is all that's needed of them here.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// An object with `members`.
+    pub fn object(members: Vec<(&str, Json)>) -> Json {
+        Json::Object(
+            members
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )
+    }
+
+    /// Reads the JSON value that is the whole of `text`, or says what's wrong with it.
+    pub fn parse(text: &str) -> std::result::Result<Json, String> {
+        let mut parser = Parser {
+            text: text.as_bytes(),
+            pos: 0,
+        };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        match parser.peek() {
+            None => Ok(value),
+            Some(_) => Err(format!("Unexpected text at {}", parser.pos)),
+        }
+    }
+
+    /// The member `key` of an object, unless it's missing or null.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value)
+                .filter(|value| **value != Json::Null),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    /// The value as a count, if it's a whole number that isn't negative.
+    pub fn as_usize(&self) -> Option<usize> {
+        match *self {
+            Json::Number(number) if number >= 0.0 && number.fract() == 0.0 => Some(number as usize),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+impl From<&str> for Json {
+    fn from(text: &str) -> Json {
+        Json::String(text.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(text: String) -> Json {
+        Json::String(text)
+    }
+}
+
+impl From<usize> for Json {
+    fn from(number: usize) -> Json {
+        Json::Number(number as f64)
+    }
+}
+
+impl From<bool> for Json {
+    fn from(value: bool) -> Json {
+        Json::Bool(value)
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(value) => write!(f, "{}", value),
+            Json::Number(number) if number.is_finite() => write!(f, "{}", number),
+            Json::Number(_) => write!(f, "null"),
+            Json::String(text) => write_string(f, text),
+            Json::Array(values) => {
+                write!(f, "[")?;
+                for (n, value) in values.iter().enumerate() {
+                    if n > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(members) => {
+                write!(f, "{{")?;
+                for (n, (key, value)) in members.iter().enumerate() {
+                    if n > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, text: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for ch in text.chars() {
+        match ch {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            ch if (ch as u32) < 0x20 => write!(f, "\\u{:04x}", ch as u32)?,
+            ch => write!(f, "{}", ch)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+struct Parser<'a> {
+    text: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.text.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(|byte| byte.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, word: &str) -> std::result::Result<(), String> {
+        if self.text[self.pos..].starts_with(word.as_bytes()) {
+            self.pos += word.len();
+            Ok(())
+        } else {
+            Err(format!("Expected {} at {}", word, self.pos))
+        }
+    }
+
+    fn value(&mut self) -> std::result::Result<Json, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'n') => self.expect("null").map(|_| Json::Null),
+            Some(b't') => self.expect("true").map(|_| Json::Bool(true)),
+            Some(b'f') => self.expect("false").map(|_| Json::Bool(false)),
+            Some(b'"') => self.string().map(Json::String),
+            Some(b'[') => {
+                self.pos += 1;
+                let mut values = Vec::new();
+                self.skip_whitespace();
+                if self.peek() == Some(b']') {
+                    self.pos += 1;
+                    return Ok(Json::Array(values));
+                }
+                loop {
+                    values.push(self.value()?);
+                    self.skip_whitespace();
+                    match self.peek() {
+                        Some(b',') => self.pos += 1,
+                        Some(b']') => {
+                            self.pos += 1;
+                            return Ok(Json::Array(values));
+                        }
+                        _ => return Err(format!("Expected , or ] at {}", self.pos)),
+                    }
+                }
+            }
+            Some(b'{') => {
+                self.pos += 1;
+                let mut members = Vec::new();
+                self.skip_whitespace();
+                if self.peek() == Some(b'}') {
+                    self.pos += 1;
+                    return Ok(Json::Object(members));
+                }
+                loop {
+                    self.skip_whitespace();
+                    let key = self.string()?;
+                    self.skip_whitespace();
+                    self.expect(":")?;
+                    members.push((key, self.value()?));
+                    self.skip_whitespace();
+                    match self.peek() {
+                        Some(b',') => self.pos += 1,
+                        Some(b'}') => {
+                            self.pos += 1;
+                            return Ok(Json::Object(members));
+                        }
+                        _ => return Err(format!("Expected , or }} at {}", self.pos)),
+                    }
+                }
+            }
+            Some(byte) if byte == b'-' || byte.is_ascii_digit() => {
+                let start = self.pos;
+                while self
+                    .peek()
+                    .is_some_and(|byte| byte.is_ascii_digit() || b"+-.eE".contains(&byte))
+                {
+                    self.pos += 1;
+                }
+                let number = String::from_utf8_lossy(&self.text[start..self.pos]);
+                number
+                    .parse()
+                    .map(Json::Number)
+                    .map_err(|_| format!("Bad number {}", number))
+            }
+            Some(_) => Err(format!("Unexpected character at {}", self.pos)),
+            None => Err("Unexpected end of text".to_string()),
+        }
+    }
+
+    fn string(&mut self) -> std::result::Result<String, String> {
+        self.expect("\"")?;
+        let mut bytes = Vec::new();
+        loop {
+            match self.peek() {
+                None => return Err("Unterminated string".to_string()),
+                Some(b'"') => {
+                    self.pos += 1;
+                    return String::from_utf8(bytes).map_err(|err| err.to_string());
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    let escaped = self.peek().ok_or("Unterminated string")?;
+                    self.pos += 1;
+                    let ch = match escaped {
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'u' => self.unicode_escape()?,
+                        other => char::from(other),
+                    };
+                    bytes.extend(ch.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                Some(byte) => {
+                    bytes.push(byte);
+                    self.pos += 1;
+                }
+            }
+        }
+    }
+
+    /// The character of a `\u` escape, the `\u` read already, joining the two halves of a
+    /// surrogate pair.
+    fn unicode_escape(&mut self) -> std::result::Result<char, String> {
+        let unit = self.hex4()?;
+        if (0xd800..0xdc00).contains(&unit) && self.text[self.pos..].starts_with(b"\\u") {
+            let start = self.pos;
+            self.pos += 2;
+            let low = self.hex4()?;
+            if (0xdc00..0xe000).contains(&low) {
+                let code = 0x10000 + ((unit - 0xd800) << 10) + (low - 0xdc00);
+                return Ok(char::from_u32(code).unwrap_or('\u{fffd}'));
+            }
+            self.pos = start;
+        }
+        Ok(char::from_u32(unit).unwrap_or('\u{fffd}'))
+    }
+
+    fn hex4(&mut self) -> std::result::Result<u32, String> {
+        let digits = self
+            .text
+            .get(self.pos..self.pos + 4)
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+            .ok_or_else(|| format!("Bad \\u escape at {}", self.pos))?;
+        self.pos += 4;
+        Ok(digits)
+    }
+}
+
+/// How serious a diagnostic is, from errors down to hints.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+    Information,
+    Hint,
+}
+
+impl Severity {
+    /// Letter marking lines with a diagnostic of this severity in the gutter.
+    pub fn sign(self) -> char {
+        match self {
+            Severity::Error => 'E',
+            Severity::Warning => 'W',
+            Severity::Information => 'I',
+            Severity::Hint => 'H',
+        }
+    }
+}
+
+/// A problem a language server found in a document.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    /// Line, from 0, and column where it starts, in UTF-16 code units as servers count them.
+    pub start: (usize, usize),
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn from_json(json: &Json) -> Option<Diagnostic> {
+        let severity = match json.get("severity").and_then(Json::as_usize) {
+            Some(2) => Severity::Warning,
+            Some(3) => Severity::Information,
+            Some(4) => Severity::Hint,
+            _ => Severity::Error,
+        };
+        Some(Diagnostic {
+            start: position(json.get("range")?.get("start")?)?,
+            severity,
+            message: json.get("message")?.as_str()?.to_string(),
+        })
+    }
+}
+
+/// Line and column of an LSP `Position`.
+fn position(json: &Json) -> Option<(usize, usize)> {
+    Some((
+        json.get("line")?.as_usize()?,
+        json.get("character")?.as_usize()?,
+    ))
+}
+
+/// The language identifier servers know files of the type named `filetype` by.
+pub fn language_id(filetype: &str) -> String {
+    match filetype {
+        "Shell" => "shellscript".to_string(),
+        "Make" => "makefile".to_string(),
+        name => name.to_ascii_lowercase(),
+    }
+}
+
+/// What an answer from the language server is to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Request {
+    Definition,
+    Hover,
+}
+
+impl Request {
+    fn method(self) -> &'static str {
+        match self {
+            Request::Definition => "textDocument/definition",
+            Request::Hover => "textDocument/hover",
+        }
+    }
+}
+
+/// The file and the line and column, in UTF-16 code units, a definition request was answered
+/// with: the first of the locations, which come as a `Location`, a list of them or a list of
+/// `LocationLink`s.
+pub fn definition_location(result: &Json) -> Option<(PathBuf, usize, usize)> {
+    let location = match result {
+        Json::Array(locations) => locations.first()?,
+        location => location,
+    };
+    let uri = location
+        .get("uri")
+        .or_else(|| location.get("targetUri"))?
+        .as_str()?;
+    let range = location
+        .get("range")
+        .or_else(|| location.get("targetSelectionRange"))?;
+    let (line, col) = position(range.get("start")?)?;
+    Some((uri_to_path(uri)?, line, col))
+}
+
+/// The text a hover request was answered with, which comes as `MarkupContent`, a
+/// `MarkedString` or a list of those.
+pub fn hover_text(result: &Json) -> String {
+    fn text(contents: &Json) -> String {
+        match contents {
+            Json::String(text) => text.clone(),
+            Json::Array(parts) => parts
+                .iter()
+                .map(text)
+                .filter(|part| !part.is_empty())
+                .collect::<Vec<String>>()
+                .join("\n\n"),
+            contents => contents
+                .get("value")
+                .and_then(Json::as_str)
+                .unwrap_or_default()
+                .to_string(),
+        }
+    }
+    result.get("contents").map(text).unwrap_or_default()
+}
+
+/// Byte index into `line` of the column `units` UTF-16 code units in, or the end of the line
+/// if it's past it.
+pub fn byte_col(line: &str, units: usize) -> usize {
+    let mut counted = 0;
+    for (idx, ch) in line.char_indices() {
+        if counted >= units {
+            return idx;
+        }
+        counted += ch.len_utf16();
+    }
+    line.len()
+}
+
+/// Column of byte `col` of `line` in UTF-16 code units.
+pub fn utf16_col(line: &str, col: usize) -> usize {
+    line.get(..col)
+        .unwrap_or(line)
+        .chars()
+        .map(char::len_utf16)
+        .sum()
+}
+
+/// The `file:` URI of `path`, which should be absolute.
+pub fn path_to_uri(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let mut uri = String::from("file://");
+    if !path.starts_with('/') {
+        uri.push('/');
+    }
+    for byte in path.bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-._~:".contains(&byte) {
+            uri.push(char::from(byte));
+        } else {
+            uri.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    uri
+}
+
+/// The path of a `file:` URI, if that's what `uri` is.
+pub fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let encoded = uri.strip_prefix("file://")?.as_bytes();
+    let mut bytes = Vec::new();
+    let mut idx = 0;
+    while idx < encoded.len() {
+        let decoded = encoded
+            .get(idx + 1..idx + 3)
+            .filter(|_| encoded[idx] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match decoded {
+            Some(byte) => {
+                bytes.push(byte);
+                idx += 3;
+            }
+            None => {
+                bytes.push(encoded[idx]);
+                idx += 1;
+            }
+        }
+    }
+    let path = String::from_utf8(bytes).ok()?;
+    // Drive letters come after a slash, as in file:///C:/dir.
+    let path = match path.as_bytes() {
+        [b'/', _, b':', ..] => path[1..].to_string(),
+        _ => path,
+    };
+    Some(PathBuf::from(path))
+}
+
+/// `message` with the header JSON-RPC messages go over a stream with.
+fn frame(message: &Json) -> Vec<u8> {
+    let body = message.to_string();
+    format!("Content-Length: {}\r\n\r\n{}", body.len(), body).into_bytes()
+}
+
+/// Reads the next message framed by `frame` from `reader`, or None at the end of the stream.
+/// Messages that aren't JSON are skipped.
+fn read_message(reader: &mut impl BufRead) -> Result<Option<Json>> {
+    loop {
+        let mut length = None;
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header)? == 0 {
+                return Ok(None);
+            }
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("Content-Length") {
+                    length = value.trim().parse::<usize>().ok();
+                }
+            }
+        }
+        let Some(length) = length else {
+            continue;
+        };
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body)?;
+        if let Ok(message) = Json::parse(&String::from_utf8_lossy(&body)) {
+            return Ok(Some(message));
+        }
+    }
+}
+
+/// The answer to a request, or the error the server gave instead.
+pub struct Response {
+    pub id: usize,
+    pub result: std::result::Result<Json, String>,
+}
+
+/// A language server running as a child process, talking JSON-RPC over its standard input
+/// and output. Messages from it are read on a thread of their own and picked up by `poll`, so
+/// nothing here waits on the server.
+pub struct Client {
+    child: Child,
+    stdin: ChildStdin,
+    incoming: Receiver<Json>,
+    next_id: usize,
+    /// Messages held back until the server answers `initialize`, which nothing may come
+    /// before. None once it has.
+    held: Option<Vec<Json>>,
+    /// Documents opened on the server, by URI, with their version and the `changes` of the
+    /// text last sent.
+    documents: Vec<(String, usize, usize)>,
+    /// Diagnostics last published for each document, by URI.
+    diagnostics: Vec<(String, Vec<Diagnostic>)>,
+}
+
+impl Client {
+    /// Starts the server `command`, a program followed by its arguments, for the workspace at
+    /// `root`, and asks it to initialize.
+    pub fn start(command: &str, root: &Path) -> Result<Client> {
+        let mut words = command.split_whitespace();
+        let program = words
+            .next()
+            .ok_or_else(|| Error::other("No language server command"))?;
+        let mut child = Command::new(program)
+            .args(words)
+            .current_dir(root)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let stdin = child.stdin.take().ok_or_else(|| Error::other("No stdin"))?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| Error::other("No stdout"))?;
+        let (sender, incoming) = mpsc::channel();
+        std::thread::spawn(move || {
+            let mut reader = BufReader::new(stdout);
+            while let Ok(Some(message)) = read_message(&mut reader) {
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+        let mut client = Client {
+            child,
+            stdin,
+            incoming,
+            next_id: 1,
+            held: None,
+            documents: Vec::new(),
+            diagnostics: Vec::new(),
+        };
+        let root_uri = path_to_uri(root);
+        let name = root.file_name().map_or_else(
+            || root_uri.clone(),
+            |name| name.to_string_lossy().to_string(),
+        );
+        let capabilities = Json::object(vec![(
+            "textDocument",
+            Json::object(vec![
+                ("publishDiagnostics", Json::object(vec![])),
+                (
+                    "hover",
+                    Json::object(vec![(
+                        "contentFormat",
+                        Json::Array(vec!["plaintext".into(), "markdown".into()]),
+                    )]),
+                ),
+                ("definition", Json::object(vec![])),
+            ]),
+        )]);
+        client.request(
+            "initialize",
+            Json::object(vec![
+                ("processId", (std::process::id() as usize).into()),
+                ("rootUri", root_uri.clone().into()),
+                (
+                    "workspaceFolders",
+                    Json::Array(vec![Json::object(vec![
+                        ("uri", root_uri.into()),
+                        ("name", name.into()),
+                    ])]),
+                ),
+                ("capabilities", capabilities),
+            ]),
+        )?;
+        client.held = Some(Vec::new());
+        Ok(client)
+    }
+
+    /// Sends `message`, or holds it back while the server initializes.
+    fn send(&mut self, message: Json) -> Result<()> {
+        match self.held.as_mut() {
+            Some(held) => held.push(message),
+            None => self.stdin.write_all(&frame(&message))?,
+        }
+        Ok(())
+    }
+
+    fn request(&mut self, method: &str, params: Json) -> Result<usize> {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.send(Json::object(vec![
+            ("jsonrpc", "2.0".into()),
+            ("id", id.into()),
+            ("method", method.into()),
+            ("params", params),
+        ]))?;
+        Ok(id)
+    }
+
+    fn notify(&mut self, method: &str, params: Json) -> Result<()> {
+        self.send(Json::object(vec![
+            ("jsonrpc", "2.0".into()),
+            ("method", method.into()),
+            ("params", params),
+        ]))
+    }
+
+    /// Brings the server's copy of the document at `path` up to date: opens it with `text` as
+    /// `language`, or sends the whole of `text` again if `changes`, anything that changes
+    /// with the text, isn't what it was last time.
+    pub fn sync(
+        &mut self,
+        path: &Path,
+        language: &str,
+        changes: usize,
+        text: impl FnOnce() -> String,
+    ) -> Result<()> {
+        let uri = path_to_uri(path);
+        let document = self.documents.iter().position(|(open, _, _)| *open == uri);
+        match document {
+            None => {
+                let item = Json::object(vec![
+                    ("uri", uri.clone().into()),
+                    ("languageId", language.into()),
+                    ("version", 1.into()),
+                    ("text", text().into()),
+                ]);
+                self.documents.push((uri, 1, changes));
+                self.notify(
+                    "textDocument/didOpen",
+                    Json::object(vec![("textDocument", item)]),
+                )
+            }
+            Some(idx) if self.documents[idx].2 != changes => {
+                let version = self.documents[idx].1 + 1;
+                self.documents[idx] = (uri.clone(), version, changes);
+                let document = Json::object(vec![("uri", uri.into()), ("version", version.into())]);
+                let change = Json::object(vec![("text", text().into())]);
+                self.notify(
+                    "textDocument/didChange",
+                    Json::object(vec![
+                        ("textDocument", document),
+                        ("contentChanges", Json::Array(vec![change])),
+                    ]),
+                )
+            }
+            Some(_) => Ok(()),
+        }
+    }
+
+    /// Asks `request` about line `line` and column `col` of the document at `path`, columns
+    /// counted in UTF-16 code units. The answer comes from `poll` with the id returned.
+    pub fn ask(&mut self, request: Request, path: &Path, line: usize, col: usize) -> Result<usize> {
+        let params = Json::object(vec![
+            (
+                "textDocument",
+                Json::object(vec![("uri", path_to_uri(path).into())]),
+            ),
+            (
+                "position",
+                Json::object(vec![("line", line.into()), ("character", col.into())]),
+            ),
+        ]);
+        self.request(request.method(), params)
+    }
+
+    /// Handles what the server has sent since last time, keeping the diagnostics it published
+    /// and answering its requests, and returns the answers to ours. Fails once the server has
+    /// gone away.
+    pub fn poll(&mut self) -> Result<Vec<Response>> {
+        let mut responses = Vec::new();
+        loop {
+            let message = match self.incoming.try_recv() {
+                Ok(message) => message,
+                Err(TryRecvError::Empty) => return Ok(responses),
+                Err(TryRecvError::Disconnected) => {
+                    return Err(Error::other("the language server exited"))
+                }
+            };
+            let id = message.get("id").cloned();
+            match (message.get("method").and_then(Json::as_str), id) {
+                (Some("textDocument/publishDiagnostics"), _) => {
+                    let params = message.get("params");
+                    if let Some(uri) = params.and_then(|params| params.get("uri")) {
+                        let found = params
+                            .and_then(|params| params.get("diagnostics"))
+                            .and_then(Json::as_array)
+                            .unwrap_or_default()
+                            .iter()
+                            .filter_map(Diagnostic::from_json)
+                            .collect();
+                        let uri = uri.as_str().unwrap_or_default().to_string();
+                        self.diagnostics.retain(|(known, _)| *known != uri);
+                        self.diagnostics.push((uri, found));
+                    }
+                }
+                // Nothing the server asks of an editor this small needs more than a null.
+                (Some(_), Some(id)) => self.stdin.write_all(&frame(&Json::object(vec![
+                    ("jsonrpc", "2.0".into()),
+                    ("id", id),
+                    ("result", Json::Null),
+                ])))?,
+                (Some(_), None) => {}
+                (None, Some(id)) => {
+                    let id = id.as_usize().unwrap_or_default();
+                    let result = match message.get("error") {
+                        Some(error) => Err(error
+                            .get("message")
+                            .and_then(Json::as_str)
+                            .unwrap_or("request failed")
+                            .to_string()),
+                        None => Ok(message.get("result").cloned().unwrap_or(Json::Null)),
+                    };
+                    // The first answer is to `initialize`, which lets everything else through.
+                    if let Some(held) = self.held.take().filter(|_| id == 1) {
+                        self.notify("initialized", Json::object(vec![]))?;
+                        for message in held {
+                            self.send(message)?;
+                        }
+                    } else {
+                        responses.push(Response { id, result });
+                    }
+                }
+                (None, None) => {}
+            }
+        }
+    }
+
+    /// Diagnostics last published for the document at `path`.
+    pub fn diagnostics(&self, path: &Path) -> &[Diagnostic] {
+        let uri = path_to_uri(path);
+        self.diagnostics
+            .iter()
+            .find(|(known, _)| *known == uri)
+            .map_or(&[], |(_, found)| found.as_slice())
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.held = None;
+        let _ = self.notify("exit", Json::Null);
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// The language servers of an editor: the command starting one for each filetype, and the
+/// ones started so far.
+#[derive(Default)]
+pub struct Servers {
+    commands: Vec<(String, String)>,
+    /// Clients by filetype name, None for servers that failed to start or have stopped, which
+    /// aren't tried again.
+    clients: Vec<(String, Option<Client>)>,
+}
+
+impl Servers {
+    /// Has `command` start the language server for files of the type named `filetype`.
+    pub fn set_command(&mut self, filetype: &str, command: &str) {
+        self.commands
+            .retain(|(name, _)| !name.eq_ignore_ascii_case(filetype));
+        self.commands
+            .push((filetype.to_string(), command.to_string()));
+    }
+
+    /// Whether any server is running, for the editor to look for messages from it.
+    pub fn is_running(&self) -> bool {
+        self.clients.iter().any(|(_, client)| client.is_some())
+    }
+
+    /// The running server for `filetype`, started for the workspace at `root` if it hasn't
+    /// been yet. Fails with what went wrong the one time a server doesn't start.
+    pub fn client(
+        &mut self,
+        filetype: &str,
+        root: &Path,
+    ) -> std::result::Result<Option<&mut Client>, String> {
+        let started = self.clients.iter().position(|(name, _)| name == filetype);
+        let idx = match started {
+            Some(idx) => idx,
+            None => {
+                let Some((_, command)) = self
+                    .commands
+                    .iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case(filetype))
+                else {
+                    return Ok(None);
+                };
+                let started = Client::start(command, root);
+                let failed = started
+                    .as_ref()
+                    .err()
+                    .map(|err| format!("Language server {} didn't start: {}", command, err));
+                self.clients.push((filetype.to_string(), started.ok()));
+                if let Some(failed) = failed {
+                    return Err(failed);
+                }
+                self.clients.len() - 1
+            }
+        };
+        Ok(self.clients[idx].1.as_mut())
+    }
+
+    /// The running server for `filetype`, if any, without starting one.
+    pub fn running(&self, filetype: &str) -> Option<&Client> {
+        self.clients
+            .iter()
+            .find(|(name, _)| name == filetype)
+            .and_then(|(_, client)| client.as_ref())
+    }
+
+    /// Polls every running server, stopping those that fail. Returns the answers, each with
+    /// the filetype of its server, and the problems.
+    pub fn poll(&mut self) -> (Vec<(String, Response)>, Vec<String>) {
+        let mut responses = Vec::new();
+        let mut errors = Vec::new();
+        for (name, slot) in &mut self.clients {
+            let Some(client) = slot.as_mut() else {
+                continue;
+            };
+            match client.poll() {
+                Ok(answers) => {
+                    responses.extend(answers.into_iter().map(|answer| (name.clone(), answer)))
+                }
+                Err(err) => {
+                    errors.push(format!("Language server for {} stopped: {}", name, err));
+                    *slot = None;
+                }
+            }
+        }
+        (responses, errors)
+    }
+
+    /// Stops the server for `filetype` after it failed, leaving it stopped.
+    pub fn stop(&mut self, filetype: &str) {
+        for (name, slot) in &mut self.clients {
+            if name == filetype {
+                *slot = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_round_trip() {
+        let text = r#"{"a":[1,-2.5,true,null],"b":"q\"\\\n\u00e9\ud83d\ude00","c":{}}"#;
+        let json = Json::parse(text).unwrap();
+        assert_eq!(
+            json.get("b").and_then(Json::as_str),
+            Some("q\"\\\n\u{e9}\u{1f600}")
+        );
+        assert_eq!(
+            json.get("a").and_then(Json::as_array).map(<[_]>::len),
+            Some(4)
+        );
+        assert_eq!(Json::parse(&json.to_string()), Ok(json));
+        assert!(Json::parse("[1,").is_err());
+        assert!(Json::parse("{} x").is_err());
+    }
+
+    #[test]
+    fn framed_messages() {
+        let message = Json::object(vec![("id", 3.into()), ("method", "initialize".into())]);
+        let mut stream = frame(&message);
+        stream.extend(frame(&Json::Null));
+        let mut reader = &stream[..];
+        assert_eq!(read_message(&mut reader).unwrap(), Some(message));
+        assert_eq!(read_message(&mut reader).unwrap(), Some(Json::Null));
+        assert_eq!(read_message(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn positions_and_uris() {
+        let line = "a\u{e9}\u{1f600}b";
+        assert_eq!(utf16_col(line, line.len() - 1), 4);
+        assert_eq!(byte_col(line, 4), line.len() - 1);
+        assert_eq!(byte_col(line, 99), line.len());
+        let path = Path::new("/tmp/a dir/x%.rs");
+        assert_eq!(path_to_uri(path), "file:///tmp/a%20dir/x%25.rs");
+        assert_eq!(uri_to_path(&path_to_uri(path)).as_deref(), Some(path));
+    }
+}
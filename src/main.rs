@@ -1,15 +1,70 @@
-use milo::editor::Editor;
-use std::io::Result;
+use milo::{Editor, Terminal};
+use std::io::{Read, Result};
 
 fn main() -> Result<()> {
+    let files = std::env::args().skip(1).collect::<Vec<String>>();
+    if files.first().is_some_and(|arg| arg == "--export") {
+        if let Err(err) = milo::export::run(&files[1..]) {
+            eprintln!("milo: {}", err);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    if files.first().is_some_and(|arg| arg == "--execute") {
+        if let Err(err) = milo::batch::run(&files[1..]) {
+            eprintln!("milo: {}", err);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    // -R opens the files read-only, and --view in view mode too. --restore picks up the
+    // session saved on quitting in this directory. - reads text from standard input.
+    let read_only = files.iter().any(|arg| arg == "-R");
+    let view = files.iter().any(|arg| arg == "--view");
+    let restore = files.iter().any(|arg| arg == "--restore");
+    let stdin = files.iter().any(|arg| arg == "-");
+    let files = files
+        .into_iter()
+        .filter(|arg| !["-R", "--view", "--restore", "-"].contains(&arg.as_str()))
+        .collect::<Vec<String>>();
+
+    let mut piped = Vec::new();
+    if stdin {
+        std::io::stdin().read_to_end(&mut piped)?;
+    }
+    // Keys come from the terminal even with standard input piped, and the screen goes there
+    // with standard output piped, which gets the text on quitting instead.
+    let output = Terminal::attach()?;
     let mut editor = Editor::new()?;
 
-    editor.open(std::env::args().nth(1))?;
-    editor.set_status("HELP: Ctrl-S = save | Ctrl-F = find | Ctrl-Q = quit".to_string());
+    editor.show_help();
+    milo::config::load(&mut editor);
+    editor.set_open_read_only(read_only, view);
+    let restored = restore && editor.restore_session()?;
+    if restore && !restored {
+        editor.set_status("No session to restore in this directory".to_string());
+    }
+    if let Some(output) = output {
+        editor.set_output(output);
+    }
+    if files.is_empty() && !restored && !stdin {
+        editor.open(None)?;
+    }
+    if stdin {
+        editor.open_stdin(&piped)?;
+    }
+    for file in files {
+        editor.open(Some(file))?;
+    }
+    if !restored {
+        editor.switch_buffer(0);
+    }
 
     while editor.keep_alive() {
         editor.refresh_screen();
         editor.process_keypress()?;
+        editor.process_pending()?;
     }
 
     Ok(())
@@ -0,0 +1,402 @@
+use crate::buffer::ViewAlign;
+use crate::terminal::{Key, Motion};
+
+/// Mode of the vi-like editing the `modal` option turns on.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Mode {
+    /// Keys move around and work on the text instead of typing.
+    #[default]
+    Normal,
+    /// Keys type, as they do without modes.
+    Insert,
+    /// Moving extends a selection from where the mode started.
+    Visual,
+}
+
+impl Mode {
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::Normal => "NORMAL",
+            Mode::Insert => "INSERT",
+            Mode::Visual => "VISUAL",
+        }
+    }
+}
+
+/// Where `i`, `a`, `o` and the like start inserting.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InsertAt {
+    Cursor,
+    AfterCursor,
+    /// Before the first character of the line that isn't a blank.
+    LineStart,
+    LineEnd,
+    LineBelow,
+    LineAbove,
+}
+
+/// What `d`, `c` and `y` do to the text a motion or text object after them covers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operator {
+    Delete,
+    /// Deletes, then inserts in place of what went.
+    Change,
+    Yank,
+}
+
+/// A search for a character on the cursor line, as `f`, `t`, `F` and `T` start it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CharSearch {
+    pub ch: char,
+    pub forward: bool,
+    /// Stops next to the character rather than on it.
+    pub till: bool,
+}
+
+/// What an operator applies to, from the cursor.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Target {
+    /// As many whole lines as the count, as doubling the operator asks for.
+    Lines(usize),
+    /// Up to where a motion done the count times leads. Going up or down takes whole lines.
+    Motion(Motion, usize),
+    /// Up to the character the search finds the count times, and with `f` over it.
+    Char(CharSearch, usize),
+    /// A text object like `iw` or `a(`, as `region::text_object` reads it.
+    Object(String),
+}
+
+/// What keys typed in normal or visual mode ask for, done `count` times where there's one.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Move(Motion, usize),
+    /// To a line by number, counting from 1, or to the last line without one.
+    GotoLine(Option<usize>),
+    Insert(InsertAt),
+    DeleteChars(usize),
+    /// To the character a search finds on the line, the count times.
+    FindChar(CharSearch, usize),
+    Operate(Operator, Target),
+    Put {
+        before: bool,
+        count: usize,
+    },
+    Undo(usize),
+    Redo(usize),
+    /// Moves the cursor and the view together by the count in lines, or by the editor's
+    /// half-page scroll without one.
+    Scroll {
+        up: bool,
+        count: Option<usize>,
+    },
+    /// Scrolls the view to put the cursor line at the center, top or bottom.
+    Align(ViewAlign),
+    /// Starts selecting, for the next motions to extend.
+    Visual,
+    DeleteSelection,
+    /// Deletes the selection and inserts in its place.
+    ChangeSelection,
+    YankSelection,
+    /// Back to normal mode, out of insert or visual mode.
+    Normal,
+    Search {
+        forward: bool,
+    },
+    SearchNext {
+        forward: bool,
+    },
+    CommandLine,
+    /// Names the cursor's place after a letter, to jump back to.
+    SetMark(char),
+    /// To the place a letter names, or with `line` to the start of the text on its line.
+    GotoMark {
+        name: char,
+        line: bool,
+    },
+    /// Back through the places jumped away from by the count, or forward again.
+    Jump {
+        back: bool,
+        count: usize,
+    },
+    /// Starts recording a macro into a register.
+    Record(char),
+    StopRecording,
+    /// Plays the macro in a register, or the one played last without one.
+    Play {
+        register: Option<char>,
+        count: usize,
+    },
+    /// A key the modes leave to the usual bindings, like the arrows and Ctrl keys, or any key
+    /// in insert mode.
+    Pass(Key),
+}
+
+/// Vi-like modes on top of the usual keys: turns the keys typed into commands for the editor
+/// to carry out, keeping track of the mode and of counts and operators typed so far.
+#[derive(Debug, Default)]
+pub struct Modal {
+    mode: Mode,
+    count: Option<usize>,
+    /// Prefix waiting for the key that completes it, like the `g` of `gg` or the `f` of `fx`.
+    pending: Option<char>,
+    /// Operator waiting for what it applies to, with the count typed before it.
+    operator: Option<(char, Option<usize>)>,
+    /// Last search for a character on the line, for `;` and `,` to repeat.
+    last_search: Option<CharSearch>,
+}
+
+impl Modal {
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+        self.count = None;
+        self.pending = None;
+        self.operator = None;
+    }
+
+    /// Keys typed towards a command that isn't complete yet, for the status bar.
+    pub fn pending(&self) -> String {
+        let count = |count: Option<usize>| count.map(|count| count.to_string()).unwrap_or_default();
+        let operator = self
+            .operator
+            .map(|(operator, before)| format!("{}{}", count(before), operator))
+            .unwrap_or_default();
+        format!(
+            "{}{}{}",
+            operator,
+            count(self.count),
+            self.pending.map(String::from).unwrap_or_default()
+        )
+    }
+
+    /// The command `key` completes, if any, with `q` stopping the macro being `recording`.
+    /// Keys that mean nothing in the mode are dropped rather than typed.
+    pub fn key(&mut self, key: Key, recording: bool) -> Option<Command> {
+        if self.mode == Mode::Insert {
+            return Some(match key {
+                Key::Escape => {
+                    self.set_mode(Mode::Normal);
+                    Command::Normal
+                }
+                key => Command::Pass(key),
+            });
+        }
+        let ch = match key {
+            Key::Printable(ch) => ch,
+            Key::Escape
+                if self.count.is_some() || self.pending.is_some() || self.operator.is_some() =>
+            {
+                self.set_mode(self.mode);
+                return None;
+            }
+            Key::Escape if self.mode == Mode::Visual => {
+                self.set_mode(Mode::Normal);
+                return Some(Command::Normal);
+            }
+            Key::Control('R') => {
+                let count = self.count.take().unwrap_or(1);
+                self.set_mode(self.mode);
+                return Some(Command::Redo(count));
+            }
+            // Terminals send Ctrl-I as Tab.
+            Key::Control('O') | Key::Tab => {
+                let count = self.count.take().unwrap_or(1);
+                self.set_mode(self.mode);
+                return Some(Command::Jump {
+                    back: key == Key::Control('O'),
+                    count,
+                });
+            }
+            Key::Control(ch @ ('D' | 'U')) => {
+                let count = self.count.take();
+                self.set_mode(self.mode);
+                return Some(Command::Scroll {
+                    up: ch == 'U',
+                    count,
+                });
+            }
+            key => {
+                self.set_mode(self.mode);
+                return Some(Command::Pass(key));
+            }
+        };
+        // After a prefix, a digit is what the prefix asks for, like the character to find.
+        let digit = ch.to_digit(10).filter(|&d| d > 0 || self.count.is_some());
+        if let Some(digit) = digit.filter(|_| self.pending.is_none()) {
+            let count = self.count.unwrap_or(0).saturating_mul(10);
+            self.count = Some(count.saturating_add(digit as usize));
+            return None;
+        }
+        let pending = self.pending.take();
+        let visual = self.mode == Mode::Visual;
+        let given = self.count.take();
+        let count = given.unwrap_or(1);
+        if let Some((operator, before)) = self.operator.take() {
+            return self.operand(operator, before, pending, ch, given);
+        }
+        if let Some(motion) = motion(ch).filter(|_| pending.is_none()) {
+            let count = if "0$".contains(ch) { 1 } else { count };
+            return Some(Command::Move(motion, count));
+        }
+        let command = match (pending, ch) {
+            (Some(find @ ('f' | 't' | 'F' | 'T')), ch) => {
+                Command::FindChar(self.char_search(find, ch), count)
+            }
+            (Some('g'), 'g') => Command::GotoLine(Some(count)),
+            (Some('z'), 'z') => Command::Align(ViewAlign::Center),
+            (Some('z'), 't') => Command::Align(ViewAlign::Top),
+            (Some('z'), 'b') => Command::Align(ViewAlign::Bottom),
+            (Some('m'), name) => Command::SetMark(name),
+            (Some(mark @ ('\'' | '`')), name) => Command::GotoMark {
+                name,
+                line: mark == '\'',
+            },
+            (Some('q'), register) => Command::Record(register),
+            (Some('@'), '@') => Command::Play {
+                register: None,
+                count,
+            },
+            (Some('@'), register) => Command::Play {
+                register: Some(register),
+                count,
+            },
+            (Some(_), _) => return None,
+            (None, 'd' | 'x') if visual => {
+                self.mode = Mode::Normal;
+                Command::DeleteSelection
+            }
+            (None, 'y') if visual => {
+                self.mode = Mode::Normal;
+                Command::YankSelection
+            }
+            (None, 'c') if visual => {
+                self.mode = Mode::Insert;
+                Command::ChangeSelection
+            }
+            (None, 'q') if recording => Command::StopRecording,
+            (None, 'd' | 'c' | 'y') => {
+                self.operator = Some((ch, given));
+                return None;
+            }
+            (None, 'f' | 't' | 'F' | 'T' | 'g' | 'z' | 'm' | '\'' | '`' | 'q' | '@') => {
+                self.pending = Some(ch);
+                self.count = given;
+                return None;
+            }
+            (None, ';' | ',') => Command::FindChar(self.repeat_search(ch == ';')?, count),
+            (None, 'G') => Command::GotoLine(given),
+            (None, 'v') if visual => {
+                self.mode = Mode::Normal;
+                Command::Normal
+            }
+            (None, 'v') => {
+                self.mode = Mode::Visual;
+                Command::Visual
+            }
+            (None, _) if visual && "iaIAoO".contains(ch) => return None,
+            (None, 'i') => self.insert(InsertAt::Cursor),
+            (None, 'a') => self.insert(InsertAt::AfterCursor),
+            (None, 'I') => self.insert(InsertAt::LineStart),
+            (None, 'A') => self.insert(InsertAt::LineEnd),
+            (None, 'o') => self.insert(InsertAt::LineBelow),
+            (None, 'O') => self.insert(InsertAt::LineAbove),
+            (None, 'x') => Command::DeleteChars(count),
+            (None, 'p') => Command::Put {
+                before: false,
+                count,
+            },
+            (None, 'P') => Command::Put {
+                before: true,
+                count,
+            },
+            (None, 'u') => Command::Undo(count),
+            (None, '/') => Command::Search { forward: true },
+            (None, '?') => Command::Search { forward: false },
+            (None, 'n') => Command::SearchNext { forward: true },
+            (None, 'N') => Command::SearchNext { forward: false },
+            (None, ':') => Command::CommandLine,
+            (None, _) => return None,
+        };
+        Some(command)
+    }
+
+    fn insert(&mut self, at: InsertAt) -> Command {
+        self.mode = Mode::Insert;
+        Command::Insert(at)
+    }
+
+    /// The command `ch` completes for the `operator` key typed after the count `before`, if
+    /// it's what the operator applies to. Counts before and after the operator multiply.
+    fn operand(
+        &mut self,
+        operator: char,
+        before: Option<usize>,
+        pending: Option<char>,
+        ch: char,
+        given: Option<usize>,
+    ) -> Option<Command> {
+        let count = before.unwrap_or(1).saturating_mul(given.unwrap_or(1));
+        let target = match (pending, ch) {
+            (Some(kind @ ('i' | 'a')), object) => Target::Object(format!("{}{}", kind, object)),
+            (Some(find), ch) => Target::Char(self.char_search(find, ch), count),
+            (None, 'i' | 'a' | 'f' | 't' | 'F' | 'T') => {
+                self.operator = Some((operator, before));
+                self.pending = Some(ch);
+                self.count = given;
+                return None;
+            }
+            (None, ';' | ',') => Target::Char(self.repeat_search(ch == ';')?, count),
+            (None, ch) if ch == operator => Target::Lines(count),
+            (None, '0' | '$') => Target::Motion(motion(ch)?, 1),
+            (None, ch) => Target::Motion(motion(ch)?, count),
+        };
+        let operator = match operator {
+            'd' => Operator::Delete,
+            'c' => {
+                self.mode = Mode::Insert;
+                Operator::Change
+            }
+            _ => Operator::Yank,
+        };
+        Some(Command::Operate(operator, target))
+    }
+
+    /// Search for `ch` that the `find` key starts, kept for `;` and `,` to repeat.
+    fn char_search(&mut self, find: char, ch: char) -> CharSearch {
+        let search = CharSearch {
+            ch,
+            forward: find.is_lowercase(),
+            till: find.eq_ignore_ascii_case(&'t'),
+        };
+        self.last_search = Some(search);
+        search
+    }
+
+    /// The last search for a character, the same way or the other way unless `same`.
+    fn repeat_search(&self, same: bool) -> Option<CharSearch> {
+        self.last_search.map(|search| CharSearch {
+            forward: search.forward == same,
+            ..search
+        })
+    }
+}
+
+/// Motion the key `ch` moves by in normal and visual mode, and for operators.
+fn motion(ch: char) -> Option<Motion> {
+    Some(match ch {
+        'h' => Motion::Left,
+        'j' => Motion::Down,
+        'k' => Motion::Up,
+        'l' => Motion::Right,
+        'w' => Motion::WordForward,
+        'b' => Motion::WordBackward,
+        '{' => Motion::ParagraphUp,
+        '}' => Motion::ParagraphDown,
+        '0' => Motion::Home,
+        '$' => Motion::End,
+        _ => return None,
+    })
+}
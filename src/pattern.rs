@@ -0,0 +1,594 @@
+use crate::region::is_word_char;
+
+/// What a search looks for: plain text, or a regular expression.
+#[derive(Clone, Debug, Default)]
+pub struct Pattern {
+    /// The query as typed.
+    pub text: String,
+    /// Only count matches that aren't part of a longer word.
+    pub whole_word: bool,
+    /// The regular expression compiled into steps.
+    regex: Option<Vec<Inst>>,
+}
+
+impl Pattern {
+    /// Pattern looking for `text` as it is.
+    pub fn literal(text: &str, whole_word: bool) -> Self {
+        Pattern {
+            text: text.to_string(),
+            whole_word,
+            regex: None,
+        }
+    }
+
+    /// Pattern looking for `text` as a regular expression with `regex`, or as it is.
+    pub fn new(text: &str, whole_word: bool, regex: bool) -> Result<Self, String> {
+        if regex {
+            Self::regex(text, whole_word)
+        } else {
+            Ok(Self::literal(text, whole_word))
+        }
+    }
+
+    /// Pattern looking for matches of the regular expression `text`, or what's wrong with it.
+    ///
+    /// The syntax is the usual core: `.`, `[...]` and `[^...]` classes, `\d \w \s` and their
+    /// capitals, `^ $ \b` anchors, `* + ? {n} {n,} {n,m}` with a trailing `?` to match as
+    /// little as possible, `|` and `( )` groups.
+    pub fn regex(text: &str, whole_word: bool) -> Result<Self, String> {
+        let mut parser = Parser {
+            chars: text.chars().collect(),
+            pos: 0,
+        };
+        let alternatives = parser.alternatives()?;
+        if parser.pos < parser.chars.len() {
+            return Err("unmatched )".to_string());
+        }
+        Ok(Pattern {
+            text: text.to_string(),
+            whole_word,
+            regex: Some(compile(&alternatives)?),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    pub fn is_regex(&self) -> bool {
+        self.regex.is_some()
+    }
+
+    /// Byte ranges of the matches in `haystack`, from left to right and not overlapping. A
+    /// regular expression can match nothing at all, like `^` does, so a range can be empty;
+    /// the search goes on a character past it, and not right where a longer match ended.
+    pub fn find_iter(&self, haystack: &str) -> Vec<(usize, usize)> {
+        let found = match &self.regex {
+            None if self.text.is_empty() => Vec::new(),
+            None => haystack
+                .match_indices(self.text.as_str())
+                .map(|(idx, found)| (idx, idx + found.len()))
+                .collect(),
+            Some(program) => {
+                let mut found = Vec::new();
+                let mut steps = MAX_STEPS;
+                let mut from = 0;
+                let mut last_end = None;
+                while from <= haystack.len() {
+                    let Some((start, end)) = find(program, haystack, from, &mut steps) else {
+                        break;
+                    };
+                    if start < end || last_end != Some(end) {
+                        found.push((start, end));
+                        last_end = Some(end);
+                    }
+                    from = if start < end {
+                        end
+                    } else {
+                        end + haystack[end..].chars().next().map_or(1, char::len_utf8)
+                    };
+                }
+                found
+            }
+        };
+        found
+            .into_iter()
+            .filter(|&(start, end)| {
+                !self.whole_word
+                    || !(haystack[..start]
+                        .chars()
+                        .next_back()
+                        .is_some_and(is_word_char)
+                        || haystack[end..].chars().next().is_some_and(is_word_char))
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    Char(char),
+    Any,
+    Class {
+        ranges: Vec<(char, char)>,
+        negated: bool,
+    },
+    Start,
+    End,
+    WordBoundary,
+    Group(Vec<Vec<Node>>),
+    Repeat {
+        node: Box<Node>,
+        min: usize,
+        max: Option<usize>,
+        greedy: bool,
+    },
+}
+
+const DIGITS: &[(char, char)] = &[('0', '9')];
+const WORD: &[(char, char)] = &[('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')];
+const SPACE: &[(char, char)] = &[(' ', ' '), ('\t', '\r')];
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let ch = self.peek();
+        self.pos += 1;
+        ch
+    }
+
+    /// Branches separated by `|`, up to a `)` or the end.
+    fn alternatives(&mut self) -> Result<Vec<Vec<Node>>, String> {
+        let mut alternatives = vec![Vec::new()];
+        while let Some(ch) = self.peek() {
+            match ch {
+                ')' => break,
+                '|' => {
+                    self.pos += 1;
+                    alternatives.push(Vec::new());
+                }
+                _ => {
+                    let atom = self.atom()?;
+                    let node = self.quantified(atom)?;
+                    alternatives.last_mut().expect("one branch").push(node);
+                }
+            }
+        }
+        Ok(alternatives)
+    }
+
+    fn atom(&mut self) -> Result<Node, String> {
+        Ok(match self.next().expect("peeked") {
+            '.' => Node::Any,
+            '^' => Node::Start,
+            '$' => Node::End,
+            '(' => {
+                let alternatives = self.alternatives()?;
+                if self.next() != Some(')') {
+                    return Err("unclosed (".to_string());
+                }
+                Node::Group(alternatives)
+            }
+            '[' => self.class()?,
+            '\\' => match self.next().ok_or("trailing \\")? {
+                'b' => Node::WordBoundary,
+                escape => match class_escape(escape) {
+                    Some((ranges, negated)) => Node::Class {
+                        ranges: ranges.to_vec(),
+                        negated,
+                    },
+                    None => Node::Char(literal_escape(escape)),
+                },
+            },
+            ch @ ('*' | '+' | '?') => return Err(format!("nothing to repeat before {}", ch)),
+            ch => Node::Char(ch),
+        })
+    }
+
+    /// The rest of a `[...]` class, after the `[`.
+    fn class(&mut self) -> Result<Node, String> {
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.pos += 1;
+        }
+        let mut ranges = Vec::new();
+        let mut first = true;
+        loop {
+            let ch = self.next().ok_or("unclosed [")?;
+            if ch == ']' && !first {
+                break;
+            }
+            first = false;
+            let low = if ch == '\\' {
+                let escape = self.next().ok_or("unclosed [")?;
+                if let Some((class, false)) = class_escape(escape) {
+                    ranges.extend_from_slice(class);
+                    continue;
+                }
+                literal_escape(escape)
+            } else {
+                ch
+            };
+            let high = if self.peek() == Some('-') && self.chars.get(self.pos + 1) != Some(&']') {
+                self.pos += 1;
+                match self.next().ok_or("unclosed [")? {
+                    '\\' => literal_escape(self.next().ok_or("unclosed [")?),
+                    high => high,
+                }
+            } else {
+                low
+            };
+            if high < low {
+                return Err(format!("bad range {}-{}", low, high));
+            }
+            ranges.push((low, high));
+        }
+        Ok(Node::Class { ranges, negated })
+    }
+
+    /// `atom` with any repetition that follows it.
+    fn quantified(&mut self, atom: Node) -> Result<Node, String> {
+        let (min, max) = match self.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') => match self.counts() {
+                Some(counts) => return self.repeat(atom, counts),
+                None => return Ok(atom),
+            },
+            _ => return Ok(atom),
+        };
+        self.pos += 1;
+        self.repeat(atom, (min, max))
+    }
+
+    fn repeat(&mut self, atom: Node, (min, max): (usize, Option<usize>)) -> Result<Node, String> {
+        if matches!(atom, Node::Start | Node::End | Node::WordBoundary) {
+            return Err("nothing to repeat".to_string());
+        }
+        let greedy = self.peek() != Some('?');
+        if !greedy {
+            self.pos += 1;
+        }
+        Ok(Node::Repeat {
+            node: Box::new(atom),
+            min,
+            max,
+            greedy,
+        })
+    }
+
+    /// Reads `{n}`, `{n,}` or `{n,m}`, leaving a `{` that starts none of them as a literal.
+    fn counts(&mut self) -> Option<(usize, Option<usize>)> {
+        let close = self.chars[self.pos..].iter().position(|&ch| ch == '}')?;
+        let inside = self.chars[self.pos + 1..self.pos + close]
+            .iter()
+            .collect::<String>();
+        let counts = match inside.split_once(',') {
+            None => {
+                let count = inside.parse().ok()?;
+                (count, Some(count))
+            }
+            Some((min, "")) => (min.parse().ok()?, None),
+            Some((min, max)) => (min.parse().ok()?, Some(max.parse().ok()?)),
+        };
+        if counts.1.is_some_and(|max| max < counts.0) {
+            return None;
+        }
+        self.pos += close + 1;
+        Some(counts)
+    }
+}
+
+/// Ranges of `\d`, `\w` and `\s`, or of what their capitals leave out.
+fn class_escape(escape: char) -> Option<(&'static [(char, char)], bool)> {
+    match escape {
+        'd' => Some((DIGITS, false)),
+        'w' => Some((WORD, false)),
+        's' => Some((SPACE, false)),
+        'D' => Some((DIGITS, true)),
+        'W' => Some((WORD, true)),
+        'S' => Some((SPACE, true)),
+        _ => None,
+    }
+}
+
+fn literal_escape(escape: char) -> char {
+    match escape {
+        'n' => '\n',
+        't' => '\t',
+        ch => ch,
+    }
+}
+
+/// A step of a compiled pattern, as run by `find`.
+#[derive(Clone, Debug)]
+enum Inst {
+    /// Takes one character that matches the node, which is a `Char`, `Any` or `Class`.
+    Take(Node),
+    Start,
+    End,
+    WordBoundary,
+    /// Goes on at both places, the first preferred.
+    Split(usize, usize),
+    Jump(usize),
+    Match,
+}
+
+/// Steps a pattern compiles to at most, which counted repeats multiply.
+const MAX_PROGRAM: usize = 10_000;
+/// Steps taken at most looking through one line, after which the matches found so far are
+/// all there is. Each character costs at most a step per part of the pattern, so only huge
+/// patterns on long lines come near.
+const MAX_STEPS: usize = 50_000_000;
+
+/// Compiles the branches into steps, with a `Match` at the end.
+fn compile(alternatives: &[Vec<Node>]) -> Result<Vec<Inst>, String> {
+    let mut program = Vec::new();
+    emit_alternatives(&mut program, alternatives)?;
+    program.push(Inst::Match);
+    Ok(program)
+}
+
+fn emit_alternatives(program: &mut Vec<Inst>, alternatives: &[Vec<Node>]) -> Result<(), String> {
+    let mut jumps = Vec::new();
+    for (idx, nodes) in alternatives.iter().enumerate() {
+        let last = idx + 1 == alternatives.len();
+        let split = program.len();
+        if !last {
+            program.push(Inst::Split(split + 1, 0));
+        }
+        for node in nodes {
+            emit(program, node)?;
+        }
+        if !last {
+            jumps.push(program.len());
+            program.push(Inst::Jump(0));
+            let next = program.len();
+            program[split] = Inst::Split(split + 1, next);
+        }
+    }
+    let end = program.len();
+    for jump in jumps {
+        program[jump] = Inst::Jump(end);
+    }
+    Ok(())
+}
+
+fn emit(program: &mut Vec<Inst>, node: &Node) -> Result<(), String> {
+    if program.len() > MAX_PROGRAM {
+        return Err("pattern too big".to_string());
+    }
+    match node {
+        Node::Start => program.push(Inst::Start),
+        Node::End => program.push(Inst::End),
+        Node::WordBoundary => program.push(Inst::WordBoundary),
+        Node::Group(alternatives) => emit_alternatives(program, alternatives)?,
+        Node::Repeat {
+            node,
+            min,
+            max,
+            greedy,
+        } => {
+            for _ in 0..*min {
+                emit(program, node)?;
+            }
+            let split = |body, out| {
+                if *greedy {
+                    Inst::Split(body, out)
+                } else {
+                    Inst::Split(out, body)
+                }
+            };
+            match max {
+                None => {
+                    let start = program.len();
+                    program.push(Inst::Jump(0));
+                    emit(program, node)?;
+                    program.push(Inst::Jump(start));
+                    program[start] = split(start + 1, program.len());
+                }
+                Some(max) => {
+                    let mut splits = Vec::new();
+                    for _ in *min..*max {
+                        splits.push(program.len());
+                        program.push(Inst::Jump(0));
+                        emit(program, node)?;
+                    }
+                    let out = program.len();
+                    for at in splits {
+                        program[at] = split(at + 1, out);
+                    }
+                }
+            }
+        }
+        single => program.push(Inst::Take(single.clone())),
+    }
+    Ok(())
+}
+
+/// Threads of a search, each at a step of the program and with where its match started, in
+/// order of preference. Each step is there once, for the thread that got to it first.
+struct Threads {
+    list: Vec<(usize, usize)>,
+    seen: Vec<bool>,
+}
+
+impl Threads {
+    fn new(len: usize) -> Self {
+        Threads {
+            list: Vec::new(),
+            seen: vec![false; len],
+        }
+    }
+
+    fn clear(&mut self) {
+        for &(pc, _) in &self.list {
+            self.seen[pc] = false;
+        }
+        self.list.clear();
+    }
+}
+
+/// Follows the steps that take no character from `pc`, at `pos` in `text`, adding a thread
+/// for each `Take` and `Match` reached, most preferred first.
+fn add_thread(
+    program: &[Inst],
+    threads: &mut Threads,
+    text: &str,
+    pos: usize,
+    pc: usize,
+    start: usize,
+) {
+    let mut stack = vec![pc];
+    while let Some(pc) = stack.pop() {
+        if threads.seen[pc] {
+            continue;
+        }
+        threads.seen[pc] = true;
+        // Steps that take no character are kept in the list too, only to be unmarked on
+        // clearing.
+        threads.list.push((pc, start));
+        match &program[pc] {
+            Inst::Jump(to) => stack.push(*to),
+            Inst::Split(first, second) => {
+                stack.push(*second);
+                stack.push(*first);
+            }
+            Inst::Start if pos == 0 => stack.push(pc + 1),
+            Inst::End if pos == text.len() => stack.push(pc + 1),
+            Inst::WordBoundary => {
+                let before = text[..pos].chars().next_back().is_some_and(is_word_char);
+                let after = text[pos..].chars().next().is_some_and(is_word_char);
+                if before != after {
+                    stack.push(pc + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The leftmost match in `text` starting at `from` or later, preferring earlier branches and
+/// longer greedy or shorter lazy repeats, as a backtracking search would. `steps` counts down
+/// the budget, and no match is found once it runs out.
+fn find(program: &[Inst], text: &str, from: usize, steps: &mut usize) -> Option<(usize, usize)> {
+    let mut current = Threads::new(program.len());
+    let mut next = Threads::new(program.len());
+    let mut found = None;
+    let mut pos = from;
+    loop {
+        // A match found earlier rules out any that would start later.
+        if found.is_none() {
+            add_thread(program, &mut current, text, pos, 0, pos);
+        }
+        if current.list.is_empty() {
+            break;
+        }
+        let ch = text[pos..].chars().next();
+        for idx in 0..current.list.len() {
+            *steps = steps.checked_sub(1)?;
+            let (pc, start) = current.list[idx];
+            match &program[pc] {
+                Inst::Match => {
+                    found = Some((start, pos));
+                    // The rest are less preferred than this one.
+                    break;
+                }
+                Inst::Take(node) => {
+                    if let Some(ch) = ch.filter(|&ch| single_matches(node, ch)) {
+                        add_thread(program, &mut next, text, pos + ch.len_utf8(), pc + 1, start);
+                    }
+                }
+                _ => {}
+            }
+        }
+        let Some(ch) = ch else {
+            break;
+        };
+        pos += ch.len_utf8();
+        current.clear();
+        std::mem::swap(&mut current, &mut next);
+    }
+    found
+}
+
+fn single_matches(node: &Node, ch: char) -> bool {
+    match node {
+        Node::Char(expected) => ch == *expected,
+        Node::Any => true,
+        Node::Class { ranges, negated } => {
+            ranges.iter().any(|&(low, high)| (low..=high).contains(&ch)) != *negated
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Texts of the matches of the regular expression `regex` in `haystack`.
+    fn matches<'a>(regex: &str, haystack: &'a str) -> Vec<&'a str> {
+        let pattern = Pattern::regex(regex, false).expect("a valid pattern");
+        pattern
+            .find_iter(haystack)
+            .into_iter()
+            .map(|(start, end)| &haystack[start..end])
+            .collect()
+    }
+
+    #[test]
+    fn classes_and_repeats() {
+        assert_eq!(matches(r"\d+", "a1 22 333"), ["1", "22", "333"]);
+        assert_eq!(matches("[a-c]{2}", "abcab"), ["ab", "ca"]);
+        assert_eq!(matches("[^ ]+", " é x "), ["é", "x"]);
+        assert_eq!(matches("a.*b", "aXbYb"), ["aXbYb"]);
+        assert_eq!(matches("a.*?b", "aXbYb"), ["aXb"]);
+        assert_eq!(matches("x{2,3}", "xxxxxxx"), ["xxx", "xxx"]);
+        assert_eq!(matches("(ab)+c?", "ababcab"), ["ababc", "ab"]);
+    }
+
+    #[test]
+    fn branches_and_anchors() {
+        // The first branch that matches wins, as in a backtracking search.
+        assert_eq!(matches("a|ab", "ab"), ["a"]);
+        assert_eq!(matches("(ab|a)c", "ac abc"), ["ac", "abc"]);
+        assert_eq!(matches("^a", "aa"), ["a"]);
+        assert_eq!(matches("a$", "aa"), ["a"]);
+        assert_eq!(matches(r"\bx\w*", "xy axe x"), ["xy", "x"]);
+        assert_eq!(matches("(a*)*b", "aab"), ["aab"]);
+    }
+
+    #[test]
+    fn empty_matches() {
+        let ranges = |regex: &str, haystack: &str| {
+            let pattern = Pattern::regex(regex, false).expect("a valid pattern");
+            pattern.find_iter(haystack)
+        };
+        assert_eq!(ranges("^", "ab"), [(0, 0)]);
+        assert_eq!(ranges("$", "ab"), [(2, 2)]);
+        assert_eq!(ranges("$", ""), [(0, 0)]);
+        assert_eq!(ranges(r"\b", "ab é"), [(0, 0), (2, 2), (3, 3), (5, 5)]);
+        assert_eq!(ranges("x*", "axxb"), [(0, 0), (1, 3), (4, 4)]);
+    }
+
+    #[test]
+    fn long_lines_and_bad_patterns() {
+        let line = "x".repeat(100_000);
+        assert!(matches("x*y", &line).is_empty());
+        assert_eq!(matches("x*", &line).len(), 1);
+        assert!(Pattern::regex("(a", false).is_err());
+        assert!(Pattern::regex("a)", false).is_err());
+        assert!(Pattern::regex("*a", false).is_err());
+        assert!(Pattern::regex("(a{1000}){1000}", false).is_err());
+    }
+}
@@ -0,0 +1,73 @@
+use crate::buffer::Cursor;
+use std::fs;
+use std::io::Result;
+use std::path::{Path, PathBuf};
+
+/// Name of the file in the home directory that keeps the positions.
+const POSITIONS_FILE: &str = ".milo_positions";
+/// Files remembered at most; the least recently edited ones are forgotten first.
+const MAX_POSITIONS: usize = 100;
+
+fn positions_file() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| Path::new(&home).join(POSITIONS_FILE))
+}
+
+/// Entries of the positions file, most recent first. Each line holds the cursor row and
+/// column, the row and column offsets, and the file's absolute path, separated by tabs.
+fn read_entries() -> Vec<(Cursor, PathBuf)> {
+    let content = match positions_file().and_then(|path| fs::read_to_string(path).ok()) {
+        Some(content) => content,
+        None => return Vec::new(),
+    };
+    content
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(5, '\t');
+            let mut number = || fields.next()?.parse::<usize>().ok();
+            let cursor = Cursor {
+                cursor_row: number()?,
+                cursor_col: number()?,
+                row_offset: number()?,
+                col_offset: number()?,
+                ..Cursor::default()
+            };
+            Some((cursor, PathBuf::from(fields.next()?)))
+        })
+        .collect()
+}
+
+/// Where the cursor was when `file` was last edited.
+pub fn load(file: &Path) -> Option<Cursor> {
+    let file = file.canonicalize().ok()?;
+    read_entries()
+        .into_iter()
+        .find(|(_, path)| *path == file)
+        .map(|(cursor, _)| cursor)
+}
+
+/// Remembers `cursor` as the last position in `file`. Files that don't exist on disk are
+/// skipped, since they have nothing to come back to.
+pub fn store(file: &Path, cursor: Cursor) -> Result<()> {
+    let (positions, file) = match (positions_file(), file.canonicalize()) {
+        (Some(positions), Ok(file)) => (positions, file),
+        _ => return Ok(()),
+    };
+    let mut entries = read_entries();
+    entries.retain(|(_, path)| *path != file);
+    entries.insert(0, (cursor, file));
+    entries.truncate(MAX_POSITIONS);
+    let content = entries
+        .iter()
+        .map(|(cursor, path)| {
+            format!(
+                "{}\t{}\t{}\t{}\t{}\n",
+                cursor.cursor_row,
+                cursor.cursor_col,
+                cursor.row_offset,
+                cursor.col_offset,
+                path.display()
+            )
+        })
+        .collect::<String>();
+    fs::write(positions, content)
+}
@@ -0,0 +1,383 @@
+use crate::buffer::Buffer;
+use crate::highlight::Highlight;
+
+/// A (row, col) location in the buffer.
+pub type Position = (usize, usize);
+
+/// Brackets, each with its opener first.
+pub const PAIRS: [(char, char); 3] = [('(', ')'), ('[', ']'), ('{', '}')];
+const QUOTES: [char; 3] = ['"', '\'', '`'];
+/// How many lines away from a bracket its match is looked for.
+const MAX_BRACKET_LINES: usize = 2_000;
+
+/// A span of buffer text from `start` up to, but not including, `end`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Region {
+    pub fn new(start: Position, end: Position) -> Self {
+        Self { start, end }
+    }
+
+    pub fn at(pos: Position) -> Self {
+        Self::new(pos, pos)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    pub fn contains(&self, other: &Region) -> bool {
+        self.start <= other.start && other.end <= self.end
+    }
+}
+
+pub fn is_word_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_'
+}
+
+fn chars_after(
+    buffer: &Buffer,
+    (row, col): Position,
+) -> impl Iterator<Item = (Position, char)> + '_ {
+    (row..buffer.line_count()).flat_map(move |r| {
+        let skip = if r == row { col } else { 0 };
+        buffer
+            .line(r)
+            .map(|line| line.content())
+            .unwrap_or_default()
+            .char_indices()
+            .filter(move |&(c, _)| c >= skip)
+            .map(move |(c, ch)| ((r, c), ch))
+    })
+}
+
+fn chars_before(
+    buffer: &Buffer,
+    (row, col): Position,
+) -> impl Iterator<Item = (Position, char)> + '_ {
+    (0..=row.min(buffer.line_count())).rev().flat_map(move |r| {
+        let take = if r == row { col } else { usize::MAX };
+        buffer
+            .line(r)
+            .map(|line| line.content())
+            .unwrap_or_default()
+            .char_indices()
+            .rev()
+            .filter(move |&(c, _)| c < take)
+            .map(move |(c, ch)| ((r, c), ch))
+    })
+}
+
+/// The word touching `pos`, if any.
+pub fn word(buffer: &Buffer, (row, col): Position) -> Option<Region> {
+    let line = buffer.line(row)?.content();
+    let start = line
+        .get(..col)?
+        .char_indices()
+        .rev()
+        .take_while(|&(_, ch)| is_word_char(ch))
+        .last()
+        .map_or(col, |(c, _)| c);
+    let end = line[col..]
+        .char_indices()
+        .find(|&(_, ch)| !is_word_char(ch))
+        .map_or(line.len(), |(c, _)| col + c);
+    if start == end {
+        None
+    } else {
+        Some(Region::new((row, start), (row, end)))
+    }
+}
+
+/// The innermost `quote`-delimited string on a single line enclosing `region`. With `around`,
+/// the quotes themselves are included.
+pub fn quoted(buffer: &Buffer, region: Region, quote: char, around: bool) -> Option<Region> {
+    let row = region.start.0;
+    if region.end.0 != row {
+        return None;
+    }
+    let line = buffer.line(row)?.content();
+    let mut escaped = false;
+    let quotes = line
+        .char_indices()
+        .filter(|&(_, ch)| {
+            let is_quote = ch == quote && !escaped;
+            escaped = ch == '\\' && !escaped;
+            is_quote
+        })
+        .map(|(c, _)| c)
+        .collect::<Vec<usize>>();
+
+    quotes
+        .chunks_exact(2)
+        .map(|pair| {
+            if around {
+                Region::new((row, pair[0]), (row, pair[1] + 1))
+            } else {
+                Region::new((row, pair[0] + 1), (row, pair[1]))
+            }
+        })
+        .find(|quoted| quoted.contains(&region))
+}
+
+/// The innermost `open`/`close` pair enclosing `region`, possibly spanning lines. With `around`,
+/// the delimiters themselves are included.
+pub fn bracketed(
+    buffer: &Buffer,
+    region: Region,
+    (open, close): (char, char),
+    around: bool,
+) -> Option<Region> {
+    let mut depth = 0;
+    let (open_pos, _) = chars_before(buffer, region.start).find(|&(_, ch)| {
+        if ch == close {
+            depth += 1;
+        } else if ch == open {
+            if depth == 0 {
+                return true;
+            }
+            depth -= 1;
+        }
+        false
+    })?;
+
+    let mut depth = 0;
+    let (close_pos, _) = chars_after(buffer, region.end).find(|&(_, ch)| {
+        if ch == open {
+            depth += 1;
+        } else if ch == close {
+            if depth == 0 {
+                return true;
+            }
+            depth -= 1;
+        }
+        false
+    })?;
+
+    Some(if around {
+        Region::new(open_pos, (close_pos.0, close_pos.1 + close.len_utf8()))
+    } else {
+        Region::new((open_pos.0, open_pos.1 + open.len_utf8()), close_pos)
+    })
+}
+
+/// The bracket paired with the one at `pos`, counting the pairs of the same kind nested in
+/// between. Brackets in strings and comments, as the filetype highlights them, only pair with
+/// others in strings and comments, so that a `)` in a string doesn't end the call it's passed
+/// to.
+pub fn matching_bracket(buffer: &Buffer, pos: Position) -> Option<Position> {
+    let ch = buffer.line(pos.0)?.content().get(pos.1..)?.chars().next()?;
+    let &(open, close) = PAIRS
+        .iter()
+        .find(|&&(open, close)| ch == open || ch == close)?;
+    let mut highlights: Option<(usize, Vec<Highlight>)> = None;
+    let mut quoted = |(row, col): Position| {
+        if highlights.as_ref().is_none_or(|(cached, _)| *cached != row) {
+            highlights = Some((row, buffer.line_highlights(row)));
+        }
+        let render = buffer
+            .line(row)
+            .map_or(0, |line| line.cursor_to_render_position(col));
+        let highlight = highlights.as_ref().and_then(|(_, found)| found.get(render));
+        matches!(highlight, Some(Highlight::String | Highlight::Comment))
+    };
+    let inside = quoted(pos);
+    let (chars, same, other): (Box<dyn Iterator<Item = (Position, char)>>, char, char) =
+        if ch == open {
+            let after = (pos.0, pos.1 + open.len_utf8());
+            (Box::new(chars_after(buffer, after)), open, close)
+        } else {
+            (Box::new(chars_before(buffer, pos)), close, open)
+        };
+    let mut depth = 0;
+    for (at, ch) in chars {
+        if at.0.abs_diff(pos.0) > MAX_BRACKET_LINES {
+            break;
+        }
+        if (ch != same && ch != other) || quoted(at) != inside {
+            continue;
+        }
+        if ch == same {
+            depth += 1;
+        } else if depth == 0 {
+            return Some(at);
+        } else {
+            depth -= 1;
+        }
+    }
+    None
+}
+
+fn class(ch: char) -> u8 {
+    if ch.is_whitespace() {
+        0
+    } else if is_word_char(ch) {
+        1
+    } else {
+        2
+    }
+}
+
+/// The run of characters of the same kind (word, blank or punctuation) under `pos`.
+fn run(buffer: &Buffer, (row, col): Position) -> Option<Region> {
+    let line = buffer.line(row)?.content();
+    let kind = class(line.get(col..)?.chars().next()?);
+    let start = line[..col]
+        .char_indices()
+        .rev()
+        .take_while(|&(_, ch)| class(ch) == kind)
+        .last()
+        .map_or(col, |(c, _)| c);
+    let end = line[col..]
+        .char_indices()
+        .find(|&(_, ch)| class(ch) != kind)
+        .map_or(line.len(), |(c, _)| col + c);
+    Some(Region::new((row, start), (row, end)))
+}
+
+fn word_object(buffer: &Buffer, pos: Position, around: bool) -> Option<Region> {
+    let inner = run(buffer, pos)?;
+    if !around {
+        return Some(inner);
+    }
+    let line = buffer.line(pos.0)?.content();
+    let blank_at = |col: usize| line[col..].starts_with(char::is_whitespace);
+    if blank_at(inner.start.1) {
+        // Blanks take the word after them along.
+        return Some(
+            run(buffer, inner.end).map_or(inner, |next| Region::new(inner.start, next.end)),
+        );
+    }
+    match run(buffer, inner.end) {
+        Some(next) if blank_at(next.start.1) => Some(Region::new(inner.start, next.end)),
+        _ => {
+            let before = line[..inner.start.1].trim_end().len();
+            Some(Region::new((pos.0, before), inner.end))
+        }
+    }
+}
+
+fn paragraph_object(buffer: &Buffer, row: usize, around: bool) -> Option<Region> {
+    let blank = |row: usize| {
+        buffer
+            .line(row)
+            .map(|line| line.content().trim().is_empty())
+    };
+    let kind = blank(row)?;
+    let extent = |row: usize, kind: bool| {
+        let first = (0..row)
+            .rev()
+            .take_while(|&r| blank(r) == Some(kind))
+            .last()
+            .unwrap_or(row);
+        let last = (row + 1..buffer.line_count())
+            .take_while(|&r| blank(r) == Some(kind))
+            .last()
+            .unwrap_or(row);
+        (first, last)
+    };
+    let (first, mut last) = extent(row, kind);
+    if around && last + 1 < buffer.line_count() {
+        last = extent(last + 1, !kind).1;
+    }
+    Some(if last + 1 < buffer.line_count() {
+        Region::new((first, 0), (last + 1, 0))
+    } else {
+        Region::new((first, 0), (last, buffer.line(last)?.len()))
+    })
+}
+
+/// Resolves a vi-style text object like `iw`, `a"`, `i(` or `ap` at `pos`. The first character
+/// picks the inner object (`i`) or the object with its surroundings (`a`), the second one the
+/// kind of object: `w` for words, a quote for strings, a bracket (or `b`/`B`) for bracketed
+/// text and `p` for paragraphs.
+pub fn text_object(buffer: &Buffer, pos: Position, object: &str) -> Option<Region> {
+    let mut chars = object.chars();
+    let around = match chars.next()? {
+        'i' => false,
+        'a' => true,
+        _ => return None,
+    };
+    let kind = chars.next()?;
+    let pair = match kind {
+        'w' => return word_object(buffer, pos, around),
+        'p' => return paragraph_object(buffer, pos.0, around),
+        '"' | '\'' | '`' => {
+            let quoted = quoted(buffer, Region::at(pos), kind, true)?;
+            return Some(if around {
+                quoted
+            } else {
+                Region::new((pos.0, quoted.start.1 + 1), (pos.0, quoted.end.1 - 1))
+            });
+        }
+        '(' | ')' | 'b' => ('(', ')'),
+        '[' | ']' => ('[', ']'),
+        '{' | '}' | 'B' => ('{', '}'),
+        '<' | '>' => ('<', '>'),
+        _ => return None,
+    };
+    // On an opening bracket, the object is the pair it opens rather than the enclosing one.
+    let on_open = buffer
+        .line(pos.0)
+        .and_then(|line| line.content().get(pos.1..))
+        .is_some_and(|rest| rest.starts_with(pair.0));
+    let at = if on_open {
+        (pos.0, pos.1 + pair.0.len_utf8())
+    } else {
+        pos
+    };
+    bracketed(buffer, Region::at(at), pair, around)
+}
+
+/// Whole lines spanned by `region`.
+pub fn lines(buffer: &Buffer, region: Region) -> Option<Region> {
+    let last = buffer.line(region.end.0)?;
+    Some(Region::new((region.start.0, 0), (region.end.0, last.len())))
+}
+
+fn span(buffer: &Buffer, region: Region) -> usize {
+    if region.start.0 == region.end.0 {
+        return region.end.1 - region.start.1;
+    }
+    (region.start.0..region.end.0)
+        .map(|row| buffer.line(row).map_or(0, |line| line.len()) + 1)
+        .sum::<usize>()
+        - region.start.1
+        + region.end.1
+}
+
+/// Grows `region` to the next enclosing syntactic unit: word, string, bracketed expression,
+/// the same including its delimiters, the full lines of a block (e.g. a function with its
+/// signature), and finally the whole buffer. With a syntax backend, what it draws as one
+/// thing, like a string or a comment, is a unit too. Shrinking is a matter of walking back the
+/// regions that led here.
+pub fn expand(buffer: &Buffer, region: Region) -> Option<Region> {
+    let mut candidates = Vec::new();
+    candidates.extend(word(buffer, region.start));
+    candidates.extend(buffer.syntax_regions(region));
+    for &quote in QUOTES.iter() {
+        candidates.extend(quoted(buffer, region, quote, false));
+        candidates.extend(quoted(buffer, region, quote, true));
+    }
+    for &pair in PAIRS.iter() {
+        candidates.extend(bracketed(buffer, region, pair, false));
+        if let Some(block) = bracketed(buffer, region, pair, true) {
+            candidates.push(block);
+            candidates.extend(lines(buffer, block));
+        }
+    }
+    candidates.extend(lines(buffer, region));
+    if buffer.line_count() > 0 {
+        let last = buffer.line_count() - 1;
+        candidates.extend(lines(buffer, Region::new((0, 0), (last, 0))));
+    }
+
+    candidates
+        .into_iter()
+        .filter(|candidate| candidate.contains(&region) && *candidate != region)
+        .min_by_key(|&candidate| span(buffer, candidate))
+}
@@ -0,0 +1,142 @@
+use std::ffi::OsString;
+use std::fs::{self, Metadata, OpenOptions};
+use std::io::{ErrorKind, Result, Write};
+use std::path::{Path, PathBuf};
+
+/// Links followed at most to find the file a symbolic link points at, as the system does.
+const MAX_LINKS: usize = 40;
+
+/// Writes `content` to the file at `path` so that a crash or a full disk midway leaves it as
+/// it was: into a file next to it first, which then takes its place. The file keeps its
+/// permissions and, where allowed, its owner. A symbolic link is followed, so it's the file it
+/// points at that changes. With `backup`, the file as it was is copied first to its name with
+/// a `~` after it.
+pub fn write_file(path: &Path, content: &[u8], backup: bool) -> Result<()> {
+    let target = resolve_links(path);
+    let existing = fs::metadata(&target).ok();
+    if backup && existing.is_some() {
+        fs::copy(&target, backup_path(&target))?;
+    }
+    // Putting a new file in place of one with other hard links would part it from them.
+    if existing
+        .as_ref()
+        .is_some_and(|metadata| links(metadata) > 1)
+    {
+        return fs::write(&target, content);
+    }
+    let temp = temp_path(&target);
+    let written = write_temp(&temp, content, existing.as_ref());
+    match written.and_then(|()| fs::rename(&temp, &target)) {
+        Ok(()) => {
+            sync_dir(&target);
+            Ok(())
+        }
+        Err(err) => {
+            let _ = fs::remove_file(&temp);
+            // A directory that takes no new files can still have the file itself rewritten.
+            if err.kind() == ErrorKind::PermissionDenied {
+                fs::write(&target, content)
+            } else {
+                Err(err)
+            }
+        }
+    }
+}
+
+/// Where `path` leads through any symbolic links, even if what the last one points at
+/// doesn't exist yet.
+fn resolve_links(path: &Path) -> PathBuf {
+    let mut path = path.to_path_buf();
+    for _ in 0..MAX_LINKS {
+        let Ok(link) = fs::read_link(&path) else {
+            break;
+        };
+        path = match path.parent() {
+            Some(dir) => dir.join(link),
+            None => link,
+        };
+    }
+    path
+}
+
+/// The file `content` goes into before taking the place of `target`, hidden next to it.
+fn temp_path(target: &Path) -> PathBuf {
+    let name = target.file_name().unwrap_or_default().to_string_lossy();
+    target.with_file_name(format!(".{}.{}.tmp", name, std::process::id()))
+}
+
+/// The backup of `target`, its name with a `~` after it.
+fn backup_path(target: &Path) -> PathBuf {
+    let mut name = OsString::from(target.as_os_str());
+    name.push("~");
+    PathBuf::from(name)
+}
+
+fn write_temp(temp: &Path, content: &[u8], original: Option<&Metadata>) -> Result<()> {
+    let mut file = OpenOptions::new().write(true).create_new(true).open(temp)?;
+    file.write_all(content)?;
+    if let Some(original) = original {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            // Only the superuser can give a file away, which is no reason not to save it.
+            let _ = std::os::unix::fs::fchown(&file, Some(original.uid()), Some(original.gid()));
+        }
+        file.set_permissions(original.permissions())?;
+    }
+    file.sync_all()
+}
+
+fn links(metadata: &Metadata) -> u64 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        metadata.nlink()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        1
+    }
+}
+
+/// Makes the renaming into `target` last through a crash, where the system allows.
+fn sync_dir(target: &Path) {
+    #[cfg(unix)]
+    if let Some(dir) = target.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        let _ = fs::File::open(dir).and_then(|dir| dir.sync_all());
+    }
+    #[cfg(not(unix))]
+    let _ = target;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn through_links_with_backup() {
+        let dir = std::env::temp_dir().join(format!("milo-save-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("file.txt");
+        fs::write(&file, "old\n").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&file, fs::Permissions::from_mode(0o640)).unwrap();
+            std::os::unix::fs::symlink("file.txt", dir.join("link")).unwrap();
+            write_file(&dir.join("link"), b"new\n", true).unwrap();
+            assert!(fs::symlink_metadata(dir.join("link"))
+                .unwrap()
+                .file_type()
+                .is_symlink());
+            let mode = fs::metadata(&file).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o640);
+        }
+        #[cfg(not(unix))]
+        write_file(&file, b"new\n", true).unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "new\n");
+        assert_eq!(fs::read_to_string(backup_path(&file)).unwrap(), "old\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
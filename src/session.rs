@@ -0,0 +1,215 @@
+use crate::buffer::Cursor;
+use crate::window::Layout;
+use std::fs;
+use std::io::Result;
+use std::path::{Path, PathBuf};
+
+/// Name of the file in the home directory that keeps the sessions.
+const SESSIONS_FILE: &str = ".milo_sessions";
+/// Sessions remembered at most; those of the directories left longest ago are forgotten first.
+const MAX_SESSIONS: usize = 20;
+
+/// The files and windows the editor had open when it quit, to pick up from with
+/// `milo --restore` in the same directory.
+#[derive(Clone)]
+pub struct Session {
+    /// Working directory of the editor, which `:cd` may have changed.
+    pub cwd: PathBuf,
+    /// Files open, in the order they were opened, with the cursor in each.
+    pub files: Vec<(PathBuf, Cursor)>,
+    /// Index in `files` of the one shown, if it was one of them.
+    pub current: Option<usize>,
+    pub layout: Layout,
+    /// Each window's view of the file shown, and its own working directory if it has one.
+    pub windows: Vec<(Cursor, Option<PathBuf>)>,
+    /// Index in `windows` of the focused one.
+    pub active: usize,
+}
+
+impl Session {
+    /// The session as lines of the sessions file, each a keyword and its fields separated by
+    /// tabs, starting with the directory it belongs to.
+    fn to_lines(&self, dir: &Path) -> Vec<String> {
+        let mut lines = vec![
+            format!("session\t{}", dir.display()),
+            format!("cwd\t{}", self.cwd.display()),
+            format!("layout\t{}", self.layout.to_text()),
+            format!("active\t{}", self.active),
+        ];
+        for (cursor, cwd) in &self.windows {
+            let cwd = cwd.as_ref().map(|cwd| cwd.display().to_string());
+            lines.push(format!(
+                "window\t{}\t{}",
+                cursor_fields(*cursor),
+                cwd.unwrap_or_default()
+            ));
+        }
+        for (file, cursor) in &self.files {
+            lines.push(format!(
+                "file\t{}\t{}",
+                cursor_fields(*cursor),
+                file.display()
+            ));
+        }
+        if let Some(current) = self.current {
+            lines.push(format!("current\t{}", current));
+        }
+        lines
+    }
+
+    /// Reads the lines of a session after its first, or None if they don't make one.
+    fn parse<'a>(lines: impl Iterator<Item = &'a str>) -> Option<Session> {
+        let mut session = Session {
+            cwd: PathBuf::new(),
+            files: Vec::new(),
+            current: None,
+            layout: Layout::new(0),
+            windows: Vec::new(),
+            active: 0,
+        };
+        for line in lines {
+            let (keyword, rest) = line.split_once('\t')?;
+            match keyword {
+                "cwd" => session.cwd = PathBuf::from(rest),
+                "layout" => session.layout = Layout::parse(rest)?,
+                "active" => session.active = rest.parse().ok()?,
+                "window" => {
+                    let (cursor, cwd) = parse_cursor(rest)?;
+                    let cwd = Some(PathBuf::from(cwd)).filter(|_| !cwd.is_empty());
+                    session.windows.push((cursor, cwd));
+                }
+                "file" => {
+                    let (cursor, file) = parse_cursor(rest)?;
+                    session.files.push((PathBuf::from(file), cursor));
+                }
+                "current" => session.current = Some(rest.parse().ok()?),
+                _ => return None,
+            }
+        }
+        let mut shown = session.layout.windows();
+        shown.sort_unstable();
+        let consistent = shown == (0..session.windows.len()).collect::<Vec<usize>>()
+            && session.active < session.windows.len()
+            && session
+                .current
+                .is_none_or(|current| current < session.files.len());
+        consistent.then_some(session)
+    }
+}
+
+fn cursor_fields(cursor: Cursor) -> String {
+    format!(
+        "{}\t{}\t{}\t{}",
+        cursor.cursor_row, cursor.cursor_col, cursor.row_offset, cursor.col_offset
+    )
+}
+
+/// The cursor at the start of `fields`, and the field after it.
+fn parse_cursor(fields: &str) -> Option<(Cursor, &str)> {
+    let mut fields = fields.splitn(5, '\t');
+    let mut number = || fields.next()?.parse::<usize>().ok();
+    let cursor = Cursor {
+        cursor_row: number()?,
+        cursor_col: number()?,
+        row_offset: number()?,
+        col_offset: number()?,
+        ..Cursor::default()
+    };
+    Some((cursor, fields.next()?))
+}
+
+fn sessions_file() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| Path::new(&home).join(SESSIONS_FILE))
+}
+
+/// The sessions in `text`, each with the directory it belongs to, most recent first. Sessions
+/// that don't read right are left out.
+fn read_sessions(text: &str) -> Vec<(PathBuf, Session)> {
+    let mut sessions = Vec::new();
+    let mut lines = text.lines().peekable();
+    while let Some(line) = lines.next() {
+        let Some(dir) = line.strip_prefix("session\t") else {
+            continue;
+        };
+        let mut body = Vec::new();
+        while let Some(line) = lines.next_if(|line| !line.starts_with("session\t")) {
+            body.push(line);
+        }
+        if let Some(session) = Session::parse(body.into_iter()) {
+            sessions.push((PathBuf::from(dir), session));
+        }
+    }
+    sessions
+}
+
+/// The session last saved for `dir`.
+pub fn load(dir: &Path) -> Option<Session> {
+    let text = fs::read_to_string(sessions_file()?).ok()?;
+    read_sessions(&text)
+        .into_iter()
+        .find(|(known, _)| known == dir)
+        .map(|(_, session)| session)
+}
+
+/// Saves `session` as the one to restore in `dir`, in place of any saved before.
+pub fn store(dir: &Path, session: &Session) -> Result<()> {
+    let Some(path) = sessions_file() else {
+        return Ok(());
+    };
+    let text = fs::read_to_string(&path).unwrap_or_default();
+    let mut sessions = read_sessions(&text);
+    sessions.retain(|(known, _)| known != dir);
+    sessions.insert(0, (dir.to_path_buf(), session.clone()));
+    sessions.truncate(MAX_SESSIONS);
+    let text = sessions
+        .iter()
+        .flat_map(|(dir, session)| session.to_lines(dir))
+        .map(|line| line + "\n")
+        .collect::<String>();
+    fs::write(path, text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::window::Split;
+
+    #[test]
+    fn round_trip() {
+        let mut layout = Layout::new(0);
+        layout.split(0, 1, Split::Vertical);
+        layout.split(1, 2, Split::Horizontal);
+        let cursor = Cursor {
+            cursor_row: 7,
+            cursor_col: 2,
+            row_offset: 3,
+            ..Cursor::default()
+        };
+        let session = Session {
+            cwd: PathBuf::from("/src"),
+            files: vec![
+                (PathBuf::from("/src/a.rs"), cursor),
+                (PathBuf::from("/src/b c.rs"), Cursor::default()),
+            ],
+            current: Some(1),
+            layout,
+            windows: vec![
+                (cursor, None),
+                (Cursor::default(), Some(PathBuf::from("/tmp"))),
+                (Cursor::default(), None),
+            ],
+            active: 2,
+        };
+        let mut text = session.to_lines(Path::new("/src")).join("\n");
+        text.push_str("\nsession\t/broken\nlayout\tv(0:1)\n");
+        let sessions = read_sessions(&text);
+        assert_eq!(sessions.len(), 1);
+        let (dir, read) = &sessions[0];
+        assert_eq!(dir, Path::new("/src"));
+        assert_eq!(read.layout.to_text(), "v(0:1,h(1:1,2:1):1)");
+        assert_eq!(read.files[1].0, PathBuf::from("/src/b c.rs"));
+        assert_eq!(read.files[0].1.cursor_row, 7);
+        assert_eq!(read.windows[1].1, Some(PathBuf::from("/tmp")));
+        assert_eq!((read.current, read.active), (Some(1), 2));
+    }
+}
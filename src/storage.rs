@@ -0,0 +1,324 @@
+use crate::line::Line;
+use std::iter::FromIterator;
+use std::ops::{Index, IndexMut, Range};
+
+/// The storage buffers keep their lines in.
+pub type Lines = GapBuffer<Line>;
+
+/// How a buffer keeps its lines: a sequence that edits go into at the cursor, mostly.
+pub trait Storage:
+    Default + FromIterator<Line> + Index<usize, Output = Line> + IndexMut<usize, Output = Line>
+{
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn get(&self, row: usize) -> Option<&Line>;
+
+    fn get_mut(&mut self, row: usize) -> Option<&mut Line>;
+
+    /// Puts `lines` in before line `at`, or at the end if `at` is the number of lines.
+    fn insert_lines(&mut self, at: usize, lines: Vec<Line>);
+
+    /// Takes out the lines in `rows`.
+    fn remove_lines(&mut self, rows: Range<usize>);
+
+    fn iter(&self) -> impl Iterator<Item = &Line>;
+
+    fn iter_mut(&mut self) -> impl Iterator<Item = &mut Line>;
+
+    fn insert(&mut self, at: usize, line: Line) {
+        self.insert_lines(at, vec![line]);
+    }
+
+    fn remove(&mut self, row: usize) {
+        self.remove_lines(row..row + 1);
+    }
+}
+
+/// Lines in one contiguous vector. Inserting or removing a line moves all the ones after it.
+impl Storage for Vec<Line> {
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+
+    fn get(&self, row: usize) -> Option<&Line> {
+        self.as_slice().get(row)
+    }
+
+    fn get_mut(&mut self, row: usize) -> Option<&mut Line> {
+        self.as_mut_slice().get_mut(row)
+    }
+
+    fn insert_lines(&mut self, at: usize, lines: Vec<Line>) {
+        self.splice(at..at, lines);
+    }
+
+    fn remove_lines(&mut self, rows: Range<usize>) {
+        self.drain(rows);
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &Line> {
+        self.as_slice().iter()
+    }
+
+    fn iter_mut(&mut self) -> impl Iterator<Item = &mut Line> {
+        self.as_mut_slice().iter_mut()
+    }
+}
+
+/// A sequence with a gap in it where the last edit was. Items before the gap are kept in
+/// order, and the ones after it in reverse, so that moving the gap takes time proportional to
+/// how far it goes and editing at the gap takes next to none. Edits in a big file mostly
+/// happen near each other, so they no longer move everything after them along.
+#[derive(Debug)]
+pub struct GapBuffer<T> {
+    before: Vec<T>,
+    /// Items after the gap, the nearest last.
+    after: Vec<T>,
+}
+
+impl<T> Default for GapBuffer<T> {
+    fn default() -> Self {
+        Self {
+            before: Vec::new(),
+            after: Vec::new(),
+        }
+    }
+}
+
+impl<T> GapBuffer<T> {
+    pub fn len(&self) -> usize {
+        self.before.len() + self.after.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, idx: usize) -> Option<&T> {
+        match idx.checked_sub(self.before.len()) {
+            None => self.before.get(idx),
+            Some(past) => self
+                .after
+                .len()
+                .checked_sub(past + 1)
+                .map(|n| &self.after[n]),
+        }
+    }
+
+    pub fn get_mut(&mut self, idx: usize) -> Option<&mut T> {
+        match idx.checked_sub(self.before.len()) {
+            None => self.before.get_mut(idx),
+            Some(past) => match self.after.len().checked_sub(past + 1) {
+                Some(n) => Some(&mut self.after[n]),
+                None => None,
+            },
+        }
+    }
+
+    /// Moves the gap to just before item `idx`.
+    fn move_gap(&mut self, idx: usize) {
+        let idx = idx.min(self.len());
+        while self.before.len() > idx {
+            let item = self.before.pop().expect("before the gap");
+            self.after.push(item);
+        }
+        while self.before.len() < idx {
+            let item = self.after.pop().expect("after the gap");
+            self.before.push(item);
+        }
+    }
+
+    /// Puts `items` in before item `idx`, leaving the gap after them.
+    pub fn insert_all(&mut self, idx: usize, items: Vec<T>) {
+        self.move_gap(idx);
+        self.before.extend(items);
+    }
+
+    /// Takes out the items in `range`, leaving the gap where they were.
+    pub fn remove_range(&mut self, range: Range<usize>) {
+        self.move_gap(range.start);
+        let count = range.len().min(self.after.len());
+        self.after.truncate(self.after.len() - count);
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            items: self,
+            range: 0..self.len(),
+        }
+    }
+
+    pub fn iter_mut(&mut self) -> impl DoubleEndedIterator<Item = &mut T> {
+        self.before.iter_mut().chain(self.after.iter_mut().rev())
+    }
+}
+
+/// Items of a `GapBuffer` in order.
+pub struct Iter<'a, T> {
+    items: &'a GapBuffer<T>,
+    range: Range<usize>,
+}
+
+impl<T> Clone for Iter<'_, T> {
+    fn clone(&self) -> Self {
+        Self {
+            items: self.items,
+            range: self.range.clone(),
+        }
+    }
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        self.range.next().and_then(|idx| self.items.get(idx))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.range.size_hint()
+    }
+
+    fn nth(&mut self, n: usize) -> Option<&'a T> {
+        self.range.nth(n).and_then(|idx| self.items.get(idx))
+    }
+}
+
+impl<T> DoubleEndedIterator for Iter<'_, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.range.next_back().and_then(|idx| self.items.get(idx))
+    }
+}
+
+impl<T> ExactSizeIterator for Iter<'_, T> {}
+
+impl<T> Index<usize> for GapBuffer<T> {
+    type Output = T;
+
+    fn index(&self, idx: usize) -> &T {
+        let len = self.len();
+        self.get(idx)
+            .unwrap_or_else(|| panic!("index {} out of range for {} items", idx, len))
+    }
+}
+
+impl<T> IndexMut<usize> for GapBuffer<T> {
+    fn index_mut(&mut self, idx: usize) -> &mut T {
+        let len = self.len();
+        self.get_mut(idx)
+            .unwrap_or_else(|| panic!("index {} out of range for {} items", idx, len))
+    }
+}
+
+impl<T> FromIterator<T> for GapBuffer<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Self {
+            before: iter.into_iter().collect(),
+            after: Vec::new(),
+        }
+    }
+}
+
+impl Storage for GapBuffer<Line> {
+    fn len(&self) -> usize {
+        GapBuffer::len(self)
+    }
+
+    fn get(&self, row: usize) -> Option<&Line> {
+        GapBuffer::get(self, row)
+    }
+
+    fn get_mut(&mut self, row: usize) -> Option<&mut Line> {
+        GapBuffer::get_mut(self, row)
+    }
+
+    fn insert_lines(&mut self, at: usize, lines: Vec<Line>) {
+        self.insert_all(at, lines);
+    }
+
+    fn remove_lines(&mut self, rows: Range<usize>) {
+        self.remove_range(rows);
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &Line> {
+        GapBuffer::iter(self)
+    }
+
+    fn iter_mut(&mut self) -> impl Iterator<Item = &mut Line> {
+        GapBuffer::iter_mut(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks `gap` holds what `vec` does, read every way it can be.
+    fn assert_same(gap: &GapBuffer<usize>, vec: &[usize]) {
+        assert_eq!(gap.len(), vec.len());
+        for idx in 0..vec.len() + 2 {
+            assert_eq!(gap.get(idx), vec.get(idx), "item {}", idx);
+        }
+        assert!(gap.iter().eq(vec.iter()));
+        assert!(gap.iter().rev().eq(vec.iter().rev()));
+        for n in 0..vec.len() + 1 {
+            assert_eq!(gap.iter().nth(n), vec.get(n), "nth {}", n);
+            assert_eq!(
+                gap.iter().rev().nth(n),
+                vec.iter().rev().nth(n),
+                "nth back {}",
+                n
+            );
+        }
+        assert_eq!(gap.iter().len(), vec.len());
+    }
+
+    #[test]
+    fn edits_like_a_vec() {
+        let mut gap: GapBuffer<usize> = (0..5).collect();
+        let mut vec: Vec<usize> = (0..5).collect();
+        // A fixed walk of positions, near each other and far apart, both ways.
+        let mut seed = 7usize;
+        let mut next = 100;
+        for step in 0..200 {
+            seed = (seed * 1_103_515_245 + 12_345) % (1 << 31);
+            let at = seed % (vec.len() + 1);
+            if step % 3 == 2 && !vec.is_empty() {
+                let end = (at + seed / 7 % 4).min(vec.len());
+                gap.remove_range(at..end);
+                vec.drain(at..end);
+            } else {
+                let items: Vec<usize> = (next..next + seed / 11 % 3).collect();
+                next += items.len();
+                gap.insert_all(at, items.clone());
+                vec.splice(at..at, items);
+            }
+            assert_same(&gap, &vec);
+        }
+        for (idx, item) in gap.iter_mut().enumerate() {
+            *item += idx;
+        }
+        for (idx, item) in vec.iter_mut().enumerate() {
+            *item += idx;
+        }
+        gap[0] = 1;
+        vec[0] = 1;
+        assert_same(&gap, &vec);
+        let len = vec.len();
+        gap.remove_range(0..len);
+        assert_same(&gap, &[]);
+    }
+
+    #[test]
+    fn remove_past_the_end() {
+        let mut gap: GapBuffer<usize> = (0..4).collect();
+        gap.remove_range(2..10);
+        assert_same(&gap, &[0, 1]);
+        gap.insert_all(9, vec![5]);
+        assert_same(&gap, &[0, 1, 5]);
+    }
+}
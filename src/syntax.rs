@@ -0,0 +1,182 @@
+use crate::filetype::Filetype;
+use crate::highlight::Highlight;
+use crate::line::Line;
+use std::ops::Range;
+use std::sync::RwLock;
+
+/// Highlighting worked out from a parse of the whole text, like a tree-sitter grammar gives,
+/// used in place of `highlight_line` for the filetypes there's a grammar for.
+pub trait SyntaxBackend: Send {
+    /// Takes in the text as it is now, `first` being the first line that changed since the
+    /// last time, or 0 the first time, so that the parse can pick up from there.
+    fn update(&mut self, lines: &[&str], first: usize);
+
+    /// Byte ranges of line `row` and how to draw them, as of the last update. Whatever they
+    /// leave out is drawn as normal text.
+    fn spans(&self, row: usize) -> Vec<(Range<usize>, Highlight)>;
+}
+
+/// Makes a backend for buffers of a filetype, or None for filetypes it has no grammar for.
+pub type BackendFactory = fn(&Filetype) -> Option<Box<dyn SyntaxBackend>>;
+
+/// Sources of syntax backends, the last added first.
+static BACKENDS: RwLock<Vec<BackendFactory>> = RwLock::new(Vec::new());
+
+/// Adds a source of syntax backends, ahead of those added before it. Buffers of filetypes
+/// none of them has a grammar for keep to the keyword highlighter.
+pub fn register_backend(factory: BackendFactory) {
+    let mut backends = BACKENDS.write().unwrap_or_else(|err| err.into_inner());
+    backends.insert(0, factory);
+}
+
+/// A backend for buffers of `filetype`, from the first source with a grammar for it.
+pub fn backend_for(filetype: &Filetype) -> Option<Box<dyn SyntaxBackend>> {
+    let backends = BACKENDS.read().unwrap_or_else(|err| err.into_inner());
+    backends.iter().find_map(|factory| factory(filetype))
+}
+
+/// Highlights of the first `cols` columns of the rendered `line`, with the byte ranges of it in
+/// `spans` drawn as they say.
+pub fn span_highlights(
+    line: &Line,
+    spans: &[(Range<usize>, Highlight)],
+    cols: usize,
+) -> Vec<Highlight> {
+    let mut highlights = vec![Highlight::Normal; cols];
+    for (range, highlight) in spans {
+        let start = line.cursor_to_render_position(line.floor_char_boundary(range.start));
+        let end = line.cursor_to_render_position(line.floor_char_boundary(range.end));
+        for each in highlights.iter_mut().take(end).skip(start) {
+            *each = *highlight;
+        }
+    }
+    highlights
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::{Buffer, Cursor};
+    use crate::region::{self, Region};
+    use std::sync::Mutex;
+
+    /// First rows changed, as the test backend was told them, in order.
+    static UPDATES: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+
+    static MARKED: Filetype = Filetype {
+        name: "marked",
+        extensions: &[],
+        filenames: &[],
+        interpreters: &[],
+        indent: "\t",
+        indent_after: &[],
+        line_comment: None,
+        block_comment: None,
+        keywords: &[],
+        quotes: &[],
+        long_strings: &[],
+    };
+
+    /// Draws every `x` as a keyword, and from `#` on as a comment.
+    struct Marker {
+        lines: Vec<String>,
+    }
+
+    impl SyntaxBackend for Marker {
+        fn update(&mut self, lines: &[&str], first: usize) {
+            UPDATES.lock().unwrap().push(first);
+            self.lines = lines.iter().map(|line| line.to_string()).collect();
+        }
+
+        fn spans(&self, row: usize) -> Vec<(Range<usize>, Highlight)> {
+            let Some(line) = self.lines.get(row) else {
+                return Vec::new();
+            };
+            let comment = line.find('#').unwrap_or(line.len());
+            let mut spans = line[..comment]
+                .match_indices('x')
+                .map(|(idx, _)| (idx..idx + 1, Highlight::Keyword))
+                .collect::<Vec<_>>();
+            if comment < line.len() {
+                spans.push((comment..line.len(), Highlight::Comment));
+            }
+            spans
+        }
+    }
+
+    fn marker(filetype: &Filetype) -> Option<Box<dyn SyntaxBackend>> {
+        if filetype.name != MARKED.name {
+            return None;
+        }
+        Some(Box::new(Marker { lines: Vec::new() }))
+    }
+
+    #[test]
+    fn spans_on_rendered_columns() {
+        use Highlight::{Keyword as K, Normal as N};
+        let line = Line::with_tab_stop("\tx é x".to_string(), 4);
+        let spans = [(1..2, K), (6..7, K)];
+        assert_eq!(
+            span_highlights(&line, &spans, 9),
+            [N, N, N, N, K, N, N, N, K]
+        );
+        // Only as many columns as asked for, from the start of the character a range starts
+        // inside of.
+        assert_eq!(
+            span_highlights(&line, &[(4..5, K)], 7),
+            [N, N, N, N, N, N, K]
+        );
+    }
+
+    #[test]
+    fn edits_report_first_stale_row() {
+        use Highlight::{Keyword as K, Normal as N};
+        register_backend(marker);
+        let mut buffer = Buffer::new();
+        buffer.read_bytes(b"a\n\tx\nb\nc\nd\n", None).expect("text");
+        buffer.set_tab_stop(4);
+        buffer.set_filetype(Some(&MARKED));
+        let highlights = buffer.frame_highlights(10, 40, None);
+        assert_eq!(highlights[1], [N, N, N, N, K]);
+        assert_eq!(buffer.line_highlights(1), [N, N, N, N, K]);
+
+        let at = |row| Cursor {
+            cursor_row: row,
+            ..Cursor::default()
+        };
+        buffer.set_cursor_position(at(3));
+        buffer.insert_char('x');
+        buffer.frame_highlights(10, 40, None);
+        // Nothing changed since, so nothing is passed on.
+        buffer.frame_highlights(10, 40, None);
+        buffer.set_cursor_position(at(4));
+        buffer.insert_char('y');
+        buffer.set_cursor_position(at(2));
+        buffer.insert_char('x');
+        let highlights = buffer.frame_highlights(10, 40, None);
+        assert_eq!(*UPDATES.lock().unwrap(), [0, 3, 2]);
+        assert_eq!(highlights[2], [K, N]);
+        assert_eq!(highlights[3], [K, N]);
+    }
+
+    #[test]
+    fn expands_to_what_is_drawn_as_one() {
+        register_backend(marker);
+        let mut buffer = Buffer::new();
+        buffer
+            .read_bytes(b"f(a #b, c)\ng(d) #e\n#h\ni\n", None)
+            .expect("text");
+        buffer.set_filetype(Some(&MARKED));
+        buffer.frame_highlights(10, 40, None);
+        let expand = |region| region::expand(&buffer, region).expect("a bigger region");
+        // The comment, inside the brackets it runs past.
+        let comment = expand(Region::at((0, 4)));
+        assert_eq!(comment, Region::new((0, 4), (0, 10)));
+        // Going on from the end of one line into the next.
+        let word = expand(Region::at((2, 1)));
+        assert_eq!(word, Region::new((2, 1), (2, 2)));
+        let line = expand(word);
+        assert_eq!(line, Region::new((2, 0), (2, 2)));
+        assert_eq!(expand(line), Region::new((1, 5), (2, 2)));
+    }
+}
@@ -0,0 +1,80 @@
+use crate::editor::Editor;
+use std::io::Result;
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+#[cfg(windows)]
+use std::os::windows::io::{AsRawHandle, RawHandle};
+use std::time::{Duration, Instant};
+
+/// Longest between calls to `on_timeout`, since a change of the window size doesn't make the
+/// descriptor readable.
+const RESIZE_POLL: Duration = Duration::from_millis(250);
+
+/// The editor as one event source among many. Register the task's file descriptor with the
+/// application's reactor (tokio's `AsyncFd`, a mio `SourceFd`, plain `poll`...) for
+/// readability and call `on_readable` whenever it fires; nothing here ever blocks waiting for
+/// input.
+///
+/// The editor also has work that no input announces: writing swap files and saving after a
+/// pause in typing, noticing the file change on disk, talking to language servers, taking
+/// the status message down and redrawing after a resize. Keep a timer armed for
+/// `next_deadline` alongside the descriptor, and call `on_timeout` when it goes off.
+pub struct EditorTask {
+    editor: Editor,
+}
+
+impl EditorTask {
+    /// Takes over `editor` and draws its first frame.
+    pub fn new(mut editor: Editor) -> Self {
+        editor.refresh_screen();
+        Self { editor }
+    }
+
+    pub fn editor(&mut self) -> &mut Editor {
+        &mut self.editor
+    }
+
+    /// Handles the input that has arrived, up to a frame's worth, and redraws once. Any input
+    /// left over keeps the descriptor readable. Returns false when the editor has been asked
+    /// to quit, after which the task should be dropped.
+    pub fn on_readable(&mut self) -> Result<bool> {
+        self.editor.process_pending()?;
+        if self.editor.keep_alive() {
+            self.editor.refresh_screen();
+        }
+        Ok(self.editor.keep_alive())
+    }
+
+    /// When `on_timeout` is next due. It moves with every call to `on_readable` and
+    /// `on_timeout`, so the timer is armed again after each.
+    pub fn next_deadline(&self) -> Instant {
+        self.editor
+            .next_deadline()
+            .min(Instant::now() + RESIZE_POLL)
+    }
+
+    /// Does the work that has come due by `next_deadline`, and redraws. Returns false when the
+    /// editor has been asked to quit, as `on_readable` does.
+    pub fn on_timeout(&mut self) -> Result<bool> {
+        self.editor.process_due()?;
+        if self.editor.keep_alive() {
+            self.editor.refresh_screen();
+        }
+        Ok(self.editor.keep_alive())
+    }
+}
+
+#[cfg(unix)]
+impl AsRawFd for EditorTask {
+    fn as_raw_fd(&self) -> RawFd {
+        std::io::stdin().as_raw_fd()
+    }
+}
+
+/// The console input handle, for waiting on with `WaitForSingleObject` and the like.
+#[cfg(windows)]
+impl AsRawHandle for EditorTask {
+    fn as_raw_handle(&self) -> RawHandle {
+        std::io::stdin().as_raw_handle()
+    }
+}
@@ -1,97 +1,121 @@
-use libc::{c_int, c_ulong, c_void, termios as Termios, winsize as WinSize};
-use libc::{
-    BRKINT, CS8, ECHO, ICANON, ICRNL, IEXTEN, INPCK, ISIG, ISTRIP, IXON, OPOST, STDIN_FILENO,
-    STDOUT_FILENO, TIOCGWINSZ, VMIN, VTIME,
-};
-use std::io::{self, Error, ErrorKind, Read, Result};
-use std::mem;
-
-extern "C" {
-    pub fn tcgetattr(fd: c_int, termios: *mut Termios) -> c_int;
-    pub fn tcsetattr(fd: c_int, optional_actions: c_int, termios: *const Termios) -> c_int;
-    pub fn iscntrl(c: c_int) -> c_int;
-    pub fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
-}
-
-trait TermiosAttrExt {
-    fn get_attr() -> Result<Termios>;
-    fn set_attr(&self) -> Result<()>;
-    fn enable_raw_mode(&mut self) -> Result<()>;
-}
-
-impl TermiosAttrExt for Termios {
-    fn get_attr() -> Result<Termios> {
-        let mut termios = unsafe { mem::zeroed::<Termios>() };
-        unsafe {
-            if tcgetattr(STDIN_FILENO, &mut termios) != 0 {
-                return Err(Error::new(ErrorKind::Other, "Can't get term attributes"));
-            }
-        }
-        Ok(termios)
-    }
+use crate::backend::{Backend, Native};
+use crate::terminfo::{self, Terminfo};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, ErrorKind, Read, Result, Write};
+use std::ops::{BitOr, Sub};
+use std::time::{Duration, Instant};
 
-    fn set_attr(&self) -> Result<()> {
-        unsafe {
-            if tcsetattr(STDIN_FILENO, libc::TCSAFLUSH, self) != 0 {
-                return Err(Error::new(ErrorKind::Other, "Can't get term attributes"));
-            }
-        }
-        Ok(())
-    }
+/// How long `read_key` waits at a time, between checks for resizes it has no use for.
+const KEY_WAIT: Duration = Duration::from_secs(60);
+/// How long the bytes after an escape get to come by default, before it counts as Escape
+/// pressed on its own.
+pub const ESCAPE_TIMEOUT: Duration = Duration::from_millis(50);
+/// Sequence ending a bracketed paste.
+const PASTE_END: &[u8] = b"\x1b[201~";
+/// Switches to the alternate screen, which has no scrollback and is thrown away on leaving.
+const ENTER_SCREEN: &str = "\x1b[?1049h";
+/// Starts of `$TERM` for the families of terminals that take after xterm, with its mouse
+/// reports in the SGR encoding, bracketed paste and focus reports.
+const XTERM_LIKE: [&str; 15] = [
+    "xterm",
+    "screen",
+    "tmux",
+    "rxvt",
+    "alacritty",
+    "kitty",
+    "foot",
+    "wezterm",
+    "st-",
+    "vte",
+    "gnome",
+    "konsole",
+    "iterm",
+    "contour",
+    "ghostty",
+];
 
-    fn enable_raw_mode(&mut self) -> Result<()> {
-        self.c_lflag &= !(ECHO | ICANON | ISIG | IEXTEN);
-        self.c_iflag &= !(IXON | ICRNL | BRKINT | INPCK | ISTRIP);
-        self.c_oflag &= !(OPOST);
-        self.c_oflag |= CS8;
-        self.c_cc[VMIN] = 0;
-        self.c_cc[VTIME] = 1;
-        self.set_attr()
-    }
+/// What the terminal can be asked to do besides drawing, so that sequences it doesn't know
+/// aren't sent to show up as garbage.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Capabilities {
+    /// Whether it has a screen to draw on that leaves the shell's as it was.
+    pub alternate_screen: bool,
+    /// Whether it reports the mouse in the SGR encoding.
+    pub mouse: bool,
+    pub bracketed_paste: bool,
+    /// Whether it reports gaining and losing focus.
+    pub focus: bool,
 }
 
-trait WinSizeAttrExt {
-    fn get_window_size() -> Result<(usize, usize)>;
-    fn get_cursor_position() -> Result<(usize, usize)>;
-}
+impl Capabilities {
+    /// Works out what the terminal can do from `$TERM` and its terminfo entry.
+    pub fn detect() -> Self {
+        let term = std::env::var("TERM").unwrap_or_default();
+        let info = Terminfo::load(&term);
+        let alternate_screen = info
+            .as_ref()
+            .map(|info| info.string(terminfo::ENTER_CA_MODE).is_some());
+        let key_mouse = info
+            .as_ref()
+            .and_then(|info| info.string(terminfo::KEY_MOUSE));
+        Capabilities::of(&term, alternate_screen, key_mouse)
+    }
 
-impl WinSizeAttrExt for WinSize {
-    fn get_window_size() -> Result<(usize, usize)> {
-        let mut ws = unsafe { mem::zeroed::<WinSize>() };
-        unsafe {
-            if ioctl(STDOUT_FILENO, TIOCGWINSZ, &mut ws) == -1 || ws.ws_col == 0 {
-                let botright = "\x1b[999C\x1b[999B";
-                if Terminal::write(botright) != botright.len() as isize {
-                    return Err(Error::new(ErrorKind::Other, "Can't get window size"));
-                }
-                return Self::get_cursor_position();
-            }
-            Ok((ws.ws_row as usize, ws.ws_col as usize))
+    /// What a terminal of type `term` can do, given whether its terminfo entry has an
+    /// alternate screen and what it says starts mouse reports. Without an entry, terminals go
+    /// by their family, and `dumb` ones, or those with no type, get only the text.
+    fn of(term: &str, alternate_screen: Option<bool>, key_mouse: Option<&[u8]>) -> Self {
+        if term.is_empty() || term == "dumb" {
+            return Capabilities {
+                alternate_screen: false,
+                mouse: false,
+                bracketed_paste: false,
+                focus: false,
+            };
+        }
+        let xterm_like = XTERM_LIKE.iter().any(|family| term.starts_with(family));
+        Capabilities {
+            alternate_screen: alternate_screen.unwrap_or(xterm_like),
+            mouse: xterm_like || key_mouse == Some(b"\x1b[<"),
+            bracketed_paste: xterm_like,
+            focus: xterm_like,
         }
     }
 
-    fn get_cursor_position() -> Result<(usize, usize)> {
-        Terminal::write("\x1b[6n\r\n");
-
-        let cursor_buf = io::stdin()
-            .bytes()
-            .take_while(|c| !matches!(c, Ok(b'R')))
-            .collect::<Result<Vec<_>>>()?;
-
-        let dimensions = cursor_buf[2..]
-            .split(|&c| c == b';')
-            .filter_map(|buf| std::str::from_utf8(buf).ok())
-            .filter_map(|buf| buf.parse().ok())
-            .collect::<Vec<_>>();
-
-        if dimensions.len() != 2 {
-            return Err(Error::new(ErrorKind::Other, "Can't get window size"));
+    /// What gives the terminal back: turns off the reports it can send, resets colors, shows
+    /// the cursor and goes back to the screen the shell was on, or without an alternate screen
+    /// clears the one drawn on.
+    fn leave_screen(self) -> String {
+        let mut seq = String::new();
+        if self.focus {
+            seq.push_str("\x1b[?1004l");
+        }
+        if self.mouse {
+            seq.push_str("\x1b[?1000;1002;1006l");
+        }
+        if self.bracketed_paste {
+            seq.push_str("\x1b[?2004l");
         }
+        seq.push_str("\x1b[m\x1b[?25h");
+        seq.push_str(if self.alternate_screen {
+            "\x1b[?1049l"
+        } else {
+            "\x1b[H\x1b[2J"
+        });
+        seq
+    }
 
-        Ok((dimensions[0], dimensions[1]))
+    fn enter_screen(self) -> &'static str {
+        if self.alternate_screen {
+            ENTER_SCREEN
+        } else {
+            ""
+        }
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Motion {
     Up,
     Down,
@@ -101,50 +125,268 @@ pub enum Motion {
     PgDn,
     Home,
     End,
+    /// To the end of the word the cursor is in, or of the next one.
+    WordForward,
+    /// To the start of the word the cursor is in, or of the one before.
+    WordBackward,
+    /// To the blank line before the paragraph the cursor is in, or the one before.
+    ParagraphUp,
+    /// To the blank line after the paragraph the cursor is in, or the one after.
+    ParagraphDown,
+}
+
+/// Modifier keys held down with another key, as a set of bits.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Modifiers(pub u8);
+
+impl Modifiers {
+    pub const NONE: Modifiers = Modifiers(0);
+    pub const SHIFT: Modifiers = Modifiers(1);
+    pub const ALT: Modifiers = Modifiers(2);
+    pub const CTRL: Modifiers = Modifiers(4);
+
+    /// The modifiers in the parameter terminals send with a key, which is 1 plus their bits.
+    fn from_param(param: u8) -> Self {
+        Modifiers(param.saturating_sub(1) & 0b111)
+    }
+
+    pub fn contains(self, other: Modifiers) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl BitOr for Modifiers {
+    type Output = Modifiers;
+
+    fn bitor(self, other: Modifiers) -> Modifiers {
+        Modifiers(self.0 | other.0)
+    }
+}
+
+impl Sub for Modifiers {
+    type Output = Modifiers;
+
+    /// The modifiers in `self` but not in `other`.
+    fn sub(self, other: Modifiers) -> Modifiers {
+        Modifiers(self.0 & !other.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MouseButton {
+    Left,
+    Middle,
+    Right,
 }
 
+/// Mouse activity, at 0-based screen `row` and `col` where it has a position.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MouseEvent {
+    /// One notch of the scroll wheel, with `fast` set when a modifier key was held.
+    Wheel { up: bool, fast: bool },
+    Press {
+        button: MouseButton,
+        row: usize,
+        col: usize,
+    },
+    /// Movement with a button held down.
+    Drag {
+        button: MouseButton,
+        row: usize,
+        col: usize,
+    },
+    Release {
+        button: MouseButton,
+        row: usize,
+        col: usize,
+    },
+    /// A press of the left button with Ctrl held.
+    ControlClick { row: usize, col: usize },
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum Key {
     Printable(char),
     Move(Motion),
+    /// A motion with Shift held, which selects text on the way.
+    Select(Motion),
+    /// A motion with Ctrl or Alt held, and maybe Shift too.
+    Modified(Motion, Modifiers),
     Control(char),
+    /// A printable character typed with Alt held, which terminals send after an escape.
+    Alt(char),
+    /// F1 to F12.
+    Function(u8),
+    Insert,
     Delete,
     Backspace,
     Newline,
     Escape,
     Tab,
+    FocusGained,
+    FocusLost,
+    Mouse(MouseEvent),
+    /// Text pasted into the terminal, with line breaks as `\n`.
+    Paste(String),
+}
+
+impl Key {
+    /// The key for `motion` with `modifiers` held.
+    pub fn motion(motion: Motion, modifiers: Modifiers) -> Key {
+        if modifiers.is_empty() {
+            Key::Move(motion)
+        } else if modifiers == Modifiers::SHIFT {
+            Key::Select(motion)
+        } else {
+            Key::Modified(motion, modifiers)
+        }
+    }
+}
+
+/// Something for the editor to react to.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    Key(Key),
+    /// The terminal window changed size, to this many rows and columns.
+    Resize(usize, usize),
+    /// Nothing happened for as long as the editor was willing to wait.
+    Tick,
+}
+
+/// Output on its way to the terminal, collected so that a whole frame goes out at once instead
+/// of flickering in piece by piece. Rows drawn with `draw_at` are also remembered, so that the
+/// next frame only sends the ones that changed.
+#[derive(Default)]
+pub struct Screen {
+    buffer: String,
+    /// What the last frame drew at each position through `draw_at`.
+    drawn: HashMap<(usize, usize), String>,
+    /// What this frame has drawn through `draw_at` so far, to become `drawn` once flushed.
+    drawing: HashMap<(usize, usize), String>,
+}
+
+impl Screen {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn append(&mut self, content: &str) {
+        self.buffer.push_str(content);
+    }
+
+    /// Moves the cursor to the 0-based `row` and `col`.
+    pub fn move_to(&mut self, row: usize, col: usize) {
+        self.append(&format!("\x1b[{};{}H", row + 1, col + 1));
+    }
+
+    /// Draws `content` from the 0-based `row` and `col`, unless the last frame drew the same
+    /// there. That only holds up if `content` covers the same cells each time, padding
+    /// included, and if nothing else draws over it in between. A flush without anything drawn
+    /// this way, such as from a view that took over the screen, forgets the last frame.
+    pub fn draw_at(&mut self, row: usize, col: usize, content: &str) {
+        if self.drawn.get(&(row, col)).map(String::as_str) != Some(content) {
+            self.move_to(row, col);
+            self.append(content);
+        }
+        self.drawing.insert((row, col), content.to_string());
+    }
+
+    /// Forgets what the last frame drew, so that the next one draws everything.
+    pub fn invalidate(&mut self) {
+        self.drawn.clear();
+    }
+
+    /// Blanks the whole screen.
+    pub fn clear(&mut self) {
+        self.append("\x1b[2J");
+        self.invalidate();
+    }
+
+    /// Sends everything appended so far to the terminal.
+    pub fn flush(&mut self) {
+        let mut stdout = io::stdout().lock();
+        // Output goes nowhere once the terminal is gone, and there's no one left to tell.
+        let _ = stdout
+            .write_all(self.buffer.as_bytes())
+            .and_then(|_| stdout.flush());
+        self.buffer.clear();
+        self.drawn = std::mem::take(&mut self.drawing);
+    }
 }
 
+/// The controlling terminal in raw mode, restored when dropped. Reads keys and other events
+/// from standard input and draws through a `Screen` on standard output.
 pub struct Terminal {
-    orig_termios: Termios,
+    backend: Native,
     num_rows: usize,
     num_cols: usize,
-    term_buffer: String,
-    key_buffer: Vec<u8>,
+    screen: Screen,
+    keys: KeyDecoder<TerminalInput>,
+    capabilities: Capabilities,
+    /// Reports asked for, to ask for again after giving the terminal back: focus changes, the
+    /// mouse and pastes.
+    reports: [bool; 3],
 }
 
 impl Terminal {
+    /// Switches the terminal to raw mode on the alternate screen, if it has one, and starts
+    /// watching for window size changes.
     pub fn new() -> Result<Self> {
-        let orig_termios = Termios::get_attr()?;
+        let capabilities = Capabilities::detect();
+        let backend = Native::enable_raw_mode()?;
+        let (num_rows, num_cols) = Native::window_size()?;
+        Terminal::write(capabilities.enter_screen());
 
-        let mut curr_termios = orig_termios;
-        curr_termios.enable_raw_mode()?;
-
-        let (num_rows, num_cols) = WinSize::get_window_size()?;
+        // The panic message would land on the alternate screen and vanish with it, so the
+        // terminal is put back first. Dropping the terminal while unwinding finds it done.
+        let restore = backend.clone();
+        let default_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            Terminal::write(&capabilities.leave_screen());
+            let _ = restore.restore();
+            default_hook(info);
+        }));
 
         Ok(Self {
-            orig_termios,
+            backend,
             num_rows,
             num_cols,
-            term_buffer: String::new(),
-            key_buffer: Vec::new(),
+            screen: Screen::new(),
+            keys: KeyDecoder::new(TerminalInput),
+            capabilities,
+            reports: [false; 3],
         })
     }
 
-    pub fn refresh(&mut self) -> Result<()> {
-        let (rows, cols) = WinSize::get_window_size()?;
+    /// Points standard input and output at the controlling terminal where either is
+    /// redirected, so that `new` finds the terminal in the middle of a pipeline. Call it
+    /// before `new`, after reading what's piped in. Returns the standard output it replaced,
+    /// if it did.
+    pub fn attach() -> Result<Option<File>> {
+        Native::attach_terminal()
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    /// How long the bytes after an escape get to come before it counts as Escape pressed on
+    /// its own. Slow connections can need longer for Alt keys and sequences to come whole.
+    pub fn set_escape_timeout(&mut self, timeout: Duration) {
+        self.keys.set_escape_timeout(timeout);
+    }
+
+    /// Reads the window size again. Returns whether it changed.
+    pub fn refresh(&mut self) -> Result<bool> {
+        let (rows, cols) = Native::window_size()?;
+        let changed = (rows, cols) != (self.num_rows, self.num_cols);
         self.num_rows = rows;
         self.num_cols = cols;
-        Ok(())
+        Ok(changed)
     }
 
     pub fn rows(&self) -> usize {
@@ -155,49 +397,277 @@ impl Terminal {
         self.num_cols
     }
 
+    /// Sends `seq` straight to the terminal, bypassing the screen. Returns the number of bytes
+    /// written, or -1 on failure.
     pub fn write(seq: &str) -> isize {
-        unsafe { libc::write(STDOUT_FILENO, seq.as_ptr() as *const c_void, seq.len()) }
+        let mut stdout = io::stdout().lock();
+        match stdout
+            .write_all(seq.as_bytes())
+            .and_then(|_| stdout.flush())
+        {
+            Ok(()) => seq.len() as isize,
+            Err(_) => -1,
+        }
+    }
+
+    /// Asks the terminal to report focus changes as `Key::FocusGained`/`Key::FocusLost`, if it
+    /// can.
+    pub fn report_focus(&mut self, enable: bool) {
+        self.reports[0] = enable;
+        if !self.capabilities.focus {
+            return;
+        }
+        Terminal::write(if enable { "\x1b[?1004h" } else { "\x1b[?1004l" });
+    }
+
+    /// Asks the terminal to report mouse buttons, drags and the wheel in the SGR encoding as
+    /// `Key::Mouse`, if it can.
+    pub fn report_mouse(&mut self, enable: bool) {
+        self.reports[1] = enable;
+        if !self.capabilities.mouse {
+            return;
+        }
+        Terminal::write(if enable {
+            "\x1b[?1000;1002;1006h"
+        } else {
+            "\x1b[?1000;1002;1006l"
+        });
+    }
+
+    /// Asks the terminal to mark pasted text, so that it comes in whole as `Key::Paste`
+    /// instead of as keys that could set off bindings. Terminals that can't leave pastes to
+    /// come in as typing.
+    pub fn report_paste(&mut self, enable: bool) {
+        self.reports[2] = enable;
+        if !self.capabilities.bracketed_paste {
+            return;
+        }
+        Terminal::write(if enable { "\x1b[?2004h" } else { "\x1b[?2004l" });
+    }
+
+    /// Gives the terminal back the way `new` found it for as long as `run` takes, for a
+    /// program that uses it, like a shell command, then takes it over again. The reports
+    /// asked for are back on afterwards, and the screen is blank for the next frame to fill.
+    pub fn suspend<T>(&mut self, run: impl FnOnce() -> T) -> Result<T> {
+        Terminal::write(&self.capabilities.leave_screen());
+        self.backend.restore()?;
+        let result = run();
+        self.resume()?;
+        Ok(result)
     }
 
+    /// Gives the terminal back and stops, for the shell to take over until it continues the
+    /// editor, as Ctrl-Z does elsewhere. Returns once continued, with the terminal taken over
+    /// again as `suspend` leaves it.
+    pub fn stop(&mut self) -> Result<()> {
+        self.suspend(Native::stop)
+    }
+
+    /// Takes the terminal over again after giving it back, in raw mode and with the reports
+    /// asked for.
+    fn resume(&mut self) -> Result<()> {
+        self.backend = Native::enable_raw_mode()?;
+        Terminal::write(self.capabilities.enter_screen());
+        let [focus, mouse, paste] = self.reports;
+        self.report_focus(focus);
+        self.report_mouse(mouse);
+        self.report_paste(paste);
+        self.screen.clear();
+        Ok(())
+    }
+
+    pub fn screen(&mut self) -> &mut Screen {
+        &mut self.screen
+    }
+
+    /// Shorthand for `screen().append(content)`.
     pub fn append(&mut self, content: &str) {
-        self.term_buffer.push_str(content);
+        self.screen.append(content);
     }
 
+    /// Shorthand for `screen().flush()`.
     pub fn flush(&mut self) {
-        Terminal::write(self.term_buffer.as_str());
-        self.term_buffer.clear();
+        self.screen.flush();
+    }
+
+    /// Whether more input is already waiting to be read.
+    pub fn input_pending(&self) -> bool {
+        self.keys.has_pending() || Native::wait_for_input(Duration::ZERO).unwrap_or(false)
+    }
+
+    /// Waits up to `timeout` for a key or a change of the window size, reporting a `Tick` if
+    /// neither comes. Being stopped and continued by signals reports a resize, since the window
+    /// may have changed meanwhile and the screen needs drawing again either way.
+    pub fn read_event(&mut self, timeout: Duration) -> Result<Event> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if Native::take_stop() {
+                self.stop()?;
+                let (rows, cols) = Native::window_size()?;
+                return Ok(Event::Resize(rows, cols));
+            }
+            // Stopped some other way, the shell may have put the terminal back in the
+            // meantime.
+            if Native::take_continue() {
+                self.backend.restore()?;
+                self.resume()?;
+                let (rows, cols) = Native::window_size()?;
+                return Ok(Event::Resize(rows, cols));
+            }
+            if Native::take_resize() {
+                let (rows, cols) = Native::window_size()?;
+                return Ok(Event::Resize(rows, cols));
+            }
+            if self.keys.has_pending() {
+                return self.keys.decode().map(Event::Key);
+            }
+            let wait = deadline.saturating_duration_since(Instant::now());
+            // A resize cuts the wait short, to be picked up at the top.
+            if Native::wait_for_input(wait)? {
+                return self.keys.decode().map(Event::Key);
+            }
+            if wait.is_zero() {
+                return Ok(Event::Tick);
+            }
+        }
+    }
+
+    pub fn read_key_timeout(&mut self, timeout: Duration) -> Result<Option<Key>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.read_event(deadline.saturating_duration_since(Instant::now()))? {
+                Event::Key(key) => return Ok(Some(key)),
+                Event::Tick => return Ok(None),
+                Event::Resize(..) => {}
+            }
+        }
     }
 
+    /// Waits for the next key, however long it takes. Resizes in the meantime go unreported.
     pub fn read_key(&mut self) -> Result<Key> {
-        let read_key = || io::stdin().bytes().next();
-        let key = if let Some(pending_key) = self.key_buffer.pop() {
-            pending_key
-        } else {
-            std::iter::repeat_with(read_key)
-                .skip_while(|c| c.is_none())
-                .flatten()
-                .next()
-                .unwrap()?
-        };
+        loop {
+            if let Event::Key(key) = self.read_event(KEY_WAIT)? {
+                return Ok(key);
+            }
+        }
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            return;
+        }
+        Terminal::write(&self.capabilities.leave_screen());
+        // Nothing more can be done about a terminal that won't go back, and panicking here
+        // would only lose the rest of the cleanup.
+        let _ = self.backend.restore();
+    }
+}
+
+/// Where keys come from, a byte at a time.
+pub trait InputSource {
+    /// The next byte, or None if none comes within `timeout`.
+    fn read_byte(&mut self, timeout: Duration) -> Option<Result<u8>>;
+
+    /// The next byte, however long it takes, for the rest of a sequence already started.
+    fn wait_byte(&mut self) -> Result<u8> {
+        loop {
+            if let Some(byte) = self.read_byte(KEY_WAIT) {
+                return byte;
+            }
+        }
+    }
+}
+
+/// Input that's all there already, like bytes in memory. Its end is taken for a pause in
+/// typing, or an error where a sequence needs more.
+impl<R: Read> InputSource for R {
+    fn read_byte(&mut self, _: Duration) -> Option<Result<u8>> {
+        let mut byte = [0];
+        match self.read(&mut byte) {
+            Ok(0) => None,
+            Ok(_) => Some(Ok(byte[0])),
+            Err(err) => Some(Err(err)),
+        }
+    }
+
+    fn wait_byte(&mut self) -> Result<u8> {
+        self.read_byte(Duration::ZERO)
+            .unwrap_or_else(|| Err(ErrorKind::UnexpectedEof.into()))
+    }
+}
+
+/// Standard input of the terminal in raw mode.
+pub struct TerminalInput;
+
+impl InputSource for TerminalInput {
+    fn read_byte(&mut self, timeout: Duration) -> Option<Result<u8>> {
+        match Native::wait_for_input(timeout) {
+            Ok(true) => Native::read_byte(),
+            Ok(false) => None,
+            Err(err) => Some(Err(err)),
+        }
+    }
+}
+
+/// Turns the bytes terminals send for keys, escape sequences and all, into `Key`s.
+pub struct KeyDecoder<I> {
+    input: I,
+    /// Bytes read ahead of the key decoded last, the next one last.
+    pending: Vec<u8>,
+    escape_timeout: Duration,
+}
+
+impl<I: InputSource> KeyDecoder<I> {
+    pub fn new(input: I) -> Self {
+        Self {
+            input,
+            pending: Vec::new(),
+            escape_timeout: ESCAPE_TIMEOUT,
+        }
+    }
+
+    pub fn set_escape_timeout(&mut self, timeout: Duration) {
+        self.escape_timeout = timeout;
+    }
+
+    /// Whether bytes read ahead are waiting to be decoded.
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Decodes the next key, waiting for its first byte.
+    pub fn decode(&mut self) -> Result<Key> {
+        let key = self.next_byte()?;
 
         Ok(if key == b'\x1b' {
-            let seq = self
-                .key_buffer
-                .iter()
-                .rev()
-                .map(|byte| Some(Ok(*byte)))
-                .chain(std::iter::repeat_with(read_key))
-                .take(3)
-                .map(|k| k.transpose())
-                .collect::<Result<Vec<Option<u8>>>>()?;
+            // Bytes of a sequence come in together, so none following soon means a bare Escape.
+            let mut seq = Vec::with_capacity(3);
+            while seq.len() < 3 {
+                let byte = match seq.as_slice() {
+                    // One byte late means the rest aren't coming either.
+                    [.., None] => None,
+                    // Parameters of a control sequence always have more after them.
+                    [Some(b'['), Some(b'0'..=b'?')] => Some(self.next_byte()?),
+                    _ => self.next_byte_soon()?,
+                };
+                seq.push(byte);
+            }
 
             let (key, pending) = match seq.as_slice() {
                 [None, None, None] => (Key::Escape, None),
 
-                [Some(b'['), Some(b'A'), pending] => (Key::Move(Motion::Up), *pending),
-                [Some(b'['), Some(b'B'), pending] => (Key::Move(Motion::Down), *pending),
-                [Some(b'['), Some(b'C'), pending] => (Key::Move(Motion::Right), *pending),
-                [Some(b'['), Some(b'D'), pending] => (Key::Move(Motion::Left), *pending),
+                // Terminals in application mode send SS3, `\x1bO`, for the arrows, Home and End.
+                [Some(b'[' | b'O'), Some(b'A'), pending] => (Key::Move(Motion::Up), *pending),
+                [Some(b'[' | b'O'), Some(b'B'), pending] => (Key::Move(Motion::Down), *pending),
+                [Some(b'[' | b'O'), Some(b'C'), pending] => (Key::Move(Motion::Right), *pending),
+                [Some(b'[' | b'O'), Some(b'D'), pending] => (Key::Move(Motion::Left), *pending),
+                [Some(b'O'), Some(b'H'), pending] => (Key::Move(Motion::Home), *pending),
+                [Some(b'O'), Some(b'F'), pending] => (Key::Move(Motion::End), *pending),
+                [Some(b'O'), Some(code @ b'P'..=b'S'), pending] => {
+                    (Key::Function(code - b'P' + 1), *pending)
+                }
 
                 [Some(b'['), Some(b'5'), Some(b'~')] => (Key::Move(Motion::PgUp), None),
                 [Some(b'['), Some(b'6'), Some(b'~')] => (Key::Move(Motion::PgDn), None),
@@ -212,17 +682,56 @@ impl Terminal {
                 [Some(b'['), Some(b'O'), Some(b'F')] => (Key::Move(Motion::End), None),
                 [Some(b'['), Some(b'F'), pending] => (Key::Move(Motion::End), *pending),
 
+                [Some(b'['), Some(b'1'), Some(b';')] => (self.read_modified_motion()?, None),
+                [Some(b'['), Some(b'1'), Some(digit @ b'1'..=b'9')] => {
+                    (self.read_function(10 + digit - b'0')?, None)
+                }
+                [Some(b'['), Some(b'2'), Some(digit @ (b'1' | b'3' | b'4'))] => {
+                    (self.read_function(20 + digit - b'0')?, None)
+                }
+
+                [Some(b'['), Some(b'2'), Some(b'~')] => (Key::Insert, None),
                 [Some(b'['), Some(b'3'), Some(b'~')] => (Key::Delete, None),
+                [Some(b'['), Some(code @ b'2'..=b'8'), Some(b';')] => {
+                    (self.read_modified_key(*code)?, None)
+                }
+
+                [Some(b'['), Some(b'<'), Some(first)] => (self.read_mouse(*first)?, None),
+                [Some(b'['), Some(b'2'), Some(b'0')] => (self.read_paste()?, None),
 
+                [Some(b'['), Some(b'I'), pending] => (Key::FocusGained, *pending),
+                [Some(b'['), Some(b'O'), pending] => (Key::FocusLost, *pending),
+
+                // Another escape: this one was pressed on its own.
+                [Some(b'\x1b'), ..] => {
+                    self.pending.extend(seq.iter().rev().flatten());
+                    (Key::Escape, None)
+                }
+                // A key typed with Alt, unless it starts a sequence the editor doesn't know.
+                [Some(ch @ b' '..=b'~'), rest @ ..] if *ch != b'[' || rest[0].is_none() => {
+                    self.pending.extend(rest.iter().rev().flatten());
+                    (Key::Alt(*ch as char), None)
+                }
+                // Any other control sequence goes whole, instead of coming out as typing.
+                [Some(b'['), Some(b'@'..=b'~'), pending] => {
+                    self.pending.extend(*pending);
+                    (self.decode()?, None)
+                }
+                [Some(b'['), Some(b' '..=b'?'), Some(last)] => {
+                    let mut last = *last;
+                    while !(b'@'..=b'~').contains(&last) {
+                        last = self.next_byte()?;
+                    }
+                    (self.decode()?, None)
+                }
                 _ => {
-                    self.key_buffer.clear();
-                    self.key_buffer.extend(seq.iter().rev().filter_map(|&k| k));
-                    (self.read_key()?, None)
+                    self.pending.extend(seq.iter().rev().flatten());
+                    (self.decode()?, None)
                 }
             };
 
             if let Some(key) = pending {
-                self.key_buffer.push(key);
+                self.pending.push(key);
             }
 
             key
@@ -232,18 +741,355 @@ impl Terminal {
                 b'\r' => Key::Newline,
                 b'\t' => Key::Tab,
                 key if key < 32 => Key::Control((key + 64) as char),
-                key => Key::Printable(key as char),
+                key if key < 128 => Key::Printable(key as char),
+                key => self.read_utf8(key)?,
             }
         })
     }
+
+    /// Next byte, read ahead or waited for.
+    fn next_byte(&mut self) -> Result<u8> {
+        match self.pending.pop() {
+            Some(byte) => Ok(byte),
+            None => self.input.wait_byte(),
+        }
+    }
+
+    /// Next byte, read ahead or come within the escape timeout.
+    fn next_byte_soon(&mut self) -> Result<Option<u8>> {
+        match self.pending.pop() {
+            Some(byte) => Ok(Some(byte)),
+            None => self.input.read_byte(self.escape_timeout).transpose(),
+        }
+    }
+
+    /// Reads the rest of a UTF-8 encoded character given its first byte. Malformed input comes
+    /// out as the replacement character.
+    fn read_utf8(&mut self, first: u8) -> Result<Key> {
+        let len = match first {
+            0xC0..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF7 => 4,
+            _ => 1,
+        };
+        let mut bytes = vec![first];
+        while bytes.len() < len {
+            let Some(byte) = self.next_byte_soon()? else {
+                break;
+            };
+            if byte & 0xC0 != 0x80 {
+                // Not a continuation byte, so it starts the next key.
+                self.pending.push(byte);
+                break;
+            }
+            bytes.push(byte);
+        }
+        let ch = std::str::from_utf8(&bytes)
+            .ok()
+            .and_then(|text| text.chars().next())
+            .unwrap_or(char::REPLACEMENT_CHARACTER);
+        Ok(Key::Printable(ch))
+    }
+
+    /// Reads the rest of a bracketed paste, `\x1b[200~` followed by the text and `\x1b[201~`,
+    /// given its first three bytes after the escape. A stray end marker is skipped in favour
+    /// of the next key.
+    fn read_paste(&mut self) -> Result<Key> {
+        // F9 is `\x1b[20~`.
+        let marker = match self.next_byte()? {
+            b'~' => return Ok(Key::Function(9)),
+            b';' => return self.read_function(20),
+            byte => [byte, self.next_byte()?],
+        };
+        if marker != *b"0~" {
+            return self.decode();
+        }
+        let mut text = Vec::new();
+        while !text.ends_with(PASTE_END) {
+            text.push(self.next_byte()?);
+        }
+        text.truncate(text.len() - PASTE_END.len());
+        // Terminals send line breaks in pastes as carriage returns, like the Enter key.
+        let text = String::from_utf8_lossy(&text)
+            .replace("\r\n", "\n")
+            .replace('\r', "\n");
+        Ok(Key::Paste(text))
+    }
+
+    /// Reads the rest of an arrow, Home, End or F1 to F4 key pressed with modifiers, `\x1b[1;`
+    /// followed by the modifiers and the key's letter. Modifiers of function keys are dropped.
+    fn read_modified_motion(&mut self) -> Result<Key> {
+        let mut param = 0u8;
+        let mut byte = self.next_byte()?;
+        while byte.is_ascii_digit() {
+            param = param.saturating_mul(10).saturating_add(byte - b'0');
+            byte = self.next_byte()?;
+        }
+        let motion = match byte {
+            b'A' => Motion::Up,
+            b'B' => Motion::Down,
+            b'C' => Motion::Right,
+            b'D' => Motion::Left,
+            b'H' => Motion::Home,
+            b'F' => Motion::End,
+            b'P'..=b'S' => return Ok(Key::Function(byte - b'P' + 1)),
+            _ => return self.decode(),
+        };
+        Ok(Key::motion(motion, Modifiers::from_param(param)))
+    }
+
+    /// Reads the rest of an editing or paging key pressed with modifiers, `\x1b[` followed by
+    /// its one digit `code` and a `;`, then the modifiers and a `~`. Modifiers are dropped.
+    fn read_modified_key(&mut self, code: u8) -> Result<Key> {
+        let mut last = self.next_byte()?;
+        while !(b'@'..=b'~').contains(&last) {
+            last = self.next_byte()?;
+        }
+        Ok(match (code, last) {
+            (b'2', b'~') => Key::Insert,
+            (b'3', b'~') => Key::Delete,
+            (b'4' | b'8', b'~') => Key::Move(Motion::End),
+            (b'5', b'~') => Key::Move(Motion::PgUp),
+            (b'6', b'~') => Key::Move(Motion::PgDn),
+            (b'7', b'~') => Key::Move(Motion::Home),
+            _ => return self.decode(),
+        })
+    }
+
+    /// Reads the rest of a function key, `\x1b[` followed by its two digit `code`, maybe
+    /// modifiers after a `;`, and a `~`. Modifiers are dropped, and codes of keys the editor
+    /// has no use for are skipped in favour of the next key.
+    fn read_function(&mut self, code: u8) -> Result<Key> {
+        let mut last = self.next_byte()?;
+        while !(b'@'..=b'~').contains(&last) {
+            last = self.next_byte()?;
+        }
+        Ok(match (code, last) {
+            (11..=15, b'~') => Key::Function(code - 10),
+            (17..=21, b'~') => Key::Function(code - 11),
+            (23 | 24, b'~') => Key::Function(code - 12),
+            _ => return self.decode(),
+        })
+    }
+
+    /// Reads the rest of an SGR mouse report, `\x1b[<button;col;row` followed by `M` on press
+    /// or `m` on release, given its first parameter byte. Reports the editor has no use for are
+    /// skipped in favour of the next key.
+    fn read_mouse(&mut self, first: u8) -> Result<Key> {
+        let mut report = vec![first];
+        while !matches!(report.last(), Some(b'M') | Some(b'm')) {
+            report.push(self.next_byte()?);
+        }
+        let params = std::str::from_utf8(&report[..report.len() - 1])
+            .unwrap_or_default()
+            .split(';')
+            .filter_map(|param| param.parse::<u16>().ok())
+            .collect::<Vec<u16>>();
+
+        let (code, col, row) = match params.as_slice() {
+            &[code, col, row] => (code, col as usize, row as usize),
+            _ => return self.decode(),
+        };
+        let (row, col) = (row.saturating_sub(1), col.saturating_sub(1));
+        // Bits 2-4 of the code carry Shift, Meta and Control, bit 5 marks motion and bit 6
+        // the wheel. The low bits pick the button.
+        let fast = code & 0b11100 != 0;
+        let button = match code & 0b11 {
+            0 => MouseButton::Left,
+            1 => MouseButton::Middle,
+            _ => MouseButton::Right,
+        };
+        let event = match (code & !0b11111, code & 0b11) {
+            (64, 0) => MouseEvent::Wheel { up: true, fast },
+            (64, 1) => MouseEvent::Wheel { up: false, fast },
+            // Release without a button is the legacy encoding, and sideways wheels aren't used.
+            (_, 3) | (64, _) => return self.decode(),
+            (32, _) => MouseEvent::Drag { button, row, col },
+            (0, _) if report.last() == Some(&b'm') => MouseEvent::Release { button, row, col },
+            (0, 0) if code & 0b10000 != 0 => MouseEvent::ControlClick { row, col },
+            (0, _) => MouseEvent::Press { button, row, col },
+            _ => return self.decode(),
+        };
+        Ok(Key::Mouse(event))
+    }
 }
 
-impl Drop for Terminal {
-    fn drop(&mut self) {
-        Terminal::write("\x1b[2J");
-        Terminal::write("\x1b[H");
-        self.orig_termios
-            .set_attr()
-            .expect("Failed to restore terminal state");
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every key in `input`, as if it had been typed in one go.
+    fn keys(input: &[u8]) -> Vec<Key> {
+        let mut decoder = KeyDecoder::new(input);
+        let mut keys = Vec::new();
+        while decoder.has_pending() || !decoder.input.is_empty() {
+            keys.push(decoder.decode().expect("a whole key"));
+        }
+        keys
+    }
+
+    #[test]
+    fn arrows() {
+        use Motion::*;
+        assert_eq!(
+            keys(b"\x1b[A\x1b[B\x1b[C\x1b[D"),
+            [Up, Down, Right, Left].map(Key::Move)
+        );
+        assert_eq!(
+            keys(b"\x1bOA\x1bOB\x1bOC\x1bOD"),
+            [Up, Down, Right, Left].map(Key::Move)
+        );
+    }
+
+    #[test]
+    fn home_and_end() {
+        let home = b"\x1b[1~\x1b[7~\x1b[H\x1bOH\x1b[OH";
+        assert_eq!(keys(home), vec![Key::Move(Motion::Home); 5]);
+        let end = b"\x1b[4~\x1b[8~\x1b[F\x1bOF\x1b[OF";
+        assert_eq!(keys(end), vec![Key::Move(Motion::End); 5]);
+    }
+
+    #[test]
+    fn editing_and_paging_keys() {
+        assert_eq!(
+            keys(b"\x1b[3~\x1b[2~\x1b[5~\x1b[6~\x7f\r\t"),
+            [
+                Key::Delete,
+                Key::Insert,
+                Key::Move(Motion::PgUp),
+                Key::Move(Motion::PgDn),
+                Key::Backspace,
+                Key::Newline,
+                Key::Tab,
+            ]
+        );
+    }
+
+    #[test]
+    fn bare_escape() {
+        assert_eq!(keys(b"\x1b"), [Key::Escape]);
+        assert_eq!(keys(b"\x1b\x1b"), [Key::Escape, Key::Escape]);
+        assert_eq!(keys(b"\x1b\x1b[A"), [Key::Escape, Key::Move(Motion::Up)]);
+    }
+
+    #[test]
+    fn interleaved_sequences() {
+        assert_eq!(
+            keys(b"a\x1b[Ab\x1b[3~\x11\x1b[1;2D\x1b[Ix\xc3\xa9"),
+            [
+                Key::Printable('a'),
+                Key::Move(Motion::Up),
+                Key::Printable('b'),
+                Key::Delete,
+                Key::Control('Q'),
+                Key::Select(Motion::Left),
+                Key::FocusGained,
+                Key::Printable('x'),
+                Key::Printable('é'),
+            ]
+        );
+    }
+
+    #[test]
+    fn modified_motions() {
+        assert_eq!(
+            keys(b"\x1b[1;2H\x1b[1;5C\x1b[1;6B\x1b[1;3D\x1b[1;1A"),
+            [
+                Key::Select(Motion::Home),
+                Key::Modified(Motion::Right, Modifiers::CTRL),
+                Key::Modified(Motion::Down, Modifiers::CTRL | Modifiers::SHIFT),
+                Key::Modified(Motion::Left, Modifiers::ALT),
+                Key::Move(Motion::Up),
+            ]
+        );
+    }
+
+    #[test]
+    fn function_keys() {
+        assert_eq!(
+            keys(b"\x1bOP\x1bOQ\x1bOR\x1bOS\x1b[15~\x1b[17~\x1b[18~\x1b[19~"),
+            (1..=8).map(Key::Function).collect::<Vec<Key>>()
+        );
+        assert_eq!(
+            keys(b"\x1b[20~\x1b[21~\x1b[23~\x1b[24~"),
+            (9..=12).map(Key::Function).collect::<Vec<Key>>()
+        );
+        // With modifiers, which are dropped, and the codes some terminals send for F1 to F4.
+        assert_eq!(
+            keys(b"\x1b[1;5P\x1b[15;2~\x1b[20;3~\x1b[11~\x1b[14~"),
+            [1, 5, 9, 1, 4].map(Key::Function)
+        );
+    }
+
+    #[test]
+    fn longer_sequences() {
+        // Editing and paging keys with modifiers, and sequences the editor has no use for.
+        assert_eq!(
+            keys(b"\x1b[3;5~\x1b[6;2~\x1b[?1;2cx\x1b[Zy\x1b[12;34Hz"),
+            [
+                Key::Delete,
+                Key::Move(Motion::PgDn),
+                Key::Printable('x'),
+                Key::Printable('y'),
+                Key::Printable('z'),
+            ]
+        );
+    }
+
+    #[test]
+    fn alt_keys() {
+        assert_eq!(
+            keys(b"\x1bx\x1bB\x1b.\x1b\x1b[Aa"),
+            [
+                Key::Alt('x'),
+                Key::Alt('B'),
+                Key::Alt('.'),
+                Key::Escape,
+                Key::Move(Motion::Up),
+                Key::Printable('a'),
+            ]
+        );
+        // Alt with the first byte of a sequence, typed on its own.
+        assert_eq!(keys(b"\x1bO"), [Key::Alt('O')]);
+        assert_eq!(keys(b"\x1b["), [Key::Alt('[')]);
+    }
+
+    #[test]
+    fn paste_and_mouse() {
+        assert_eq!(
+            keys(b"\x1b[200~one\r\ntwo\x1b[201~\x1b[<0;3;2M\x1b[<64;1;1M\x1b[<16;5;1M"),
+            [
+                Key::Paste("one\ntwo".to_string()),
+                Key::Mouse(MouseEvent::Press {
+                    button: MouseButton::Left,
+                    row: 1,
+                    col: 2,
+                }),
+                Key::Mouse(MouseEvent::Wheel {
+                    up: true,
+                    fast: false,
+                }),
+                Key::Mouse(MouseEvent::ControlClick { row: 0, col: 4 }),
+            ]
+        );
+    }
+
+    #[test]
+    fn sequence_cut_short() {
+        let mut decoder = KeyDecoder::new(&b"\x1b[200~unfinished"[..]);
+        let err = decoder.decode().expect_err("the paste never ends");
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn capabilities_by_term() {
+        let dumb = Capabilities::of("dumb", Some(false), None);
+        assert_eq!(dumb.leave_screen(), "\x1b[m\x1b[?25h\x1b[H\x1b[2J");
+        assert_eq!(Capabilities::of("", None, None), dumb);
+        let xterm = Capabilities::of("xterm-256color", None, None);
+        assert!(xterm.alternate_screen && xterm.mouse && xterm.bracketed_paste);
+        let linux = Capabilities::of("linux", Some(false), Some(b"\x1b[M"));
+        assert!(!linux.alternate_screen && !linux.mouse && !linux.focus);
+        assert!(Capabilities::of("mlterm", Some(true), Some(b"\x1b[<")).mouse);
     }
 }
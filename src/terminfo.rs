@@ -0,0 +1,103 @@
+use std::convert::TryFrom;
+use std::path::PathBuf;
+
+/// Index of `max_colors` among the numbers of an entry, as term.h orders them.
+pub const MAX_COLORS: usize = 13;
+/// Index of `enter_ca_mode`, what switches to the alternate screen, among the strings.
+pub const ENTER_CA_MODE: usize = 28;
+/// Index of `key_mouse`, what starts a mouse report, among the strings.
+pub const KEY_MOUSE: usize = 355;
+
+/// A compiled terminfo entry, describing what a terminal type can do.
+pub struct Terminfo {
+    bytes: Vec<u8>,
+    /// Bytes a number takes: 2 in the legacy format and 4 in the extended one.
+    width: usize,
+    /// Counts of the numbers and the strings, and where the numbers start.
+    numbers: usize,
+    strings: usize,
+    numbers_at: usize,
+}
+
+impl Terminfo {
+    /// The entry for `term`, looked for where ncurses looks.
+    pub fn load(term: &str) -> Option<Terminfo> {
+        let first = term.chars().next()?;
+        let mut dirs = Vec::new();
+        dirs.extend(std::env::var_os("TERMINFO").map(PathBuf::from));
+        if let Some(home) = std::env::var_os("HOME") {
+            dirs.push(PathBuf::from(home).join(".terminfo"));
+        }
+        if let Some(listed) = std::env::var_os("TERMINFO_DIRS") {
+            dirs.extend(std::env::split_paths(&listed));
+        }
+        for dir in [
+            "/etc/terminfo",
+            "/lib/terminfo",
+            "/usr/share/terminfo",
+            "/usr/lib/terminfo",
+        ] {
+            dirs.push(PathBuf::from(dir));
+        }
+        // Entries go under their first letter, or its code in hex on macOS.
+        let bytes = dirs.iter().find_map(|dir| {
+            [first.to_string(), format!("{:x}", first as u32)]
+                .iter()
+                .find_map(|sub| std::fs::read(dir.join(sub).join(term)).ok())
+        })?;
+        Terminfo::parse(bytes)
+    }
+
+    /// Reads the header of a compiled entry.
+    fn parse(bytes: Vec<u8>) -> Option<Terminfo> {
+        let header = |idx: usize| -> Option<usize> {
+            let pair = bytes.get(idx * 2..idx * 2 + 2)?;
+            Some(u16::from_le_bytes([pair[0], pair[1]]) as usize)
+        };
+        let width = match header(0)? {
+            0o432 => 2,
+            0o1036 => 4,
+            _ => return None,
+        };
+        let (names, booleans) = (header(1)?, header(2)?);
+        let (numbers, strings) = (header(3)?, header(4)?);
+        // The numbers come after the names and the booleans, padded to an even size.
+        let start = 12 + names + booleans;
+        Some(Terminfo {
+            width,
+            numbers,
+            strings,
+            numbers_at: start + start % 2,
+            bytes,
+        })
+    }
+
+    /// The number at `idx`, or None if the entry doesn't have it.
+    pub fn number(&self, idx: usize) -> Option<u32> {
+        if idx >= self.numbers {
+            return None;
+        }
+        let at = self.numbers_at + idx * self.width;
+        let bytes = self.bytes.get(at..at + self.width)?;
+        let value = match self.width {
+            2 => i16::from_le_bytes([bytes[0], bytes[1]]) as i32,
+            _ => i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        };
+        u32::try_from(value).ok()
+    }
+
+    /// The string at `idx`, or None if the entry doesn't have it.
+    pub fn string(&self, idx: usize) -> Option<&[u8]> {
+        if idx >= self.strings {
+            return None;
+        }
+        let offsets = self.numbers_at + self.numbers * self.width;
+        let at = offsets + idx * 2;
+        let offset = i16::from_le_bytes([*self.bytes.get(at)?, *self.bytes.get(at + 1)?]);
+        // Missing strings have offsets of -1, and cancelled ones -2.
+        let start = offsets + self.strings * 2 + usize::try_from(offset).ok()?;
+        let text = self.bytes.get(start..)?;
+        let end = text.iter().position(|&byte| byte == 0)?;
+        Some(&text[..end])
+    }
+}
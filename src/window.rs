@@ -0,0 +1,424 @@
+use crate::buffer::Cursor;
+use std::cmp::min;
+use std::path::{Path, PathBuf};
+
+/// Screen area in 0-based cells.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct Rect {
+    pub top: usize,
+    pub left: usize,
+    pub height: usize,
+    pub width: usize,
+}
+
+impl Rect {
+    pub fn contains(&self, row: usize, col: usize) -> bool {
+        (self.top..self.top + self.height).contains(&row)
+            && (self.left..self.left + self.width).contains(&col)
+    }
+}
+
+/// A view into the buffer, occupying one leaf of the layout.
+#[derive(Default)]
+pub struct Window {
+    /// Cursor and scroll offsets of this view. Only up to date while the window isn't focused,
+    /// since the focused window's view lives in the buffer itself.
+    pub cursor: Cursor,
+    pub rect: Rect,
+    /// Scroll together with other scroll-bound windows.
+    pub scroll_bind: bool,
+    /// Working directory of this window alone, overriding the editor's.
+    pub cwd: Option<PathBuf>,
+    /// Views this window had into files shown in it before, most recent first, to go back to
+    /// when they're shown again.
+    pub views: Vec<(PathBuf, Cursor)>,
+}
+
+impl Window {
+    pub fn remember_view(&mut self, file: PathBuf, cursor: Cursor) {
+        self.views.retain(|(path, _)| *path != file);
+        self.views.insert(0, (file, cursor));
+    }
+
+    pub fn recall_view(&self, file: &Path) -> Option<Cursor> {
+        self.views
+            .iter()
+            .find(|(path, _)| path == file)
+            .map(|&(_, cursor)| cursor)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Split {
+    /// Windows stacked on top of each other.
+    Horizontal,
+    /// Windows side by side, separated by a column.
+    Vertical,
+}
+
+/// How the columns between side by side windows are drawn.
+#[derive(Clone, Copy, PartialEq)]
+pub enum BorderStyle {
+    /// An inverse video `|`, matching the status bars.
+    Bar,
+    /// Box-drawing `│`.
+    Line,
+    /// Box-drawing `┃`.
+    Heavy,
+    /// Box-drawing `║`.
+    Double,
+}
+
+impl BorderStyle {
+    pub fn vertical(self) -> char {
+        match self {
+            BorderStyle::Bar => '|',
+            BorderStyle::Line => '│',
+            BorderStyle::Heavy => '┃',
+            BorderStyle::Double => '║',
+        }
+    }
+
+    /// Escape sequence to draw the border in, highlighted where it runs along the focused
+    /// window.
+    pub fn attributes(self, active: bool) -> &'static str {
+        match (self, active) {
+            (BorderStyle::Bar, false) => "\x1b[7m",
+            (BorderStyle::Bar, true) => "\x1b[7;33m",
+            (_, false) => "\x1b[2m",
+            (_, true) => "\x1b[1;33m",
+        }
+    }
+}
+
+/// What the gutter left of the text shows.
+#[derive(Clone, Copy, PartialEq)]
+pub enum LineNumbers {
+    /// No gutter.
+    Off,
+    /// The number of each line.
+    Absolute,
+    /// The number of the cursor line, and how far every other line is from it.
+    Relative,
+}
+
+impl LineNumbers {
+    pub fn name(self) -> &'static str {
+        match self {
+            LineNumbers::Off => "off",
+            LineNumbers::Absolute => "absolute",
+            LineNumbers::Relative => "relative",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<LineNumbers> {
+        match name {
+            "off" => Some(LineNumbers::Off),
+            "absolute" => Some(LineNumbers::Absolute),
+            "relative" => Some(LineNumbers::Relative),
+            _ => None,
+        }
+    }
+
+    /// Columns the gutter takes for `lines` lines of text, counting the space after the
+    /// numbers.
+    pub fn width(self, lines: usize) -> usize {
+        match self {
+            LineNumbers::Off => 0,
+            _ => lines.to_string().len().max(3) + 1,
+        }
+    }
+
+    /// Gutter of the line at `row` with the cursor on `cursor_row`, `width` columns wide.
+    pub fn label(self, row: usize, cursor_row: usize, width: usize) -> String {
+        let number = match self {
+            LineNumbers::Off => return String::new(),
+            LineNumbers::Relative if row != cursor_row => row.abs_diff(cursor_row),
+            _ => row + 1,
+        };
+        format!("{:>pad$} ", number, pad = width.saturating_sub(1))
+    }
+}
+
+/// Minimum number of cells a window keeps along either direction.
+pub const MIN_SIZE: usize = 2;
+
+#[derive(Clone)]
+enum Node {
+    Window(usize),
+    /// Children with their size along the split direction. Sizes are kept as the actual cells
+    /// of the last arrangement and act as weights when the available space changes.
+    Split(Split, Vec<(Node, usize)>),
+}
+
+/// Tree of splits deciding where each window goes on the screen. Windows are referred to by
+/// their index in the editor's window list.
+#[derive(Clone)]
+pub struct Layout {
+    root: Node,
+}
+
+impl Layout {
+    pub fn new(window: usize) -> Self {
+        Self {
+            root: Node::Window(window),
+        }
+    }
+
+    /// Windows in screen order, from the top left.
+    pub fn windows(&self) -> Vec<usize> {
+        fn collect(node: &Node, windows: &mut Vec<usize>) {
+            match node {
+                Node::Window(idx) => windows.push(*idx),
+                Node::Split(_, children) => children
+                    .iter()
+                    .for_each(|(child, _)| collect(child, windows)),
+            }
+        }
+        let mut windows = Vec::new();
+        collect(&self.root, &mut windows);
+        windows
+    }
+
+    /// The layout as text, for keeping it in a session: a window as its index, and a split as
+    /// `h` or `v` followed by its children and their sizes, like `v(0:40,h(1:12,2:11):39)`.
+    pub fn to_text(&self) -> String {
+        fn write(node: &Node, text: &mut String) {
+            match node {
+                Node::Window(idx) => text.push_str(&idx.to_string()),
+                Node::Split(split, children) => {
+                    text.push(if *split == Split::Horizontal {
+                        'h'
+                    } else {
+                        'v'
+                    });
+                    text.push('(');
+                    for (n, (child, size)) in children.iter().enumerate() {
+                        if n > 0 {
+                            text.push(',');
+                        }
+                        write(child, text);
+                        text.push_str(&format!(":{}", size));
+                    }
+                    text.push(')');
+                }
+            }
+        }
+        let mut text = String::new();
+        write(&self.root, &mut text);
+        text
+    }
+
+    /// Reads a layout written by `to_text`.
+    pub fn parse(text: &str) -> Option<Layout> {
+        fn number(text: &mut &str) -> Option<usize> {
+            let end = text
+                .find(|ch: char| !ch.is_ascii_digit())
+                .unwrap_or(text.len());
+            let (digits, rest) = text.split_at(end);
+            *text = rest;
+            digits.parse().ok()
+        }
+        fn node(text: &mut &str) -> Option<Node> {
+            let split = match text.chars().next()? {
+                'h' => Split::Horizontal,
+                'v' => Split::Vertical,
+                _ => return number(text).map(Node::Window),
+            };
+            *text = text.get(1..)?.strip_prefix('(')?;
+            let mut children = Vec::new();
+            loop {
+                let child = node(text)?;
+                *text = text.strip_prefix(':')?;
+                children.push((child, number(text)?));
+                match text.chars().next()? {
+                    ',' => *text = &text[1..],
+                    ')' => break,
+                    _ => return None,
+                }
+            }
+            *text = &text[1..];
+            (children.len() > 1).then_some(Node::Split(split, children))
+        }
+        let mut text = text;
+        let root = node(&mut text)?;
+        text.is_empty().then_some(Layout { root })
+    }
+
+    /// Splits `target` in two, placing `window` after it.
+    pub fn split(&mut self, target: usize, window: usize, split: Split) {
+        fn split_node(node: &mut Node, target: usize, window: usize, split: Split) -> bool {
+            match node {
+                Node::Window(idx) if *idx == target => {
+                    *node = Node::Split(
+                        split,
+                        vec![(Node::Window(target), 1), (Node::Window(window), 1)],
+                    );
+                    true
+                }
+                Node::Window(_) => false,
+                Node::Split(dir, children) => {
+                    let pos = children.iter().position(
+                        |(child, _)| matches!(child, Node::Window(idx) if *idx == target),
+                    );
+                    match pos {
+                        Some(pos) if *dir == split => {
+                            let size = children[pos].1;
+                            children[pos].1 = size - size / 2;
+                            children.insert(pos + 1, (Node::Window(window), size / 2));
+                            true
+                        }
+                        _ => children
+                            .iter_mut()
+                            .any(|(child, _)| split_node(child, target, window, split)),
+                    }
+                }
+            }
+        }
+        split_node(&mut self.root, target, window, split);
+    }
+
+    /// Takes `target` out of the layout, handing its space to a neighbour. Windows after it
+    /// are renumbered, matching their removal from the window list.
+    pub fn remove(&mut self, target: usize) {
+        fn remove_node(node: &mut Node, target: usize) {
+            match node {
+                Node::Window(idx) => {
+                    if *idx > target {
+                        *idx -= 1;
+                    }
+                }
+                Node::Split(_, children) => {
+                    let pos = children.iter().position(
+                        |(child, _)| matches!(child, Node::Window(idx) if *idx == target),
+                    );
+                    if let Some(pos) = pos {
+                        let (_, size) = children.remove(pos);
+                        let neighbour = pos.min(children.len() - 1);
+                        children[neighbour].1 += size;
+                    }
+                    children
+                        .iter_mut()
+                        .for_each(|(child, _)| remove_node(child, target));
+                    if children.len() == 1 {
+                        *node = children.remove(0).0;
+                    }
+                }
+            }
+        }
+        remove_node(&mut self.root, target);
+    }
+
+    /// Grows `target` by `delta` cells along `split` at the expense of its neighbour, or
+    /// shrinks it for a negative `delta`. Neither window goes below the minimum size.
+    pub fn resize(&mut self, target: usize, split: Split, delta: isize) {
+        // None when `target` isn't under the node, otherwise whether it has been resized yet.
+        fn resize_node(node: &mut Node, target: usize, split: Split, delta: isize) -> Option<bool> {
+            let (dir, children) = match node {
+                Node::Window(idx) => return if *idx == target { Some(false) } else { None },
+                Node::Split(dir, children) => (*dir, children),
+            };
+            let (pos, done) = children
+                .iter_mut()
+                .enumerate()
+                .find_map(|(n, (child, _))| {
+                    resize_node(child, target, split, delta).map(|done| (n, done))
+                })?;
+            if done || dir != split {
+                return Some(done);
+            }
+            let neighbour = if pos + 1 < children.len() {
+                pos + 1
+            } else {
+                pos - 1
+            };
+            let amount = delta.unsigned_abs();
+            if delta > 0 {
+                let amount = min(amount, children[neighbour].1.saturating_sub(MIN_SIZE));
+                children[pos].1 += amount;
+                children[neighbour].1 -= amount;
+            } else {
+                let amount = min(amount, children[pos].1.saturating_sub(MIN_SIZE));
+                children[pos].1 -= amount;
+                children[neighbour].1 += amount;
+            }
+            Some(true)
+        }
+        resize_node(&mut self.root, target, split, delta);
+    }
+
+    /// Shares the space of every split evenly among its children.
+    pub fn equalize(&mut self) {
+        fn equalize_node(node: &mut Node) {
+            if let Node::Split(_, children) = node {
+                for (child, size) in children.iter_mut() {
+                    *size = 1;
+                    equalize_node(child);
+                }
+            }
+        }
+        equalize_node(&mut self.root);
+    }
+
+    /// Assigns every window its area within `rect`. Returns the columns separating side by
+    /// side windows.
+    pub fn arrange(&mut self, rect: Rect, windows: &mut [Window]) -> Vec<Rect> {
+        fn arrange_node(
+            node: &mut Node,
+            rect: Rect,
+            windows: &mut [Window],
+            separators: &mut Vec<Rect>,
+        ) {
+            let (split, children) = match node {
+                Node::Window(idx) => {
+                    if let Some(window) = windows.get_mut(*idx) {
+                        window.rect = rect;
+                    }
+                    return;
+                }
+                Node::Split(split, children) => (*split, children),
+            };
+            let (total, gap) = match split {
+                Split::Horizontal => (rect.height, 0),
+                Split::Vertical => (rect.width, 1),
+            };
+            let available = total.saturating_sub(gap * (children.len() - 1));
+            let weights = children.iter().map(|(_, size)| *size).sum::<usize>().max(1);
+            let last = children.len() - 1;
+            let mut used = 0;
+            let mut offset = 0;
+            for (n, (child, size)) in children.iter_mut().enumerate() {
+                *size = if n == last {
+                    available.saturating_sub(used)
+                } else {
+                    (available * *size / weights).min(available.saturating_sub(used))
+                };
+                let child_rect = match split {
+                    Split::Horizontal => Rect {
+                        top: rect.top + offset,
+                        height: *size,
+                        ..rect
+                    },
+                    Split::Vertical => Rect {
+                        left: rect.left + offset,
+                        width: *size,
+                        ..rect
+                    },
+                };
+                arrange_node(child, child_rect, windows, separators);
+                used += *size;
+                offset += *size + gap;
+                if gap > 0 && n != last {
+                    separators.push(Rect {
+                        left: rect.left + offset - gap,
+                        width: gap,
+                        ..rect
+                    });
+                }
+            }
+        }
+        let mut separators = Vec::new();
+        arrange_node(&mut self.root, rect, windows, &mut separators);
+        separators
+    }
+}